@@ -0,0 +1,358 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+//! Command line tool to operate the NEARswap contract.
+//! Amounts are given and displayed in human units (eg `1.5`). Token decimals are read from the
+//! token `ft_metadata` and cached in `~/.clp-cli/decimals.json`.
+//! Change calls are signed with the `--account` key from `~/.near-credentials`.
+
+use clap::{Parser, Subcommand};
+use serde_json::{json, Value};
+
+mod rpc;
+mod units;
+
+use rpc::{Res, Rpc};
+use units::*;
+
+/// Name used for NEAR in swap and quote commands.
+const NEAR: &str = "near";
+
+#[derive(Parser)]
+#[clap(name = "clp-cli", about = "Operate the NEARswap contract")]
+struct Cli {
+    /// NEAR network: testnet or mainnet.
+    #[clap(long, default_value = "testnet")]
+    network: String,
+    /// NEARswap contract account.
+    #[clap(long, env = "CLP_CONTRACT")]
+    contract: String,
+    /// Account signing the transactions.
+    #[clap(long)]
+    account: Option<String>,
+    #[clap(subcommand)]
+    cmd: Cmd,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+    /// Lists pools.
+    ListPools,
+    /// Shows pool reserves and total shares.
+    PoolInfo { token: String },
+    /// Quotes a swap of `amount` of `from` (token or "near") to `to`.
+    Quote { from: String, to: String, amount: String },
+    /// Creates a new pool for `token`.
+    CreatePool { token: String },
+    /// Adds liquidity from the deposit. `max_tokens` limits the amount of tokens pulled.
+    AddLiquidity {
+        token: String,
+        near: String,
+        max_tokens: String,
+        /// Tolerated shares slippage in percents, with respect to the simulated operation.
+        #[clap(long, default_value = "0.5")]
+        slippage: f64,
+    },
+    /// Redeems LP shares into the deposit.
+    WithdrawLiquidity {
+        token: String,
+        shares: String,
+        /// Tolerated amounts slippage in percents, with respect to the simulated operation.
+        #[clap(long, default_value = "0.5")]
+        slippage: f64,
+    },
+    /// Swaps `amount` of `from` (token or "near") to `to` using the deposit.
+    Swap {
+        from: String,
+        to: String,
+        amount: String,
+        /// Tolerated output slippage in percents, with respect to the current quote.
+        #[clap(long, default_value = "0.5")]
+        slippage: f64,
+    },
+    /// Shows the account deposit of NEAR and `tokens`.
+    Deposit { account: String, tokens: Vec<String> },
+    /// Owner: sets the protocol fee destinations, as `account:weight` pairs.
+    SetFeeDestinations { destinations: Vec<String> },
+    /// Owner: transfers the contract ownership.
+    ChangeOwner { account: String },
+    /// Owner: whitelists tokens.
+    WhitelistTokens { tokens: Vec<String> },
+    /// Owner: removes a token from the whitelist.
+    RemoveWhitelistedToken { token: String },
+}
+
+struct App {
+    rpc: Rpc,
+    contract: String,
+    decimals: DecimalsCache,
+}
+
+impl App {
+    /// Returns the number of decimals of `token` ("near" for NEAR).
+    async fn decimals(&mut self, token: &str) -> Res<u8> {
+        if token == NEAR {
+            return Ok(NEAR_DECIMALS);
+        }
+        if let Some(d) = self.decimals.get(token) {
+            return Ok(d);
+        }
+        let meta: Value = self.rpc.view(token, "ft_metadata", json!({})).await?;
+        let d = meta["decimals"]
+            .as_u64()
+            .ok_or("token metadata doesn't define decimals")? as u8;
+        self.decimals.insert(token, d);
+        Ok(d)
+    }
+
+    async fn parse(&mut self, token: &str, amount: &str) -> Res<u128> {
+        let d = self.decimals(token).await?;
+        Ok(parse_amount(amount, d)?)
+    }
+
+    async fn format(&mut self, token: &str, amount: u128) -> Res<String> {
+        let d = self.decimals(token).await?;
+        Ok(format!("{} {}", format_amount(amount, d), token))
+    }
+
+    async fn view(&self, method: &str, args: Value) -> Res<Value> {
+        self.rpc.view(&self.contract, method, args).await
+    }
+
+    async fn call(&self, method: &str, args: Value, deposit: u128) -> Res<Option<Value>> {
+        self.rpc.call(&self.contract, method, args, deposit).await
+    }
+
+    /// Returns the expected output of swapping `amount` of `from` to `to`.
+    async fn quote(&self, from: &str, to: &str, amount: u128) -> Res<u128> {
+        let out = match (from, to) {
+            (NEAR, NEAR) => return Err("can't swap NEAR to NEAR".into()),
+            (NEAR, t) => {
+                self.view(
+                    "price_near_to_token_in",
+                    json!({"token": t, "ynear_in": amount.to_string()}),
+                )
+                .await?
+            }
+            (t, NEAR) => {
+                self.view(
+                    "price_token_to_near_in",
+                    json!({"token": t, "tokens_in": amount.to_string()}),
+                )
+                .await?
+            }
+            (f, t) => {
+                self.view(
+                    "price_token_to_token_in",
+                    json!({"from": f, "to": t, "tokens_in": amount.to_string()}),
+                )
+                .await?
+            }
+        };
+        to_u128(&out)
+    }
+
+    async fn run(&mut self, cmd: Cmd) -> Res<()> {
+        match cmd {
+            Cmd::ListPools => {
+                println!("{}", self.view("list_pools", json!({})).await?);
+            }
+            Cmd::PoolInfo { token } => {
+                let p = self.view("pool_info", json!({ "token": token })).await?;
+                if p.is_null() {
+                    return Err(format!("pool {} doesn't exist", token).into());
+                }
+                println!("NEAR:   {}", self.format(NEAR, to_u128(&p["ynear"])?).await?);
+                println!("tokens: {}", self.format(&token, to_u128(&p["tokens"])?).await?);
+                println!(
+                    "shares: {}",
+                    format_amount(to_u128(&p["total_shares"])?, NEAR_DECIMALS)
+                );
+            }
+            Cmd::Quote { from, to, amount } => {
+                let amount = self.parse(&from, &amount).await?;
+                let out = self.quote(&from, &to, amount).await?;
+                println!("{}", self.format(&to, out).await?);
+            }
+            Cmd::CreatePool { token } => {
+                let created = self
+                    .call("create_pool", json!({ "token": token }), 0)
+                    .await?
+                    .and_then(|v| v.as_bool())
+                    .ok_or("missing create_pool result")?;
+                if created {
+                    println!("pool {} created", token);
+                } else {
+                    println!("pool {} not created: not a NEP-141 token or the pool exists", token);
+                }
+            }
+            Cmd::AddLiquidity {
+                token,
+                near,
+                max_tokens,
+                slippage,
+            } => {
+                let ynear = self.parse(NEAR, &near).await?;
+                let max_tokens = self.parse(&token, &max_tokens).await?;
+                let args = json!({
+                    "token": token,
+                    "ynear": ynear.to_string(),
+                    "max_tokens": max_tokens.to_string(),
+                });
+                let preview = self.view("simulate_add_liquidity", args).await?;
+                let min_shares = min_with_slippage(to_u128(&preview["shares"])?, slippage);
+                let shares = self
+                    .call(
+                        "add_liquidity",
+                        json!({
+                            "token": token,
+                            "ynear": ynear.to_string(),
+                            "max_tokens": max_tokens.to_string(),
+                            "min_shares": min_shares.to_string(),
+                        }),
+                        1,
+                    )
+                    .await?
+                    .ok_or("missing add_liquidity result")?;
+                println!(
+                    "minted {} shares",
+                    format_amount(to_u128(&shares)?, NEAR_DECIMALS)
+                );
+            }
+            Cmd::WithdrawLiquidity {
+                token,
+                shares,
+                slippage,
+            } => {
+                let shares = parse_amount(&shares, NEAR_DECIMALS)?;
+                let preview = self
+                    .view(
+                        "simulate_withdraw_liquidity",
+                        json!({"token": token, "shares": shares.to_string()}),
+                    )
+                    .await?;
+                let ynear = to_u128(&preview["ynear"])?;
+                let tokens = to_u128(&preview["tokens"])?;
+                self.call(
+                    "withdraw_liquidity",
+                    json!({
+                        "token": token,
+                        "shares": shares.to_string(),
+                        "min_ynear": min_with_slippage(ynear, slippage).max(1).to_string(),
+                        "min_tokens": min_with_slippage(tokens, slippage).max(1).to_string(),
+                    }),
+                    1,
+                )
+                .await?;
+                println!(
+                    "redeemed ~{} and ~{}",
+                    self.format(NEAR, ynear).await?,
+                    self.format(&token, tokens).await?
+                );
+            }
+            Cmd::Swap {
+                from,
+                to,
+                amount,
+                slippage,
+            } => {
+                let amount = self.parse(&from, &amount).await?;
+                let quote = self.quote(&from, &to, amount).await?;
+                let min_out = min_with_slippage(quote, slippage).max(1).to_string();
+                let (method, args) = match (from.as_str(), to.as_str()) {
+                    (NEAR, t) => (
+                        "swap_near_to_token_exact_in",
+                        json!({"ynear_in": amount.to_string(), "token": t, "min_tokens": min_out}),
+                    ),
+                    (t, NEAR) => (
+                        "swap_token_to_near_exact_in",
+                        json!({"token": t, "tokens_paid": amount.to_string(), "min_ynear": min_out}),
+                    ),
+                    (f, t) => (
+                        "swap_tokens_exact_in",
+                        json!({
+                            "token_in": f,
+                            "tokens_in": amount.to_string(),
+                            "token_out": t,
+                            "min_tokens_out": min_out,
+                        }),
+                    ),
+                };
+                let out = self
+                    .call(method, args, 1)
+                    .await?
+                    .ok_or("missing swap result")?;
+                println!("bought {}", self.format(&to, to_u128(&out)?).await?);
+            }
+            Cmd::Deposit { account, tokens } => {
+                let ynear = self
+                    .view("get_deposit_near", json!({ "account_id": account }))
+                    .await?;
+                println!("{}", self.format(NEAR, to_u128(&ynear)?).await?);
+                for t in tokens {
+                    let amount = self
+                        .view(
+                            "get_deposit_token",
+                            json!({"account_id": account, "token_id": t}),
+                        )
+                        .await?;
+                    println!("{}", self.format(&t, to_u128(&amount)?).await?);
+                }
+            }
+            Cmd::SetFeeDestinations { destinations } => {
+                let mut dsts = Vec::new();
+                for d in destinations {
+                    let (account, weight) = d
+                        .split_once(':')
+                        .ok_or_else(|| format!("expected account:weight, got {}", d))?;
+                    let weight: u32 = weight.parse()?;
+                    dsts.push(json!({ "account": account, "weight": weight }));
+                }
+                self.call("set_fee_destinations", json!({ "destinations": dsts }), 0)
+                    .await?;
+            }
+            Cmd::ChangeOwner { account } => {
+                self.call("change_owner", json!({ "new_owner": account }), 0)
+                    .await?;
+            }
+            Cmd::WhitelistTokens { tokens } => {
+                self.call("extend_whitelisted_tokens", json!({ "tokens": tokens }), 0)
+                    .await?;
+            }
+            Cmd::RemoveWhitelistedToken { token } => {
+                self.call("remove_whitelisted_token", json!({ "token": token }), 0)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a JSON U128 (decimal string) value.
+fn to_u128(v: &Value) -> Res<u128> {
+    v.as_str()
+        .ok_or_else(|| format!("expected a number string, got: {}", v))?
+        .parse::<u128>()
+        .map_err(|e| e.into())
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let rpc = match Rpc::new(&cli.network, cli.account.as_deref()) {
+        Ok(rpc) => rpc,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut app = App {
+        rpc,
+        contract: cli.contract,
+        decimals: DecimalsCache::load(),
+    };
+    if let Err(e) = app.run(cli.cmd).await {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
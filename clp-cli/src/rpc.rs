@@ -0,0 +1,141 @@
+//! Thin wrapper over the NEAR JSON RPC client for NEARswap view and change calls.
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use near_crypto::{InMemorySigner, SecretKey};
+use near_jsonrpc_client::{methods, JsonRpcClient};
+use near_jsonrpc_primitives::types::query::QueryResponseKind;
+use near_primitives::transaction::{Action, FunctionCallAction, Transaction};
+use near_primitives::types::{AccountId, BlockReference, Finality, FunctionArgs};
+use near_primitives::views::{FinalExecutionStatus, QueryRequest};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+
+pub type Res<T> = Result<T, Box<dyn Error>>;
+
+/// Prepaid gas for change calls: 100 TGas.
+pub const GAS: u64 = 100_000_000_000_000;
+
+/// Key file format used by near-cli in `~/.near-credentials`.
+#[derive(Deserialize)]
+struct KeyFile {
+    account_id: AccountId,
+    private_key: SecretKey,
+}
+
+pub struct Rpc {
+    client: JsonRpcClient,
+    signer: Option<InMemorySigner>,
+}
+
+impl Rpc {
+    /// Connects to `network` (testnet or mainnet) RPC endpoint. If `account` is set, loads
+    /// its key from the near-cli credentials directory to sign transactions.
+    pub fn new(network: &str, account: Option<&str>) -> Res<Self> {
+        let url = match network {
+            "mainnet" => "https://rpc.mainnet.near.org",
+            "testnet" => "https://rpc.testnet.near.org",
+            _ => return Err(format!("unknown network: {}", network).into()),
+        };
+        let signer = match account {
+            Some(a) => Some(load_signer(network, a)?),
+            None => None,
+        };
+        Ok(Self {
+            client: JsonRpcClient::connect(url),
+            signer,
+        })
+    }
+
+    /// Calls a view method and deserializes its JSON result.
+    pub async fn view<T: DeserializeOwned>(
+        &self,
+        contract: &str,
+        method: &str,
+        args: Value,
+    ) -> Res<T> {
+        let request = methods::query::RpcQueryRequest {
+            block_reference: BlockReference::Finality(Finality::Final),
+            request: QueryRequest::CallFunction {
+                account_id: contract.parse()?,
+                method_name: method.to_string(),
+                args: FunctionArgs::from(args.to_string().into_bytes()),
+            },
+        };
+        match self.client.call(request).await?.kind {
+            QueryResponseKind::CallResult(r) => Ok(serde_json::from_slice(&r.result)?),
+            _ => Err("unexpected view response".into()),
+        }
+    }
+
+    /// Signs and sends a function call transaction, waits for its final result and returns the
+    /// method return value (JSON) if any.
+    pub async fn call(
+        &self,
+        contract: &str,
+        method: &str,
+        args: Value,
+        deposit: u128,
+    ) -> Res<Option<Value>> {
+        let signer = self
+            .signer
+            .as_ref()
+            .ok_or("change calls require the --account option")?;
+        let key = self
+            .client
+            .call(methods::query::RpcQueryRequest {
+                block_reference: BlockReference::latest(),
+                request: QueryRequest::ViewAccessKey {
+                    account_id: signer.account_id.clone(),
+                    public_key: signer.public_key.clone(),
+                },
+            })
+            .await?;
+        let nonce = match key.kind {
+            QueryResponseKind::AccessKey(k) => k.nonce,
+            _ => return Err("can't read the access key nonce".into()),
+        };
+        let tx = Transaction {
+            signer_id: signer.account_id.clone(),
+            public_key: signer.public_key.clone(),
+            nonce: nonce + 1,
+            receiver_id: contract.parse()?,
+            block_hash: key.block_hash,
+            actions: vec![Action::FunctionCall(FunctionCallAction {
+                method_name: method.to_string(),
+                args: args.to_string().into_bytes(),
+                gas: GAS,
+                deposit,
+            })],
+        };
+        let outcome = self
+            .client
+            .call(methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest {
+                signed_transaction: tx.sign(signer),
+            })
+            .await?;
+        for o in outcome.receipts_outcome.iter() {
+            for l in o.outcome.logs.iter() {
+                println!("log: {}", l);
+            }
+        }
+        match outcome.status {
+            FinalExecutionStatus::SuccessValue(v) if v.is_empty() => Ok(None),
+            FinalExecutionStatus::SuccessValue(v) => Ok(serde_json::from_slice(&v).ok()),
+            FinalExecutionStatus::Failure(e) => Err(format!("transaction failed: {}", e).into()),
+            s => Err(format!("unexpected transaction status: {:?}", s).into()),
+        }
+    }
+}
+
+fn load_signer(network: &str, account: &str) -> Res<InMemorySigner> {
+    let home = std::env::var("HOME")?;
+    let path = PathBuf::from(home)
+        .join(".near-credentials")
+        .join(network)
+        .join(format!("{}.json", account));
+    let k: KeyFile = serde_json::from_slice(&std::fs::read(&path)?)?;
+    Ok(InMemorySigner::from_secret_key(k.account_id, k.private_key))
+}
@@ -0,0 +1,114 @@
+//! Conversion between human readable amounts (eg "1.5") and on-chain integer amounts.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Number of decimals of NEAR and NEARswap LP shares.
+pub const NEAR_DECIMALS: u8 = 24;
+
+/// Parses a decimal amount string into the smallest token units.
+pub fn parse_amount(amount: &str, decimals: u8) -> Result<u128, String> {
+    let err = || format!("invalid amount: {}", amount);
+    let (int, frac) = match amount.find('.') {
+        Some(i) => (&amount[..i], &amount[i + 1..]),
+        None => (amount, ""),
+    };
+    if (int.is_empty() && frac.is_empty()) || frac.len() > decimals as usize {
+        return Err(err());
+    }
+    let digits = format!("{}{:0<width$}", int, frac, width = decimals as usize);
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(err());
+    }
+    digits.parse::<u128>().map_err(|_| err())
+}
+
+/// Formats an amount in the smallest token units as a decimal string.
+pub fn format_amount(amount: u128, decimals: u8) -> String {
+    if decimals == 0 {
+        return amount.to_string();
+    }
+    let s = format!("{:0>width$}", amount, width = decimals as usize + 1);
+    let (int, frac) = s.split_at(s.len() - decimals as usize);
+    let frac = frac.trim_end_matches('0');
+    if frac.is_empty() {
+        int.to_string()
+    } else {
+        format!("{}.{}", int, frac)
+    }
+}
+
+/// Applies a slippage tolerance (in percents) to an expected output amount.
+pub fn min_with_slippage(amount: u128, slippage: f64) -> u128 {
+    let bps = (slippage * 100.0).round() as u128;
+    assert!(bps <= 10_000, "slippage must be in the [0, 100] range");
+    amount - amount / 10_000 * bps - amount % 10_000 * bps / 10_000
+}
+
+/// Token decimals cache persisted in the user home directory, so the token metadata is
+/// queried only once per token.
+pub struct DecimalsCache {
+    path: PathBuf,
+    decimals: HashMap<String, u8>,
+}
+
+impl DecimalsCache {
+    pub fn load() -> Self {
+        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+        let path = PathBuf::from(home).join(".clp-cli").join("decimals.json");
+        let decimals = fs::read(&path)
+            .ok()
+            .and_then(|b| serde_json::from_slice(&b).ok())
+            .unwrap_or_default();
+        Self { path, decimals }
+    }
+
+    pub fn get(&self, token: &str) -> Option<u8> {
+        self.decimals.get(token).cloned()
+    }
+
+    pub fn insert(&mut self, token: &str, decimals: u8) {
+        self.decimals.insert(token.to_string(), decimals);
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Ok(b) = serde_json::to_vec_pretty(&self.decimals) {
+            let _ = fs::write(&self.path, b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_amount_works() {
+        assert_eq!(parse_amount("1", 24), Ok(1_000_000_000_000_000_000_000_000));
+        assert_eq!(parse_amount("1.5", 6), Ok(1_500_000));
+        assert_eq!(parse_amount(".25", 2), Ok(25));
+        assert_eq!(parse_amount("12", 0), Ok(12));
+        assert!(parse_amount("1.234", 2).is_err());
+        assert!(parse_amount("1,5", 6).is_err());
+        assert!(parse_amount("-1", 6).is_err());
+        assert!(parse_amount(".", 6).is_err());
+    }
+
+    #[test]
+    fn format_amount_works() {
+        assert_eq!(format_amount(1_500_000, 6), "1.5");
+        assert_eq!(format_amount(25, 2), "0.25");
+        assert_eq!(format_amount(1_000_000, 6), "1");
+        assert_eq!(format_amount(0, 6), "0");
+        assert_eq!(format_amount(12, 0), "12");
+    }
+
+    #[test]
+    fn min_with_slippage_works() {
+        assert_eq!(min_with_slippage(10_000, 0.5), 9_950);
+        assert_eq!(min_with_slippage(10_000, 0.0), 10_000);
+        assert_eq!(min_with_slippage(10_000, 100.0), 0);
+        assert_eq!(min_with_slippage(u128::MAX, 1.0), u128::MAX - u128::MAX / 100);
+    }
+}
@@ -0,0 +1,659 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+//! Typed async client for the NEARswap contract.
+//!
+//! Methods mirror the contract public API. Arguments and return values use the contract types
+//! (`nearswap::types`), so they are always in sync with the deployed interface.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), near_clp_client::Error> {
+//! use near_clp_client::NearSwapClient;
+//!
+//! let c = NearSwapClient::new("https://rpc.testnet.near.org", "beta-1.nearswap.testnet")?;
+//! let pool = c.pool_info("gold.nearswap.testnet").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+
+use near_crypto::InMemorySigner;
+use near_jsonrpc_client::{methods, JsonRpcClient};
+use near_jsonrpc_primitives::types::query::QueryResponseKind;
+use near_primitives::transaction::{Action, FunctionCallAction, Transaction};
+use near_primitives::types::{AccountId, BlockReference, Finality, FunctionArgs};
+use near_primitives::views::{FinalExecutionStatus, QueryRequest};
+use near_sdk::json_types::{U128, U64};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+pub use nearswap::types::{
+    Candle, ContractSourceMetadata, ExternalSwap, GasAction, GovStakeInfo, GovStakingInfo, Launch,
+    LaunchConfig, LiquidityEvent, LiquidityPreview, PoolInfo, PoolImbalance, PoolInvariant,
+    PoolLendingInfo, PoolRate, PoolSnapshot, PoolStakingInfo, PoolTemplate, PriceBounds,
+    PriceSample, Resolution, SwapQuote, SwapRequest, SwapRoute,
+};
+
+/// Default prepaid gas for change calls: 100 TGas.
+pub const DEFAULT_GAS: u64 = 100_000_000_000_000;
+
+#[derive(Debug)]
+pub enum Error {
+    /// RPC request failed.
+    Rpc(String),
+    /// Transaction was executed but failed.
+    Execution(String),
+    /// Response or argument (de)serialization failed.
+    Json(serde_json::Error),
+    /// Change call attempted without a signer.
+    NoSigner,
+    InvalidAccountId(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Rpc(e) => write!(f, "rpc error: {}", e),
+            Error::Execution(e) => write!(f, "execution failed: {}", e),
+            Error::Json(e) => write!(f, "json error: {}", e),
+            Error::NoSigner => write!(f, "change calls require a signer"),
+            Error::InvalidAccountId(a) => write!(f, "invalid account id: {}", a),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+fn account(a: &str) -> Result<AccountId> {
+    a.parse().map_err(|_| Error::InvalidAccountId(a.to_string()))
+}
+
+/// NEARswap contract client. View methods don't require a signer.
+pub struct NearSwapClient {
+    client: JsonRpcClient,
+    contract: AccountId,
+    signer: Option<InMemorySigner>,
+    gas: u64,
+}
+
+impl NearSwapClient {
+    pub fn new(rpc_url: &str, contract: &str) -> Result<Self> {
+        Ok(Self {
+            client: JsonRpcClient::connect(rpc_url),
+            contract: account(contract)?,
+            signer: None,
+            gas: DEFAULT_GAS,
+        })
+    }
+
+    /// Sets the signer used for change calls.
+    pub fn with_signer(mut self, signer: InMemorySigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Sets the prepaid gas for change calls.
+    pub fn with_gas(mut self, gas: u64) -> Self {
+        self.gas = gas;
+        self
+    }
+
+    /// Calls a contract view method.
+    pub async fn view<T: DeserializeOwned>(&self, method: &str, args: Value) -> Result<T> {
+        let request = methods::query::RpcQueryRequest {
+            block_reference: BlockReference::Finality(Finality::Final),
+            request: QueryRequest::CallFunction {
+                account_id: self.contract.clone(),
+                method_name: method.to_string(),
+                args: FunctionArgs::from(args.to_string().into_bytes()),
+            },
+        };
+        let r = self
+            .client
+            .call(request)
+            .await
+            .map_err(|e| Error::Rpc(e.to_string()))?;
+        match r.kind {
+            QueryResponseKind::CallResult(r) => Ok(serde_json::from_slice(&r.result)?),
+            _ => Err(Error::Rpc("unexpected view response".to_string())),
+        }
+    }
+
+    /// Signs and sends a contract function call and waits for the final result.
+    /// Returns the raw method return value.
+    pub async fn call_raw(&self, method: &str, args: Value, deposit: u128) -> Result<Vec<u8>> {
+        let signer = self.signer.as_ref().ok_or(Error::NoSigner)?;
+        let key = self
+            .client
+            .call(methods::query::RpcQueryRequest {
+                block_reference: BlockReference::latest(),
+                request: QueryRequest::ViewAccessKey {
+                    account_id: signer.account_id.clone(),
+                    public_key: signer.public_key.clone(),
+                },
+            })
+            .await
+            .map_err(|e| Error::Rpc(e.to_string()))?;
+        let nonce = match key.kind {
+            QueryResponseKind::AccessKey(k) => k.nonce,
+            _ => return Err(Error::Rpc("can't read the access key nonce".to_string())),
+        };
+        let tx = Transaction {
+            signer_id: signer.account_id.clone(),
+            public_key: signer.public_key.clone(),
+            nonce: nonce + 1,
+            receiver_id: self.contract.clone(),
+            block_hash: key.block_hash,
+            actions: vec![Action::FunctionCall(FunctionCallAction {
+                method_name: method.to_string(),
+                args: args.to_string().into_bytes(),
+                gas: self.gas,
+                deposit,
+            })],
+        };
+        let outcome = self
+            .client
+            .call(methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest {
+                signed_transaction: tx.sign(signer),
+            })
+            .await
+            .map_err(|e| Error::Rpc(e.to_string()))?;
+        match outcome.status {
+            FinalExecutionStatus::SuccessValue(v) => Ok(v),
+            FinalExecutionStatus::Failure(e) => Err(Error::Execution(e.to_string())),
+            s => Err(Error::Execution(format!("unexpected status: {:?}", s))),
+        }
+    }
+
+    /// Calls a contract change method and deserializes its return value.
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        args: Value,
+        deposit: u128,
+    ) -> Result<T> {
+        let v = self.call_raw(method, args, deposit).await?;
+        Ok(serde_json::from_slice(&v)?)
+    }
+
+    /// Calls a contract change method which doesn't return a value.
+    pub async fn call_unit(&self, method: &str, args: Value, deposit: u128) -> Result<()> {
+        self.call_raw(method, args, deposit).await.map(|_| ())
+    }
+
+    /**********************
+     Views
+    **********************/
+
+    pub async fn list_pools(&self) -> Result<Vec<String>> {
+        self.view("list_pools", json!({})).await
+    }
+
+    pub async fn pool_info(&self, token: &str) -> Result<Option<PoolInfo>> {
+        self.view("pool_info", json!({ "token": token })).await
+    }
+
+    pub async fn pool_id(&self, token: &str) -> Result<Option<u32>> {
+        self.view("pool_id", json!({ "token": token })).await
+    }
+
+    pub async fn pool_info_by_id(&self, pool_id: u32) -> Result<Option<PoolInfo>> {
+        self.view("pool_info_by_id", json!({ "pool_id": pool_id })).await
+    }
+
+    pub async fn get_deposit_near(&self, account_id: &str) -> Result<U128> {
+        self.view("get_deposit_near", json!({ "account_id": account_id }))
+            .await
+    }
+
+    pub async fn get_deposit_token(&self, account_id: &str, token_id: &str) -> Result<U128> {
+        self.view(
+            "get_deposit_token",
+            json!({ "account_id": account_id, "token_id": token_id }),
+        )
+        .await
+    }
+
+    pub async fn balance_of(&self, token: &str, holder: &str) -> Result<U128> {
+        self.view("balance_of", json!({ "token": token, "holder": holder }))
+            .await
+    }
+
+    pub async fn total_supply(&self, token: &str) -> Result<U128> {
+        self.view("total_supply", json!({ "token": token })).await
+    }
+
+    pub async fn price_near_to_token_in(&self, token: &str, ynear_in: U128) -> Result<U128> {
+        self.view(
+            "price_near_to_token_in",
+            json!({ "token": token, "ynear_in": ynear_in }),
+        )
+        .await
+    }
+
+    pub async fn price_token_to_near_in(&self, token: &str, tokens_in: U128) -> Result<U128> {
+        self.view(
+            "price_token_to_near_in",
+            json!({ "token": token, "tokens_in": tokens_in }),
+        )
+        .await
+    }
+
+    pub async fn price_token_to_token_in(
+        &self,
+        from: &str,
+        to: &str,
+        tokens_in: U128,
+    ) -> Result<U128> {
+        self.view(
+            "price_token_to_token_in",
+            json!({ "from": from, "to": to, "tokens_in": tokens_in }),
+        )
+        .await
+    }
+
+    pub async fn simulate_add_liquidity(
+        &self,
+        token: &str,
+        ynear: U128,
+        max_tokens: U128,
+    ) -> Result<LiquidityPreview> {
+        self.view(
+            "simulate_add_liquidity",
+            json!({ "token": token, "ynear": ynear, "max_tokens": max_tokens }),
+        )
+        .await
+    }
+
+    pub async fn simulate_withdraw_liquidity(
+        &self,
+        token: &str,
+        shares: U128,
+    ) -> Result<LiquidityPreview> {
+        self.view(
+            "simulate_withdraw_liquidity",
+            json!({ "token": token, "shares": shares }),
+        )
+        .await
+    }
+
+    pub async fn pool_history(
+        &self,
+        token: &str,
+        from_epoch: u64,
+        limit: u64,
+    ) -> Result<Vec<PoolSnapshot>> {
+        self.view(
+            "pool_history",
+            json!({ "token": token, "from_epoch": from_epoch, "limit": limit }),
+        )
+        .await
+    }
+
+    pub async fn candles(
+        &self,
+        token: &str,
+        resolution: Resolution,
+        from: U64,
+        limit: u64,
+    ) -> Result<Vec<Candle>> {
+        self.view(
+            "candles",
+            json!({ "token": token, "resolution": resolution, "from": from, "limit": limit }),
+        )
+        .await
+    }
+
+    pub async fn swap_request(&self, id: u64) -> Result<Option<SwapRequest>> {
+        self.view("swap_request", json!({ "id": id })).await
+    }
+
+    pub async fn event_seq(&self) -> Result<u64> {
+        self.view("event_seq", json!({})).await
+    }
+
+    pub async fn best_quote(
+        &self,
+        token_in: Option<&str>,
+        token_out: Option<&str>,
+        amount_in: U128,
+        slippage_bps: u16,
+    ) -> Result<SwapQuote> {
+        self.view(
+            "best_quote",
+            json!({
+                "token_in": token_in,
+                "token_out": token_out,
+                "amount_in": amount_in,
+                "slippage_bps": slippage_bps,
+            }),
+        )
+        .await
+    }
+
+    pub async fn estimate_gas(&self, action: GasAction) -> Result<U64> {
+        self.view("estimate_gas", json!({ "action": action })).await
+    }
+
+    pub async fn contract_source_metadata(&self) -> Result<ContractSourceMetadata> {
+        self.view("contract_source_metadata", json!({})).await
+    }
+
+    pub async fn pool_staking(&self, token: &str) -> Result<PoolStakingInfo> {
+        self.view("pool_staking", json!({ "token": token })).await
+    }
+
+    pub async fn gov_staking_info(&self) -> Result<GovStakingInfo> {
+        self.view("gov_staking_info", json!({})).await
+    }
+
+    pub async fn gov_stake(&self, account: &str) -> Result<Option<GovStakeInfo>> {
+        self.view("gov_stake", json!({ "account": account })).await
+    }
+
+    pub async fn pool_lending(&self, token: &str) -> Result<PoolLendingInfo> {
+        self.view("pool_lending", json!({ "token": token })).await
+    }
+
+    pub async fn pool_rate(&self, token: &str) -> Result<Option<PoolRate>> {
+        self.view("pool_rate", json!({ "token": token })).await
+    }
+
+    pub async fn pool_invariant(&self, token: &str) -> Result<PoolInvariant> {
+        self.view("pool_invariant", json!({ "token": token })).await
+    }
+
+    pub async fn pool_imbalance(&self, token: &str) -> Result<Option<PoolImbalance>> {
+        self.view("pool_imbalance", json!({ "token": token })).await
+    }
+
+    pub async fn gm_twap(&self, token: &str, window: u64) -> Result<Option<U128>> {
+        self.view("gm_twap", json!({ "token": token, "window": window })).await
+    }
+
+    pub async fn twap_window(&self, token: &str) -> Result<u64> {
+        self.view("twap_window", json!({ "token": token })).await
+    }
+
+    pub async fn pool_templates(&self) -> Result<Vec<(String, PoolTemplate)>> {
+        self.view("pool_templates", json!({})).await
+    }
+
+    pub async fn launch(&self, token: &str) -> Result<Option<Launch>> {
+        self.view("launch", json!({ "token": token })).await
+    }
+
+    pub async fn launch_cost(&self, token: &str, amount: U128) -> Result<U128> {
+        self.view("launch_cost", json!({ "token": token, "amount": amount })).await
+    }
+
+    pub async fn pool_swap_fee(&self, token: &str, near_in: bool) -> Result<u16> {
+        self.view("pool_swap_fee", json!({ "token": token, "near_in": near_in })).await
+    }
+
+    pub async fn pool_max_trade_ynear(&self, token: &str) -> Result<Option<U128>> {
+        self.view("pool_max_trade_ynear", json!({ "token": token })).await
+    }
+
+    pub async fn find_route(
+        &self,
+        from: Option<&str>,
+        to: Option<&str>,
+        amount_in: U128,
+    ) -> Result<Option<SwapRoute>> {
+        self.view("find_route", json!({ "from": from, "to": to, "amount_in": amount_in }))
+            .await
+    }
+
+    pub async fn amm_adapters(&self) -> Result<Vec<(String, String)>> {
+        self.view("amm_adapters", json!({})).await
+    }
+
+    pub async fn external_swap(&self, id: u64) -> Result<Option<ExternalSwap>> {
+        self.view("external_swap", json!({ "id": id })).await
+    }
+
+    pub async fn to_near_value(&self, token: &str, amount: U128) -> Result<U128> {
+        self.view("to_near_value", json!({ "token": token, "amount": amount })).await
+    }
+
+    pub async fn from_near_value(&self, token: &str, near_amount: U128) -> Result<U128> {
+        self.view(
+            "from_near_value",
+            json!({ "token": token, "near_amount": near_amount }),
+        )
+        .await
+    }
+
+    /// Returns up to `limit` liquidity events of the `account` position in the `token` pool,
+    /// starting from the `from_index` event.
+    pub async fn position_history(
+        &self,
+        account: &str,
+        token: &str,
+        from_index: u64,
+        limit: u64,
+    ) -> Result<Vec<LiquidityEvent>> {
+        self.view(
+            "position_history",
+            json!({ "account": account, "token": token, "from_index": from_index, "limit": limit }),
+        )
+        .await
+    }
+
+    /// Returns up to `limit` spot price samples of the `token` pool recorded not earlier than
+    /// `from_ts` (timestamp in nanoseconds).
+    pub async fn price_history(
+        &self,
+        token: &str,
+        from_ts: U64,
+        limit: u64,
+    ) -> Result<Vec<PriceSample>> {
+        self.view(
+            "price_history",
+            json!({ "token": token, "from_ts": from_ts, "limit": limit }),
+        )
+        .await
+    }
+
+    /**********************
+     Change calls
+    **********************/
+
+    /// Registers the signer account (`account_id = None`) or `account_id` and deposits
+    /// `amount` yNEAR for storage.
+    pub async fn storage_deposit(&self, account_id: Option<&str>, amount: u128) -> Result<()> {
+        self.call_unit(
+            "storage_deposit",
+            json!({ "account_id": account_id }),
+            amount,
+        )
+        .await
+    }
+
+    pub async fn add_to_account_whitelist(&self, token_ids: &[&str]) -> Result<()> {
+        self.call_unit(
+            "add_to_account_whitelist",
+            json!({ "token_ids": token_ids }),
+            0,
+        )
+        .await
+    }
+
+    pub async fn deposit_near(&self, amount: u128) -> Result<()> {
+        self.call_unit("deposit_near", json!({}), amount).await
+    }
+
+    pub async fn withdraw_near(&self, amount: U128, recipient: Option<&str>) -> Result<U128> {
+        self.call(
+            "withdraw_near",
+            json!({ "amount": amount, "recipient": recipient }),
+            1,
+        )
+        .await
+    }
+
+    pub async fn withdraw_token(
+        &self,
+        token: &str,
+        amount: U128,
+        recipient: Option<&str>,
+        is_contract: bool,
+        tx_call_msg: &str,
+    ) -> Result<U128> {
+        self.call(
+            "withdraw_token",
+            json!({
+                "token": token,
+                "amount": amount,
+                "recipient": recipient,
+                "is_contract": is_contract,
+                "tx_call_msg": tx_call_msg,
+            }),
+            1,
+        )
+        .await
+    }
+
+    /// Returns false if the `token` isn't a NEP-141 token and the pool wasn't created.
+    pub async fn create_pool(
+        &self,
+        token: &str,
+        price_bounds: Option<PriceBounds>,
+        template: Option<&str>,
+    ) -> Result<bool> {
+        self.call(
+            "create_pool",
+            json!({ "token": token, "price_bounds": price_bounds, "template": template }),
+            0,
+        )
+        .await
+    }
+
+    pub async fn add_liquidity(
+        &self,
+        token: &str,
+        ynear: U128,
+        max_tokens: U128,
+        min_shares: U128,
+    ) -> Result<U128> {
+        self.call(
+            "add_liquidity",
+            json!({
+                "token": token,
+                "ynear": ynear,
+                "max_tokens": max_tokens,
+                "min_shares": min_shares,
+            }),
+            1,
+        )
+        .await
+    }
+
+    pub async fn withdraw_liquidity(
+        &self,
+        token: &str,
+        shares: U128,
+        min_ynear: U128,
+        min_tokens: U128,
+    ) -> Result<()> {
+        self.call_unit(
+            "withdraw_liquidity",
+            json!({
+                "token": token,
+                "shares": shares,
+                "min_ynear": min_ynear,
+                "min_tokens": min_tokens,
+            }),
+            1,
+        )
+        .await
+    }
+
+    pub async fn swap_near_to_token_exact_in(
+        &self,
+        ynear_in: U128,
+        token: &str,
+        min_tokens: U128,
+    ) -> Result<U128> {
+        self.call(
+            "swap_near_to_token_exact_in",
+            json!({ "ynear_in": ynear_in, "token": token, "min_tokens": min_tokens }),
+            1,
+        )
+        .await
+    }
+
+    pub async fn swap_token_to_near_exact_in(
+        &self,
+        token: &str,
+        tokens_paid: U128,
+        min_ynear: U128,
+    ) -> Result<U128> {
+        self.call(
+            "swap_token_to_near_exact_in",
+            json!({ "token": token, "tokens_paid": tokens_paid, "min_ynear": min_ynear }),
+            1,
+        )
+        .await
+    }
+
+    pub async fn swap_tokens_exact_in(
+        &self,
+        token_in: &str,
+        tokens_in: U128,
+        token_out: &str,
+        min_tokens_out: U128,
+    ) -> Result<U128> {
+        self.call(
+            "swap_tokens_exact_in",
+            json!({
+                "token_in": token_in,
+                "tokens_in": tokens_in,
+                "token_out": token_out,
+                "min_tokens_out": min_tokens_out,
+            }),
+            1,
+        )
+        .await
+    }
+
+    pub async fn request_swap(
+        &self,
+        token_in: Option<&str>,
+        amount_in: U128,
+        token_out: Option<&str>,
+        min_out: U128,
+        parts: u8,
+    ) -> Result<u64> {
+        self.call(
+            "request_swap",
+            json!({
+                "token_in": token_in,
+                "amount_in": amount_in,
+                "token_out": token_out,
+                "min_out": min_out,
+                "parts": parts,
+            }),
+            1,
+        )
+        .await
+    }
+
+    pub async fn execute_swap(&self, id: u64) -> Result<U128> {
+        self.call("execute_swap", json!({ "id": id }), 1).await
+    }
+
+    pub async fn cancel_swap(&self, id: u64) -> Result<U128> {
+        self.call("cancel_swap", json!({ "id": id }), 1).await
+    }
+}
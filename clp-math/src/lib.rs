@@ -0,0 +1,385 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+//! NEARswap AMM math. Pure functions, without any NEAR SDK dependency, shared by the contract
+//! and off-chain tools. All intermediate results are computed using `u256` to avoid overflows.
+//!
+//! Rounding policy: every division rounds in favor of the pool, so dust trades can't drain it.
+//! Amounts paid by the pool (swap outputs, redeemed liquidity, minted shares) are rounded
+//! down with `mul_div_floor`. Amounts paid to the pool (fees, added liquidity) are rounded up
+//! with `mul_div_ceil`.
+
+#![cfg_attr(not(test), no_std)]
+
+use uint::construct_uint;
+
+construct_uint! {
+    /// 256-bit unsigned integer.
+    pub struct u256(4);
+}
+
+/// 1 NEAR = 1e24 yNEAR. Spot prices are scaled by this denominator.
+pub const NDENOM: u128 = 1_000_000_000_000_000_000_000_000;
+
+/// Swap fee numerator: 0.3% of the input amount.
+pub const FEE_NUM: u128 = 3;
+/// Swap fee denominator.
+pub const FEE_DENOM: u128 = 1000;
+/// Default swap fee in basis points, equal to `FEE_NUM / FEE_DENOM`.
+pub const FEE_BPS: u16 = 30;
+
+/// Returns `n / d` rounded up. Panics if `d` is zero.
+#[inline]
+pub fn div_ceil(n: u256, d: u256) -> u256 {
+    let (q, r) = n.div_mod(d);
+    if r.is_zero() {
+        q
+    } else {
+        q + 1
+    }
+}
+
+/// Returns `a * b / c` rounded down. Panics if `c` is zero or the result doesn't fit u128.
+#[inline]
+pub fn mul_div_floor(a: u128, b: u128, c: u128) -> u128 {
+    (u256::from(a) * u256::from(b) / u256::from(c)).as_u128()
+}
+
+/// Returns `a * b / c` rounded up. Panics if `c` is zero or the result doesn't fit u128.
+#[inline]
+pub fn mul_div_ceil(a: u128, b: u128, c: u128) -> u128 {
+    div_ceil(u256::from(a) * u256::from(b), u256::from(c)).as_u128()
+}
+
+/// Calculates amout of tokens a user buys for `in_amount` tokens, when a total balance
+/// in the pool is `in_bal` and `out_bal` of paid tokens and buying tokens respectively.
+/// Panics if both `in_amount` and `in_bal` are zero.
+#[inline]
+#[allow(non_snake_case)]
+pub fn calc_out_amount(in_amount: u128, in_bal: u128, out_bal: u128) -> u128 {
+    // formula: y = (x * Y * X) / (x + X)^2
+    let x = u256::from(in_amount);
+    let X = u256::from(in_bal);
+    let numerator = x * u256::from(out_bal) * X;
+    let mut denominator = x + X;
+    denominator *= denominator;
+
+    // rounded down
+    let r = numerator / denominator;
+    r.as_u128()
+}
+
+/// Returns the fee (rounded up) charged for swapping `in_amount`.
+#[inline]
+pub fn calc_fee(in_amount: u128) -> u128 {
+    mul_div_ceil(in_amount, FEE_NUM, FEE_DENOM)
+}
+
+/// Returns the fee (rounded up) charged for swapping `in_amount` with a `fee_bps` fee.
+#[inline]
+pub fn calc_fee_bps(in_amount: u128, fee_bps: u16) -> u128 {
+    mul_div_ceil(in_amount, fee_bps as u128, 10_000)
+}
+
+/// Returns swap out amount and fee. The fee is deducted from the input amount.
+#[allow(non_snake_case)]
+pub fn calc_out_with_fee(x: u128, X: u128, Y: u128) -> (u128, u128) {
+    calc_out_with_fee_bps(x, X, Y, FEE_BPS)
+}
+
+/// Returns swap out amount and fee for a `fee_bps` swap fee. The fee is deducted from the
+/// input amount.
+#[allow(non_snake_case)]
+pub fn calc_out_with_fee_bps(x: u128, X: u128, Y: u128, fee_bps: u16) -> (u128, u128) {
+    if x == 0 {
+        return (0, 0);
+    }
+    let fee = calc_fee_bps(x, fee_bps);
+    (calc_out_amount(x - fee, X, Y), fee)
+}
+
+/// Returns the pool spot price: yNEAR for 1 token unit, multiplied by `NDENOM`.
+/// Panics if `tokens` is zero.
+pub fn spot_price(ynear: u128, tokens: u128) -> u256 {
+    u256::from(ynear) * u256::from(NDENOM) / u256::from(tokens)
+}
+
+/// Fixed point one of the `log2` and `exp2` values: they have 64 fractional bits.
+pub const LOG_ONE: u128 = 1 << 64;
+
+/// 1 and ln(2) with 63 fractional bits, used by `exp2`.
+const Q63: u128 = 1 << 63;
+const LN2_Q63: u128 = 6_393_154_322_601_327_829;
+
+/// Returns `log2(x)`, with 64 fractional bits (see `LOG_ONE`), rounded down.
+/// Panics if `x` is zero.
+pub fn log2(x: u128) -> u128 {
+    assert!(x > 0, "log2 of zero");
+    let n = 127 - x.leading_zeros();
+    // mantissa in [1, 2), with 63 fractional bits.
+    let mut m = if n >= 63 { x >> (n - 63) } else { x << (63 - n) };
+    let mut r = (n as u128) << 64;
+    let mut bit = LOG_ONE >> 1;
+    while bit > 0 {
+        m = m * m >> 63;
+        if m >= 2 * Q63 {
+            m >>= 1;
+            r |= bit;
+        }
+        bit >>= 1;
+    }
+    r
+}
+
+/// Returns `2^y`, where `y` has 64 fractional bits (see `LOG_ONE`), rounded down and capped
+/// at `u128::MAX`. Inverse of `log2`.
+pub fn exp2(y: u128) -> u128 {
+    let n = y >> 64;
+    if n >= 128 {
+        return u128::MAX;
+    }
+    // 2^f = e^(f * ln2) for the fraction f in [0, 1), with the Taylor series.
+    let x = ((y & (LOG_ONE - 1)) >> 1) * LN2_Q63 >> 63;
+    let (mut m, mut term, mut k) = (Q63, Q63, 1);
+    while term > 0 {
+        term = term * x / Q63 / k;
+        m += term;
+        k += 1;
+    }
+    if n >= 63 {
+        let shift = (n - 63) as u32;
+        if m.leading_zeros() < shift {
+            return u128::MAX;
+        }
+        m << shift
+    } else {
+        m >> (63 - n)
+    }
+}
+
+/// Computes the liquidity added to a pool with `pool_ynear`, `pool_tokens` reserves and
+/// `total_shares` when depositing `ynear` and at most `max_tokens`.
+/// The first deposit (`total_shares == 0`) defines the price and mints `ynear` shares.
+/// Otherwise tokens are added proportionally to the reserves and, if it would exceed
+/// `max_tokens`, `ynear` is reduced to match `max_tokens`. Shares are minted for the binding
+/// amount (rounded down) and the added amounts are rounded up.
+/// Returns: (ynear added, tokens added, shares minted).
+pub fn calc_add_liquidity(
+    pool_ynear: u128,
+    pool_tokens: u128,
+    total_shares: u128,
+    ynear: u128,
+    max_tokens: u128,
+) -> (u128, u128, u128) {
+    if total_shares == 0 {
+        return (ynear, max_tokens, ynear);
+    }
+    let added_tokens = mul_div_ceil(ynear, pool_tokens, pool_ynear);
+    if added_tokens <= max_tokens {
+        let shares_minted = mul_div_floor(ynear, total_shares, pool_ynear);
+        (ynear, added_tokens, shares_minted)
+    } else {
+        // Adjust near according to max_tokens
+        let added_near = mul_div_ceil(max_tokens, pool_ynear, pool_tokens);
+        let shares_minted = mul_div_floor(max_tokens, total_shares, pool_tokens);
+        (added_near, max_tokens, shares_minted)
+    }
+}
+
+/// Computes the yNEAR and tokens (rounded down) redeemed by burning `shares` from a pool
+/// with `pool_ynear`, `pool_tokens` reserves and `total_shares`.
+/// Panics if `total_shares` is zero.
+/// Returns: (ynear, tokens).
+pub fn calc_withdraw_liquidity(
+    pool_ynear: u128,
+    pool_tokens: u128,
+    total_shares: u128,
+    shares: u128,
+) -> (u128, u128) {
+    let ynear = mul_div_floor(shares, pool_ynear, total_shares);
+    let tokens = mul_div_floor(shares, pool_tokens, total_shares);
+    (ynear, tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const G: u128 = 1_000_000_000;
+
+    #[test]
+    fn out_amount() {
+        // same supply: y = (x * Y * X) / (x + X)^2, rounded down.
+        assert_eq!(calc_out_amount(1, 10, 10), 0);
+        assert_eq!(calc_out_amount(1, G, G), 0);
+        assert_eq!(calc_out_amount(2, G, G), 1);
+        assert_eq!(calc_out_amount(100, G, G), 99);
+        assert_eq!(calc_out_amount(10_000, G, G), 9999);
+        assert_eq!(calc_out_amount(20_000, NDENOM, NDENOM), 19999);
+        // 2:1
+        assert_eq!(calc_out_amount(10_000, 2 * G, G), 4999);
+        assert_eq!(calc_out_amount(20_000, 2 * NDENOM, NDENOM), 9999);
+        // 1:2
+        assert_eq!(calc_out_amount(1, G, 2 * G), 1);
+        assert_eq!(calc_out_amount(10_000, G, 2 * G), 19999);
+        assert_eq!(calc_out_amount(10, 12 * NDENOM, 2400), 0);
+        // empty input or output reserve
+        assert_eq!(calc_out_amount(0, G, G), 0);
+        assert_eq!(calc_out_amount(G, G, 0), 0);
+    }
+
+    #[test]
+    fn out_amount_never_exceeds_reserve() {
+        // x * X * Y must fit into u256.
+        let bals = [1, 10, G, NDENOM, 1 << 84];
+        for &x in bals.iter() {
+            for &in_bal in bals.iter() {
+                for &out_bal in bals.iter() {
+                    let y = calc_out_amount(x, in_bal, out_bal);
+                    assert!(y < out_bal, "x={} X={} Y={} y={}", x, in_bal, out_bal, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn out_amount_is_monotonic_for_small_inputs() {
+        // the output grows with the input as long as the input is smaller than the reserve.
+        let mut prev = 0;
+        for i in 1..1000 {
+            let y = calc_out_amount(i * G, 1000 * G, 1000 * G);
+            assert!(y >= prev);
+            prev = y;
+        }
+    }
+
+    #[test]
+    fn fee() {
+        assert_eq!(calc_fee(0), 0);
+        // rounded up
+        assert_eq!(calc_fee(1), 1);
+        assert_eq!(calc_fee(333), 1);
+        assert_eq!(calc_fee(334), 2);
+        assert_eq!(calc_fee(1000), 3);
+        assert_eq!(calc_fee(1001), 4);
+        assert_eq!(calc_fee(NDENOM), 3 * NDENOM / 1000);
+        // doesn't overflow
+        assert_eq!(calc_fee(u128::MAX), u128::MAX / 1000 * 3 + 2);
+    }
+
+    #[test]
+    fn fee_bps() {
+        for x in [0, 1, 333, 334, 1000, 1001, NDENOM, u128::MAX].iter() {
+            assert_eq!(calc_fee_bps(*x, FEE_BPS), calc_fee(*x));
+        }
+        assert_eq!(calc_fee_bps(NDENOM, 5), NDENOM / 2000);
+        assert_eq!(calc_fee_bps(1, 0), 0);
+    }
+
+    #[test]
+    fn out_with_fee() {
+        assert_eq!(calc_out_with_fee(0, G, G), (0, 0));
+        assert_eq!(calc_out_with_fee(1000, G, G), (calc_out_amount(997, G, G), 3));
+        let (y, fee) = calc_out_with_fee(1_000_000, G, G);
+        assert_eq!(fee, 3000);
+        assert!(y < calc_out_amount(1_000_000, G, G));
+    }
+
+    #[test]
+    fn price() {
+        assert_eq!(spot_price(G, G), u256::from(NDENOM));
+        assert_eq!(spot_price(2 * G, G), u256::from(2 * NDENOM));
+        assert_eq!(spot_price(G, 2 * G), u256::from(NDENOM / 2));
+        // doesn't overflow
+        assert_eq!(
+            spot_price(u128::MAX, 1),
+            u256::from(u128::MAX) * u256::from(NDENOM)
+        );
+    }
+
+    #[test]
+    fn add_liquidity_first_deposit() {
+        assert_eq!(calc_add_liquidity(0, 0, 0, 10 * G, 5 * G), (10 * G, 5 * G, 10 * G));
+    }
+
+    #[test]
+    fn add_liquidity_proportional() {
+        // pool 1:2, enough tokens
+        assert_eq!(
+            calc_add_liquidity(10 * G, 20 * G, 10 * G, G, 3 * G),
+            (G, 2 * G, G)
+        );
+        // shares are proportional to the total shares
+        assert_eq!(
+            calc_add_liquidity(10 * G, 20 * G, 30 * G, G, 3 * G),
+            (G, 2 * G, 3 * G)
+        );
+        // tokens are rounded up, shares are rounded down
+        assert_eq!(calc_add_liquidity(3, 10, 3, 1, 10), (1, 4, 1));
+        assert_eq!(calc_add_liquidity(3, 10, 2, 1, 10), (1, 4, 0));
+    }
+
+    #[test]
+    fn add_liquidity_limited_by_max_tokens() {
+        // pool 1:2, only G tokens allowed: ynear is reduced to G/2.
+        assert_eq!(
+            calc_add_liquidity(10 * G, 20 * G, 10 * G, G, G),
+            (G / 2, G, G / 2)
+        );
+        // ynear is rounded up, shares are minted for the tokens and rounded down.
+        assert_eq!(calc_add_liquidity(10, 3, 10, 10, 1), (4, 1, 3));
+        // previously shares were minted for the rounded up ynear, making the
+        // add + withdraw round trip profitable.
+        assert_eq!(calc_add_liquidity(1, 1000, 1000, 1, 1), (1, 1, 1));
+    }
+
+    #[test]
+    fn add_liquidity_no_overflow() {
+        let big = u128::MAX / 4;
+        assert_eq!(
+            calc_add_liquidity(big, big, big, big, u128::MAX),
+            (big, big, big)
+        );
+    }
+
+    #[test]
+    fn withdraw_liquidity() {
+        assert_eq!(calc_withdraw_liquidity(10 * G, 20 * G, 10 * G, G), (G, 2 * G));
+        assert_eq!(calc_withdraw_liquidity(10 * G, 20 * G, 10 * G, 0), (0, 0));
+        assert_eq!(
+            calc_withdraw_liquidity(10 * G, 20 * G, 10 * G, 10 * G),
+            (10 * G, 20 * G)
+        );
+        // rounded down
+        assert_eq!(calc_withdraw_liquidity(10, 10, 3, 1), (3, 3));
+    }
+
+    #[test]
+    fn add_then_withdraw_doesnt_drain_pool() {
+        let (mut ynear, mut tokens, total) = (1000 * G, 3000 * G + 7, 1000 * G);
+        for i in 1..50u128 {
+            let (dy, dt, s) = calc_add_liquidity(ynear, tokens, total, i * 12345, u128::MAX);
+            let (ry, rt) = calc_withdraw_liquidity(ynear + dy, tokens + dt, total + s, s);
+            assert!(ry <= dy && rt <= dt, "round trip must not profit: i={}", i);
+            ynear += dy - ry;
+            tokens += dt - rt;
+        }
+    }
+
+    #[test]
+    fn log2_exp2() {
+        assert_eq!(log2(1), 0);
+        assert_eq!(log2(1 << 100), 100 * LOG_ONE);
+        assert_eq!(exp2(0), 1);
+        assert_eq!(exp2(80 * LOG_ONE), 1 << 80);
+        assert_eq!(exp2(128 * LOG_ONE), u128::MAX);
+        // round trip, rounded down with a relative error below 1e-18, plus 1.
+        for &x in [1000 * G, NDENOM, 12_345_678_901_234_567_890_123, u128::MAX].iter() {
+            let y = exp2(log2(x));
+            assert!(y <= x && x - y <= x / 1_000_000_000_000_000_000 + 1, "x={} y={}", x, y);
+        }
+        // geometric mean of 1 and 4 NEAR.
+        let gm = exp2((log2(NDENOM) + log2(4 * NDENOM)) / 2);
+        assert!(2 * NDENOM - gm < G, "gm={}", gm);
+    }
+}
@@ -0,0 +1,173 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+//! Property based tests of the AMM invariants. A pool model is driven through random
+//! sequences of liquidity and swap operations, using the same math as the contract.
+
+use clp_math::*;
+use proptest::prelude::*;
+
+/// Max pool reserve. x * X * Y must fit into u256.
+const MAX_RESERVE: u128 = 1 << 80;
+/// Max amount of a single operation.
+const MAX_AMOUNT: u128 = 1 << 70;
+const LPS: usize = 3;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Add {
+        lp: usize,
+        ynear: u128,
+        max_tokens: u128,
+    },
+    /// withdraws `permille` / 1000 of the LP shares.
+    Withdraw {
+        lp: usize,
+        permille: u128,
+    },
+    SwapNearIn(u128),
+    SwapTokensIn(u128),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (0..LPS, 1..MAX_AMOUNT, 1..MAX_AMOUNT).prop_map(|(lp, ynear, max_tokens)| Op::Add {
+            lp,
+            ynear,
+            max_tokens
+        }),
+        (0..LPS, 1..=1000u128).prop_map(|(lp, permille)| Op::Withdraw { lp, permille }),
+        (1..MAX_AMOUNT).prop_map(Op::SwapNearIn),
+        (1..MAX_AMOUNT).prop_map(Op::SwapTokensIn),
+    ]
+}
+
+#[derive(Debug, Default)]
+struct Pool {
+    ynear: u128,
+    tokens: u128,
+    total_shares: u128,
+    shares: [u128; LPS],
+}
+
+impl Pool {
+    fn k(&self) -> u256 {
+        u256::from(self.ynear) * u256::from(self.tokens)
+    }
+
+    /// Applies `op` to the pool. Returns false if the operation is not applicable in the
+    /// current state (the contract would reject it or the reserves would exceed the test range).
+    fn apply(&mut self, op: &Op) -> bool {
+        match *op {
+            Op::Add {
+                lp,
+                ynear,
+                max_tokens,
+            } => {
+                if self.total_shares > 0
+                    && u256::from(ynear) * u256::from(self.tokens) / u256::from(self.ynear)
+                        >= u256::from(MAX_RESERVE)
+                {
+                    return false;
+                }
+                let (y, t, s) = calc_add_liquidity(
+                    self.ynear,
+                    self.tokens,
+                    self.total_shares,
+                    ynear,
+                    max_tokens,
+                );
+                assert!(t <= max_tokens, "added more tokens than allowed");
+                if self.ynear + y > MAX_RESERVE || self.tokens + t > MAX_RESERVE {
+                    return false;
+                }
+                self.ynear += y;
+                self.tokens += t;
+                self.total_shares += s;
+                self.shares[lp] += s;
+            }
+            Op::Withdraw { lp, permille } => {
+                let s = self.shares[lp] * permille / 1000;
+                if s == 0 {
+                    return false;
+                }
+                let (y, t) =
+                    calc_withdraw_liquidity(self.ynear, self.tokens, self.total_shares, s);
+                self.ynear -= y;
+                self.tokens -= t;
+                self.total_shares -= s;
+                self.shares[lp] -= s;
+            }
+            Op::SwapNearIn(x) => {
+                if self.total_shares == 0 || self.ynear + x > MAX_RESERVE {
+                    return false;
+                }
+                let (y, _) = calc_out_with_fee(x, self.ynear, self.tokens);
+                self.ynear += x;
+                self.tokens -= y;
+            }
+            Op::SwapTokensIn(x) => {
+                if self.total_shares == 0 || self.tokens + x > MAX_RESERVE {
+                    return false;
+                }
+                let (y, _) = calc_out_with_fee(x, self.tokens, self.ynear);
+                self.tokens += x;
+                self.ynear -= y;
+            }
+        }
+        true
+    }
+}
+
+proptest! {
+    #[test]
+    fn pool_invariants(ops in prop::collection::vec(op(), 1..50)) {
+        let mut p = Pool::default();
+        for op in ops.iter() {
+            let k = p.k();
+            if !p.apply(op) {
+                continue;
+            }
+            // shares are conserved
+            prop_assert_eq!(p.shares.iter().sum::<u128>(), p.total_shares);
+            // a pool with shares has both reserves, a pool without shares is empty
+            if p.total_shares > 0 {
+                prop_assert!(p.ynear > 0 && p.tokens > 0, "{:?}", p);
+            } else {
+                prop_assert!(p.ynear == 0 && p.tokens == 0, "{:?}", p);
+            }
+            if let Op::SwapNearIn(_) | Op::SwapTokensIn(_) = op {
+                prop_assert!(p.k() >= k, "k decreased: {:?} after {:?}", p, op);
+            }
+        }
+    }
+
+    #[test]
+    fn swap_never_decreases_k(
+        x in 1..MAX_RESERVE,
+        ynear in 1..MAX_RESERVE,
+        tokens in 1..MAX_RESERVE,
+    ) {
+        let (y, fee) = calc_out_with_fee(x, ynear, tokens);
+        prop_assert!(fee <= x);
+        prop_assert!(y < tokens);
+        let k = u256::from(ynear) * u256::from(tokens);
+        prop_assert!(u256::from(ynear + x) * u256::from(tokens - y) >= k);
+    }
+
+    /// Adding liquidity and redeeming the minted shares never returns more than was added.
+    #[test]
+    fn withdraw_add_doesnt_profit(
+        ynear in (1 << 40)..MAX_RESERVE,
+        tokens in (1 << 40)..MAX_RESERVE,
+        total_shares in 1..MAX_RESERVE,
+        add_ynear in 1..(1u128 << 40),
+        max_tokens in 1..(1u128 << 40),
+    ) {
+        let (y, t, s) = calc_add_liquidity(ynear, tokens, total_shares, add_ynear, max_tokens);
+        prop_assert!(y <= add_ynear && t <= max_tokens);
+        let (ry, rt) = calc_withdraw_liquidity(ynear + y, tokens + t, total_shares + s, s);
+        prop_assert!(ry <= y, "got {} yNEAR back, added {}", ry, y);
+        prop_assert!(rt <= t, "got {} tokens back, added {}", rt, t);
+    }
+}
@@ -0,0 +1,545 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+//! End-to-end tests: NEARswap and a NEP-141 token (mock-token) deployed in a sandbox,
+//! exercising the full promise chains: token deposits (`ft_transfer_call` ->
+//! `ft_on_transfer`), liquidity, swaps and withdrawals with their resolve callbacks.
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use workspaces::network::Sandbox;
+use workspaces::result::ExecutionFinalResult;
+use workspaces::{parse_near, Account, Contract, Worker};
+
+const NEARSWAP_WASM: &str = "../res/nearswap.wasm";
+const TOKEN_WASM: &str = "../res/mock_token.wasm";
+
+type Res<T> = anyhow::Result<T>;
+
+struct Env {
+    worker: Worker<Sandbox>,
+    clp: Contract,
+    token: Contract,
+    alice: Account,
+}
+
+/// Deploys and initializes NEARswap and a token, creates the token pool and an `alice`
+/// account registered in NEARswap, owning 1000 NEAR worth of tokens.
+async fn setup() -> Res<Env> {
+    let worker = workspaces::sandbox().await?;
+    let root = worker.root_account()?;
+    let clp = root
+        .create_subaccount("clp")
+        .initial_balance(parse_near!("50 N"))
+        .transact()
+        .await?
+        .into_result()?
+        .deploy(&std::fs::read(NEARSWAP_WASM)?)
+        .await?
+        .into_result()?;
+    let alice = root
+        .create_subaccount("alice")
+        .initial_balance(parse_near!("100 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    clp.call("new")
+        .args_json(json!({ "owner": root.id() }))
+        .transact()
+        .await?
+        .into_result()?;
+    let token = deploy_token(&worker, &clp, "dai", &alice).await?;
+    register(&clp, &alice, &token).await?;
+
+    Ok(Env {
+        worker,
+        clp,
+        token,
+        alice,
+    })
+}
+
+/// Deploys the `name` token, mints 1000 NEAR worth of tokens to the `holder`, registers
+/// NEARswap in the token and creates the token pool.
+async fn deploy_token(
+    worker: &Worker<Sandbox>,
+    clp: &Contract,
+    name: &str,
+    holder: &Account,
+) -> Res<Contract> {
+    let root = worker.root_account()?;
+    let token = root
+        .create_subaccount(name)
+        .initial_balance(parse_near!("50 N"))
+        .transact()
+        .await?
+        .into_result()?
+        .deploy(&std::fs::read(TOKEN_WASM)?)
+        .await?
+        .into_result()?;
+    token.call("new").transact().await?.into_result()?;
+    token
+        .call("mint")
+        .args_json(json!({"account_id": holder.id(), "amount": parse_near!("1000 N").to_string()}))
+        .transact()
+        .await?
+        .into_result()?;
+    token
+        .call("storage_deposit")
+        .args_json(json!({ "account_id": clp.id() }))
+        .deposit(parse_near!("1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    root.call(clp.id(), "extend_whitelisted_tokens")
+        .args_json(json!({ "tokens": [token.id()] }))
+        .transact()
+        .await?
+        .into_result()?;
+    root.call(clp.id(), "create_pool")
+        .args_json(json!({ "token": token.id() }))
+        .transact()
+        .await?
+        .into_result()?;
+    Ok(token)
+}
+
+/// Registers the `account` in NEARswap, if not registered yet, and whitelists the `token`
+/// in its deposit.
+async fn register(clp: &Contract, account: &Account, token: &Contract) -> Res<()> {
+    account
+        .call(clp.id(), "storage_deposit")
+        .args_json(json!({ "registration_only": true }))
+        .deposit(parse_near!("1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+    account
+        .call(clp.id(), "add_to_account_whitelist")
+        .args_json(json!({ "token_ids": [token.id()] }))
+        .transact()
+        .await?
+        .into_result()?;
+    Ok(())
+}
+
+impl Env {
+    /// Calls a NEARswap method as alice, attaching `deposit`.
+    async fn call(&self, method: &str, args: Value, deposit: u128) -> Res<ExecutionFinalResult> {
+        self.call_as(&self.alice, method, args, deposit).await
+    }
+
+    async fn call_as(
+        &self,
+        account: &Account,
+        method: &str,
+        args: Value,
+        deposit: u128,
+    ) -> Res<ExecutionFinalResult> {
+        Ok(account
+            .call(self.clp.id(), method)
+            .args_json(args)
+            .deposit(deposit)
+            .max_gas()
+            .transact()
+            .await?)
+    }
+
+    async fn view<T: DeserializeOwned>(&self, method: &str, args: Value) -> Res<T> {
+        Ok(self.clp.view(method).args_json(args).await?.json()?)
+    }
+
+    async fn ft_balance(&self, account: &Account) -> Res<u128> {
+        let b: String = self
+            .token
+            .view("ft_balance_of")
+            .args_json(json!({ "account_id": account.id() }))
+            .await?
+            .json()?;
+        Ok(b.parse()?)
+    }
+
+    async fn deposit_token(&self) -> Res<u128> {
+        self.deposit_of(&self.alice, &self.token).await
+    }
+
+    /// Returns the `account` deposit of the `token`.
+    async fn deposit_of(&self, account: &Account, token: &Contract) -> Res<u128> {
+        let d: String = self
+            .view(
+                "get_deposit_token",
+                json!({"account_id": account.id(), "token_id": token.id()}),
+            )
+            .await?;
+        Ok(d.parse()?)
+    }
+
+    async fn deposit_near(&self) -> Res<u128> {
+        self.deposit_near_of(&self.alice).await
+    }
+
+    async fn deposit_near_of(&self, account: &Account) -> Res<u128> {
+        let d: String = self
+            .view("get_deposit_near", json!({ "account_id": account.id() }))
+            .await?;
+        Ok(d.parse()?)
+    }
+
+    /// Deposits NEAR and tokens to alice NEARswap deposit.
+    async fn fund(&self, ynear: u128, tokens: u128) -> Res<()> {
+        self.fund_as(&self.alice, &self.token, ynear, tokens).await
+    }
+
+    /// Deposits NEAR and `token` tokens to the `account` NEARswap deposit.
+    async fn fund_as(
+        &self,
+        account: &Account,
+        token: &Contract,
+        ynear: u128,
+        tokens: u128,
+    ) -> Res<()> {
+        self.call_as(account, "deposit_near", json!({}), ynear)
+            .await?
+            .into_result()?;
+        account
+            .call(token.id(), "ft_transfer_call")
+            .args_json(json!({
+                "receiver_id": self.clp.id(),
+                "amount": tokens.to_string(),
+                "msg": "",
+            }))
+            .deposit(1)
+            .max_gas()
+            .transact()
+            .await?
+            .into_result()?;
+        Ok(())
+    }
+
+    /// Adds liquidity from alice deposit.
+    async fn add_liquidity(&self, ynear: u128, tokens: u128) -> Res<u128> {
+        self.add_liquidity_as(&self.alice, &self.token, ynear, tokens).await
+    }
+
+    /// Adds liquidity to the `token` pool from the `account` deposit.
+    async fn add_liquidity_as(
+        &self,
+        account: &Account,
+        token: &Contract,
+        ynear: u128,
+        tokens: u128,
+    ) -> Res<u128> {
+        let shares: String = self
+            .call_as(
+                account,
+                "add_liquidity",
+                json!({
+                    "token": token.id(),
+                    "ynear": ynear.to_string(),
+                    "max_tokens": tokens.to_string(),
+                    "min_shares": "0",
+                }),
+                1,
+            )
+            .await?
+            .into_result()?
+            .json()?;
+        Ok(shares.parse()?)
+    }
+}
+
+fn field_u128(p: &Value, field: &str) -> u128 {
+    p[field].as_str().unwrap().parse().unwrap()
+}
+
+#[tokio::test]
+async fn deposit_and_add_liquidity() -> Res<()> {
+    let e = setup().await?;
+    let tokens = parse_near!("100 N");
+    let ynear = parse_near!("10 N");
+    e.fund(ynear, tokens).await?;
+    assert_eq!(e.deposit_token().await?, tokens);
+    assert_eq!(e.ft_balance(&e.alice).await?, parse_near!("900 N"));
+
+    let shares = e.add_liquidity(ynear, tokens).await?;
+    assert_eq!(shares, ynear);
+    let p: Value = e.view("pool_info", json!({ "token": e.token.id() })).await?;
+    assert_eq!(field_u128(&p, "ynear"), ynear);
+    assert_eq!(field_u128(&p, "tokens"), tokens);
+    assert_eq!(field_u128(&p, "total_shares"), shares);
+    assert_eq!(e.deposit_token().await?, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn swaps() -> Res<()> {
+    let e = setup().await?;
+    e.fund(parse_near!("20 N"), parse_near!("200 N")).await?;
+    e.add_liquidity(parse_near!("10 N"), parse_near!("100 N")).await?;
+
+    let ynear_in = parse_near!("1 N");
+    let quote: String = e
+        .view(
+            "price_near_to_token_in",
+            json!({"token": e.token.id(), "ynear_in": ynear_in.to_string()}),
+        )
+        .await?;
+    let tokens_before = e.deposit_token().await?;
+    let out: String = e
+        .call(
+            "swap_near_to_token_exact_in",
+            json!({
+                "ynear_in": ynear_in.to_string(),
+                "token": e.token.id(),
+                "min_tokens": quote,
+            }),
+            1,
+        )
+        .await?
+        .into_result()?
+        .json()?;
+    assert_eq!(out, quote);
+    let out: u128 = out.parse()?;
+    assert_eq!(e.deposit_token().await?, tokens_before + out);
+
+    // slippage protection: asking for more than the quote fails and doesn't change deposits.
+    let near_before = e.deposit_near().await?;
+    let res = e
+        .call(
+            "swap_token_to_near_exact_in",
+            json!({
+                "token": e.token.id(),
+                "tokens_paid": out.to_string(),
+                "min_ynear": ynear_in.to_string(),
+            }),
+            1,
+        )
+        .await?;
+    assert!(res.is_failure());
+    assert_eq!(e.deposit_near().await?, near_before);
+
+    let near_out: String = e
+        .call(
+            "swap_token_to_near_exact_in",
+            json!({"token": e.token.id(), "tokens_paid": out.to_string(), "min_ynear": "1"}),
+            1,
+        )
+        .await?
+        .into_result()?
+        .json()?;
+    let near_out: u128 = near_out.parse()?;
+    // fees are charged in both directions.
+    assert!(near_out < ynear_in);
+    assert_eq!(e.deposit_near().await?, near_before + near_out);
+    Ok(())
+}
+
+#[tokio::test]
+async fn withdraw_liquidity_and_tokens() -> Res<()> {
+    let e = setup().await?;
+    let tokens = parse_near!("100 N");
+    e.fund(parse_near!("10 N"), tokens).await?;
+    let shares = e.add_liquidity(parse_near!("10 N"), tokens).await?;
+
+    e.call(
+        "withdraw_liquidity",
+        json!({
+            "token": e.token.id(),
+            "shares": shares.to_string(),
+            "min_ynear": "1",
+            "min_tokens": "1",
+        }),
+        1,
+    )
+    .await?
+    .into_result()?;
+    assert_eq!(e.deposit_token().await?, tokens);
+
+    let balance = e.ft_balance(&e.alice).await?;
+    e.call(
+        "withdraw_token",
+        json!({
+            "token": e.token.id(),
+            "amount": tokens.to_string(),
+            "is_contract": false,
+            "tx_call_msg": "",
+        }),
+        1,
+    )
+    .await?
+    .into_result()?;
+    assert_eq!(e.ft_balance(&e.alice).await?, balance + tokens);
+    assert_eq!(e.deposit_token().await?, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn failed_token_withdraw_is_refunded() -> Res<()> {
+    let e = setup().await?;
+    let tokens = parse_near!("10 N");
+    e.fund(parse_near!("1 N"), tokens).await?;
+    // bob is not registered in the token contract, so the transfer fails.
+    let bob = e
+        .worker
+        .root_account()?
+        .create_subaccount("bob")
+        .initial_balance(parse_near!("5 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let res = e
+        .call(
+            "withdraw_token",
+            json!({
+                "token": e.token.id(),
+                "amount": tokens.to_string(),
+                "recipient": bob.id(),
+                "is_contract": false,
+                "tx_call_msg": "",
+            }),
+            1,
+        )
+        .await?;
+    let withdrawn: String = res.json()?;
+    assert_eq!(withdrawn, "0");
+    assert_eq!(e.deposit_token().await?, tokens);
+    assert_eq!(e.ft_balance(&bob).await?, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn storage_registration() -> Res<()> {
+    let e = setup().await?;
+    let bounds: Value = e.view("storage_balance_bounds", json!({})).await?;
+    let min = field_u128(&bounds, "min");
+    // registration only keeps the minimum balance and refunds the rest.
+    assert_eq!(e.deposit_near().await?, min);
+    e.call("storage_deposit", json!({}), parse_near!("1 N"))
+        .await?
+        .into_result()?;
+    assert_eq!(e.deposit_near().await?, min + parse_near!("1 N"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn liquidity_redeemed_pro_rata() -> Res<()> {
+    let e = setup().await?;
+    let bob = e
+        .worker
+        .root_account()?
+        .create_subaccount("bob")
+        .initial_balance(parse_near!("50 N"))
+        .transact()
+        .await?
+        .into_result()?;
+    e.token
+        .call("mint")
+        .args_json(json!({"account_id": bob.id(), "amount": parse_near!("100 N").to_string()}))
+        .transact()
+        .await?
+        .into_result()?;
+    register(&e.clp, &bob, &e.token).await?;
+    e.fund(parse_near!("20 N"), parse_near!("100 N")).await?;
+    e.fund_as(&bob, &e.token, parse_near!("5 N"), parse_near!("100 N"))
+        .await?;
+    let alice_shares = e.add_liquidity(parse_near!("9 N"), parse_near!("90 N")).await?;
+    let bob_shares = e
+        .add_liquidity_as(&bob, &e.token, parse_near!("1 N"), parse_near!("11 N"))
+        .await?;
+    assert_eq!(alice_shares, 9 * bob_shares);
+
+    // the swap fee stays in the pool and is shared by the LPs.
+    e.call(
+        "swap_near_to_token_exact_in",
+        json!({
+            "ynear_in": parse_near!("5 N").to_string(),
+            "token": e.token.id(),
+            "min_tokens": "1",
+        }),
+        1,
+    )
+    .await?
+    .into_result()?;
+    let p: Value = e.view("pool_info", json!({ "token": e.token.id() })).await?;
+    let (ynear, tokens) = (field_u128(&p, "ynear"), field_u128(&p, "tokens"));
+
+    for (account, shares, part) in vec![(&e.alice, alice_shares, 9), (&bob, bob_shares, 1)] {
+        let near_before = e.deposit_near_of(account).await?;
+        let tokens_before = e.deposit_of(account, &e.token).await?;
+        e.call_as(
+            account,
+            "withdraw_liquidity",
+            json!({
+                "token": e.token.id(),
+                "shares": shares.to_string(),
+                "min_ynear": "1",
+                "min_tokens": "1",
+            }),
+            1,
+        )
+        .await?
+        .into_result()?;
+        let near_out = e.deposit_near_of(account).await? - near_before;
+        let tokens_out = e.deposit_of(account, &e.token).await? - tokens_before;
+        assert_close(near_out, ynear * part / 10, 1);
+        assert_close(tokens_out, tokens * part / 10, 1);
+    }
+
+    // all liquidity is redeemed.
+    let p: Value = e.view("pool_info", json!({ "token": e.token.id() })).await?;
+    assert_eq!(field_u128(&p, "ynear"), 0);
+    assert_eq!(field_u128(&p, "tokens"), 0);
+    assert_eq!(field_u128(&p, "total_shares"), 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn token_to_token_swap() -> Res<()> {
+    let e = setup().await?;
+    let eth = deploy_token(&e.worker, &e.clp, "eth", &e.alice).await?;
+    register(&e.clp, &e.alice, &eth).await?;
+    e.fund(parse_near!("10 N"), parse_near!("100 N")).await?;
+    e.fund_as(&e.alice, &eth, parse_near!("10 N"), parse_near!("100 N"))
+        .await?;
+    e.add_liquidity(parse_near!("10 N"), parse_near!("100 N")).await?;
+    e.add_liquidity_as(&e.alice, &eth, parse_near!("10 N"), parse_near!("100 N"))
+        .await?;
+
+    let tokens_in = parse_near!("1 N");
+    let quote: String = e
+        .view(
+            "price_token_to_token_in",
+            json!({"from": e.token.id(), "to": eth.id(), "tokens_in": tokens_in.to_string()}),
+        )
+        .await?;
+    let dai_before = e.deposit_token().await?;
+    let eth_before = e.deposit_of(&e.alice, &eth).await?;
+    let out: String = e
+        .call(
+            "swap_tokens_exact_in",
+            json!({
+                "token_in": e.token.id(),
+                "tokens_in": tokens_in.to_string(),
+                "token_out": eth.id(),
+                "min_tokens_out": quote,
+            }),
+            1,
+        )
+        .await?
+        .into_result()?
+        .json()?;
+    assert_eq!(out, quote);
+    let out: u128 = out.parse()?;
+    assert!(out > 0);
+    assert_eq!(e.deposit_token().await?, dai_before - tokens_in);
+    assert_eq!(e.deposit_of(&e.alice, &eth).await?, eth_before + out);
+    Ok(())
+}
+
+fn assert_close(a: u128, b: u128, margin: u128) {
+    let diff = if a > b { a - b } else { b - a };
+    assert!(diff <= margin, "expected {} to be close to {} (margin {})", a, b, margin);
+}
@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  REWARD ACCUMULATOR
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::Balance;
+
+use crate::constants::ACC_DENOM;
+
+/**
+Per share reward distribution. Rewards distributed to a total weight (eg staked shares)
+increase `acc_reward_per_share`. A position with `weight` earns
+`weight * (acc_reward_per_share - checkpoint)`, where `checkpoint` is the accumulator value
+at the position last settlement. Positions must be settled on every stake, unstake and
+claim, before their weight changes.
+Used by the farm reward streams and the liquidity lock fee boosts. */
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Default)]
+pub struct RewardAcc {
+    /// accumulated rewards per unit of weight, multiplied by `ACC_DENOM`.
+    pub acc_reward_per_share: Balance,
+}
+
+impl RewardAcc {
+    /// Distributes `reward` to `total_weight` units of weight. Returns `false` and doesn't
+    /// distribute anything when there is no weight.
+    /// Rounding error is below `total_weight / ACC_DENOM` per distribution.
+    pub fn distribute(&mut self, reward: Balance, total_weight: Balance) -> bool {
+        if total_weight == 0 {
+            return false;
+        }
+        self.acc_reward_per_share += clp_math::mul_div_floor(reward, ACC_DENOM, total_weight);
+        true
+    }
+
+    /// Returns the rewards earned by `weight` since the `checkpoint`.
+    pub fn earned(&self, weight: Balance, checkpoint: Balance) -> Balance {
+        clp_math::mul_div_floor(weight, self.acc_reward_per_share - checkpoint, ACC_DENOM)
+    }
+
+    /// Returns the rewards earned by `weight` since the `checkpoint` and moves the
+    /// checkpoint to the current accumulator value.
+    pub fn settle(&self, weight: Balance, checkpoint: &mut Balance) -> Balance {
+        let r = self.earned(weight, *checkpoint);
+        *checkpoint = self.acc_reward_per_share;
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distribute_pro_rata() {
+        let mut a = RewardAcc::default();
+        let (mut cp1, mut cp2) = (0, 0);
+        assert!(!a.distribute(100, 0));
+        assert!(a.distribute(300, 3));
+        assert_eq!(a.settle(1, &mut cp1), 100);
+        assert!(a.distribute(300, 3));
+        assert_eq!(a.settle(1, &mut cp1), 100);
+        assert_eq!(a.settle(2, &mut cp2), 400);
+        assert_eq!(a.settle(2, &mut cp2), 0);
+    }
+}
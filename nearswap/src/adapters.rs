@@ -0,0 +1,274 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  EXTERNAL AMM ADAPTERS
+***********************/
+
+use std::convert::TryInto;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    assert_one_yocto, env, ext_contract, near_bindgen, AccountId, Balance, Gas, PromiseOrValue,
+    PromiseResult,
+};
+
+use crate::constants::*;
+use crate::ft_token::*;
+use crate::*;
+
+/// Amount of gas for the `ft_transfer_call` to an adapter, including the adapter swap and
+/// the transfer of the bought tokens back to the contract.
+pub const GAS_FOR_ADAPTER_SWAP: Gas = 100 * TGAS;
+
+/// Amount of gas for the `swap_external` callback.
+pub const GAS_FOR_RESOLVE_ADAPTER_SWAP: Gas = 15 * TGAS;
+
+/**
+External AMM adapter interface. The sold tokens are transferred to the adapter with the
+token `ft_transfer_call` and an `AdapterSwapMsg` JSON message. The adapter swaps them on its
+AMM for at least `min_out` of `token_out` and transfers the bought tokens back to the
+contract with `ft_transfer_call` and the `return_msg` message. The adapter `ft_on_transfer`
+returns the unused sold tokens: all of them if the swap failed. */
+#[ext_contract(ext_amm_adapter)]
+pub trait AmmAdapter {
+    /// Returns the `token_out` amount bought for `amount_in` of `token_in`, used by the
+    /// clients to quote and split the routes between the pools and the adapters.
+    fn get_return(&self, token_in: AccountId, amount_in: U128, token_out: AccountId) -> U128;
+}
+
+/// `ft_transfer_call` message of the tokens sold through an adapter.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct AdapterSwapMsg {
+    pub token_out: AccountId,
+    pub min_out: U128,
+    /// message of the `ft_transfer_call` returning the bought tokens.
+    pub return_msg: String,
+}
+
+/// Swap through an adapter, see `swap_external`. It's closed when both the bought tokens and
+/// the transfer result arrived: the adapter may return the tokens before or after its
+/// `ft_on_transfer` result is resolved.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ExternalSwap {
+    pub account: AccountId,
+    pub adapter: AccountId,
+    pub token_in: AccountId,
+    pub amount_in: U128,
+    pub token_out: AccountId,
+    pub min_out: U128,
+    /// bought tokens returned by the adapter.
+    pub amount_out: Option<U128>,
+    /// the transfer to the adapter was resolved, see `resolve_adapter_swap`.
+    pub resolved: bool,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: registers the `adapter` contract of an external AMM under a display `name`.
+    /// `None` removes the adapter. Swaps in progress are not affected.
+    pub fn set_amm_adapter(&mut self, adapter: ValidAccountId, name: Option<String>) {
+        self.assert_owner();
+        let adapter: AccountId = adapter.into();
+        self.log_admin_action("set_amm_adapter", &(&adapter, &name));
+        match name {
+            Some(n) => {
+                self.amm_adapters.insert(&adapter, &n);
+            }
+            None => {
+                self.amm_adapters.remove(&adapter);
+            }
+        }
+    }
+
+    /// Returns the registered external AMM adapters with their names.
+    pub fn amm_adapters(&self) -> Vec<(AccountId, String)> {
+        self.amm_adapters.iter().collect()
+    }
+
+    /// Returns the `swap_external` swap in progress.
+    pub fn external_swap(&self, id: u64) -> Option<ExternalSwap> {
+        self.external_swaps.get(&id)
+    }
+
+    /**
+    Swaps `amount_in` of `token_in` from the caller deposit for at least `min_out` of
+    `token_out` on the external AMM of the registered `adapter`. The bought tokens are
+    credited to the caller deposit when the adapter transfers them back, the unused sold
+    tokens are refunded by the `resolve_adapter_swap` callback. Routes through external
+    venues are composed by the caller: the deposit balances are shared with the pool swaps,
+    eg `swap_path` can extend the route. The caller must have a `token_out` deposit.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the swap id. */
+    #[payable]
+    pub fn swap_external(
+        &mut self,
+        adapter: AccountId,
+        token_in: AccountId,
+        amount_in: U128,
+        token_out: AccountId,
+        min_out: U128,
+        deadline: Option<Deadline>,
+    ) -> u64 {
+        let _m = MethodMetrics::start("swap_external");
+        assert_one_yocto();
+        assert_deadline(&deadline);
+        assert!(!self.paused, "{}", ERR80_PAUSED);
+        assert!(self.amm_adapters.get(&adapter).is_some(), "{}", ERR131_NOT_AMM_ADAPTER);
+        assert!(amount_in.0 > 0 && min_out.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        assert!(token_in != token_out, "{}", ERR130_INVALID_PATH);
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let mut d = self.get_deposit(&user);
+        assert!(
+            self.whitelisted_tokens.contains(&token_out) && d.tokens.contains_key(&token_out),
+            "{}",
+            ERR23_TOKEN_NOT_WHITELISTED
+        );
+        d.remove(&token_in, amount_in.0);
+        // the tokens leave the contract now, so the transfer is recorded upfront.
+        self.record_token_out(&token_in, amount_in.0);
+
+        let id = self.next_order;
+        self.next_order += 1;
+        self.external_swaps.insert(
+            &id,
+            &ExternalSwap {
+                account: user.clone(),
+                adapter: adapter.clone(),
+                token_in: token_in.clone(),
+                amount_in,
+                token_out: token_out.clone(),
+                min_out,
+                amount_out: None,
+                resolved: false,
+            },
+        );
+        d.update_storage(start_storage);
+        self.deposits.insert(&user, &d.into());
+
+        let msg = AdapterSwapMsg {
+            token_out: token_out.clone(),
+            min_out,
+            return_msg: format!("{}:{}", ADAPTER_RETURN_MSG, id),
+        };
+        ext_fungible_token::ft_transfer_call(
+            adapter.try_into().unwrap(),
+            amount_in,
+            None,
+            near_sdk::serde_json::to_string(&msg).unwrap(),
+            &token_in,
+            1,
+            self.gas_for(GasOp::AdapterSwap),
+        )
+        .then(ext_self::resolve_adapter_swap(
+            id,
+            &env::current_account_id(),
+            0,
+            self.gas_for(GasOp::ResolveAdapterSwap),
+        ));
+        event_log!(
+            self,
+            "External swap {}: {} {} -> {} through {}",
+            id,
+            amount_in.0,
+            token_in,
+            token_out,
+            adapter
+        );
+        id
+    }
+
+    /// Callback for the `swap_external` transfer to the adapter. Refunds the unused sold
+    /// tokens to the swap account. The swap is closed if the bought tokens were already
+    /// returned or nothing was used. Returns the refunded amount.
+    #[private]
+    pub fn resolve_adapter_swap(&mut self, id: u64) -> U128 {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Contract expected a result on the callback"
+        );
+        let mut s = match self.external_swaps.get(&id) {
+            Some(s) => s,
+            None => return U128(0),
+        };
+        let amount_in = s.amount_in.0;
+        // `ft_transfer_call` returns the amount used by the adapter.
+        let used = match env::promise_result(0) {
+            PromiseResult::Successful(data) => {
+                near_sdk::serde_json::from_slice::<U128>(&data).map_or(0, |u| u.0)
+            }
+            _ => 0,
+        };
+        let unused = amount_in - used.min(amount_in);
+        self.record_token_in(&s.token_in, unused);
+        let start_storage = env::storage_usage();
+        let mut d = self.get_deposit(&s.account);
+        d.add(&s.token_in, unused);
+        if s.amount_out.is_some() || unused == amount_in {
+            self.external_swaps.remove(&id);
+        } else {
+            s.resolved = true;
+            self.external_swaps.insert(&id, &s);
+        }
+        d.update_storage(start_storage);
+        self.deposits.insert(&s.account, &d.into());
+        if unused > 0 {
+            event_log!(self, "External swap {}: refunded {} {}", id, unused, s.token_in);
+        }
+        unused.into()
+    }
+}
+
+impl NearSwap {
+    /// Credits `amount` of `token` returned by the `sender` adapter to the `id` external swap
+    /// account. The swap is closed if the transfer to the adapter was already resolved.
+    /// Panics, so the tokens are refunded, if the transfer doesn't match the swap.
+    pub(crate) fn adapter_return(
+        &mut self,
+        sender: &AccountId,
+        token: &AccountId,
+        amount: Balance,
+        id: u64,
+    ) -> PromiseOrValue<U128> {
+        let mut s = self.external_swaps.get(&id).expect(ERR132_INVALID_ADAPTER_RETURN);
+        assert!(
+            &s.adapter == sender && &s.token_out == token && s.amount_out.is_none(),
+            "{}",
+            ERR132_INVALID_ADAPTER_RETURN
+        );
+        let start_storage = env::storage_usage();
+        if s.resolved {
+            self.external_swaps.remove(&id);
+        } else {
+            s.amount_out = Some(amount.into());
+            self.external_swaps.insert(&id, &s);
+        }
+        let mut d = self.get_deposit(&s.account);
+        d.add(token, amount);
+        d.update_storage(start_storage);
+        self.deposits.insert(&s.account, &d.into());
+        self.record_token_in(token, amount);
+        if amount < s.min_out.0 {
+            event_log!(
+                self,
+                "External swap {}: adapter returned {} below the minimum {}",
+                id,
+                amount,
+                s.min_out.0
+            );
+        }
+        event_log!(self, "External swap {}: bought {} {}", id, amount, token);
+        PromiseOrValue::Value(U128(0))
+    }
+}
+
+/// Parses the `ft_on_transfer` message of the tokens returned by an adapter:
+/// `ADAPTER_RETURN_MSG` with the swap id, eg `adapter_return:12`. Returns the swap id.
+pub(crate) fn parse_adapter_return_msg(msg: &str) -> Option<u64> {
+    let id = msg.strip_prefix(ADAPTER_RETURN_MSG)?.strip_prefix(':')?;
+    Some(id.parse().expect(ERR132_INVALID_ADAPTER_RETURN))
+}
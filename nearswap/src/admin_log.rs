@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  ADMIN ACTIONS LOG
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U64;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json;
+use near_sdk::{env, near_bindgen, AccountId};
+
+use crate::*;
+
+/// Privileged action recorded in the append-only admin log.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug)]
+pub struct AdminLogEntry {
+    /// block timestamp, in nanoseconds.
+    pub timestamp: U64,
+    pub actor: AccountId,
+    /// contract method or event name, eg `change_owner`.
+    pub action: String,
+    /// JSON encoded action arguments.
+    pub args: String,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Returns up to `limit` admin log entries starting from the `from_index` entry, in
+    /// chronological order.
+    pub fn admin_log(&self, from_index: u64, limit: u64) -> Vec<AdminLogEntry> {
+        let end = self.admin_log.len().min(from_index.saturating_add(limit));
+        (from_index..end)
+            .map(|i| self.admin_log.get(i).unwrap())
+            .collect()
+    }
+
+    pub fn admin_log_len(&self) -> u64 {
+        self.admin_log.len()
+    }
+}
+
+impl NearSwap {
+    /// Appends the `action` of the caller with its `args` to the admin log. The log storage
+    /// is paid by the contract.
+    pub(crate) fn log_admin_action<T: Serialize>(&mut self, action: &str, args: &T) {
+        self.admin_log.push(&AdminLogEntry {
+            timestamp: env::block_timestamp().into(),
+            actor: env::predecessor_account_id(),
+            action: action.to_string(),
+            args: serde_json::to_string(args).unwrap(),
+        });
+    }
+}
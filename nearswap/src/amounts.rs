@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  AMOUNT TYPES
+***********************/
+
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Deserializer, Serialize, Serializer};
+use near_sdk::Balance;
+
+/// Defines an amount newtype over `Balance`. Amounts of the same type can be added and
+/// subtracted, mixing types requires an explicit conversion through the inner value.
+/// Borsh encodes it as the inner `Balance` and JSON as a decimal string, like `U128`.
+macro_rules! amount_type {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(
+            BorshDeserialize, BorshSerialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd,
+            Ord, Debug,
+        )]
+        pub struct $name(pub Balance);
+
+        impl Add for $name {
+            type Output = Self;
+            fn add(self, other: Self) -> Self {
+                $name(self.0 + other.0)
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+            fn sub(self, other: Self) -> Self {
+                $name(self.0 - other.0)
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, other: Self) {
+                self.0 += other.0;
+            }
+        }
+
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, other: Self) {
+                self.0 -= other.0;
+            }
+        }
+
+        impl From<U128> for $name {
+            fn from(v: U128) -> Self {
+                $name(v.0)
+            }
+        }
+
+        impl From<$name> for U128 {
+            fn from(v: $name) -> Self {
+                U128(v.0)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                U128(self.0).serialize(s)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+                U128::deserialize(d).map(|v| $name(v.0))
+            }
+        }
+    };
+}
+
+amount_type!(
+    /// Pool liquidity shares.
+    Shares
+);
+
+amount_type!(
+    /// NEAR amount, in yNEAR.
+    NearAmount
+);
+
+amount_type!(
+    /// Pool token amount, in the token smallest units.
+    TokenAmount
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amount_encoding() {
+        let s = Shares(10) + Shares(5) - Shares(3);
+        assert_eq!(s, Shares(12));
+        assert_eq!(near_sdk::serde_json::to_string(&s).unwrap(), "\"12\"");
+        let d: NearAmount = near_sdk::serde_json::from_str("\"7\"").unwrap();
+        assert_eq!(d, NearAmount(7));
+        // storage compatible with the plain `Balance` fields.
+        assert_eq!(TokenAmount(9).try_to_vec().unwrap(), 9u128.try_to_vec().unwrap());
+    }
+}
@@ -0,0 +1,309 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  BATCH AUCTIONS
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance, BlockHeight};
+
+use crate::constants::*;
+use crate::*;
+
+/// Swap submitted to a pool batch auction with `submit_batch_swap`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub struct BatchOrder {
+    pub id: u64,
+    pub account: AccountId,
+    /// sells NEAR for the pool token, otherwise sells the pool token for NEAR.
+    pub sell_near: bool,
+    pub amount_in: U128,
+    pub min_out: U128,
+}
+
+/// Pool batch auction, collecting swaps until `start + batch_window`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+pub struct Batch {
+    pub start: BlockHeight,
+    pub orders: Vec<BatchOrder>,
+}
+
+/// Batch settlement amounts.
+struct Clearing {
+    /// yNEAR and tokens sold by the batch.
+    near_in: Balance,
+    tokens_in: Balance,
+    /// the batch sells NEAR in excess in the pool, otherwise it sells tokens.
+    near_to_pool: bool,
+    /// excess amount swapped in the pool.
+    pool_in: Balance,
+    /// tokens bought by the NEAR sellers and yNEAR bought by the token sellers.
+    tokens_out: Balance,
+    near_out: Balance,
+}
+
+impl Clearing {
+    fn out_for(&self, o: &BatchOrder) -> Balance {
+        if o.sell_near {
+            clp_math::mul_div_floor(o.amount_in.0, self.tokens_out, self.near_in)
+        } else {
+            clp_math::mul_div_floor(o.amount_in.0, self.near_out, self.tokens_in)
+        }
+    }
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: enables batch auctions of `window` blocks in the `token` pool, or disables them
+    /// with 0. A pending batch is settled when the auctions are disabled.
+    pub fn set_batch_auction(&mut self, token: AccountId, window: BlockHeight) {
+        self.assert_owner();
+        assert!(window <= MAX_BATCH_WINDOW, "{}", ERR104_INVALID_BATCH_WINDOW);
+        let mut p = self.get_pool(&token);
+        if window == 0 {
+            if let Some(b) = self.batches.remove(&token) {
+                self.internal_settle_batch(&token, &mut p, b);
+            }
+        }
+        p.batch_window = window;
+        self.set_pool(&token, &p);
+        self.log_admin_action("set_batch_auction", &(token, window));
+    }
+
+    /**
+    Submits a swap of `amount_in` NEAR (`sell_near`) or `token` from the caller deposit to
+    the `token` pool batch auction. Swaps submitted within the pool batch window are settled
+    together at a uniform price with `settle_batch`: opposite swaps are netted and only the
+    excess is swapped in the pool. The bought amount is credited to the caller deposit, or
+    the swap is refunded if it would buy less than `min_out`.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the order id. */
+    #[payable]
+    pub fn submit_batch_swap(
+        &mut self,
+        token: AccountId,
+        sell_near: bool,
+        amount_in: U128,
+        min_out: U128,
+    ) -> u64 {
+        assert_one_yocto();
+        assert!(amount_in.0 > 0 && min_out.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        let mut p = self.get_pool(&token);
+        self.assert_pool_active(&p);
+        assert!(p.batch_window > 0, "{}", ERR99_NO_BATCH_AUCTION);
+        let now = env::block_index();
+        let mut b = match self.batches.get(&token) {
+            Some(b) if now >= b.start + p.batch_window => {
+                self.internal_settle_batch(&token, &mut p, b);
+                self.set_pool(&token, &p);
+                Batch {
+                    start: now,
+                    orders: Vec::new(),
+                }
+            }
+            Some(b) => b,
+            None => Batch {
+                start: now,
+                orders: Vec::new(),
+            },
+        };
+        assert!(b.orders.len() < MAX_BATCH_ORDERS, "{}", ERR102_BATCH_FULL);
+
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let mut d = self.get_deposit(&user);
+        if sell_near {
+            d.remove_near(amount_in.0);
+            // make sure the settlement doesn't need more deposit storage.
+            d.add(&token, 0);
+        } else {
+            d.remove(&token, amount_in.0);
+        }
+        self.deposits.insert(&user, &d.into());
+
+        let id = self.next_order;
+        self.next_order += 1;
+        b.orders.push(BatchOrder {
+            id,
+            account: user,
+            sell_near,
+            amount_in,
+            min_out,
+        });
+        self.batches.insert(&token, &b);
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Batch swap {}: selling {} {}",
+            id,
+            amount_in.0,
+            if sell_near { "yNEAR" } else { token.as_str() }
+        );
+        id
+    }
+
+    /// Settles the `token` pool batch auction once its window is over. Can be called by
+    /// anyone.
+    pub fn settle_batch(&mut self, token: AccountId) {
+        let mut p = self.get_pool(&token);
+        self.assert_pool_active(&p);
+        let b = self.batches.get(&token).expect(ERR103_NO_BATCH);
+        assert!(
+            env::block_index() >= b.start + p.batch_window,
+            "{}",
+            ERR101_BATCH_NOT_CLOSED
+        );
+        self.batches.remove(&token);
+        self.internal_settle_batch(&token, &mut p, b);
+        self.set_pool(&token, &p);
+    }
+
+    /// Returns the pending `token` pool batch auction.
+    pub fn batch(&self, token: AccountId) -> Option<Batch> {
+        self.batches.get(&token)
+    }
+}
+
+impl NearSwap {
+    /// Settles the batch `b` in the `token` pool `p`. Swaps which would buy less than their
+    /// `min_out` are refunded and the batch is cleared again without them. The caller must
+    /// save the pool.
+    fn internal_settle_batch(&mut self, token: &AccountId, p: &mut Pool, b: Batch) {
+        self.run_twamm(token, p);
+        let mut orders = b.orders;
+        let mut refunds = Vec::new();
+        let c = loop {
+            let c = self.clear_batch(p, &orders);
+            let (ok, failed): (Vec<BatchOrder>, Vec<BatchOrder>) =
+                orders.into_iter().partition(|o| c.out_for(o) >= o.min_out.0);
+            orders = ok;
+            if failed.is_empty() {
+                break c;
+            }
+            refunds.extend(failed);
+        };
+
+        p.record_snapshot();
+        if c.pool_in > 0 {
+            if c.near_to_pool {
+                let (out, fee) = self.calc_out_with_fee(c.pool_in, p.ynear, p.tokens, p.fee_bps);
+                p.ynear += c.pool_in - self.take_fees(p, fee, true);
+                p.tokens -= out;
+                p.record_trade(c.pool_in);
+            } else {
+                let (out, fee) = self.calc_out_with_fee(c.pool_in, p.tokens, p.ynear, p.fee_bps);
+                p.tokens += c.pool_in - self.take_fees(p, fee, false);
+                p.ynear -= out;
+                p.record_trade(out);
+            }
+        }
+        for o in orders.iter() {
+            let mut d = self.get_deposit(&o.account);
+            let out = c.out_for(o);
+            if o.sell_near {
+                d.add(token, out);
+            } else {
+                d.add_near(out);
+            }
+            self.deposits.insert(&o.account, &d.into());
+        }
+        for o in refunds.iter() {
+            let mut d = self.get_deposit(&o.account);
+            if o.sell_near {
+                d.add_near(o.amount_in.0);
+            } else {
+                d.add(token, o.amount_in.0);
+            }
+            self.deposits.insert(&o.account, &d.into());
+        }
+        event_log!(
+            self,
+            "Batch auction {} settled: {} swaps selling {} yNEAR and {} tokens, {} refunded, {} swapped in the pool",
+            token,
+            orders.len(),
+            c.near_in,
+            c.tokens_in,
+            refunds.len(),
+            c.pool_in
+        );
+    }
+
+    /// Nets the `orders` flows and computes the excess swapped in the `p` pool.
+    fn clear_batch(&self, p: &Pool, orders: &[BatchOrder]) -> Clearing {
+        let (mut near_in, mut tokens_in) = (0, 0);
+        for o in orders {
+            if o.sell_near {
+                near_in += o.amount_in.0;
+            } else {
+                tokens_in += o.amount_in.0;
+            }
+        }
+        let tokens_value = if p.tokens > 0 {
+            clp_math::mul_div_floor(tokens_in, p.ynear, p.tokens)
+        } else {
+            0
+        };
+        if near_in >= tokens_value {
+            let x = batch_pool_in(near_in, tokens_in, p.ynear, p.tokens, p.fee_bps);
+            let (out, _) = clp_math::calc_out_with_fee_bps(x, p.ynear, p.tokens, p.fee_bps);
+            Clearing {
+                near_in,
+                tokens_in,
+                near_to_pool: true,
+                pool_in: x,
+                tokens_out: tokens_in + out,
+                near_out: near_in - x,
+            }
+        } else {
+            let x = batch_pool_in(tokens_in, near_in, p.tokens, p.ynear, p.fee_bps);
+            let (out, _) = clp_math::calc_out_with_fee_bps(x, p.tokens, p.ynear, p.fee_bps);
+            Clearing {
+                near_in,
+                tokens_in,
+                near_to_pool: false,
+                pool_in: x,
+                tokens_out: tokens_in - x,
+                near_out: near_in + out,
+            }
+        }
+    }
+}
+
+/**
+Returns the amount of asset X, sold in excess by a batch, to swap in the pool so that the
+batch clears at a uniform price: the price of the netted flows equals the average price of
+the pool swap. The price is found with a binary search, rounded in favour of the pool.
+`nx`, `ny`: X and Y sold by the batch; `rx`, `ry`: X and Y pool reserves. */
+fn batch_pool_in(nx: Balance, ny: Balance, rx: Balance, ry: Balance, fee_bps: u16) -> Balance {
+    if rx == 0 || ry == 0 {
+        return 0;
+    }
+    // prices are in Y for X, multiplied by NDENOM.
+    let x_for = |y: Balance, q: Balance| -> Balance {
+        let x = clp_math::div_ceil(u256::from(y) * u256::from(NDENOM), u256::from(q.max(1)));
+        x.min(u256::from(u128::MAX)).as_u128()
+    };
+    let avg_out = |x: Balance| -> Balance {
+        let (out, _) = clp_math::calc_out_with_fee_bps(x, rx, ry, fee_bps);
+        clp_math::mul_div_floor(out, NDENOM, x)
+    };
+    let spot = clp_math::mul_div_floor(ry, NDENOM, rx);
+    let x0 = nx.saturating_sub(x_for(ny, spot));
+    if x0 == 0 {
+        return 0;
+    }
+    let (mut lo, mut hi) = (avg_out(x0), spot);
+    while hi > lo + 1 {
+        let mid = lo + (hi - lo) / 2;
+        let matched = x_for(ny, mid);
+        if matched >= nx || avg_out(nx - matched) > mid {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    nx.saturating_sub(x_for(ny, lo))
+}
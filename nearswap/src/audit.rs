@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  SHARES AUDIT
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId, Balance};
+
+use crate::*;
+
+/// Progress and result of the pool shares audit.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct SharesAudit {
+    /// index of the next holder to audit.
+    pub cursor: u64,
+    /// holdings of the holders audited so far.
+    pub sum: Balance,
+    /// pool `shares_nonce` when the audit started.
+    pub nonce: u64,
+    /// result of the last completed audit: true if the holdings matched the total shares.
+    pub last_result: Option<bool>,
+}
+
+/// Status of the shares audit returned by `audit_shares`.
+#[derive(Serialize, Deserialize)]
+pub struct AuditStatus {
+    /// index of the next holder to audit, pass it as the next `from_index`.
+    pub cursor: u64,
+    pub holders: u64,
+    /// holdings of the holders audited so far, including the shares escrowed by votes.
+    pub sum: U128,
+    pub total_shares: U128,
+    /// set once all holders are audited: true if `sum == total_shares`.
+    pub result: Option<bool>,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Audits the `token` pool shares in chunks: sums the holdings of up to `limit` holders,
+    starting from `from_index`, and once all holders are summed checks that the sum equals
+    the pool total shares. Holdings are the LP shares, locked shares, farm stakes and
+    delegated shares of a holder, plus all shares escrowed by fee votes.
+    `from_index` 0 starts a new audit, otherwise it must be the `cursor` returned by the
+    previous call. The audit must be restarted when the pool shares changed since it
+    started. Can be called by anyone. */
+    pub fn audit_shares(&mut self, token: AccountId, from_index: u64, limit: u64) -> AuditStatus {
+        let mut p = self.get_pool(&token);
+        let mut a = std::mem::take(&mut p.audit);
+        if from_index == 0 {
+            a.cursor = 0;
+            a.sum = p.vote_escrow;
+            a.nonce = p.shares_nonce;
+        } else {
+            assert!(from_index == a.cursor, "{}", ERR83_INVALID_AUDIT_CURSOR);
+            assert!(a.nonce == p.shares_nonce, "{}", ERR84_AUDIT_OUTDATED);
+        }
+
+        let f = self.get_farm(&token);
+        let holders = p.holders.as_vector();
+        let end = holders.len().min(from_index.saturating_add(limit));
+        for i in from_index..end {
+            a.sum += self.holder_shares(&token, &p, &f, &holders.get(i).unwrap());
+        }
+        a.cursor = end;
+
+        let mut result = None;
+        if end == holders.len() {
+            result = Some(a.sum == p.total_shares);
+            a.last_result = result;
+            event_log!(
+                self,
+                "Shares audit of {}: sum {}, total shares {}",
+                token,
+                a.sum,
+                p.total_shares
+            );
+        }
+        let status = AuditStatus {
+            cursor: a.cursor,
+            holders: holders.len(),
+            sum: a.sum.into(),
+            total_shares: p.total_shares.into(),
+            result,
+        };
+        p.audit = a;
+        self.set_pool(&token, &p);
+        status
+    }
+}
+
+impl NearSwap {
+    /// Returns the `account` holdings in the `token` pool `p` with the farm `f`: its LP
+    /// shares, locked shares, farm stake and delegated shares.
+    pub(crate) fn holder_shares(
+        &self,
+        token: &AccountId,
+        p: &Pool,
+        f: &Farm,
+        account: &AccountId,
+    ) -> Balance {
+        p.shares.get(account).unwrap_or(0)
+            + p.locks.get(account).map(|l| l.shares).unwrap_or(0)
+            + f.stakes.get(account).map(|s| s.shares).unwrap_or(0)
+            + self.delegated_shares(token, account)
+    }
+
+    fn delegated_shares(&self, token: &AccountId, account: &AccountId) -> Balance {
+        self.delegations
+            .get(&(token.clone(), account.clone()))
+            .map(|d| d.shares)
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  POOL CREATION BOND
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId, Balance, Promise};
+
+use crate::*;
+
+/// Anti-spam bond required by `create_pool`, set by the owner.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub struct PoolBondConfig {
+    /// minimum NEAR attached to `create_pool`. Zero disables the bond.
+    pub amount: U128,
+    /// pool NEAR reserve which allows to refund the bond, see `refund_pool_bond`.
+    pub min_liquidity: U128,
+}
+
+/// NEAR bonded by a pool creator.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+pub struct PoolBond {
+    pub creator: AccountId,
+    pub amount: U128,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: sets the pool creation bond. Doesn't affect the bonds of existing pools.
+    pub fn set_pool_bond_config(&mut self, config: PoolBondConfig) {
+        self.assert_owner();
+        self.log_admin_action("set_pool_bond_config", &config);
+        self.pool_bond_config = config;
+    }
+
+    pub fn pool_bond_config(&self) -> PoolBondConfig {
+        self.pool_bond_config.clone()
+    }
+
+    /// Returns the bond of the `token` pool creator, if not refunded nor slashed yet.
+    pub fn pool_bond(&self, token: AccountId) -> Option<PoolBond> {
+        self.pool_bonds.get(&token)
+    }
+
+    /// Refunds the `token` pool bond to the pool creator once the pool NEAR reserve reached
+    /// the bond `min_liquidity`. Can be called by anyone.
+    pub fn refund_pool_bond(&mut self, token: AccountId) -> Promise {
+        let p = self.get_pool(&token);
+        let b = self.pool_bonds.get(&token).expect(ERR88_NO_POOL_BOND);
+        let min_liquidity = self.pool_bond_config.min_liquidity.0;
+        assert!(
+            p.ynear >= min_liquidity,
+            "{} (ynear {}, required {})",
+            ERR89_POOL_LIQUIDITY_TOO_LOW,
+            p.ynear,
+            min_liquidity
+        );
+        self.pool_bonds.remove(&token);
+        event_log!(self, "Pool {} bond refunded to {}", token, b.creator);
+        Promise::new(b.creator).transfer(b.amount.0)
+    }
+
+    /**
+    Guardian: slashes the `token` pool bond, adding it to the NEAR protocol fees, see
+    `distribute_fees`. With `remove_pool`, the junk pool is removed as well; only a pool
+    without liquidity can be removed. */
+    pub fn slash_pool_bond(&mut self, token: AccountId, remove_pool: bool) {
+        self.assert_guardian();
+        let b = self.pool_bonds.remove(&token).expect(ERR88_NO_POOL_BOND);
+        if remove_pool {
+            let p = self.get_pool(&token);
+            assert!(p.total_shares == 0, "{}", ERR90_POOL_NOT_EMPTY);
+            self.pools.remove(&token);
+            self.remove_pool_id(&token);
+            self.pool_metadata.remove(&token);
+            event_log!(self, "Pool {} removed", token);
+        }
+        event_log!(self, "Pool {} bond of {} slashed", token, b.creator);
+        self.log_admin_action("slash_pool_bond", &(token, remove_pool));
+        self.add_protocol_fees(&None, b.amount.0);
+    }
+}
+
+impl NearSwap {
+    /// Panics if the `amount` attached to `create_pool` is below the required bond.
+    pub(crate) fn assert_pool_bond(&self, amount: Balance) {
+        let required = self.pool_bond_config.amount.0;
+        assert!(
+            amount >= required,
+            "{} (attached {}, required {})",
+            ERR87_POOL_BOND_REQUIRED,
+            amount,
+            required
+        );
+    }
+
+    /// Records the `amount` attached to `create_pool` by the `creator` as the `token` pool
+    /// bond, if the bond is required. The amount was checked with `assert_pool_bond`.
+    pub(crate) fn take_pool_bond(
+        &mut self,
+        token: &AccountId,
+        creator: AccountId,
+        amount: Balance,
+    ) {
+        if self.pool_bond_config.amount.0 == 0 {
+            return;
+        }
+        self.pool_bonds.insert(
+            token,
+            &PoolBond {
+                creator,
+                amount: amount.into(),
+            },
+        );
+    }
+}
@@ -0,0 +1,369 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  ORDER BOOK
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance};
+
+use crate::constants::*;
+use crate::*;
+
+/// Order book side.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BookSide {
+    /// buys pool tokens with escrowed NEAR.
+    Bid,
+    /// sells escrowed pool tokens for NEAR.
+    Ask,
+}
+
+impl BookSide {
+    /// Returns true if price `a` has a priority over price `b`: lower asks and higher bids
+    /// are filled first.
+    fn before(self, a: Balance, b: Balance) -> bool {
+        match self {
+            BookSide::Bid => a > b,
+            BookSide::Ask => a < b,
+        }
+    }
+}
+
+/// Resting order of a pool order book. The price is in yNEAR for 1e24 token units, as the
+/// pool spot price.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub struct BookOrder {
+    pub id: u64,
+    pub account: AccountId,
+    pub price: U128,
+    /// escrowed amount still to trade: yNEAR for bids, tokens for asks.
+    pub amount: U128,
+}
+
+/// Pool order book. Each side is kept in price-time priority.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct OrderBook {
+    pub bids: Vec<BookOrder>,
+    pub asks: Vec<BookOrder>,
+}
+
+impl OrderBook {
+    pub fn orders(&self, side: BookSide) -> &Vec<BookOrder> {
+        match side {
+            BookSide::Bid => &self.bids,
+            BookSide::Ask => &self.asks,
+        }
+    }
+
+    fn orders_mut(&mut self, side: BookSide) -> &mut Vec<BookOrder> {
+        match side {
+            BookSide::Bid => &mut self.bids,
+            BookSide::Ask => &mut self.asks,
+        }
+    }
+
+    /// Inserts the order after all orders with the same or a better price.
+    fn insert(&mut self, side: BookSide, o: BookOrder) {
+        let orders = self.orders_mut(side);
+        let i = orders
+            .iter()
+            .position(|x| side.before(o.price.0, x.price.0))
+            .unwrap_or(orders.len());
+        orders.insert(i, o);
+    }
+
+    /// Returns the side and the index of the `id` order.
+    fn find(&self, id: u64) -> Option<(BookSide, usize)> {
+        for &side in [BookSide::Bid, BookSide::Ask].iter() {
+            if let Some(i) = self.orders(side).iter().position(|o| o.id == id) {
+                return Some((side, i));
+            }
+        }
+        None
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+}
+
+/// Orders at a price level, returned by `book_depth`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct BookLevel {
+    pub price: U128,
+    /// sum of the orders escrowed amounts: yNEAR for bids, tokens for asks.
+    pub amount: U128,
+    pub orders: u32,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Places an order in the `token` pool order book. A bid escrows `amount` yNEAR to buy
+    tokens, an ask escrows `amount` tokens to sell for NEAR, at `price` yNEAR for 1e24 token
+    units. Pool swaps fill book orders with a better price than the pool before trading
+    with the pool. Orders can also be filled directly with `fill_book_order`. Proceeds are
+    credited to the caller deposit.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the order id. */
+    #[payable]
+    pub fn place_book_order(
+        &mut self,
+        token: AccountId,
+        side: BookSide,
+        price: U128,
+        amount: U128,
+    ) -> u64 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        assert!(price.0 > 0 && amount.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        self.get_pool(&token);
+        let mut book = self.books.get(&token).unwrap_or_default();
+        assert!(
+            book.orders(side).len() < MAX_BOOK_ORDERS,
+            "{}",
+            ERR97_BOOK_SIDE_FULL
+        );
+
+        let user = env::predecessor_account_id();
+        let mut d = self.get_deposit(&user);
+        match side {
+            BookSide::Bid => {
+                d.remove_near(amount.0);
+                // make sure fills don't need more deposit storage.
+                d.add(&token, 0);
+            }
+            BookSide::Ask => d.remove(&token, amount.0),
+        }
+        self.deposits.insert(&user, &d.into());
+
+        let id = self.next_order;
+        self.next_order += 1;
+        book.insert(
+            side,
+            BookOrder {
+                id,
+                account: user,
+                price,
+                amount,
+            },
+        );
+        self.books.insert(&token, &book);
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Book order {}: {:?} {} {} at {}",
+            id,
+            side,
+            amount.0,
+            token,
+            price.0
+        );
+        id
+    }
+
+    /// Cancels the `id` order of the `token` order book and moves the escrowed amount back
+    /// to the caller deposit. Requires payment of exactly one yNEAR to enforce wallet
+    /// confirmation. Returns the refunded amount.
+    #[payable]
+    pub fn cancel_book_order(&mut self, token: AccountId, id: u64) -> U128 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let mut book = self.books.get(&token).expect(ERR33_ORDER_NOT_FOUND);
+        let (side, i) = book.find(id).expect(ERR33_ORDER_NOT_FOUND);
+        let o = book.orders_mut(side).remove(i);
+        assert!(o.account == user, "{}", ERR33_ORDER_NOT_FOUND);
+        self.save_book(&token, &book);
+
+        let mut d = self.get_deposit(&user);
+        match side {
+            BookSide::Bid => d.add_near(o.amount.0),
+            BookSide::Ask => d.add(&token, o.amount.0),
+        }
+        d.update_storage(start_storage);
+        self.deposits.insert(&user, &d.into());
+        event_log!(self, "Book order {} cancelled, refunded {}", id, o.amount.0);
+        o.amount
+    }
+
+    /**
+    Fills the `id` order of the `token` order book with up to `amount_in` from the caller
+    deposit: tokens for a bid, yNEAR for an ask. The order is filled at its price.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the amount bought. */
+    #[payable]
+    pub fn fill_book_order(&mut self, token: AccountId, id: u64, amount_in: U128) -> U128 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        assert!(amount_in.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        let mut book = self.books.get(&token).expect(ERR33_ORDER_NOT_FOUND);
+        let (side, i) = book.find(id).expect(ERR33_ORDER_NOT_FOUND);
+        let (paid, out) = self.fill_book(&token, &mut book, side, i, amount_in.0);
+        assert!(out > 0, "{}", ERR02_POSITIVE_ARGS);
+        self.save_book(&token, &book);
+
+        let user = env::predecessor_account_id();
+        let mut d = self.get_deposit(&user);
+        match side {
+            BookSide::Bid => {
+                d.remove(&token, paid);
+                d.add_near(out);
+            }
+            BookSide::Ask => {
+                d.remove_near(paid);
+                d.add(&token, out);
+            }
+        }
+        self.deposits.insert(&user, &d.into());
+        self.unsafe_storage_check(start_storage);
+        out.into()
+    }
+
+    /// Returns up to `limit` price levels of the `token` order book `side`, best price
+    /// first.
+    pub fn book_depth(&self, token: AccountId, side: BookSide, limit: u32) -> Vec<BookLevel> {
+        let book = self.books.get(&token).unwrap_or_default();
+        let mut levels: Vec<BookLevel> = Vec::new();
+        for o in book.orders(side) {
+            match levels.last_mut() {
+                Some(l) if l.price == o.price => {
+                    l.amount = (l.amount.0 + o.amount.0).into();
+                    l.orders += 1;
+                }
+                _ => {
+                    if levels.len() == limit as usize {
+                        break;
+                    }
+                    levels.push(BookLevel {
+                        price: o.price,
+                        amount: o.amount,
+                        orders: 1,
+                    });
+                }
+            }
+        }
+        levels
+    }
+
+    /// Returns the orders of the `token` order book `side`, in the fill order.
+    pub fn book_orders(&self, token: AccountId, side: BookSide) -> Vec<BookOrder> {
+        self.books
+            .get(&token)
+            .map(|b| b.orders(side).clone())
+            .unwrap_or_default()
+    }
+}
+
+impl NearSwap {
+    /**
+    Fills the `token` order book orders with a better price than the `p` pool, taking
+    `amount_in` of NEAR (`near_in`, fills asks) or tokens (fills bids). The pool price
+    includes the pool fee. Filled makers are credited in their deposits.
+    Returns the amount taken from `amount_in` and the amount bought. */
+    pub(crate) fn match_book(
+        &mut self,
+        token: &AccountId,
+        p: &Pool,
+        near_in: bool,
+        amount_in: Balance,
+    ) -> (Balance, Balance) {
+        let mut book = match self.books.get(token) {
+            Some(b) => b,
+            None => return (0, 0),
+        };
+        let side = if near_in { BookSide::Ask } else { BookSide::Bid };
+        let spot = if p.ynear > 0 && p.tokens > 0 {
+            Some(clp_math::spot_price(p.ynear, p.curve_tokens()))
+        } else {
+            None
+        };
+        let keep = u256::from(10_000 - p.swap_fee_bps(near_in) as u128);
+        let better = |price: Balance| match spot {
+            None => true,
+            Some(s) if near_in => u256::from(price) * keep < s * 10_000,
+            Some(s) => u256::from(price) * 10_000 > s * keep,
+        };
+
+        let (mut paid, mut out) = (0, 0);
+        while paid < amount_in {
+            match book.orders(side).first() {
+                Some(o) if better(o.price.0) => {}
+                _ => break,
+            }
+            let (pd, o) = self.fill_book(token, &mut book, side, 0, amount_in - paid);
+            if o == 0 {
+                break;
+            }
+            paid += pd;
+            out += o;
+        }
+        if paid > 0 {
+            self.save_book(token, &book);
+        }
+        (paid, out)
+    }
+
+    /// Fills the `i` order of the book `side` with up to `amount_in` paid by a taker, at the
+    /// order price, and credits the maker deposit. A fully filled order is removed.
+    /// Returns the amount paid and the amount bought by the taker.
+    fn fill_book(
+        &mut self,
+        token: &AccountId,
+        book: &mut OrderBook,
+        side: BookSide,
+        i: usize,
+        amount_in: Balance,
+    ) -> (Balance, Balance) {
+        let orders = book.orders_mut(side);
+        let o = &mut orders[i];
+        let (price, amount) = (o.price.0, o.amount.0);
+        // taker pays NEAR for asks and tokens for bids.
+        let (pay_all, out_for) = match side {
+            BookSide::Ask => (
+                clp_math::mul_div_ceil(amount, price, NDENOM),
+                clp_math::mul_div_floor(amount_in, NDENOM, price),
+            ),
+            BookSide::Bid => (
+                clp_math::mul_div_ceil(amount, NDENOM, price),
+                clp_math::mul_div_floor(amount_in, price, NDENOM),
+            ),
+        };
+        let (paid, out) = if amount_in >= pay_all {
+            (pay_all, amount)
+        } else {
+            (amount_in, out_for)
+        };
+        if out == 0 {
+            return (0, 0);
+        }
+        o.amount = (amount - out).into();
+        let maker = o.account.clone();
+        let id = o.id;
+        if amount == out {
+            orders.remove(i);
+        }
+
+        let mut d = self.get_deposit(&maker);
+        match side {
+            BookSide::Ask => d.add_near(paid),
+            BookSide::Bid => d.add(token, paid),
+        }
+        self.deposits.insert(&maker, &d.into());
+        event_log!(self, "Book order {} filled, sold {} for {}", id, out, paid);
+        (paid, out)
+    }
+
+    fn save_book(&mut self, token: &AccountId, book: &OrderBook) {
+        if book.is_empty() {
+            self.books.remove(token);
+        } else {
+            self.books.insert(token, book);
+        }
+    }
+}
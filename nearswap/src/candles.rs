@@ -0,0 +1,189 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::Vector;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{CryptoHash, IntoStorageKey};
+
+use crate::constants::{CANDLES_1D_LENGTH, CANDLES_1H_LENGTH};
+use crate::twap::T_1H;
+use crate::*;
+
+pub const T_1D: u64 = to_nanoseconds(60 * 60 * 24);
+
+/// Candle time resolution.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    Hour,
+    Day,
+}
+
+/// OHLC price candle. Prices are expressed as amount of yNEAR paid for `1e24` token units
+/// (NEAR denomination), `volume` is the amount of yNEAR traded in the candle period.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct Candle {
+    /// candle period start timestamp in nanoseconds.
+    pub start: U64,
+    pub open: U128,
+    pub high: U128,
+    pub low: U128,
+    pub close: U128,
+    pub volume: U128,
+}
+
+/// Ring buffer of the last `max_length` candles of a given `period`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct CandleSeries {
+    period: u64,
+    max_length: u64,
+    // number of candles created since the series creation.
+    count: u64,
+    candles: Vector<Candle>,
+}
+
+impl CandleSeries {
+    pub fn new<S: IntoStorageKey>(prefix: S, period: u64, max_length: u64) -> Self {
+        Self {
+            period,
+            max_length,
+            count: 0,
+            candles: Vector::new(prefix),
+        }
+    }
+
+    /// Updates the candle covering `timestamp` with a trade at `price` and `volume` yNEAR.
+    /// A new candle is created when the period of the last candle is over.
+    pub fn record(&mut self, timestamp: u64, price: u128, volume: u128) {
+        let start = timestamp - timestamp % self.period;
+        if self.count > 0 {
+            let idx = (self.count - 1) % self.max_length;
+            let mut c = self.candles.get(idx).unwrap();
+            if c.start.0 == start {
+                c.high = c.high.0.max(price).into();
+                c.low = c.low.0.min(price).into();
+                c.close = price.into();
+                c.volume = (c.volume.0 + volume).into();
+                self.candles.replace(idx, &c);
+                return;
+            }
+        }
+        let c = Candle {
+            start: start.into(),
+            open: price.into(),
+            high: price.into(),
+            low: price.into(),
+            close: price.into(),
+            volume: volume.into(),
+        };
+        if self.candles.len() < self.max_length {
+            self.candles.push(&c);
+        } else {
+            self.candles.replace(self.count % self.max_length, &c);
+        }
+        self.count += 1;
+    }
+
+    /// Returns up to `limit` candles, in chronological order, starting not earlier than `from`.
+    pub fn list(&self, from: u64, limit: u64) -> Vec<Candle> {
+        let len = self.candles.len();
+        // index of the oldest candle
+        let first = if len < self.max_length {
+            0
+        } else {
+            self.count % self.max_length
+        };
+        (0..len)
+            .map(|i| self.candles.get((first + i) % len).unwrap())
+            .filter(|c| c.start.0 >= from)
+            .take(limit as usize)
+            .collect()
+    }
+}
+
+/// Hourly and daily candles of a pool.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Candles {
+    hourly: CandleSeries,
+    daily: CandleSeries,
+}
+
+impl Candles {
+    /// `pool` is the pool hash, see `pool_hash`.
+    pub fn new(pool: &CryptoHash) -> Self {
+        let pool = *pool;
+        Self {
+            hourly: CandleSeries::new(StorageKey::PoolCandles1h { pool }, T_1H, CANDLES_1H_LENGTH),
+            daily: CandleSeries::new(StorageKey::PoolCandles1d { pool }, T_1D, CANDLES_1D_LENGTH),
+        }
+    }
+
+    /// Records a trade in all resolutions.
+    pub fn record(&mut self, timestamp: u64, price: u128, volume: u128) {
+        self.hourly.record(timestamp, price, volume);
+        self.daily.record(timestamp, price, volume);
+    }
+
+    pub fn list(&self, resolution: Resolution, from: u64, limit: u64) -> Vec<Candle> {
+        match resolution {
+            Resolution::Hour => self.hourly.list(from, limit),
+            Resolution::Day => self.daily.list(from, limit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    fn init_blockchain() {
+        let context = VMContextBuilder::new();
+        testing_env!(context.build());
+    }
+
+    #[test]
+    fn record_updates_candle() {
+        init_blockchain();
+        let mut s = CandleSeries::new(b"c".to_vec(), T_1H, 3);
+
+        s.record(T_1H + 1, 10, 1);
+        s.record(T_1H + 2, 14, 2);
+        s.record(T_1H + 3, 8, 3);
+        s.record(2 * T_1H - 1, 9, 4);
+
+        let c = s.list(0, 10);
+        assert_eq!(
+            c,
+            vec![Candle {
+                start: T_1H.into(),
+                open: 10.into(),
+                high: 14.into(),
+                low: 8.into(),
+                close: 9.into(),
+                volume: 10.into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn record_rolls_over() {
+        init_blockchain();
+        let mut s = CandleSeries::new(b"c".to_vec(), T_1H, 3);
+
+        for i in 1..6 {
+            s.record(i * T_1H, i as u128, 1);
+        }
+        // only the last 3 candles are kept
+        let starts: Vec<u64> = s.list(0, 10).iter().map(|c| c.start.0).collect();
+        assert_eq!(starts, vec![3 * T_1H, 4 * T_1H, 5 * T_1H]);
+
+        let starts: Vec<u64> = s.list(4 * T_1H, 1).iter().map(|c| c.start.0).collect();
+        assert_eq!(starts, vec![4 * T_1H]);
+    }
+}
@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  COMMIT-REVEAL SWAPS
+***********************/
+
+use std::convert::TryInto;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base64VecU8, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, BlockHeight, CryptoHash};
+
+use crate::constants::*;
+use crate::*;
+
+/// Hidden swap committed with `commit_swap`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+pub struct SwapCommitment {
+    pub account: AccountId,
+    pub hash: Base64VecU8,
+    /// first block height at which the swap can be revealed.
+    pub reveal_block: BlockHeight,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Commits a swap without disclosing its parameters. `hash` is the sha256 hash of the borsh
+    serialized `(token_in: Option<AccountId>, token_out: Option<AccountId>, amount_in: u128,
+    min_out: u128, salt: Vec<u8>)`. The swap is executed with `reveal_swap`, not earlier
+    than `SWAP_REVEAL_DELAY` blocks from now. Nothing is escrowed: the deposit must cover
+    the swap when it's revealed.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the commitment id. */
+    #[payable]
+    pub fn commit_swap(&mut self, hash: Base64VecU8) -> u64 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        assert!(hash.0.len() == 32, "{}", ERR106_INVALID_REVEAL);
+        let id = self.next_order;
+        self.next_order += 1;
+        self.swap_commitments.insert(
+            &id,
+            &SwapCommitment {
+                account: env::predecessor_account_id(),
+                hash,
+                reveal_block: env::block_index() + SWAP_REVEAL_DELAY,
+            },
+        );
+        self.unsafe_storage_check(start_storage);
+        event_log!(self, "Swap commitment {}", id);
+        id
+    }
+
+    /**
+    Reveals and executes the `id` swap commitment of the caller: swaps `amount_in` of
+    `token_in` from the caller deposit to `token_out`. `None` token denotes NEAR.
+    Panics if the parameters don't match the commitment hash or if the swap buys less than
+    `min_out`.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the amount bought. */
+    #[payable]
+    pub fn reveal_swap(
+        &mut self,
+        id: u64,
+        token_in: Option<AccountId>,
+        token_out: Option<AccountId>,
+        amount_in: U128,
+        min_out: U128,
+        salt: Base64VecU8,
+    ) -> U128 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let c = self.swap_commitments.get(&id).expect(ERR33_ORDER_NOT_FOUND);
+        assert!(c.account == user, "{}", ERR33_ORDER_NOT_FOUND);
+        assert!(
+            env::block_index() >= c.reveal_block,
+            "{}",
+            ERR105_REVEAL_TOO_EARLY
+        );
+        let hash = swap_commitment_hash(&token_in, &token_out, amount_in.0, min_out.0, salt.0);
+        assert!(c.hash.0 == hash, "{}", ERR106_INVALID_REVEAL);
+        assert!(amount_in.0 > 0 && min_out.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        self.swap_commitments.remove(&id);
+
+        let out = self.internal_swap(&user, &token_in, &token_out, amount_in.0, min_out.0);
+        self.unsafe_storage_check(start_storage);
+        event_log!(self, "Swap commitment {} revealed", id);
+        out.into()
+    }
+
+    /// Removes the `id` swap commitment of the caller.
+    /// Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    #[payable]
+    pub fn cancel_swap_commitment(&mut self, id: u64) {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let c = self.swap_commitments.get(&id).expect(ERR33_ORDER_NOT_FOUND);
+        assert!(c.account == user, "{}", ERR33_ORDER_NOT_FOUND);
+        self.swap_commitments.remove(&id);
+        self.unsafe_storage_check(start_storage);
+    }
+
+    pub fn swap_commitment(&self, id: u64) -> Option<SwapCommitment> {
+        self.swap_commitments.get(&id)
+    }
+}
+
+/// Returns the hash committed by `commit_swap` for the swap parameters.
+pub fn swap_commitment_hash(
+    token_in: &Option<AccountId>,
+    token_out: &Option<AccountId>,
+    amount_in: u128,
+    min_out: u128,
+    salt: Vec<u8>,
+) -> CryptoHash {
+    let msg = (token_in, token_out, amount_in, min_out, salt)
+        .try_to_vec()
+        .unwrap();
+    env::sha256(&msg).try_into().unwrap()
+}
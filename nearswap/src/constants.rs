@@ -4,5 +4,207 @@
 /// 64 (AccountID bytes) + 2*8 (int32) + byte
 pub const INIT_ACCOUNT_STORAGE: u64 = 64 + 16 + 4;
 
+/// Storage bytes charged for every record on top of its key and value
+/// (`storage_num_extra_bytes_record` of the runtime config).
+pub const STORAGE_RECORD_OVERHEAD: u64 = 40;
+
 /// 1 NEAR in yocto = 1e24
 pub const NDENOM: u128 = 1_000_000_000_000_000_000_000_000;
+
+/// Number of blocks an escrowed swap has to wait before its first execution.
+pub const SWAP_ESCROW_DELAY: u64 = 2;
+
+/// Number of blocks a swap commitment has to wait before it can be revealed.
+pub const SWAP_REVEAL_DELAY: u64 = 1;
+
+/// Number of blocks a quote locked with `lock_quote` can be executed.
+pub const QUOTE_LOCK_BLOCKS: u64 = 10;
+
+/// Fee for locking a quote, in basis points of the swapped amount.
+pub const QUOTE_LOCK_FEE_BPS: u128 = 10;
+
+/// Maximum adverse price move of a locked quote covered by the quote lock fund, in basis
+/// points of the quoted amount.
+pub const QUOTE_LOCK_MAX_MOVE_BPS: u128 = 50;
+
+/// Maximum number of chunks an escrowed swap can be split into.
+pub const MAX_SWAP_PARTS: u8 = 10;
+
+/// Fee paid to the `execute_dca` caller (keeper) from every DCA order chunk, in basis points.
+pub const DCA_KEEPER_FEE_BPS: u128 = 10;
+
+/// Length of the trading volume window used for fee rebate tiers: 30 days, in nanoseconds.
+pub const REBATE_PERIOD: u64 = 30 * 24 * 3600 * 1_000_000_000;
+
+/// Maximum number of fee rebate tiers.
+pub const MAX_REBATE_TIERS: usize = 10;
+
+/// Liquidity lock tiers: (duration in nanoseconds, fee share boost in basis points).
+pub const LOCK_TIERS: [(u64, u16); 3] = [
+    (30 * 24 * 3600 * 1_000_000_000, 12_500),
+    (90 * 24 * 3600 * 1_000_000_000, 15_000),
+    (180 * 24 * 3600 * 1_000_000_000, 20_000),
+];
+
+/// Minimum amount of LP shares in a liquidity lock. Bounds the pool boost accumulators.
+pub const MIN_LOCK_SHARES: u128 = NDENOM / 1000;
+
+/// Precision of the per share reward accumulators (see `RewardAcc`).
+pub const ACC_DENOM: u128 = 1_000_000_000_000_000_000;
+
+/// Minimum amount of LP shares in a farm stake. Bounds the farm reward accumulators.
+pub const MIN_FARM_STAKE: u128 = NDENOM / 1000;
+
+/// Maximum number of different reward tokens paid by a farm.
+pub const MAX_FARM_REWARD_TOKENS: usize = 5;
+
+/// Number of the largest LPs of a pool kept in the pool `top_lps` index.
+pub const MAX_TOP_LPS: usize = 20;
+
+/// Maximum protocol part of the swap fees, in basis points of the fee.
+pub const MAX_PROTOCOL_FEE_BPS: u16 = 5_000;
+
+/// Default token project part of the pool protocol fees, in basis points.
+pub const PROJECT_REVENUE_SHARE_BPS: u16 = 2_000;
+
+/// Maximum number of protocol fee destinations.
+pub const MAX_FEE_DESTINATIONS: usize = 10;
+
+/// Maximum number of assets distributed by a `distribute_fees` call.
+pub const MAX_DISTRIBUTED_ASSETS: usize = 20;
+
+/// Maximum length of the pool description and reference, in bytes.
+pub const MAX_POOL_METADATA_LEN: usize = 512;
+
+/// Default range of the pool fees LPs can vote for: (min, max) in basis points.
+pub const POOL_FEE_BOUNDS: (u16, u16) = (5, 100);
+
+/// Duration of a pool fee proposal voting: 3 days, in nanoseconds.
+pub const FEE_VOTING_PERIOD: u64 = 3 * 24 * 3600 * 1_000_000_000;
+
+/// Delay between queuing a governance action and its execution: 2 days, in nanoseconds.
+pub const GOV_TIMELOCK: u64 = 2 * 24 * 3600 * 1_000_000_000;
+
+/// Minimum part of the pool shares voting for a fee proposal to pass, in basis points.
+pub const FEE_VOTE_QUORUM_BPS: u128 = 2000;
+
+/// Maximum number of accounts delegating their pool votes to a single delegate.
+pub const MAX_DELEGATORS: usize = 50;
+
+/// Maximum number of emergency council members.
+pub const MAX_COUNCIL_MEMBERS: usize = 15;
+
+/// Age of the last TWAP oracle observation after which `health_check` reports the pool
+/// oracle as stale: 1 hour, in nanoseconds.
+pub const ORACLE_STALE_PERIOD: u64 = 3600 * 1_000_000_000;
+
+/// `ft_transfer_call` message donating the transferred tokens to the token pool, see
+/// `donate_near`.
+pub const DONATE_MSG: &str = "donate";
+
+/// `ft_transfer_call` message migrating the transferred external LP tokens, see
+/// `set_migration_source`.
+pub const MIGRATE_IN_MSG: &str = "migrate_in";
+
+/// `ft_transfer_call` message prefix of the tokens bought by an external AMM adapter, see
+/// `swap_external`.
+pub const ADAPTER_RETURN_MSG: &str = "adapter_return";
+
+/// Default pool NEAR reserve which allows to refund the pool creation bond.
+pub const POOL_BOND_MIN_LIQUIDITY: u128 = 100 * NDENOM;
+
+/// Maximum number of orders on each side of a pool order book. Bounds the gas of matching
+/// the book in swaps.
+pub const MAX_BOOK_ORDERS: usize = 50;
+
+/// Long-term (TWAMM) orders end at the boundaries of this interval: 1 hour, in seconds.
+pub const TWAMM_INTERVAL: u64 = 3600;
+
+/// Maximum duration of a long-term order: 30 days, in `TWAMM_INTERVAL`s.
+pub const MAX_TWAMM_INTERVALS: u32 = 720;
+
+/// Maximum number of swaps in a pool batch auction, see `submit_batch_swap`.
+pub const MAX_BATCH_ORDERS: usize = 30;
+
+/// Maximum pool batch auction window, in blocks.
+pub const MAX_BATCH_WINDOW: u64 = 60;
+
+/// Default pool TWAP oracle window: 1 hour, in seconds. See `set_twap_window`.
+pub const DEFAULT_TWAP_WINDOW: u64 = 3600;
+
+/// Minimum and maximum pool TWAP oracle window: 1 minute and 12 hours, in seconds.
+pub const MIN_TWAP_WINDOW: u64 = 60;
+pub const MAX_TWAP_WINDOW: u64 = 12 * 3600;
+
+/// Maximum number of swaps in a `batch_swap`.
+pub const MAX_BATCH_SWAPS: usize = 10;
+
+/// Maximum number of recipients of a `swap_split`.
+pub const MAX_SPLIT_RECIPIENTS: usize = 10;
+
+/// Maximum number of per epoch pool snapshots kept in the pool history (~1 year of 12h epochs).
+pub const POOL_HISTORY_LENGTH: u64 = 730;
+
+/// Number of hourly price candles kept per pool (30 days).
+pub const CANDLES_1H_LENGTH: u64 = 24 * 30;
+
+/// Number of daily price candles kept per pool.
+pub const CANDLES_1D_LENGTH: u64 = 365;
+
+/// Pool price sampling period of the `price_history`, in seconds (15 minutes).
+pub const PRICE_SAMPLE_PERIOD: u64 = 15 * 60;
+
+/// Number of price samples kept per pool (7 days).
+pub const PRICE_HISTORY_LENGTH: u64 = 4 * 24 * 7;
+
+/// With the `invariant-checks` feature, the contract level invariants (iterating over all
+/// pools) are checked once per this number of events.
+pub const INVARIANT_CHECK_PERIOD: u64 = 16;
+
+/// Fee (in yNEAR) charged from the sender deposit to retry a failed `withdraw_token`
+/// transfer. It's attached to the recipient `storage_deposit` on the token (0.00125 NEAR).
+pub const TRANSFER_RETRY_FEE: Balance = 1_250_000_000_000_000_000_000;
+
+/// Maximum part of a pool NEAR reserve delegated to the staking pool, in basis points.
+pub const MAX_STAKED_BPS: u16 = 5000;
+
+/// Minimum amount of NEAR staked or unstaked by `rebalance_stake`.
+pub const MIN_STAKING_CHANGE: Balance = NDENOM;
+
+/// Number of epochs before unstaked NEAR can be withdrawn from the staking pool.
+pub const NUM_EPOCHS_TO_UNLOCK: u64 = 4;
+
+/// Maximum part of a pool token reserve supplied to the lending market, in basis points.
+pub const MAX_LENT_BPS: u16 = 5000;
+
+/// Minimum change of the lent tokens made by `rebalance_lending`, in basis points of the
+/// pool token reserve.
+pub const LENDING_REBALANCE_THRESHOLD_BPS: u16 = 100;
+
+/// Maximum change of a pool exchange rate per `RATE_CHANGE_PERIOD` since the last
+/// `update_rate`, in basis points.
+pub const MAX_RATE_CHANGE_BPS: u16 = 100;
+
+/// Period of the `MAX_RATE_CHANGE_BPS` rate change bound, in nanoseconds.
+pub const RATE_CHANGE_PERIOD: u64 = 24 * 3600 * 1_000_000_000;
+
+/// Maximum dynamic fee surcharge of the swaps increasing the pool imbalance, in basis points.
+pub const MAX_FEE_SURCHARGE_BPS: u16 = 100;
+
+/// Pool imbalance, in basis points, below which the dynamic fee doesn't apply.
+pub const DYNAMIC_FEE_NEUTRAL_BPS: i64 = 50;
+
+/// Maximum owner set default max slippage of the swaps without a minimum out amount, in
+/// basis points.
+pub const MAX_DEFAULT_SLIPPAGE_BPS: u16 = 5000;
+
+/// Maximum part of a pool token reserve sold by one `convert_fees` call, in basis points.
+/// Bounds the price impact of the conversion; the rest is converted by the next calls.
+pub const FEE_CONVERSION_MAX_RESERVE_BPS: u16 = 50;
+
+/// Maximum shortfall of the `convert_fees` NEAR out amount below the pool TWAP value of the
+/// sold tokens, in basis points. Includes the swap fee and the price impact.
+pub const FEE_CONVERSION_MAX_SLIPPAGE_BPS: u16 = 200;
+
+/// Name of the pool template applied to the pools created without a template.
+pub const DEFAULT_POOL_TEMPLATE: &str = "default";
@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  EMERGENCY COUNCIL
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId};
+
+use crate::constants::*;
+use crate::*;
+
+/// Emergency decision taken by the council once `threshold` members confirmed it.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CouncilMotion {
+    /// cancels the queued governance action, see `queued_actions`.
+    Veto { action_id: u64 },
+    /// pauses swaps and adding liquidity in all pools, see `unpause`.
+    Pause,
+}
+
+/// M-of-N emergency council.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub struct Council {
+    pub members: Vec<AccountId>,
+    /// number of member confirmations required to execute a motion.
+    pub threshold: u8,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: sets the council members and the confirmation threshold. Pending confirmations
+    /// are dropped. An empty `members` list disables the council.
+    pub fn set_council(&mut self, members: Vec<ValidAccountId>, threshold: u8) {
+        self.assert_owner();
+        let mut members: Vec<AccountId> = members.into_iter().map(|a| a.into()).collect();
+        members.sort();
+        members.dedup();
+        assert!(
+            members.len() <= MAX_COUNCIL_MEMBERS
+                && (members.is_empty() || threshold > 0)
+                && threshold as usize <= members.len(),
+            "{}",
+            ERR79_INVALID_COUNCIL
+        );
+        self.council_confirmations.clear();
+        self.log_admin_action("set_council", &(&members, threshold));
+        self.council = Council { members, threshold };
+    }
+
+    pub fn council(&self) -> Council {
+        self.council.clone()
+    }
+
+    /**
+    Council member: confirms the `motion`. The motion is executed with the confirmation of
+    the `threshold` member. Confirmations are recorded on-chain until the execution, see
+    `council_confirmations`.
+    Returns true if the motion was executed. */
+    pub fn council_confirm(&mut self, motion: CouncilMotion) -> bool {
+        let start_storage = env::storage_usage();
+        let member = self.assert_council_member();
+        if let CouncilMotion::Veto { action_id } = motion {
+            assert!(
+                self.queued_actions.get(&action_id).is_some(),
+                "{}",
+                ERR76_ACTION_NOT_FOUND
+            );
+        }
+        let mut confirmations = self.council_confirmations.get(&motion).unwrap_or_default();
+        assert!(
+            !confirmations.contains(&member),
+            "{}",
+            ERR81_ALREADY_CONFIRMED
+        );
+        confirmations.push(member.clone());
+        event_log!(
+            self,
+            "Council motion {:?} confirmed by {} ({}/{})",
+            motion,
+            member,
+            confirmations.len(),
+            self.council.threshold
+        );
+
+        let executed = confirmations.len() >= self.council.threshold as usize;
+        if executed {
+            self.council_confirmations.remove(&motion);
+            match motion {
+                CouncilMotion::Veto { action_id } => self.cancel_action(action_id),
+                CouncilMotion::Pause => {
+                    self.paused = true;
+                    self.log_admin_action("council_pause", &());
+                    event_log!(self, "Contract paused by the council");
+                }
+            }
+        } else {
+            self.council_confirmations.insert(&motion, &confirmations);
+        }
+        self.unsafe_storage_check(start_storage);
+        executed
+    }
+
+    /// Council member: revokes the caller confirmation of a pending `motion`.
+    pub fn council_revoke(&mut self, motion: CouncilMotion) {
+        let start_storage = env::storage_usage();
+        let member = self.assert_council_member();
+        let mut confirmations = self.council_confirmations.get(&motion).unwrap_or_default();
+        let len = confirmations.len();
+        confirmations.retain(|a| a != &member);
+        assert!(confirmations.len() < len, "{}", ERR82_NOT_CONFIRMED);
+        if confirmations.is_empty() {
+            self.council_confirmations.remove(&motion);
+        } else {
+            self.council_confirmations.insert(&motion, &confirmations);
+        }
+        self.unsafe_storage_check(start_storage);
+        event_log!(self, "Council motion {:?} revoked by {}", motion, member);
+    }
+
+    /// Returns the members who confirmed the pending `motion`.
+    pub fn council_confirmations(&self, motion: CouncilMotion) -> Vec<AccountId> {
+        self.council_confirmations.get(&motion).unwrap_or_default()
+    }
+
+    /// Returns the pending council motions with their confirmations.
+    pub fn council_motions(&self) -> Vec<(CouncilMotion, Vec<AccountId>)> {
+        self.council_confirmations.iter().collect()
+    }
+
+    /// Owner: resumes swaps and adding liquidity after a council pause.
+    pub fn unpause(&mut self) {
+        self.assert_owner();
+        self.paused = false;
+        self.log_admin_action("unpause", &());
+        event_log!(self, "Contract unpaused");
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Guardian: pauses the `token` pool, eg when the token contract is compromised: swaps,
+    /// adding liquidity and token deposits are disabled. Withdrawals keep working and the
+    /// other pools are not affected. The owner resumes the pool with `unpause_pool`.
+    pub fn pause_pool(&mut self, token: AccountId) {
+        self.assert_guardian();
+        let mut p = self.get_pool(&token);
+        if p.paused {
+            return;
+        }
+        p.paused = true;
+        self.set_pool(&token, &p);
+        self.log_admin_action("pause_pool", &token);
+        event_log!(self, "Pool {} paused by the guardian", token);
+    }
+
+    /// Owner: resumes the `token` pool paused by `pause_pool`, `pause_own_pool` or
+    /// `verify_reserves`.
+    pub fn unpause_pool(&mut self, token: AccountId) {
+        self.assert_owner();
+        let mut p = self.get_pool(&token);
+        if !p.paused {
+            return;
+        }
+        p.paused = false;
+        self.set_pool(&token, &p);
+        self.log_admin_action("unpause_pool", &token);
+        event_log!(self, "Pool {} unpaused", token);
+    }
+}
+
+impl NearSwap {
+    fn assert_council_member(&self) -> AccountId {
+        let a = env::predecessor_account_id();
+        assert!(
+            self.council.members.contains(&a),
+            "{}",
+            ERR78_NOT_COUNCIL_MEMBER
+        );
+        a
+    }
+
+    /// Panics if the contract or the pool `p` is paused.
+    #[inline]
+    pub(crate) fn assert_pool_active(&self, p: &Pool) {
+        assert!(!self.paused, "{}", ERR80_PAUSED);
+        assert!(!p.paused, "{}", ERR85_POOL_PAUSED);
+    }
+}
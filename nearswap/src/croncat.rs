@@ -0,0 +1,164 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  CRONCAT SCHEDULED
+  TASKS
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base64VecU8, ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{env, ext_contract, near_bindgen, AccountId, Gas, Promise, PromiseResult};
+
+use crate::*;
+
+/// Amount of gas for the croncat manager `create_task` and `remove_task` calls.
+pub const GAS_FOR_CRONCAT_MANAGE_TASK: Gas = 20 * TGAS;
+
+/// Amount of gas for the `create_task` resolve callback.
+pub const GAS_FOR_RESOLVE_CREATE_TASK: Gas = 10 * TGAS;
+
+/// Croncat manager contract interface.
+#[ext_contract(ext_croncat)]
+pub trait CroncatManager {
+    fn create_task(
+        &mut self,
+        contract_id: String,
+        function_id: String,
+        cadence: String,
+        recurring: Option<bool>,
+        deposit: Option<U128>,
+        gas: Option<Gas>,
+        arguments: Option<Base64VecU8>,
+    ) -> Base64VecU8;
+
+    fn remove_task(&mut self, task_hash: Base64VecU8);
+}
+
+/// Protocol task executed by croncat agents through `croncat_tick`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum CronTask {
+    /// records the `token` pool spot price in the pool TWAP oracle.
+    PokeOracle { token: AccountId },
+    /// executes the next chunk of a DCA order, without a keeper fee.
+    ExecuteDca { order_id: u64 },
+    /// moves the `token` pool staked NEAR towards its target, see `rebalance_stake`.
+    RebalanceStake { token: AccountId },
+    /// fetches the `token` pool exchange rate, see `update_rate`.
+    UpdateRate { token: AccountId },
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: sets the croncat manager contract. `None` disables `croncat_tick`.
+    pub fn set_croncat_manager(&mut self, manager: Option<ValidAccountId>) {
+        self.assert_owner();
+        self.log_admin_action("set_croncat_manager", &manager);
+        self.croncat_manager = manager.map(|m| m.into());
+    }
+
+    pub fn croncat_manager(&self) -> Option<AccountId> {
+        self.croncat_manager.clone()
+    }
+
+    /**
+    Owner: registers a recurring `task` with the croncat manager, executed with the croncat
+    `cadence` (cron expression) and `gas`. The attached deposit is the task balance, paying
+    the croncat agents. Returns the croncat task hash, or `None` if the registration failed
+    (croncat refunds the deposit). */
+    #[payable]
+    pub fn croncat_create_task(&mut self, task: CronTask, cadence: String, gas: U64) -> Promise {
+        self.assert_owner();
+        let manager = self.croncat_manager.clone().expect(ERR45_CRONCAT_NOT_SET);
+        let args = json!({ "task": task }).to_string().into_bytes();
+        ext_croncat::create_task(
+            env::current_account_id(),
+            "croncat_tick".to_string(),
+            cadence,
+            Some(true),
+            Some(U128(0)),
+            Some(gas.into()),
+            Some(Base64VecU8(args)),
+            &manager,
+            env::attached_deposit(),
+            self.gas_for(GasOp::CroncatManageTask),
+        )
+        .then(ext_self::resolve_croncat_create_task(
+            task,
+            &env::current_account_id(),
+            0,
+            self.gas_for(GasOp::ResolveCroncatCreateTask),
+        ))
+    }
+
+    /// Callback for `croncat_create_task`. Records the created task.
+    #[private]
+    pub fn resolve_croncat_create_task(&mut self, task: CronTask) -> Option<Base64VecU8> {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Contract expected a result on the callback"
+        );
+        let hash = match env::promise_result(0) {
+            PromiseResult::Successful(data) => {
+                near_sdk::serde_json::from_slice::<Base64VecU8>(&data).ok()
+            }
+            _ => None,
+        };
+        if let Some(h) = &hash {
+            self.cron_tasks.insert(&h.0, &task);
+            event_log!(self, "Croncat task created: {:?}", h.0);
+        }
+        hash
+    }
+
+    /// Owner: removes the `task_hash` task from the croncat manager. Croncat refunds the
+    /// remaining task balance to this contract.
+    pub fn croncat_remove_task(&mut self, task_hash: Base64VecU8) -> Promise {
+        self.assert_owner();
+        let manager = self.croncat_manager.clone().expect(ERR45_CRONCAT_NOT_SET);
+        self.cron_tasks
+            .remove(&task_hash.0)
+            .expect(ERR46_CRON_TASK_NOT_FOUND);
+        event_log!(self, "Croncat task removed: {:?}", task_hash.0);
+        let gas = self.gas_for(GasOp::CroncatManageTask);
+        ext_croncat::remove_task(task_hash, &manager, 0, gas)
+    }
+
+    /// Returns the registered croncat tasks with their hashes.
+    pub fn cron_tasks(&self) -> Vec<(Base64VecU8, CronTask)> {
+        self.cron_tasks
+            .iter()
+            .map(|(h, t)| (Base64VecU8(h), t))
+            .collect()
+    }
+
+    /// Entry point of the croncat tasks. Can be called only by the croncat manager.
+    pub fn croncat_tick(&mut self, task: CronTask) {
+        let manager = self.croncat_manager.as_ref().expect(ERR45_CRONCAT_NOT_SET);
+        assert!(
+            &env::predecessor_account_id() == manager,
+            "{}",
+            ERR47_NOT_CRONCAT
+        );
+        match task {
+            CronTask::PokeOracle { token } => {
+                let mut p = self.get_pool(&token);
+                p.poke_oracle();
+                self.set_pool(&token, &p);
+            }
+            CronTask::ExecuteDca { order_id } => {
+                self.internal_execute_dca(order_id, None);
+            }
+            CronTask::RebalanceStake { token } => {
+                self.internal_rebalance_stake(&token);
+            }
+            CronTask::UpdateRate { token } => {
+                self.update_rate(token);
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  TRADE DEADLINES
+***********************/
+
+use near_sdk::env;
+use near_sdk::json_types::U64;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Expiry of a trading transaction, so a transaction delayed in the network isn't executed
+/// at an outdated price. When both limits are set, both must hold: the stricter one applies.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub struct Deadline {
+    /// latest block timestamp, in nanoseconds.
+    pub timestamp: Option<U64>,
+    /// latest block height.
+    pub max_block_height: Option<U64>,
+}
+
+impl Deadline {
+    pub fn expired(&self) -> bool {
+        self.timestamp.map_or(false, |t| env::block_timestamp() > t.0)
+            || self.max_block_height.map_or(false, |h| env::block_index() > h.0)
+    }
+}
+
+/// Panics if the `deadline` is set and expired.
+pub(crate) fn assert_deadline(deadline: &Option<Deadline>) {
+    if let Some(d) = deadline {
+        assert!(
+            !d.expired(),
+            "{} (block timestamp={}, height={})",
+            ERR129_DEADLINE_EXPIRED,
+            env::block_timestamp(),
+            env::block_index()
+        );
+    }
+}
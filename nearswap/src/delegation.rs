@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  VOTE DELEGATION
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance};
+
+use crate::constants::*;
+use crate::*;
+
+/// Pool voting power delegated with `delegate_votes`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Delegation {
+    pub delegate: AccountId,
+    /// LP shares escrowed by the delegation.
+    pub shares: Balance,
+    /// end of the last voting which counted the delegation, in nanoseconds. The delegation
+    /// can't be revoked before.
+    pub locked_until: u64,
+    /// id of the first fee proposal created after the delegation.
+    pub since_proposal: u64,
+}
+
+/// Public information of a delegation.
+#[derive(Serialize, Deserialize)]
+pub struct DelegationInfo {
+    pub delegate: AccountId,
+    pub shares: U128,
+    pub locked_until: U64,
+}
+
+/// Voting power of an account in a pool.
+#[derive(Serialize, Deserialize)]
+pub struct VotingPower {
+    /// owned LP shares, which can be escrowed by a vote.
+    pub shares: U128,
+    /// shares in the account liquidity lock (zero when delegated).
+    pub locked: U128,
+    /// shares and locked shares delegated to the account.
+    pub delegated: U128,
+    pub total: U128,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Delegates the caller voting power in the `token` pool to `delegate`: the caller liquidity
+    lock shares and `shares` of the caller LP shares, which are escrowed by the delegation.
+    The delegate votes with them in addition to its own power. Delegation is not
+    transitive: an account with delegators can't delegate and can't be a delegate of
+    an account with delegators. A delegating account can't vote itself.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation. */
+    #[payable]
+    pub fn delegate_votes(&mut self, token: AccountId, delegate: ValidAccountId, shares: U128) {
+        assert_one_yocto();
+        let delegate: AccountId = delegate.into();
+        let start_storage = env::storage_usage();
+        let shares: Balance = shares.into();
+        let user = env::predecessor_account_id();
+        assert!(
+            self.delegations.get(&(token.clone(), user.clone())).is_none(),
+            "{}",
+            ERR70_ALREADY_DELEGATED
+        );
+        assert!(
+            delegate != user
+                && self.delegations.get(&(token.clone(), delegate.clone())).is_none()
+                && self.get_delegators(&token, &user).is_empty(),
+            "{}",
+            ERR71_DELEGATION_CHAIN
+        );
+        let mut delegators = self.get_delegators(&token, &delegate);
+        assert!(
+            delegators.len() < MAX_DELEGATORS,
+            "{}",
+            ERR72_TOO_MANY_DELEGATORS
+        );
+
+        let mut p = self.get_pool(&token);
+        if shares > 0 {
+            let owned = p.shares.get(&user).unwrap_or(0);
+            assert!(
+                owned >= shares,
+                "{} (owned {}, requested {})",
+                ERR11_NOT_ENOUGH_SHARES,
+                owned,
+                shares
+            );
+            p.set_shares(&user, owned - shares);
+        }
+        assert!(
+            shares > 0 || p.locks.get(&user).is_some(),
+            "{}",
+            ERR02_POSITIVE_ARGS
+        );
+
+        delegators.push(user.clone());
+        self.delegators
+            .insert(&(token.clone(), delegate.clone()), &delegators);
+        self.delegations.insert(
+            &(token.clone(), user),
+            &Delegation {
+                delegate: delegate.clone(),
+                shares,
+                locked_until: 0,
+                since_proposal: self.next_proposal,
+            },
+        );
+        if shares > 0 {
+            self.set_pool(&token, &p);
+        }
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Delegated {} votes to {} with {} shares",
+            token,
+            delegate,
+            shares
+        );
+    }
+
+    /**
+    Revokes the caller delegation in the `token` pool and returns the escrowed shares.
+    Panics during a voting which counted the delegation.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation. */
+    #[payable]
+    pub fn undelegate_votes(&mut self, token: AccountId) -> U128 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let key = (token.clone(), user.clone());
+        let d = self.delegations.get(&key).expect(ERR73_DELEGATION_NOT_FOUND);
+        assert!(
+            env::block_timestamp() >= d.locked_until,
+            "{} (until {})",
+            ERR74_DELEGATION_LOCKED,
+            d.locked_until
+        );
+        self.delegations.remove(&key);
+        let dkey = (token.clone(), d.delegate.clone());
+        let mut delegators = self.get_delegators(&token, &d.delegate);
+        delegators.retain(|a| a != &user);
+        if delegators.is_empty() {
+            self.delegators.remove(&dkey);
+        } else {
+            self.delegators.insert(&dkey, &delegators);
+        }
+
+        if d.shares > 0 {
+            let mut p = self.get_pool(&token);
+            let owned = p.shares.get(&user).unwrap_or(0);
+            p.set_shares(&user, owned + d.shares);
+            self.set_pool(&token, &p);
+        }
+        self.unsafe_storage_check(start_storage);
+        event_log!(self, "Undelegated {} votes from {}", token, d.delegate);
+        d.shares.into()
+    }
+
+    /// Returns the `account` delegation in the `token` pool, if any.
+    pub fn delegation(&self, token: AccountId, account: AccountId) -> Option<DelegationInfo> {
+        self.delegations
+            .get(&(token, account))
+            .map(|d| DelegationInfo {
+                delegate: d.delegate,
+                shares: d.shares.into(),
+                locked_until: d.locked_until.into(),
+            })
+    }
+
+    /// Returns the accounts delegating their `token` pool voting power to `account`.
+    pub fn delegators(&self, token: AccountId, account: AccountId) -> Vec<AccountId> {
+        self.get_delegators(&token, &account)
+    }
+
+    /// Returns the current `account` voting power in the `token` pool.
+    pub fn voting_power(&self, token: AccountId, account: AccountId) -> VotingPower {
+        let p = self.get_pool(&token);
+        let shares = p.shares.get(&account).unwrap_or(0);
+        let locked = if self.delegations.get(&(token.clone(), account.clone())).is_some() {
+            0
+        } else {
+            p.locks.get(&account).map(|l| l.shares).unwrap_or(0)
+        };
+        let delegated: Balance = self
+            .get_delegators(&token, &account)
+            .iter()
+            .map(|a| {
+                let d = self.delegations.get(&(token.clone(), a.clone())).unwrap();
+                d.shares + p.locks.get(a).map(|l| l.shares).unwrap_or(0)
+            })
+            .sum();
+        VotingPower {
+            shares: shares.into(),
+            locked: locked.into(),
+            delegated: delegated.into(),
+            total: (shares + locked + delegated).into(),
+        }
+    }
+}
+
+impl NearSwap {
+    pub(crate) fn get_delegators(
+        &self,
+        token: &AccountId,
+        account: &AccountId,
+    ) -> Vec<AccountId> {
+        self.delegators
+            .get(&(token.clone(), account.clone()))
+            .unwrap_or_default()
+    }
+}
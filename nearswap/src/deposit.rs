@@ -9,7 +9,8 @@ use std::convert::TryInto;
 use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
 use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
 use near_sdk::{
-    assert_one_yocto, env, near_bindgen, AccountId, Balance, PromiseOrValue, StorageUsage,
+    assert_one_yocto, env, near_bindgen, AccountId, Balance, PromiseOrValue, PromiseResult,
+    StorageUsage,
 };
 
 //use crate::errors::*;
@@ -21,10 +22,11 @@ use crate::*;
 #[near_bindgen]
 impl FungibleTokenReceiver for NearSwap {
     /**
-    Callback on receiving tokens by this contract.
-    Returns zero.
-    Panics when account is not registered. */
-    #[allow(unused_variables)]
+    Callback on receiving tokens by this contract. The tokens are credited to the sender
+    deposit, or donated to the token pool reserves when `msg` is `DONATE_MSG`. External LP
+    tokens sent with `MIGRATE_IN_MSG` are migrated, see `migrate_in`.
+    Returns zero, or the LP tokens to refund when a migration failed.
+    Panics when account is not registered or the token pool is paused. */
     fn ft_on_transfer(
         &mut self,
         sender_id: ValidAccountId,
@@ -33,9 +35,27 @@ impl FungibleTokenReceiver for NearSwap {
     ) -> PromiseOrValue<U128> {
         let token = env::predecessor_account_id();
         let sender_id = AccountId::from(sender_id);
+        if let Some(id) = parse_adapter_return_msg(&msg) {
+            return self.adapter_return(&sender_id, &token, amount.0, id);
+        }
+        if let Some(p) = self.pools.get(&token) {
+            assert!(!p.paused, "{}", ERR85_POOL_PAUSED);
+        }
 
+        if let Some(min_shares) = parse_migrate_in_msg(&msg) {
+            return self.migrate_in(sender_id, token, amount.0, min_shares);
+        }
+        if msg == DONATE_MSG {
+            assert!(amount.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+            let mut p = self.get_pool(&token);
+            p.donate(0, amount.0);
+            self.set_pool(&token, &p);
+            self.record_token_in(&token, amount.0);
+            event_log!(self, "Donated {} {} to the pool", amount.0, token);
+            return PromiseOrValue::Value(U128(0));
+        }
         self.deposit_token(&sender_id, &token, amount.into());
-        env_log!("Deposit, {} {}", amount.0, token);
+        event_log!(self, "Deposit, {} {}", amount.0, token);
 
         return PromiseOrValue::Value(U128(0));
     }
@@ -53,7 +73,7 @@ impl NearSwap {
         let amount = env::attached_deposit();
         d.ynear += amount;
         self.deposits.insert(&sender, &d.into());
-        env_log!("Deposit, {} yNEAR", amount);
+        event_log!(self, "Deposit, {} yNEAR", amount);
     }
 
     /**
@@ -92,13 +112,16 @@ impl NearSwap {
         );
         d.add(token_id, amount);
         self.deposits.insert(&sender_id, &d.into());
+        self.record_token_in(token_id, amount);
     }
 
     /**
     Withdraws near from deposit.
-    Requires payment of exactly one yNEAR to enforce wallet confirmation. */
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    If the transfer fails, the amount is credited back to the deposit. */
     #[payable]
     pub fn withdraw_near(&mut self, amount: U128, recipient: Option<ValidAccountId>) -> Promise {
+        let _m = MethodMetrics::start("withdraw_near");
         assert_one_yocto();
         let sender = env::predecessor_account_id();
         let recipient = if let Some(a) = recipient {
@@ -106,17 +129,29 @@ impl NearSwap {
         } else {
             sender.clone()
         };
-        env_log!("Deposit withdraw, {} yNEAR", amount.0);
+        event_log!(self, "Deposit withdraw, {} yNEAR", amount.0);
         let amount = u128::from(amount);
         let mut d = self.get_deposit(&sender);
         d.remove_near(amount);
         self.deposits.insert(&sender, &d.into());
-        Promise::new(recipient).transfer(amount)
+        Promise::new(recipient)
+            .transfer(amount)
+            .then(ext_self::resolve_withdraw_near(
+                sender,
+                amount.into(),
+                &env::current_account_id(),
+                0,
+                self.gas_for(GasOp::ResolveWithdraw),
+            ))
     }
 
     /**
-    Withdraws tokens from deposit.
+    Withdraws tokens from deposit. When `is_contract` is set, tokens are sent with
+    `ft_transfer_call` and `tx_call_msg` is passed to the recipient.
     Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    If the transfer fails, or the recipient doesn't use all tokens, the remaining amount is
+    credited back to the deposit. A failed `ft_transfer` (eg the recipient is not registered
+    on the token) is first retried once, see `resolve_withdraw_token`.
     Note: `token` doesn't need to be ValidAccountId because it's already registered. */
     #[payable]
     pub fn withdraw_token(
@@ -126,7 +161,8 @@ impl NearSwap {
         recipient: Option<ValidAccountId>,
         is_contract: bool,
         tx_call_msg: String,
-    ) {
+    ) -> Promise {
+        let _m = MethodMetrics::start("withdraw_token");
         assert_one_yocto();
         let sender = env::predecessor_account_id();
         let recipient = if let Some(a) = recipient {
@@ -134,32 +170,281 @@ impl NearSwap {
         } else {
             sender.clone()
         };
-        env_log!("Deposit withdraw, {} {}", amount.0, token);
+        event_log!(self, "Deposit withdraw, {} {}", amount.0, token);
         let mut d = self.get_deposit(&sender);
         let amount = u128::from(amount);
         d.remove(&token, amount);
         self.deposits.insert(&sender, &d.into());
+        self.record_token_out(&token, amount);
 
-        if is_contract {
-            ext_fungible_token::ft_transfer(
-                recipient.try_into().unwrap(),
+        let mut resolve_gas = self.gas_for(GasOp::ResolveWithdraw);
+        let transfer = if is_contract {
+            ext_fungible_token::ft_transfer_call(
+                recipient.clone().try_into().unwrap(),
                 amount.into(),
                 Some("NEARswap withdraw".to_string()),
+                tx_call_msg,
                 &token,
                 1, // required 1yNEAR for transfers
-                GAS_FOR_FT_TRANSFER,
-            );
+                // the receiver gets all gas not needed by the resolve callback.
+                self.gas_with_unused(GasOp::FtTransferCall, resolve_gas),
+            )
         } else {
-            ext_fungible_token::ft_transfer_call(
-                recipient.try_into().unwrap(),
+            resolve_gas += self.retry_transfer_gas();
+            ext_fungible_token::ft_transfer(
+                recipient.clone(),
                 amount.into(),
                 Some("NEARswap withdraw".to_string()),
-                tx_call_msg,
                 &token,
                 1, // required 1yNEAR for transfers
-                GAS_FOR_FT_TRANSFER,
-            );
+                self.gas_for(GasOp::FtTransfer),
+            )
+        };
+        transfer.then(ext_self::resolve_withdraw_token(
+            sender,
+            recipient,
+            token,
+            amount.into(),
+            is_contract,
+            &env::current_account_id(),
+            0,
+            resolve_gas,
+        ))
+    }
+
+    /// Callback for `withdraw_near`. Credits `amount` back to the `sender` deposit if the
+    /// transfer failed. Returns the amount withdrawn.
+    #[private]
+    pub fn resolve_withdraw_near(&mut self, sender: AccountId, amount: U128) -> U128 {
+        if is_promise_success() {
+            return amount;
+        }
+        self.refund_withdraw(&sender, None, amount.0);
+        U128(0)
+    }
+
+    /**
+    Callback for `withdraw_token`. Credits back to the `sender` deposit the amount which
+    didn't reach the `recipient`: everything if the transfer failed, or the part refunded
+    by the recipient on `ft_transfer_call` (`is_call`).
+    A failed `ft_transfer` is retried once when the sender deposit covers the
+    `TRANSFER_RETRY_FEE`: the fee pays the recipient `storage_deposit` on the token, then
+    the transfer is sent again. If the retry fails, the amount is recorded in the failed
+    transfers, see `claim_failed_transfers`.
+    Returns the amount withdrawn. */
+    #[private]
+    pub fn resolve_withdraw_token(
+        &mut self,
+        sender: AccountId,
+        recipient: AccountId,
+        token: AccountId,
+        amount: U128,
+        is_call: bool,
+    ) -> PromiseOrValue<U128> {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Contract expected a result on the callback"
+        );
+        let used = match env::promise_result(0) {
+            // `ft_transfer_call` returns the amount used by the receiver.
+            PromiseResult::Successful(data) if is_call => {
+                near_sdk::serde_json::from_slice::<U128>(&data)
+                    .map(|u| std::cmp::min(u.0, amount.0))
+                    .unwrap_or(amount.0)
+            }
+            PromiseResult::Successful(_) => amount.0,
+            _ => {
+                if !is_call {
+                    if let Some(p) = self.retry_transfer(sender.clone(), recipient, &token, amount)
+                    {
+                        return PromiseOrValue::Promise(p);
+                    }
+                }
+                0
+            }
+        };
+        if used < amount.0 {
+            self.record_token_in(&token, amount.0 - used);
+            self.refund_withdraw(&sender, Some(&token), amount.0 - used);
+        }
+        PromiseOrValue::Value(U128(used))
+    }
+
+    /// Callback for the `withdraw_token` transfer retry. Records the amount in the `sender`
+    /// failed transfers if the retry failed. Returns the amount withdrawn.
+    #[private]
+    pub fn resolve_retry_transfer(
+        &mut self,
+        sender: AccountId,
+        token: AccountId,
+        amount: U128,
+    ) -> U128 {
+        if is_promise_success() {
+            return amount;
         }
+        self.record_token_in(&token, amount.0);
+        self.record_failed_transfer(sender, Some(token), amount.0);
+        U128(0)
+    }
+
+    /**
+    Retries the caller failed transfers of `token` (`None` for NEAR), recorded when a
+    withdraw failed and the amount couldn't be credited back to the deposit (the account
+    unregistered in the meantime). If the transfer fails again, the amount stays claimable.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation. */
+    #[payable]
+    pub fn claim_failed_transfers(&mut self, token: Option<AccountId>) -> Promise {
+        assert_one_yocto();
+        let user = env::predecessor_account_id();
+        let key = (user.clone(), token.clone());
+        let amount = self
+            .failed_transfers
+            .remove(&key)
+            .expect(ERR49_NO_FAILED_TRANSFER);
+        event_log!(self, "Failed transfer claim, {}", amount);
+        if let Some(t) = &token {
+            self.record_token_out(t, amount);
+        }
+        let transfer = match &token {
+            Some(t) => ext_fungible_token::ft_transfer(
+                user.clone(),
+                amount.into(),
+                Some("NEARswap failed transfer claim".to_string()),
+                t,
+                1, // required 1yNEAR for transfers
+                self.gas_for(GasOp::FtTransfer),
+            ),
+            None => Promise::new(user.clone()).transfer(amount),
+        };
+        transfer.then(ext_self::resolve_claim_failed_transfer(
+            user,
+            token,
+            amount.into(),
+            &env::current_account_id(),
+            0,
+            self.gas_for(GasOp::ResolveWithdraw),
+        ))
+    }
+
+    /// Callback for `claim_failed_transfers`. Records the amount back in the failed transfers
+    /// if the transfer failed. Returns the amount transferred.
+    #[private]
+    pub fn resolve_claim_failed_transfer(
+        &mut self,
+        account: AccountId,
+        token: Option<AccountId>,
+        amount: U128,
+    ) -> U128 {
+        if is_promise_success() {
+            return amount;
+        }
+        if let Some(t) = &token {
+            self.record_token_in(t, amount.0);
+        }
+        self.record_failed_transfer(account, token, amount.0);
+        U128(0)
+    }
+
+    /// Returns the amount of `token` (`None` for NEAR) owed to `account` from failed
+    /// transfers, see `claim_failed_transfers`.
+    pub fn failed_transfers(&self, account: AccountId, token: Option<AccountId>) -> U128 {
+        self.failed_transfers
+            .get(&(account, token))
+            .unwrap_or(0)
+            .into()
+    }
+
+    /// Retries a failed `amount` transfer of `token` to the `recipient`, registering the
+    /// recipient on the token first. The `TRANSFER_RETRY_FEE` is charged from the `sender`
+    /// deposit. Returns `None` if the sender deposit doesn't cover the fee or there is not
+    /// enough gas left.
+    fn retry_transfer(
+        &mut self,
+        sender: AccountId,
+        recipient: AccountId,
+        token: &AccountId,
+        amount: U128,
+    ) -> Option<Promise> {
+        let gas = self.retry_transfer_gas();
+        if env::prepaid_gas().saturating_sub(env::used_gas()) < gas + GAS_RESERVE {
+            return None;
+        }
+        let mut d: DepositV1 = self.deposits.get(&sender)?.into();
+        if d.ynear < TRANSFER_RETRY_FEE {
+            return None;
+        }
+        d.remove_near(TRANSFER_RETRY_FEE);
+        self.deposits.insert(&sender, &d.into());
+        event_log!(self, "Withdraw transfer retry, {} {}", amount.0, token);
+        Some(
+            ext_fungible_token::storage_deposit(
+                Some(recipient.clone().try_into().unwrap()),
+                Some(true),
+                token,
+                TRANSFER_RETRY_FEE,
+                self.gas_for(GasOp::StorageDeposit),
+            )
+            .then(ext_fungible_token::ft_transfer(
+                recipient,
+                amount,
+                Some("NEARswap withdraw".to_string()),
+                token,
+                1, // required 1yNEAR for transfers
+                self.gas_for(GasOp::FtTransfer),
+            ))
+            .then(ext_self::resolve_retry_transfer(
+                sender,
+                token.clone(),
+                amount,
+                &env::current_account_id(),
+                0,
+                self.gas_for(GasOp::ResolveWithdraw),
+            )),
+        )
+    }
+
+    /// Credits back a withdraw which didn't succeed. If the sender is not registered any more,
+    /// the amount is recorded in the failed transfers.
+    fn refund_withdraw(&mut self, sender: &AccountId, token: Option<&AccountId>, amount: Balance) {
+        let mut d = match self.deposits.get(sender) {
+            Some(d) => DepositV1::from(d),
+            None => {
+                self.record_failed_transfer(sender.clone(), token.cloned(), amount);
+                return;
+            }
+        };
+        match token {
+            Some(t) => d.add(t, amount),
+            None => d.add_near(amount),
+        }
+        self.deposits.insert(sender, &d.into());
+        event_log!(
+            self,
+            "Withdraw refund, {} {}",
+            amount,
+            token.map_or("yNEAR", |t| t.as_str())
+        );
+    }
+
+    /// Records `amount` of `token` owed to `account`, claimable with `claim_failed_transfers`.
+    /// The record storage is paid by the contract.
+    fn record_failed_transfer(
+        &mut self,
+        account: AccountId,
+        token: Option<AccountId>,
+        amount: Balance,
+    ) {
+        let key = (account, token);
+        let owed = self.failed_transfers.get(&key).unwrap_or(0) + amount;
+        self.failed_transfers.insert(&key, &owed);
+        event_log!(
+            self,
+            "Failed transfer recorded, {} {} for {}",
+            amount,
+            key.1.as_deref().unwrap_or("yNEAR"),
+            key.0
+        );
     }
 
     #[inline]
@@ -243,20 +528,46 @@ impl DepositV1 {
     }
 
     pub(crate) fn remove(&mut self, token: &AccountId, amount: u128) {
+        let available = self.tokens.get(token).cloned().unwrap_or(0);
+        assert!(
+            available >= amount,
+            "{} (needed {}, available {})",
+            ERR13_NOT_ENOUGH_TOKENS_DEPOSITED,
+            amount,
+            available
+        );
         if let Some(x) = self.tokens.get_mut(token) {
-            assert!(*x >= amount, "{}", ERR13_NOT_ENOUGH_TOKENS_DEPOSITED);
-            *x = *x - amount;
-        } else {
-            panic!("{}", ERR13_NOT_ENOUGH_TOKENS_DEPOSITED);
+            *x -= amount;
+        }
+    }
+
+    /// Adds `amount` of `token` to the deposit. `None` token denotes NEAR.
+    pub(crate) fn add_asset(&mut self, token: &Option<AccountId>, amount: u128) {
+        match token {
+            Some(t) => self.add(t, amount),
+            None => self.add_near(amount),
+        }
+    }
+
+    /// Removes `amount` of `token` from the deposit. `None` token denotes NEAR.
+    pub(crate) fn remove_asset(&mut self, token: &Option<AccountId>, amount: u128) {
+        match token {
+            Some(t) => self.remove(t, amount),
+            None => self.remove_near(amount),
         }
     }
 
     // asserts that the account has enough NEAR to cover storage and use of `amount` NEAR.
     #[inline]
     pub(crate) fn remove_near(&mut self, ynear: u128) {
+        let storage = (self.storage_used as u128) * env::storage_byte_cost();
         assert!(
-            self.ynear >= ynear + (self.storage_used as u128) * env::storage_byte_cost(),
-            "{}", ERR14_NOT_ENOUGH_NEAR_DEPOSITED,
+            self.ynear >= ynear + storage,
+            "{} (needed {} + {} for storage, available {})",
+            ERR14_NOT_ENOUGH_NEAR_DEPOSITED,
+            ynear,
+            storage,
+            self.ynear
         );
         self.ynear -= ynear;
     }
@@ -277,9 +588,18 @@ impl DepositV1 {
     /// Updates the account storage usage. This has to be called after all non AcountDeposit
     /// changs are saved. Otherwise we will not take into account storage acquired in that
     /// changes.
+    /// Storage released in the transaction is subtracted from the account storage usage.
     /// Panics if there is not enought $NEAR to cover storage usage.
     pub(crate) fn update_storage(&mut self, tx_start_storage: StorageUsage) {
-        self.storage_used += env::storage_usage() - tx_start_storage;
+        let end_storage = env::storage_usage();
+        if end_storage >= tx_start_storage {
+            self.storage_used += end_storage - tx_start_storage;
+        } else {
+            self.storage_used = self
+                .storage_used
+                .saturating_sub(tx_start_storage - end_storage)
+                .max(INIT_ACCOUNT_STORAGE);
+        }
         self.assert_storage();
     }
 }
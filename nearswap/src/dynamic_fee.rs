@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  DYNAMIC FEES
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId};
+
+use crate::constants::*;
+use crate::*;
+
+/// Imbalance penalizing swap fee of a pool. When the pool spot price deviates from the TWAP
+/// oracle price by more than `DYNAMIC_FEE_NEUTRAL_BPS` (see `pool_imbalance`), swaps moving
+/// the price further pay the pool fee plus `surcharge_bps`, and swaps moving it back pay the
+/// pool fee minus `discount_bps` (at least 0).
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct DynamicFee {
+    pub surcharge_bps: u16,
+    pub discount_bps: u16,
+}
+
+impl DynamicFee {
+    pub fn assert_valid(&self) {
+        assert!(
+            self.surcharge_bps <= MAX_FEE_SURCHARGE_BPS && self.discount_bps <= 10_000,
+            "{}",
+            ERR125_INVALID_DYNAMIC_FEE
+        );
+    }
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: enables the dynamic fee of the `token` pool, `surcharge_bps` is at most
+    /// `MAX_FEE_SURCHARGE_BPS`. `None` goes back to the flat pool fee.
+    pub fn set_pool_dynamic_fee(&mut self, token: AccountId, dynamic_fee: Option<DynamicFee>) {
+        self.assert_owner();
+        if let Some(f) = &dynamic_fee {
+            f.assert_valid();
+        }
+        let mut p = self.get_pool(&token);
+        p.dynamic_fee = dynamic_fee;
+        self.set_pool(&token, &p);
+        self.log_admin_action("set_pool_dynamic_fee", &(token, dynamic_fee));
+    }
+
+    pub fn pool_dynamic_fee(&self, token: AccountId) -> Option<DynamicFee> {
+        self.get_pool(&token).dynamic_fee
+    }
+
+    /// Returns the current `token` pool swap fee, in basis points, of a swap selling NEAR
+    /// (`near_in`) or selling the pool token.
+    pub fn pool_swap_fee(&self, token: AccountId, near_in: bool) -> u16 {
+        self.get_pool(&token).swap_fee_bps(near_in)
+    }
+}
@@ -25,3 +25,149 @@ pub const ERR22_ACC_ALREADY_REGISTERED: &str = "E22: Account already registered"
 pub const ERR23_TOKEN_NOT_WHITELISTED: &str = "E23: Token is not whitelisted";
 pub const ERR24_NON_ZERO_TOKEN_BALANCE: &str = "E24: Token balance is non-zero";
 pub const ERR25_MIN_AMOUNT: &str = "E25: Swap output amount is less than required amount";
+pub const ERR26_INIT_PRICE_OUT_OF_BOUNDS: &str =
+    "E26: Initial deposit price is outside of the pool price bounds";
+pub const ERR27_INVALID_PRICE_BOUNDS: &str = "E27: Price bounds must satisfy 0 < min <= max";
+pub const ERR28_INVALID_SWAP_ASSETS: &str = "E28: Swap assets must be different";
+pub const ERR29_SWAP_NOT_READY: &str = "E29: Swap request can't be executed in this block yet";
+pub const ERR30_INVALID_SWAP_PARTS: &str = "E30: Invalid number of swap request parts";
+pub const ERR31_SWAP_REQUEST_NOT_FOUND: &str = "E31: Swap request not found";
+pub const ERR32_INVARIANT_VIOLATION: &str = "E32: Pool accounting invariant violated";
+pub const ERR33_ORDER_NOT_FOUND: &str = "E33: Order not found";
+pub const ERR34_LIMIT_PRICE_NOT_REACHED: &str = "E34: Pool price doesn't reach the order limit price";
+pub const ERR35_TRIGGER_NOT_REACHED: &str = "E35: Pool price didn't cross the order trigger price";
+pub const ERR36_INVALID_SLIPPAGE: &str = "E36: Slippage must be at most 10000 basis points";
+pub const ERR37_DCA_NOT_DUE: &str = "E37: DCA order chunk is not due yet";
+pub const ERR38_INVALID_REBATE_TIERS: &str = "E38: Rebate tiers must be ordered by min volume, with at most 10000 bps";
+pub const ERR39_NO_REBATE: &str = "E39: No rebate to claim";
+pub const ERR40_LOCK_TOO_SMALL: &str = "E40: Amount of locked shares is below the minimum";
+pub const ERR41_INVALID_LOCK_TIER: &str = "E41: Invalid lock tier";
+pub const ERR42_LOCK_EXISTS: &str = "E42: Account already has a lock in this pool";
+pub const ERR43_LOCK_NOT_FOUND: &str = "E43: Liquidity lock not found";
+pub const ERR44_LOCK_NOT_EXPIRED: &str = "E44: Liquidity lock didn't expire yet";
+pub const ERR45_CRONCAT_NOT_SET: &str = "E45: Croncat manager is not set";
+pub const ERR46_CRON_TASK_NOT_FOUND: &str = "E46: Croncat task not found";
+pub const ERR47_NOT_CRONCAT: &str = "E47: Only the croncat manager can call this function";
+pub const ERR48_INVALID_GAS: &str = "E48: Gas override is out of the allowed range";
+pub const ERR49_NO_FAILED_TRANSFER: &str = "E49: No failed transfer to claim";
+pub const ERR50_INVALID_BATCH_SIZE: &str = "E50: Invalid number of swaps in the batch";
+pub const ERR51_INVALID_TARGET_WEIGHTS: &str = "E51: Target weights must be unique assets summing to 10000 bps";
+pub const ERR52_CAMPAIGN_ACTIVE: &str = "E52: Farm reward campaign is still active";
+pub const ERR53_TOO_MANY_REWARD_TOKENS: &str = "E53: Farm reached the maximum number of reward tokens";
+pub const ERR54_CAMPAIGN_NOT_CLOSED: &str = "E54: Previous reward campaign must be closed first";
+pub const ERR55_CAMPAIGN_NOT_FOUND: &str = "E55: Reward campaign funded by the caller not found";
+pub const ERR56_FARM_STAKE_TOO_SMALL: &str = "E56: Farm stake is below the minimum";
+pub const ERR57_FARM_STAKE_NOT_FOUND: &str = "E57: Farm stake not found";
+pub const ERR58_FARM_STAKE_PERIOD: &str = "E58: Farm stake is within the minimum staking period";
+pub const ERR59_INVALID_PENALTY: &str = "E59: Penalty must be at most 10000 basis points";
+pub const ERR60_INVALID_FEE_BOUNDS: &str = "E60: Fee bounds must satisfy min <= max <= 10000 bps";
+pub const ERR61_FEE_OUT_OF_BOUNDS: &str = "E61: Pool fee is outside of the fee bounds";
+pub const ERR62_FEE_PROPOSAL_IN_PROGRESS: &str = "E62: Pool already has a fee proposal in progress";
+pub const ERR63_VOTING_CLOSED: &str = "E63: Proposal voting is closed";
+pub const ERR64_ALREADY_VOTED: &str = "E64: Account already voted on this proposal";
+pub const ERR65_TIMELOCK_NOT_EXPIRED: &str = "E65: Queued action timelock didn't expire yet";
+pub const ERR66_VOTING_NOT_ENDED: &str = "E66: Proposal voting didn't end yet";
+pub const ERR67_VOTE_NOT_FOUND: &str = "E67: Vote not found";
+pub const ERR68_PROPOSAL_FINALIZED: &str = "E68: Proposal is already finalized";
+pub const ERR69_PROPOSAL_NOT_FOUND: &str = "E69: Proposal not found";
+pub const ERR70_ALREADY_DELEGATED: &str = "E70: Account already delegated its votes in this pool";
+pub const ERR71_DELEGATION_CHAIN: &str = "E71: Votes can't be delegated to self nor through another delegation";
+pub const ERR72_TOO_MANY_DELEGATORS: &str = "E72: Delegate reached the maximum number of delegators";
+pub const ERR73_DELEGATION_NOT_FOUND: &str = "E73: Delegation not found";
+pub const ERR74_DELEGATION_LOCKED: &str = "E74: Delegation was counted in a voting in progress";
+pub const ERR75_VOTES_DELEGATED: &str = "E75: Account delegated its votes in this pool";
+pub const ERR76_ACTION_NOT_FOUND: &str = "E76: Queued action not found";
+pub const ERR77_NOT_GUARDIAN: &str = "E77: Only the guardian can call this function";
+pub const ERR78_NOT_COUNCIL_MEMBER: &str = "E78: Only a council member can call this function";
+pub const ERR79_INVALID_COUNCIL: &str = "E79: Council threshold must be between 1 and the number of members";
+pub const ERR80_PAUSED: &str = "E80: Contract is paused";
+pub const ERR81_ALREADY_CONFIRMED: &str = "E81: Member already confirmed this motion";
+pub const ERR82_NOT_CONFIRMED: &str = "E82: Member didn't confirm this motion";
+pub const ERR83_INVALID_AUDIT_CURSOR: &str = "E83: Audit must continue from the stored cursor or restart from 0";
+pub const ERR84_AUDIT_OUTDATED: &str = "E84: Pool shares changed during the audit, restart it from 0";
+pub const ERR85_POOL_PAUSED: &str = "E85: Pool is paused";
+pub const ERR86_DONATION_TO_EMPTY_POOL: &str = "E86: Can't donate to a pool without liquidity";
+pub const ERR87_POOL_BOND_REQUIRED: &str = "E87: Attached deposit is below the pool creation bond";
+pub const ERR88_NO_POOL_BOND: &str = "E88: Pool has no bond";
+pub const ERR89_POOL_LIQUIDITY_TOO_LOW: &str = "E89: Pool liquidity is below the bond refund threshold";
+pub const ERR90_POOL_NOT_EMPTY: &str = "E90: Pool is not empty";
+pub const ERR91_NOT_RFQ_MAKER: &str = "E91: Account is not an RFQ maker";
+pub const ERR92_INVALID_QUOTE_SIGNATURE: &str = "E92: Invalid quote signature";
+pub const ERR93_QUOTE_USED: &str = "E93: Quote was already taken";
+pub const ERR94_NOT_QUOTE_TAKER: &str = "E94: Quote is reserved for another taker";
+pub const ERR95_NOT_ENOUGH_INVENTORY: &str = "E95: Not enough RFQ maker inventory";
+pub const ERR96_INVALID_MAKER_KEY: &str = "E96: Maker key must be an ed25519 public key";
+pub const ERR97_BOOK_SIDE_FULL: &str = "E97: Order book side is full";
+pub const ERR98_INVALID_TWAMM_INTERVALS: &str = "E98: TWAMM order must last between 1 and MAX_TWAMM_INTERVALS intervals";
+pub const ERR99_NO_BATCH_AUCTION: &str = "E99: Pool doesn't run batch auctions";
+pub const ERR100_BATCH_AUCTION_POOL: &str =
+    "E100: Pool settles swaps in batch auctions, use submit_batch_swap";
+pub const ERR101_BATCH_NOT_CLOSED: &str = "E101: Batch auction window is not over yet";
+pub const ERR102_BATCH_FULL: &str = "E102: Batch auction is full";
+pub const ERR103_NO_BATCH: &str = "E103: No pending batch auction";
+pub const ERR104_INVALID_BATCH_WINDOW: &str = "E104: Batch auction window is too long";
+pub const ERR105_REVEAL_TOO_EARLY: &str = "E105: Swap commitment can't be revealed in this block yet";
+pub const ERR106_INVALID_REVEAL: &str = "E106: Revealed swap doesn't match the commitment";
+pub const ERR107_QUOTE_LOCK_EXPIRED: &str = "E107: Locked quote expired";
+pub const ERR108_QUOTE_LOCK_NOT_COVERED: &str =
+    "E108: Price moved more than the quote lock fund covers";
+pub const ERR109_NOT_POOL_CREATOR: &str = "E109: Only the pool creator can do this";
+pub const ERR110_METADATA_TOO_LONG: &str = "E110: Pool metadata is too long";
+pub const ERR111_INVALID_PROTOCOL_FEE: &str = "E111: Protocol fee must be at most MAX_PROTOCOL_FEE_BPS";
+pub const ERR112_NOT_PROJECT_BENEFICIARY: &str = "E112: Account is not the token project beneficiary";
+pub const ERR113_INVALID_REVENUE_SHARE: &str = "E113: Revenue share must be at most 10000 basis points";
+pub const ERR114_INVALID_FEE_DESTINATIONS: &str = "E114: Fee destinations must be 1 to MAX_FEE_DESTINATIONS different accounts with positive weights";
+pub const ERR115_INVALID_SPLIT: &str =
+    "E115: Split recipients must be 1 to MAX_SPLIT_RECIPIENTS different accounts with positive basis points summing to 10000";
+pub const ERR116_INVALID_STAKING_CONFIG: &str =
+    "E116: Staked part must be at most MAX_STAKED_BPS and the staking pool can be changed only when no NEAR is staked";
+pub const ERR117_STAKING_NOT_SET: &str = "E117: Staking pool is not set";
+pub const ERR118_NOT_ENOUGH_LIQUID_NEAR: &str =
+    "E118: Not enough liquid NEAR in the pool, the staked NEAR must be unstaked first";
+pub const ERR119_INVALID_LENDING_CONFIG: &str =
+    "E119: Lent part must be at most MAX_LENT_BPS and the lending market can be changed only when no tokens are lent";
+pub const ERR120_LENDING_NOT_SET: &str = "E120: Lending market is not set";
+pub const ERR121_NOT_ENOUGH_LIQUID_TOKENS: &str =
+    "E121: Not enough liquid tokens in the pool, the lent tokens must be withdrawn first";
+pub const ERR122_NO_RATE_PROVIDER: &str = "E122: Pool doesn't have a rate provider";
+pub const ERR123_NOT_MIGRATION_SOURCE: &str = "E123: Token is not an approved migration source";
+pub const ERR124_INVALID_MIGRATE_MSG: &str = "E124: Invalid migrate_in minimum shares";
+pub const ERR125_INVALID_DYNAMIC_FEE: &str = "E125: Invalid dynamic fee configuration";
+pub const ERR126_TRADE_TOO_LARGE: &str =
+    "E126: Swap exceeds the pool maximum trade size, use a TWAMM order or an escrowed swap";
+pub const ERR127_INVALID_MAX_TRADE: &str = "E127: Invalid pool maximum trade size";
+pub const ERR128_INVALID_SLIPPAGE: &str = "E128: Invalid default max slippage";
+pub const ERR129_DEADLINE_EXPIRED: &str = "E129: Transaction deadline expired";
+pub const ERR130_INVALID_PATH: &str = "E130: Invalid swap path, use find_route";
+pub const ERR131_NOT_AMM_ADAPTER: &str = "E131: Account is not a registered AMM adapter";
+pub const ERR132_INVALID_ADAPTER_RETURN: &str =
+    "E132: Transfer doesn't match an external swap waiting for the bought tokens";
+pub const ERR133_UNKNOWN_POOL_ID: &str = "E133: Pool id not found";
+pub const ERR134_INVALID_TWAP_WINDOW: &str =
+    "E134: TWAP window must be between MIN_TWAP_WINDOW and MAX_TWAP_WINDOW seconds";
+pub const ERR135_SHARES_AFTER_PROPOSAL: &str =
+    "E135: Shares acquired after the proposal creation can't vote on it";
+pub const ERR136_INVALID_GOV_STAKING: &str =
+    "E136: Fee share must be at most 10000 basis points and the governance token can be changed only when nothing is staked";
+pub const ERR137_GOV_STAKING_NOT_SET: &str = "E137: Governance token is not set";
+pub const ERR138_NOT_ENOUGH_GOV_STAKE: &str = "E138: Not enough staked governance tokens";
+pub const ERR139_FEE_CONVERSION_SLIPPAGE: &str =
+    "E139: Fee conversion price is too far from the pool TWAP or the pool oracle has no observations";
+pub const ERR140_INVALID_POOL_TEMPLATE: &str = "E140: Invalid pool template";
+pub const ERR141_UNKNOWN_POOL_TEMPLATE: &str = "E141: Pool template doesn't exist";
+pub const ERR142_INVALID_LAUNCH: &str =
+    "E142: Launch must sell a positive amount with 0 < start_price <= end_price and seed_bps <= 10000";
+pub const ERR143_NO_LAUNCH: &str = "E143: Bonding curve launch doesn't exist";
+pub const ERR144_LAUNCH_COST: &str = "E144: Launch purchase cost exceeds the maximum";
+pub const ERR145_TOKEN_IN_LAUNCH: &str = "E145: Token has a bonding curve launch in progress";
+pub const ERR146_CANNOT_CANCEL_LAUNCH: &str =
+    "E146: Only the launch creator can cancel an opened launch, before the first purchase";
+pub const ERR147_NOT_LAUNCH_CREATOR: &str =
+    "E147: Only the token contract or the owner can launch a token";
+pub const ERR148_LAUNCH_NOT_ACTIVE: &str =
+    "E148: Launch is waiting for the token NEP-141 probes";
+pub const ERR149_NO_TWAP: &str = "E149: Pool oracle has no observations";
+pub const ERR150_OVER_LIQUID_NEAR: &str =
+    "E150: Payout exceeds the pool liquid NEAR, the staked NEAR is paid out after `rebalance_stake` unstakes it";
+pub const ERR151_OVER_LIQUID_TOKENS: &str =
+    "E151: Payout exceeds the pool liquid tokens, the lent tokens are paid out after `rebalance_lending` withdraws them";
@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  TWO-PHASE (ESCROWED)
+  SWAPS
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance, BlockHeight};
+
+use crate::constants::*;
+use crate::*;
+
+/// A swap escrowed by `request_swap`. Assets are identified by the token account,
+/// `None` denotes NEAR.
+/// The escrowed amount is swapped in `parts` chunks, each one executed in a different block
+/// and not earlier than `next_block`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+pub struct SwapRequest {
+    pub account: AccountId,
+    pub token_in: Option<AccountId>,
+    pub token_out: Option<AccountId>,
+    /// amount of `token_in` still held in escrow.
+    pub amount_in: U128,
+    /// minimum amount of `token_out` expected for the remaining `amount_in`.
+    pub min_out: U128,
+    /// number of chunks left to execute.
+    pub parts_left: u8,
+    /// first block height at which the next chunk can be executed.
+    pub next_block: BlockHeight,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Escrows `amount_in` of `token_in` from the caller deposit for a swap to `token_out`.
+    `None` token denotes NEAR. The swap is executed by the caller with `execute_swap`, not
+    earlier than `SWAP_ESCROW_DELAY` blocks from now, in `parts` chunks executed in different
+    blocks. Each chunk must buy at least its pro rata part of `min_out`.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the swap request id. */
+    #[payable]
+    pub fn request_swap(
+        &mut self,
+        token_in: Option<AccountId>,
+        amount_in: U128,
+        token_out: Option<AccountId>,
+        min_out: U128,
+        parts: u8,
+    ) -> u64 {
+        let _m = MethodMetrics::start("request_swap");
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let amount_in: Balance = amount_in.into();
+        assert!(amount_in > 0 && min_out.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        assert!(
+            parts > 0 && parts <= MAX_SWAP_PARTS && amount_in >= parts as u128,
+            "{}",
+            ERR30_INVALID_SWAP_PARTS
+        );
+        assert!(
+            token_in != token_out && (token_in.is_some() || token_out.is_some()),
+            "{}",
+            ERR28_INVALID_SWAP_ASSETS
+        );
+        for t in token_in.iter().chain(token_out.iter()) {
+            assert!(self.pools.get(t).is_some(), "Pool for this token doesn't exist");
+        }
+
+        let user = env::predecessor_account_id();
+        let mut d = self.get_deposit(&user);
+        match &token_in {
+            Some(t) => d.remove(t, amount_in),
+            None => d.remove_near(amount_in),
+        }
+        self.deposits.insert(&user, &d.into());
+
+        let id = self.next_swap_request;
+        self.next_swap_request += 1;
+        self.swap_requests.insert(
+            &id,
+            &SwapRequest {
+                account: user,
+                token_in,
+                token_out,
+                amount_in: amount_in.into(),
+                min_out,
+                parts_left: parts,
+                next_block: env::block_index() + SWAP_ESCROW_DELAY,
+            },
+        );
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Swap request {}, escrowed {} in {} parts",
+            id,
+            amount_in,
+            parts
+        );
+        id
+    }
+
+    /**
+    Executes the next chunk of the `id` swap request and credits the bought amount to the
+    caller deposit. The request is removed once all chunks are executed.
+    Panics if the caller is not the requester, the chunk can't be executed in the current block
+    or the chunk buys less than its part of `min_out`.
+    Returns the amount bought by this chunk. */
+    #[payable]
+    pub fn execute_swap(&mut self, id: u64) -> U128 {
+        let _m = MethodMetrics::start("execute_swap");
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let mut r = self.get_swap_request(&id, &user);
+        assert!(env::block_index() >= r.next_block, "{}", ERR29_SWAP_NOT_READY);
+
+        let amount_in: Balance = r.amount_in.into();
+        let chunk = amount_in / r.parts_left as u128;
+        let chunk_min = (u256::from(r.min_out.0) * u256::from(chunk) / u256::from(amount_in))
+            .as_u128();
+
+        // move the chunk back to the deposit, so it's swapped as a regular deposit swap.
+        let mut d = self.get_deposit(&user);
+        d.add_asset(&r.token_in, chunk);
+        self.deposits.insert(&user, &d.into());
+        let out = self.internal_swap(&user, &r.token_in, &r.token_out, chunk, chunk_min);
+
+        r.parts_left -= 1;
+        if r.parts_left == 0 {
+            self.swap_requests.remove(&id);
+        } else {
+            r.amount_in = (amount_in - chunk).into();
+            r.min_out = (r.min_out.0 - chunk_min).into();
+            r.next_block = env::block_index() + 1;
+            self.swap_requests.insert(&id, &r);
+        }
+        self.unsafe_storage_check(start_storage);
+        event_log!(self, "Swap request {}, swapped {} for {}", id, chunk, out);
+        out.into()
+    }
+
+    /// Cancels the `id` swap request and moves the remaining escrowed amount back to the
+    /// caller deposit. Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    /// Returns the refunded amount.
+    #[payable]
+    pub fn cancel_swap(&mut self, id: u64) -> U128 {
+        let _m = MethodMetrics::start("cancel_swap");
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let r = self.get_swap_request(&id, &user);
+        self.swap_requests.remove(&id);
+
+        let mut d = self.get_deposit(&user);
+        match &r.token_in {
+            Some(t) => d.add(t, r.amount_in.0),
+            None => d.add_near(r.amount_in.0),
+        }
+        d.update_storage(start_storage);
+        self.deposits.insert(&user, &d.into());
+        event_log!(
+            self,
+            "Swap request {} cancelled, refunded {}",
+            id,
+            r.amount_in.0
+        );
+        r.amount_in
+    }
+
+    /// Returns the `id` swap request, if it exists.
+    pub fn swap_request(&self, id: u64) -> Option<SwapRequest> {
+        self.swap_requests.get(&id)
+    }
+}
+
+impl NearSwap {
+    /// Returns the `id` swap request. Panics if it doesn't exist or it doesn't belong to `user`.
+    fn get_swap_request(&self, id: &u64, user: &AccountId) -> SwapRequest {
+        let r = self.swap_requests.get(id).expect(ERR31_SWAP_REQUEST_NOT_FOUND);
+        assert!(&r.account == user, "{}", ERR31_SWAP_REQUEST_NOT_FOUND);
+        r
+    }
+}
@@ -0,0 +1,487 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  LIQUIDITY FARMING
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance};
+use std::collections::HashMap;
+
+use crate::accumulator::RewardAcc;
+use crate::constants::*;
+use crate::*;
+
+/// Pool farm: LP shares staked with `farm_stake` earn the rewards of the pool campaigns.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Farm {
+    /// reward streams, one per reward token. A stream keeps its accumulator across
+    /// campaigns, so stakes never miss rewards of a finished campaign.
+    pub streams: Vec<RewardStream>,
+    pub total_staked: Balance,
+    pub stakes: LookupMap<AccountId, FarmStake>,
+}
+
+/// Reward token stream of a farm, funded by reward campaigns.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct RewardStream {
+    pub token: AccountId,
+    /// account which funded the last campaign. Gets back the undistributed rewards.
+    pub funder: AccountId,
+    /// rewards per second.
+    pub rate: Balance,
+    /// campaign end timestamp, in nanoseconds.
+    pub end: u64,
+    /// campaign rewards not distributed yet.
+    pub remaining: Balance,
+    /// accumulated rewards per staked share.
+    acc: RewardAcc,
+    /// timestamp of the last accumulator update, in nanoseconds.
+    last_update: u64,
+}
+
+/// LP shares staked in a farm.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct FarmStake {
+    pub shares: Balance,
+    /// timestamp of the last `farm_stake`, in nanoseconds. Starts the minimum staking period.
+    pub staked_at: u64,
+    /// stream accumulator checkpoints at the last stake update, by reward token.
+    acc: HashMap<AccountId, Balance>,
+    /// accrued rewards, by reward token, credited to the deposit with `claim_rewards`.
+    pub rewards: HashMap<AccountId, Balance>,
+}
+
+/// Early unstake policy of all farms, see `set_farm_unstake_policy`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub struct FarmUnstakePolicy {
+    /// minimum staking period, in nanoseconds.
+    pub min_period: U64,
+    /// part of the accrued rewards forfeited when unstaking within the minimum period,
+    /// in basis points.
+    pub penalty_bps: u16,
+}
+
+/// Public information of a farm reward campaign.
+#[derive(Serialize, Deserialize)]
+pub struct CampaignInfo {
+    pub reward_token: AccountId,
+    pub funder: AccountId,
+    /// rewards per second.
+    pub rate: U128,
+    /// end timestamp, in nanoseconds.
+    pub end: U64,
+    /// rewards not distributed yet.
+    pub remaining: U128,
+}
+
+/// Public information of a pool farm.
+#[derive(Serialize, Deserialize)]
+pub struct FarmInfo {
+    pub total_staked: U128,
+    pub campaigns: Vec<CampaignInfo>,
+}
+
+/// Public information of a farm stake.
+#[derive(Serialize, Deserialize)]
+pub struct FarmStakeInfo {
+    pub shares: U128,
+    /// end of the minimum staking period, in nanoseconds.
+    pub unlocks_at: U64,
+    /// accrued rewards, by reward token.
+    pub rewards: Vec<(AccountId, U128)>,
+}
+
+impl Farm {
+    pub fn new(pool_id: &AccountId) -> Self {
+        Self {
+            streams: Vec::new(),
+            total_staked: 0,
+            stakes: LookupMap::new(StorageKey::FarmStakes {
+                pool: pool_hash(pool_id),
+            }),
+        }
+    }
+
+    /// Distributes the rewards of all streams up to `now`.
+    pub fn update(&mut self, now: u64) {
+        for s in self.streams.iter_mut() {
+            s.update(now, self.total_staked);
+        }
+    }
+
+    /// Moves the stake rewards accrued since its last update to `st.rewards`.
+    /// `update` must be called before.
+    pub fn settle(&self, st: &mut FarmStake) {
+        for s in self.streams.iter() {
+            let checkpoint = st.acc.entry(s.token.clone()).or_insert(0);
+            let r = s.acc.settle(st.shares, checkpoint);
+            if r > 0 {
+                *st.rewards.entry(s.token.clone()).or_insert(0) += r;
+            }
+        }
+    }
+
+    /// Takes `penalty_bps` of the stake accrued rewards and distributes it to the other farm
+    /// stakers. Without them, the penalty goes back to the stream remaining rewards.
+    /// Returns the forfeited amounts by reward token.
+    pub fn forfeit(
+        &mut self,
+        st: &mut FarmStake,
+        penalty_bps: u16,
+    ) -> Vec<(AccountId, Balance)> {
+        let mut forfeited = Vec::new();
+        let others = self.total_staked - st.shares;
+        for s in self.streams.iter_mut() {
+            let r = match st.rewards.get_mut(&s.token) {
+                Some(r) => r,
+                None => continue,
+            };
+            let penalty = clp_math::mul_div_floor(*r, penalty_bps as u128, 10_000);
+            if penalty == 0 {
+                continue;
+            }
+            *r -= penalty;
+            if !s.acc.distribute(penalty, others) {
+                s.remaining += penalty;
+            }
+            forfeited.push((s.token.clone(), penalty));
+        }
+        // the penalty is distributed to the other stakers only.
+        self.settle_checkpoints(st);
+        forfeited
+    }
+
+    /// Moves the stake checkpoints to the current stream accumulators, without accruing.
+    fn settle_checkpoints(&self, st: &mut FarmStake) {
+        for s in self.streams.iter() {
+            st.acc.insert(s.token.clone(), s.acc.acc_reward_per_share);
+        }
+    }
+}
+
+impl RewardStream {
+    /// Distributes the campaign rewards accrued up to `now` to `total_staked` shares.
+    /// Rewards of periods without stakers stay in `remaining`.
+    fn update(&mut self, now: u64, total_staked: Balance) {
+        let t = now.min(self.end);
+        if t <= self.last_update {
+            return;
+        }
+        let reward = clp_math::mul_div_floor(
+            self.rate,
+            (t - self.last_update) as u128,
+            1_000_000_000,
+        )
+        .min(self.remaining);
+        self.last_update = t;
+        if self.acc.distribute(reward, total_staked) {
+            self.remaining -= reward;
+        }
+    }
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Starts a reward campaign on the `token` pool farm: `rate` of `reward_token` per second,
+    paid to the farm stakers pro rata to their staked shares for `duration` seconds.
+    The whole `rate * duration` is taken from the caller deposit and escrowed by the farm.
+    Anyone (typically the token project) can fund a campaign. A farm runs concurrent
+    campaigns of different reward tokens, each with its own rate and end, one campaign per
+    reward token at a time. A farm pays at most `MAX_FARM_REWARD_TOKENS` different reward
+    tokens over its life.
+    Rewards of periods without stakers are returned with `close_reward_campaign`.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation. */
+    #[payable]
+    pub fn create_reward_campaign(
+        &mut self,
+        token: AccountId,
+        reward_token: AccountId,
+        rate: U128,
+        duration: U64,
+    ) {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let (rate, duration) = (rate.0, duration.0);
+        assert!(rate > 0 && duration > 0, "{}", ERR02_POSITIVE_ARGS);
+        assert!(self.pools.get(&token).is_some(), "Pool for this token doesn't exist");
+        let now = env::block_timestamp();
+        let mut f = self.get_farm(&token);
+        f.update(now);
+
+        let funder = env::predecessor_account_id();
+        self.assert_campaign_funder(&token, &funder);
+        let total = rate * duration as u128;
+        let mut d = self.get_deposit(&funder);
+        d.remove(&reward_token, total);
+        self.deposits.insert(&funder, &d.into());
+
+        let end = now + duration * 1_000_000_000;
+        match f.streams.iter_mut().find(|s| s.token == reward_token) {
+            Some(s) => {
+                assert!(s.end <= now, "{}", ERR52_CAMPAIGN_ACTIVE);
+                assert!(s.remaining == 0, "{}", ERR54_CAMPAIGN_NOT_CLOSED);
+                s.funder = funder.clone();
+                s.rate = rate;
+                s.end = end;
+                s.remaining = total;
+                s.last_update = now;
+            }
+            None => {
+                assert!(
+                    f.streams.len() < MAX_FARM_REWARD_TOKENS,
+                    "{}",
+                    ERR53_TOO_MANY_REWARD_TOKENS
+                );
+                f.streams.push(RewardStream {
+                    token: reward_token.clone(),
+                    funder: funder.clone(),
+                    rate,
+                    end,
+                    remaining: total,
+                    acc: RewardAcc::default(),
+                    last_update: now,
+                });
+            }
+        }
+        self.farms.insert(&token, &f);
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Reward campaign on {} farm: {} {} per second until {}",
+            token,
+            rate,
+            reward_token,
+            end
+        );
+    }
+
+    /**
+    Closes the caller finished `reward_token` campaign on the `token` pool farm: the
+    undistributed rewards are credited back to the caller deposit.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the returned amount. */
+    #[payable]
+    pub fn close_reward_campaign(&mut self, token: AccountId, reward_token: AccountId) -> U128 {
+        assert_one_yocto();
+        let now = env::block_timestamp();
+        let mut f = self.get_farm(&token);
+        f.update(now);
+        let user = env::predecessor_account_id();
+        let s = f
+            .streams
+            .iter_mut()
+            .find(|s| s.token == reward_token)
+            .expect(ERR55_CAMPAIGN_NOT_FOUND);
+        assert!(s.funder == user, "{}", ERR55_CAMPAIGN_NOT_FOUND);
+        assert!(s.end <= now, "{}", ERR52_CAMPAIGN_ACTIVE);
+        let amount = s.remaining;
+        s.remaining = 0;
+        self.farms.insert(&token, &f);
+
+        let mut d = self.get_deposit(&user);
+        d.add(&reward_token, amount);
+        self.deposits.insert(&user, &d.into());
+        event_log!(
+            self,
+            "Reward campaign on {} farm closed, returned {} {}",
+            token,
+            amount,
+            reward_token
+        );
+        amount.into()
+    }
+
+    /**
+    Stakes `shares` of the caller `token` pool LP shares in the pool farm. Staked shares
+    can't be withdrawn nor transferred and earn the farm campaign rewards.
+    A stake must hold at least `MIN_FARM_STAKE` shares. Staking (re)starts the minimum
+    staking period of the whole stake, see `farm_unstake_policy`.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation. */
+    #[payable]
+    pub fn farm_stake(&mut self, token: AccountId, shares: U128) {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let shares: Balance = shares.into();
+        assert!(shares > 0, "{}", ERR02_POSITIVE_ARGS);
+        let user = env::predecessor_account_id();
+        let mut p = self.get_pool(&token);
+        let owned = p.shares.get(&user).unwrap_or(0);
+        assert!(
+            owned >= shares,
+            "{} (owned {}, requested {})",
+            ERR11_NOT_ENOUGH_SHARES,
+            owned,
+            shares
+        );
+        p.set_shares(&user, owned - shares);
+
+        let now = env::block_timestamp();
+        let mut f = self.get_farm(&token);
+        f.update(now);
+        let mut st = f.stakes.get(&user).unwrap_or_else(|| FarmStake {
+            shares: 0,
+            staked_at: now,
+            acc: HashMap::new(),
+            rewards: HashMap::new(),
+        });
+        f.settle(&mut st);
+        st.shares += shares;
+        st.staked_at = now;
+        assert!(st.shares >= MIN_FARM_STAKE, "{}", ERR56_FARM_STAKE_TOO_SMALL);
+        f.total_staked += shares;
+        f.stakes.insert(&user, &st);
+        self.farms.insert(&token, &f);
+        self.set_pool(&token, &p);
+        self.unsafe_storage_check(start_storage);
+        event_log!(self, "Staked {} {} shares", shares, token);
+    }
+
+    /**
+    Moves `shares` of the caller `token` farm stake back to the caller LP shares. The
+    remaining stake must be zero or at least `MIN_FARM_STAKE`. Accrued rewards stay in the
+    stake until `claim_rewards`.
+    Unstaking within the minimum staking period forfeits the policy penalty part of the
+    accrued rewards, which is distributed to the remaining farm stakers.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation. */
+    #[payable]
+    pub fn farm_unstake(&mut self, token: AccountId, shares: U128) {
+        assert_one_yocto();
+        let shares: Balance = shares.into();
+        assert!(shares > 0, "{}", ERR02_POSITIVE_ARGS);
+        let user = env::predecessor_account_id();
+        let mut f = self.get_farm(&token);
+        let mut st = f.stakes.get(&user).expect(ERR57_FARM_STAKE_NOT_FOUND);
+        assert!(
+            st.shares >= shares,
+            "{} (staked {}, requested {})",
+            ERR11_NOT_ENOUGH_SHARES,
+            st.shares,
+            shares
+        );
+        let now = env::block_timestamp();
+        f.update(now);
+        f.settle(&mut st);
+        st.shares -= shares;
+        assert!(
+            st.shares == 0 || st.shares >= MIN_FARM_STAKE,
+            "{}",
+            ERR56_FARM_STAKE_TOO_SMALL
+        );
+        f.total_staked -= shares;
+        let penalty_bps = self.farm_unstake_policy.penalty_bps;
+        if now < st.staked_at + self.farm_unstake_policy.min_period.0 && penalty_bps > 0 {
+            for (reward_token, penalty) in f.forfeit(&mut st, penalty_bps) {
+                event_log!(self, "Early unstake penalty: {} {}", penalty, reward_token);
+            }
+        }
+        f.stakes.insert(&user, &st);
+        self.farms.insert(&token, &f);
+
+        let mut p = self.get_pool(&token);
+        let owned = p.shares.get(&user).unwrap_or(0);
+        p.set_shares(&user, owned + shares);
+        self.set_pool(&token, &p);
+        event_log!(self, "Unstaked {} {} shares", shares, token);
+    }
+
+    /**
+    Credits all rewards accrued by the caller `token` farm stake to the caller deposit.
+    An empty stake is removed. A non empty stake can't claim within the minimum staking
+    period.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the claimed amounts by reward token. */
+    #[payable]
+    pub fn claim_rewards(&mut self, token: AccountId) -> Vec<(AccountId, U128)> {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let mut f = self.get_farm(&token);
+        let mut st = f.stakes.get(&user).expect(ERR57_FARM_STAKE_NOT_FOUND);
+        let now = env::block_timestamp();
+        assert!(
+            st.shares == 0 || now >= st.staked_at + self.farm_unstake_policy.min_period.0,
+            "{}",
+            ERR58_FARM_STAKE_PERIOD
+        );
+        f.update(now);
+        f.settle(&mut st);
+        let rewards: Vec<(AccountId, Balance)> =
+            st.rewards.drain().filter(|(_, r)| *r > 0).collect();
+        if st.shares == 0 {
+            f.stakes.remove(&user);
+        } else {
+            f.stakes.insert(&user, &st);
+        }
+        self.farms.insert(&token, &f);
+
+        let mut d = self.get_deposit(&user);
+        for (reward_token, r) in rewards.iter() {
+            d.add(reward_token, *r);
+            event_log!(self, "Farm reward claimed: {} {}", r, reward_token);
+        }
+        d.update_storage(start_storage);
+        self.deposits.insert(&user, &d.into());
+        rewards.into_iter().map(|(t, r)| (t, r.into())).collect()
+    }
+
+    /// Owner: sets the minimum staking period (in nanoseconds) and the part of the accrued
+    /// rewards (in basis points) forfeited by unstaking earlier. Applies to all farms and
+    /// existing stakes.
+    pub fn set_farm_unstake_policy(&mut self, policy: FarmUnstakePolicy) {
+        self.assert_owner();
+        assert!(policy.penalty_bps <= 10_000, "{}", ERR59_INVALID_PENALTY);
+        self.log_admin_action("set_farm_unstake_policy", &policy);
+        self.farm_unstake_policy = policy;
+    }
+
+    pub fn farm_unstake_policy(&self) -> FarmUnstakePolicy {
+        self.farm_unstake_policy.clone()
+    }
+
+    /// Returns the `token` pool farm total stake and reward campaigns.
+    pub fn farm_info(&self, token: AccountId) -> FarmInfo {
+        let mut f = self.get_farm(&token);
+        f.update(env::block_timestamp());
+        FarmInfo {
+            total_staked: f.total_staked.into(),
+            campaigns: f
+                .streams
+                .iter()
+                .map(|s| CampaignInfo {
+                    reward_token: s.token.clone(),
+                    funder: s.funder.clone(),
+                    rate: s.rate.into(),
+                    end: s.end.into(),
+                    remaining: s.remaining.into(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the `account` stake in the `token` pool farm with the rewards accrued so far.
+    pub fn farm_stake_info(&self, token: AccountId, account: AccountId) -> Option<FarmStakeInfo> {
+        let mut f = self.get_farm(&token);
+        f.update(env::block_timestamp());
+        f.stakes.get(&account).map(|mut st| {
+            f.settle(&mut st);
+            FarmStakeInfo {
+                shares: st.shares.into(),
+                unlocks_at: (st.staked_at + self.farm_unstake_policy.min_period.0).into(),
+                rewards: st.rewards.into_iter().map(|(t, r)| (t, r.into())).collect(),
+            }
+        })
+    }
+}
+
+impl NearSwap {
+    /// Returns the `token` pool farm, an empty one if it wasn't used yet.
+    pub(crate) fn get_farm(&self, token: &AccountId) -> Farm {
+        self.farms.get(token).unwrap_or_else(|| Farm::new(token))
+    }
+}
@@ -1,9 +1,33 @@
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
 use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::{ext_contract, Gas};
 
+use crate::util::TGAS;
+
 /// Amount of gas for fungible token transfers.
-pub const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
+pub const GAS_FOR_FT_TRANSFER: Gas = 10 * TGAS;
+
+/// Amount of gas for fungible token transfers with a receiver call (`ft_transfer_call`).
+/// Covers the receiver `ft_on_transfer` and the token `ft_resolve_transfer`.
+pub const GAS_FOR_FT_TRANSFER_CALL: Gas = 35 * TGAS;
+
+/// Amount of gas for the `ft_balance_of` view call.
+pub const GAS_FOR_FT_BALANCE_OF: Gas = 5 * TGAS;
+
+/// Amount of gas for the `ft_metadata` and `ft_total_supply` view calls probing a new pool
+/// token.
+pub const GAS_FOR_FT_METADATA: Gas = 5 * TGAS;
+
+/// Amount of gas for the `create_pool` callback, which creates the pool.
+pub const GAS_FOR_RESOLVE_CREATE_POOL: Gas = 20 * TGAS;
 
+/// Amount of gas for the token `storage_deposit` call.
+pub const GAS_FOR_STORAGE_DEPOSIT: Gas = 10 * TGAS;
+
+/// Amount of gas for the withdraw resolve callbacks.
+pub const GAS_FOR_RESOLVE_WITHDRAW: Gas = 10 * TGAS;
+
+/// NEP-141 methods called by the contract. Implemented for tests by the `mock-token` crate.
 #[ext_contract(ext_fungible_token)]
 pub trait FungibleToken {
     fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
@@ -19,6 +43,12 @@ pub trait FungibleToken {
     /// Returns the total supply of the token in a decimal string representation.
     fn ft_total_supply(&self) -> U128;
 
+    /// NEP-148: returns the token metadata.
+    fn ft_metadata(&self) -> FungibleTokenMetadata;
+
     /// Returns the balance of the account. If the account doesn't exist must returns `"0"`.
     fn ft_balance_of(&self, account_id: ValidAccountId) -> U128;
+
+    /// NEP-145: registers `account_id` storage, paid with the attached deposit.
+    fn storage_deposit(&mut self, account_id: Option<ValidAccountId>, registration_only: Option<bool>);
 }
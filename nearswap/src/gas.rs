@@ -0,0 +1,271 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  CROSS-CONTRACT GAS
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U64;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, Gas};
+
+use crate::adapters::*;
+use crate::croncat::*;
+use crate::ft_token::*;
+use crate::lending::*;
+use crate::migration::*;
+use crate::rate_provider::*;
+use crate::reserves::*;
+use crate::staking::*;
+use crate::*;
+
+/// Amount of gas for the LP shares receiver `on_mft_receive` call.
+pub const GAS_FOR_MFT_RECEIVE: Gas = 25 * TGAS;
+
+/// Gas kept for finishing the current function call when the unused gas is forwarded to a
+/// promise.
+pub const GAS_RESERVE: Gas = 5 * TGAS;
+
+/// Bounds of the owner gas overrides.
+pub const MIN_GAS_OVERRIDE: Gas = 5 * TGAS;
+pub const MAX_GAS_OVERRIDE: Gas = 250 * TGAS;
+
+/// Default gas used by the contract itself (without the cross-contract calls) per
+/// `GasAction`. Owner recalibrates them with `set_gas_estimate`.
+pub const GAS_EXEC_SWAP: Gas = 20 * TGAS;
+pub const GAS_EXEC_MULTI_HOP_SWAP: Gas = 30 * TGAS;
+pub const GAS_EXEC_LIQUIDITY: Gas = 20 * TGAS;
+pub const GAS_EXEC_WITHDRAW: Gas = 10 * TGAS;
+
+/// Cross-contract calls made by the contract.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GasOp {
+    FtTransfer,
+    FtTransferCall,
+    ResolveWithdraw,
+    MftReceive,
+    CroncatManageTask,
+    ResolveCroncatCreateTask,
+    FtBalanceOf,
+    ResolveVerifyReserves,
+    StorageDeposit,
+    StakingPoolCall,
+    ResolveStaking,
+    LendingCall,
+    ResolveLending,
+    RateProvider,
+    ResolveUpdateRate,
+    MigrationRedeem,
+    ResolveMigrateIn,
+    AdapterSwap,
+    ResolveAdapterSwap,
+    FtMetadata,
+    ResolveCreatePool,
+}
+
+impl GasOp {
+    pub const ALL: [GasOp; 21] = [
+        GasOp::FtTransfer,
+        GasOp::FtTransferCall,
+        GasOp::ResolveWithdraw,
+        GasOp::MftReceive,
+        GasOp::CroncatManageTask,
+        GasOp::ResolveCroncatCreateTask,
+        GasOp::FtBalanceOf,
+        GasOp::ResolveVerifyReserves,
+        GasOp::StorageDeposit,
+        GasOp::StakingPoolCall,
+        GasOp::ResolveStaking,
+        GasOp::LendingCall,
+        GasOp::ResolveLending,
+        GasOp::RateProvider,
+        GasOp::ResolveUpdateRate,
+        GasOp::MigrationRedeem,
+        GasOp::ResolveMigrateIn,
+        GasOp::AdapterSwap,
+        GasOp::ResolveAdapterSwap,
+        GasOp::FtMetadata,
+        GasOp::ResolveCreatePool,
+    ];
+
+    /// Default amount of gas attached to the call.
+    pub fn default_gas(&self) -> Gas {
+        match self {
+            GasOp::FtTransfer => GAS_FOR_FT_TRANSFER,
+            GasOp::FtTransferCall => GAS_FOR_FT_TRANSFER_CALL,
+            GasOp::ResolveWithdraw => GAS_FOR_RESOLVE_WITHDRAW,
+            GasOp::MftReceive => GAS_FOR_MFT_RECEIVE,
+            GasOp::CroncatManageTask => GAS_FOR_CRONCAT_MANAGE_TASK,
+            GasOp::ResolveCroncatCreateTask => GAS_FOR_RESOLVE_CREATE_TASK,
+            GasOp::FtBalanceOf => GAS_FOR_FT_BALANCE_OF,
+            GasOp::ResolveVerifyReserves => GAS_FOR_RESOLVE_VERIFY_RESERVES,
+            GasOp::StorageDeposit => GAS_FOR_STORAGE_DEPOSIT,
+            GasOp::StakingPoolCall => GAS_FOR_STAKING_POOL_CALL,
+            GasOp::ResolveStaking => GAS_FOR_RESOLVE_STAKING,
+            GasOp::LendingCall => GAS_FOR_LENDING_CALL,
+            GasOp::ResolveLending => GAS_FOR_RESOLVE_LENDING,
+            GasOp::RateProvider => GAS_FOR_RATE_PROVIDER,
+            GasOp::ResolveUpdateRate => GAS_FOR_RESOLVE_UPDATE_RATE,
+            GasOp::MigrationRedeem => GAS_FOR_MIGRATION_REDEEM,
+            GasOp::ResolveMigrateIn => GAS_FOR_RESOLVE_MIGRATE_IN,
+            GasOp::AdapterSwap => GAS_FOR_ADAPTER_SWAP,
+            GasOp::ResolveAdapterSwap => GAS_FOR_RESOLVE_ADAPTER_SWAP,
+            GasOp::FtMetadata => GAS_FOR_FT_METADATA,
+            GasOp::ResolveCreatePool => GAS_FOR_RESOLVE_CREATE_POOL,
+        }
+    }
+}
+
+/// User operations estimated by `estimate_gas`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum GasAction {
+    /// NEAR to token or token to NEAR deposit swap.
+    Swap,
+    /// token to token deposit swap, through NEAR.
+    MultiHopSwap,
+    AddLiquidity,
+    WithdrawLiquidity,
+    /// `withdraw_near` with its resolve callback.
+    WithdrawNear,
+    /// `withdraw_token` with `ft_transfer`, its resolve callback and the transfer retry.
+    WithdrawToken,
+    /// `withdraw_token` with `ft_transfer_call` and its resolve callback.
+    WithdrawTokenCall,
+}
+
+impl GasAction {
+    pub const ALL: [GasAction; 7] = [
+        GasAction::Swap,
+        GasAction::MultiHopSwap,
+        GasAction::AddLiquidity,
+        GasAction::WithdrawLiquidity,
+        GasAction::WithdrawNear,
+        GasAction::WithdrawToken,
+        GasAction::WithdrawTokenCall,
+    ];
+
+    /// Default gas used by the contract itself, without the cross-contract calls.
+    pub fn default_exec_gas(&self) -> Gas {
+        match self {
+            GasAction::Swap => GAS_EXEC_SWAP,
+            GasAction::MultiHopSwap => GAS_EXEC_MULTI_HOP_SWAP,
+            GasAction::AddLiquidity | GasAction::WithdrawLiquidity => GAS_EXEC_LIQUIDITY,
+            GasAction::WithdrawNear
+            | GasAction::WithdrawToken
+            | GasAction::WithdrawTokenCall => GAS_EXEC_WITHDRAW,
+        }
+    }
+
+    /// Cross-contract calls made by the action.
+    pub fn calls(&self) -> &'static [GasOp] {
+        match self {
+            GasAction::Swap
+            | GasAction::MultiHopSwap
+            | GasAction::AddLiquidity
+            | GasAction::WithdrawLiquidity => &[],
+            GasAction::WithdrawNear => &[GasOp::ResolveWithdraw],
+            GasAction::WithdrawToken => &[
+                GasOp::FtTransfer,
+                GasOp::ResolveWithdraw,
+                GasOp::StorageDeposit,
+                GasOp::FtTransfer,
+                GasOp::ResolveWithdraw,
+            ],
+            GasAction::WithdrawTokenCall => &[GasOp::FtTransferCall, GasOp::ResolveWithdraw],
+        }
+    }
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: overrides the gas attached to the `op` calls, eg when a token needs more gas
+    /// than expected. `None` restores the default.
+    pub fn set_gas_override(&mut self, op: GasOp, gas: Option<U64>) {
+        self.assert_owner();
+        match gas {
+            Some(g) => {
+                assert!(
+                    g.0 >= MIN_GAS_OVERRIDE && g.0 <= MAX_GAS_OVERRIDE,
+                    "{} (gas={})",
+                    ERR48_INVALID_GAS,
+                    g.0
+                );
+                self.gas_overrides.insert(&op, &g.0);
+            }
+            None => {
+                self.gas_overrides.remove(&op);
+            }
+        }
+        self.log_admin_action("set_gas_override", &(op, gas));
+    }
+
+    /// Returns the gas attached to each cross-contract call.
+    pub fn gas_config(&self) -> Vec<(GasOp, U64)> {
+        GasOp::ALL
+            .iter()
+            .map(|op| (*op, self.gas_for(*op).into()))
+            .collect()
+    }
+
+    /// Owner: recalibrates the gas used by the contract itself for the `action`, see
+    /// `estimate_gas`. `None` restores the default.
+    pub fn set_gas_estimate(&mut self, action: GasAction, gas: Option<U64>) {
+        self.assert_owner();
+        match gas {
+            Some(g) => {
+                assert!(
+                    g.0 >= MIN_GAS_OVERRIDE && g.0 <= MAX_GAS_OVERRIDE,
+                    "{} (gas={})",
+                    ERR48_INVALID_GAS,
+                    g.0
+                );
+                self.gas_estimates.insert(&action, &g.0);
+            }
+            None => {
+                self.gas_estimates.remove(&action);
+            }
+        }
+        self.log_admin_action("set_gas_estimate", &(action, gas));
+    }
+
+    /// Returns the gas to attach to the `action` transaction: the gas used by the contract,
+    /// the gas attached to its cross-contract calls (including the owner overrides) and a
+    /// reserve. Attaching less may fail the action in the middle of its promise chain.
+    pub fn estimate_gas(&self, action: GasAction) -> U64 {
+        let exec = self
+            .gas_estimates
+            .get(&action)
+            .unwrap_or_else(|| action.default_exec_gas());
+        let calls: Gas = action.calls().iter().map(|op| self.gas_for(*op)).sum();
+        (exec + calls + GAS_RESERVE).into()
+    }
+}
+
+impl NearSwap {
+    /// Returns the gas to attach to the `op` call.
+    pub(crate) fn gas_for(&self, op: GasOp) -> Gas {
+        self.gas_overrides
+            .get(&op)
+            .unwrap_or_else(|| op.default_gas())
+    }
+
+    /// Returns the gas needed to retry a failed `ft_transfer`: the recipient registration,
+    /// the transfer and its resolve callback.
+    pub(crate) fn retry_transfer_gas(&self) -> Gas {
+        self.gas_for(GasOp::StorageDeposit)
+            + self.gas_for(GasOp::FtTransfer)
+            + self.gas_for(GasOp::ResolveWithdraw)
+    }
+
+    /// Returns the gas to attach to the `op` call, extended with the unused prepaid gas which
+    /// is not needed for the `reserved` calls and for finishing this call.
+    pub(crate) fn gas_with_unused(&self, op: GasOp, reserved: Gas) -> Gas {
+        let unused = env::prepaid_gas()
+            .saturating_sub(env::used_gas())
+            .saturating_sub(reserved + GAS_RESERVE);
+        self.gas_for(op).max(unused)
+    }
+}
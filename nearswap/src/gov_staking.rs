@@ -0,0 +1,276 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  GOVERNANCE TOKEN STAKING
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance, EpochHeight};
+
+use crate::accumulator::RewardAcc;
+use crate::*;
+
+/// Staking of the protocol governance token for a share of the NEAR protocol fees.
+/// The staker part of the NEAR fees distributed during an epoch is paid, at the first
+/// staking call in the next epoch, to the stake which was active for the whole epoch:
+/// a new stake becomes active at the beginning of the next epoch.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct GovStaking {
+    /// governance token. `None` disables staking.
+    pub token: Option<AccountId>,
+    /// stakers part of the distributed NEAR protocol fees, in basis points.
+    pub fee_share_bps: u16,
+    pub stakes: LookupMap<AccountId, GovStake>,
+    /// sum of the active stakes.
+    pub total_staked: Balance,
+    /// sum of the stakes made in the current epoch, active from the next epoch.
+    pub total_pending: Balance,
+    /// stakers part of the fees distributed in the current epoch, in yNEAR. Also holds the
+    /// fees of the epochs without any active stake.
+    pub epoch_fees: Balance,
+    /// epoch of the last `epoch_fees` payout.
+    pub epoch: EpochHeight,
+    /// yNEAR fees per staked token.
+    pub fee_acc: RewardAcc,
+    /// `fee_acc` value at which the stakes pending in an epoch became active, by epoch.
+    pub activation_acc: LookupMap<EpochHeight, Balance>,
+}
+
+impl GovStaking {
+    pub fn new() -> Self {
+        Self {
+            token: None,
+            fee_share_bps: 0,
+            stakes: LookupMap::new(StorageKey::GovStakes),
+            total_staked: 0,
+            total_pending: 0,
+            epoch_fees: 0,
+            epoch: 0,
+            fee_acc: RewardAcc::default(),
+            activation_acc: LookupMap::new(StorageKey::GovActivationAcc),
+        }
+    }
+
+    /// Pays the fees of the previous epochs to the active stake and activates the pending
+    /// stakes, once per epoch. Must be called before the stakes are read or updated.
+    pub fn advance_epoch(&mut self) {
+        let epoch = env::epoch_height();
+        if self.epoch == epoch {
+            return;
+        }
+        if self.fee_acc.distribute(self.epoch_fees, self.total_staked) {
+            self.epoch_fees = 0;
+        }
+        if self.total_pending > 0 {
+            self.activation_acc
+                .insert(&self.epoch, &self.fee_acc.acc_reward_per_share);
+            self.total_staked += self.total_pending;
+            self.total_pending = 0;
+        }
+        self.epoch = epoch;
+    }
+
+    /// Moves the fees earned by the stake `s` to its claimable fees and activates its
+    /// pending stake, if its epoch is over.
+    pub fn settle(&self, s: &mut GovStake) {
+        s.fees += self.fee_acc.settle(s.staked, &mut s.checkpoint);
+        if s.pending > 0 && s.pending_epoch < self.epoch {
+            let mut checkpoint = self.activation_acc.get(&s.pending_epoch).unwrap();
+            s.fees += self.fee_acc.settle(s.pending, &mut checkpoint);
+            s.staked += s.pending;
+            s.pending = 0;
+        }
+    }
+}
+
+/// Governance token stake of an account.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct GovStake {
+    /// active stake, earning the fees.
+    pub staked: Balance,
+    /// stake made in the `pending_epoch`, active from the next epoch.
+    pub pending: Balance,
+    pub pending_epoch: EpochHeight,
+    /// `fee_acc` value at the last settlement.
+    pub checkpoint: Balance,
+    /// claimable fees, in yNEAR.
+    pub fees: Balance,
+}
+
+/// Governance token staking configuration and totals returned by `gov_staking_info`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct GovStakingInfo {
+    pub token: Option<AccountId>,
+    pub fee_share_bps: u16,
+    pub total_staked: U128,
+    pub total_pending: U128,
+    /// stakers part of the fees waiting for the next epoch payout, in yNEAR.
+    pub epoch_fees: U128,
+}
+
+/// Governance token stake returned by `gov_stake`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct GovStakeInfo {
+    pub staked: U128,
+    /// stake active from the next epoch.
+    pub pending: U128,
+    /// fees claimable with `claim_fee_share`, in yNEAR.
+    pub fees: U128,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: sets the governance `token` and the stakers part of the distributed NEAR
+    /// protocol fees, in basis points, see `distribute_fees`. The token can be changed only
+    /// when nothing is staked.
+    pub fn set_gov_staking(&mut self, token: ValidAccountId, fee_share_bps: u16) {
+        self.assert_owner();
+        let token: AccountId = token.into();
+        let g = &mut self.gov_staking;
+        g.advance_epoch();
+        assert!(
+            fee_share_bps <= 10_000
+                && (g.token.as_ref() == Some(&token)
+                    || g.total_staked + g.total_pending == 0),
+            "{}",
+            ERR136_INVALID_GOV_STAKING
+        );
+        g.token = Some(token.clone());
+        g.fee_share_bps = fee_share_bps;
+        self.log_admin_action("set_gov_staking", &(token, fee_share_bps));
+    }
+
+    pub fn gov_staking_info(&self) -> GovStakingInfo {
+        let g = &self.gov_staking;
+        GovStakingInfo {
+            token: g.token.clone(),
+            fee_share_bps: g.fee_share_bps,
+            total_staked: g.total_staked.into(),
+            total_pending: g.total_pending.into(),
+            epoch_fees: g.epoch_fees.into(),
+        }
+    }
+
+    /**
+    Stakes `amount` of the governance token from the caller deposit. The stake earns its
+    share of the NEAR protocol fees from the next epoch, see `claim_fee_share`.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation. */
+    #[payable]
+    pub fn stake_gov(&mut self, amount: U128) {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let amount: Balance = amount.into();
+        assert!(amount > 0, "{}", ERR02_POSITIVE_ARGS);
+        let token = self.gov_staking.token.clone().expect(ERR137_GOV_STAKING_NOT_SET);
+        let user = env::predecessor_account_id();
+        let mut d = self.get_deposit(&user);
+        d.remove(&token, amount);
+        self.deposits.insert(&user, &d.into());
+
+        let g = &mut self.gov_staking;
+        g.advance_epoch();
+        let mut s = g.stakes.get(&user).unwrap_or_default();
+        g.settle(&mut s);
+        s.pending += amount;
+        s.pending_epoch = g.epoch;
+        g.total_pending += amount;
+        g.stakes.insert(&user, &s);
+        self.unsafe_storage_check(start_storage);
+        event_log!(self, "Staked {} {}", amount, token);
+    }
+
+    /**
+    Moves `amount` of the caller governance token stake back to the caller deposit. The
+    pending stake is unstaked first.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation. */
+    #[payable]
+    pub fn unstake_gov(&mut self, amount: U128) {
+        assert_one_yocto();
+        let amount: Balance = amount.into();
+        assert!(amount > 0, "{}", ERR02_POSITIVE_ARGS);
+        let user = env::predecessor_account_id();
+        let g = &mut self.gov_staking;
+        let token = g.token.clone().expect(ERR137_GOV_STAKING_NOT_SET);
+        g.advance_epoch();
+        let mut s = g.stakes.get(&user).expect(ERR138_NOT_ENOUGH_GOV_STAKE);
+        g.settle(&mut s);
+        assert!(
+            s.staked + s.pending >= amount,
+            "{} (staked {}, requested {})",
+            ERR138_NOT_ENOUGH_GOV_STAKE,
+            s.staked + s.pending,
+            amount
+        );
+        let from_pending = amount.min(s.pending);
+        s.pending -= from_pending;
+        g.total_pending -= from_pending;
+        s.staked -= amount - from_pending;
+        g.total_staked -= amount - from_pending;
+        g.stakes.insert(&user, &s);
+
+        let mut d = self.get_deposit(&user);
+        d.add(&token, amount);
+        self.deposits.insert(&user, &d.into());
+        event_log!(self, "Unstaked {} {}", amount, token);
+    }
+
+    /**
+    Credits the NEAR protocol fees earned by the caller governance token stake to the
+    caller deposit. Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the claimed yNEAR. */
+    #[payable]
+    pub fn claim_fee_share(&mut self) -> U128 {
+        assert_one_yocto();
+        let user = env::predecessor_account_id();
+        let g = &mut self.gov_staking;
+        g.advance_epoch();
+        let mut s = g.stakes.get(&user).unwrap_or_default();
+        g.settle(&mut s);
+        let fees = s.fees;
+        s.fees = 0;
+        if s.staked + s.pending > 0 {
+            g.stakes.insert(&user, &s);
+        } else {
+            g.stakes.remove(&user);
+        }
+
+        let mut d = self.get_deposit(&user);
+        d.add_near(fees);
+        self.deposits.insert(&user, &d.into());
+        event_log!(self, "Fee share claimed: {} yNEAR", fees);
+        fees.into()
+    }
+
+    /// Returns the `account` governance token stake. Fees of the last epoch are included
+    /// once paid, at the first staking call in the epoch.
+    pub fn gov_stake(&self, account: AccountId) -> Option<GovStakeInfo> {
+        let g = &self.gov_staking;
+        g.stakes.get(&account).map(|mut s| {
+            g.settle(&mut s);
+            GovStakeInfo {
+                staked: s.staked.into(),
+                pending: s.pending.into(),
+                fees: s.fees.into(),
+            }
+        })
+    }
+}
+
+impl NearSwap {
+    /// Takes the governance token stakers part of the distributed NEAR protocol `fees`.
+    /// Returns the taken amount.
+    pub(crate) fn take_gov_fee_share(&mut self, fees: Balance) -> Balance {
+        let g = &mut self.gov_staking;
+        if g.token.is_none() || g.fee_share_bps == 0 {
+            return 0;
+        }
+        g.advance_epoch();
+        let share = clp_math::mul_div_floor(fees, g.fee_share_bps as u128, 10_000);
+        g.epoch_fees += share;
+        share
+    }
+}
@@ -0,0 +1,348 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  POOL FEE VOTING
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance};
+
+use crate::constants::*;
+use crate::timelock::GovAction;
+use crate::*;
+
+/// Status of a pool fee proposal.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalStatus {
+    /// open for votes, or waiting for `finalize_fee_proposal` after the voting.
+    Voting,
+    /// passed, waiting in the timelock queue, see `queued_actions`.
+    Queued,
+    /// executed.
+    Applied,
+    /// didn't pass: no majority, no quorum or the fee is outside of the current bounds.
+    Rejected,
+    /// cancelled in the timelock queue by the guardian or the council.
+    Cancelled,
+}
+
+/// Proposal of a new `token` pool fee, voted by the pool LPs with `vote_pool_fee`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+pub struct FeeProposal {
+    pub token: AccountId,
+    pub proposer: AccountId,
+    pub fee_bps: u16,
+    /// end of the voting, in nanoseconds.
+    pub voting_end: U64,
+    /// LP shares voting for and against the proposal.
+    pub yes: U128,
+    pub no: U128,
+    pub status: ProposalStatus,
+    /// id of the queued action, once the proposal passed.
+    pub action_id: Option<u64>,
+}
+
+/// Vote on a fee proposal, with the LP shares it escrowed. Votes counted through a
+/// delegation don't escrow shares.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct FeeVote {
+    pub support: bool,
+    pub shares: Balance,
+}
+
+/// Range of the pool fees LPs can vote for, set by the owner.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub struct FeeBounds {
+    pub min_bps: u16,
+    pub max_bps: u16,
+}
+
+impl FeeBounds {
+    pub fn contains(&self, fee_bps: u16) -> bool {
+        self.min_bps <= fee_bps && fee_bps <= self.max_bps
+    }
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: sets the range of the pool fees LPs can vote for. Doesn't change the current
+    /// pool fees.
+    pub fn set_pool_fee_bounds(&mut self, bounds: FeeBounds) {
+        self.assert_owner();
+        assert!(
+            bounds.min_bps <= bounds.max_bps && bounds.max_bps <= 10_000,
+            "{}",
+            ERR60_INVALID_FEE_BOUNDS
+        );
+        self.log_admin_action("set_pool_fee_bounds", &bounds);
+        self.pool_fee_bounds = bounds;
+    }
+
+    pub fn pool_fee_bounds(&self) -> FeeBounds {
+        self.pool_fee_bounds.clone()
+    }
+
+    /// Returns the `token` pool swap fee, in basis points.
+    pub fn pool_fee(&self, token: AccountId) -> u16 {
+        self.get_pool(&token).fee_bps
+    }
+
+    /**
+    Proposes a new `token` pool fee, in basis points, within the `pool_fee_bounds`. Only
+    a pool LP can propose and a pool has at most one proposal in progress.
+    Pool LPs vote with `vote_pool_fee` during `FEE_VOTING_PERIOD`. A passed proposal is
+    queued with `finalize_fee_proposal` and executed after the `GOV_TIMELOCK` delay.
+    Returns the proposal id. */
+    pub fn propose_pool_fee(&mut self, token: AccountId, fee_bps: u16) -> u64 {
+        let start_storage = env::storage_usage();
+        assert!(
+            self.pool_fee_bounds.contains(fee_bps),
+            "{}",
+            ERR61_FEE_OUT_OF_BOUNDS
+        );
+        let user = env::predecessor_account_id();
+        let mut p = self.get_pool(&token);
+        assert!(
+            p.shares.get(&user).unwrap_or(0) > 0,
+            "{}",
+            ERR11_NOT_ENOUGH_SHARES
+        );
+        assert!(
+            self.pool_fee_proposals.get(&token).is_none(),
+            "{}",
+            ERR62_FEE_PROPOSAL_IN_PROGRESS
+        );
+
+        let id = self.next_proposal;
+        self.next_proposal += 1;
+        let voting_end = env::block_timestamp() + FEE_VOTING_PERIOD;
+        self.fee_proposals.insert(
+            &id,
+            &FeeProposal {
+                token: token.clone(),
+                proposer: user,
+                fee_bps,
+                voting_end: voting_end.into(),
+                yes: 0.into(),
+                no: 0.into(),
+                status: ProposalStatus::Voting,
+                action_id: None,
+            },
+        );
+        self.pool_fee_proposals.insert(&token, &id);
+        p.vote_checkpoint = Some((id, voting_end));
+        self.set_pool(&token, &p);
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Fee proposal {}: {} pool fee {} bps, voting until {}",
+            id,
+            token,
+            fee_bps,
+            voting_end
+        );
+        id
+    }
+
+    /**
+    Votes on the fee proposal with `shares` of the caller LP shares of the proposal pool,
+    the caller liquidity lock shares and the voting power delegated to the caller (see
+    `delegate_votes`) of delegators who didn't vote yet. Locks count only if they expire
+    after the voting. Shares acquired after the proposal creation don't count: the
+    account shares are checkpointed at their first update during the voting, see
+    `Pool::voting_shares`.
+    The `shares` are escrowed until the end of the voting and returned with
+    `withdraw_fee_vote`. Counted delegations can't be revoked until the end of the voting.
+    An account votes once per proposal, and can't vote when it delegated its power.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation. */
+    #[payable]
+    pub fn vote_pool_fee(&mut self, proposal_id: u64, support: bool, shares: U128) {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let shares: Balance = shares.into();
+        let mut fp = self.get_fee_proposal(proposal_id);
+        let voting_end = fp.voting_end.0;
+        assert!(env::block_timestamp() < voting_end, "{}", ERR63_VOTING_CLOSED);
+        let user = env::predecessor_account_id();
+        let key = (proposal_id, user.clone());
+        assert!(self.fee_votes.get(&key).is_none(), "{}", ERR64_ALREADY_VOTED);
+        let token = fp.token.clone();
+        assert!(
+            self.delegations.get(&(token.clone(), user.clone())).is_none(),
+            "{}",
+            ERR75_VOTES_DELEGATED
+        );
+
+        let mut p = self.get_pool(&token);
+        if shares > 0 {
+            let owned = p.shares.get(&user).unwrap_or(0);
+            assert!(
+                owned >= shares,
+                "{} (owned {}, requested {})",
+                ERR11_NOT_ENOUGH_SHARES,
+                owned,
+                shares
+            );
+            let voting = p.voting_shares(&user, proposal_id);
+            assert!(
+                shares <= voting,
+                "{} (shares at the proposal creation {})",
+                ERR135_SHARES_AFTER_PROPOSAL,
+                voting
+            );
+            p.set_shares(&user, owned - shares);
+            p.vote_escrow += shares;
+            self.set_pool(&token, &p);
+        }
+        let mut power = shares + lock_power(&p, &user, voting_end);
+        for a in self.get_delegators(&token, &user) {
+            let k = (proposal_id, a.clone());
+            if self.fee_votes.get(&k).is_some() {
+                continue;
+            }
+            let dkey = (token.clone(), a.clone());
+            let mut d = self.delegations.get(&dkey).unwrap();
+            // a delegation made during the voting counts only the shares held before.
+            let delegated = if d.since_proposal > proposal_id {
+                d.shares.min(p.voting_shares(&a, proposal_id))
+            } else {
+                d.shares
+            };
+            power += delegated + lock_power(&p, &a, voting_end);
+            d.locked_until = d.locked_until.max(voting_end);
+            self.delegations.insert(&dkey, &d);
+            self.fee_votes.insert(&k, &FeeVote { support, shares: 0 });
+        }
+        assert!(power > 0, "{}", ERR02_POSITIVE_ARGS);
+
+        if support {
+            fp.yes = (fp.yes.0 + power).into();
+        } else {
+            fp.no = (fp.no.0 + power).into();
+        }
+        self.fee_proposals.insert(&proposal_id, &fp);
+        self.fee_votes.insert(&key, &FeeVote { support, shares });
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Fee proposal {} vote: {} with {} shares",
+            proposal_id,
+            if support { "yes" } else { "no" },
+            power
+        );
+    }
+
+    /**
+    Returns the caller shares escrowed by the vote on the fee proposal, once its voting
+    ended. Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the amount of shares. */
+    #[payable]
+    pub fn withdraw_fee_vote(&mut self, proposal_id: u64) -> U128 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let fp = self.get_fee_proposal(proposal_id);
+        assert!(
+            env::block_timestamp() >= fp.voting_end.0,
+            "{}",
+            ERR66_VOTING_NOT_ENDED
+        );
+        let user = env::predecessor_account_id();
+        let v = self
+            .fee_votes
+            .remove(&(proposal_id, user.clone()))
+            .expect(ERR67_VOTE_NOT_FOUND);
+        // votes counted through a delegation don't escrow shares.
+        if v.shares > 0 {
+            let mut p = self.get_pool(&fp.token);
+            let owned = p.shares.get(&user).unwrap_or(0);
+            p.set_shares(&user, owned + v.shares);
+            p.vote_escrow -= v.shares;
+            self.set_pool(&fp.token, &p);
+        }
+        self.unsafe_storage_check(start_storage);
+        v.shares.into()
+    }
+
+    /**
+    Finalizes the fee proposal after the end of its voting. The proposal passes when it has
+    more yes than no shares, the yes shares are at least `FEE_VOTE_QUORUM_BPS` of the pool
+    total shares, and the fee is within the current `pool_fee_bounds`. A passed proposal
+    is queued in the timelock queue and can be executed with `execute` after
+    `GOV_TIMELOCK`. Can be called by anyone.
+    Returns the new proposal status. */
+    pub fn finalize_fee_proposal(&mut self, proposal_id: u64) -> ProposalStatus {
+        let mut fp = self.get_fee_proposal(proposal_id);
+        assert!(
+            fp.status == ProposalStatus::Voting,
+            "{}",
+            ERR68_PROPOSAL_FINALIZED
+        );
+        assert!(
+            env::block_timestamp() >= fp.voting_end.0,
+            "{}",
+            ERR66_VOTING_NOT_ENDED
+        );
+
+        let p = self.get_pool(&fp.token);
+        let quorum = clp_math::mul_div_ceil(p.total_shares, FEE_VOTE_QUORUM_BPS, 10_000);
+        if fp.yes.0 > fp.no.0
+            && fp.yes.0 >= quorum
+            && self.pool_fee_bounds.contains(fp.fee_bps)
+        {
+            let action = GovAction::SetPoolFee {
+                token: fp.token.clone(),
+                fee_bps: fp.fee_bps,
+            };
+            fp.action_id = Some(self.queue_action(action, proposal_id));
+            fp.status = ProposalStatus::Queued;
+        } else {
+            self.pool_fee_proposals.remove(&fp.token);
+            fp.status = ProposalStatus::Rejected;
+        }
+        self.fee_proposals.insert(&proposal_id, &fp);
+        event_log!(
+            self,
+            "Fee proposal {} {:?}: {} pool fee {} bps",
+            proposal_id,
+            fp.status,
+            fp.token,
+            fp.fee_bps
+        );
+        fp.status
+    }
+
+    pub fn fee_proposal(&self, proposal_id: u64) -> Option<FeeProposal> {
+        self.fee_proposals.get(&proposal_id)
+    }
+
+    /// Returns the id of the `token` pool fee proposal in progress, if any.
+    pub fn pool_fee_proposal(&self, token: AccountId) -> Option<u64> {
+        self.pool_fee_proposals.get(&token)
+    }
+}
+
+/// Returns the `account` liquidity lock shares if the lock doesn't expire before `until`.
+fn lock_power(p: &Pool, account: &AccountId, until: u64) -> Balance {
+    match p.locks.get(account) {
+        Some(l) if l.expiry >= until => l.shares,
+        _ => 0,
+    }
+}
+
+impl NearSwap {
+    pub(crate) fn set_proposal_status(&mut self, proposal_id: u64, status: ProposalStatus) {
+        let mut fp = self.get_fee_proposal(proposal_id);
+        fp.status = status;
+        self.fee_proposals.insert(&proposal_id, &fp);
+    }
+
+    fn get_fee_proposal(&self, proposal_id: u64) -> FeeProposal {
+        self.fee_proposals
+            .get(&proposal_id)
+            .expect(ERR69_PROPOSAL_NOT_FOUND)
+    }
+}
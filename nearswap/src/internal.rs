@@ -3,6 +3,9 @@
 
 use crate::*;
 
+#[cfg(feature = "invariant-checks")]
+use crate::constants::INVARIANT_CHECK_PERIOD;
+
 impl NearSwap {
     #[inline]
     pub(crate) fn assert_owner(&self) {
@@ -12,6 +15,14 @@ impl NearSwap {
         );
     }
 
+    /// Increments and returns the event sequence number. Every logged event gets a unique,
+    /// monotonically increasing number, so indexers can detect missed events.
+    #[inline]
+    pub(crate) fn next_event_seq(&mut self) -> u64 {
+        self.event_seq += 1;
+        self.event_seq
+    }
+
     #[inline]
     pub(crate) fn get_pool(&self, ref token: &AccountId) -> Pool {
         self.pools
@@ -19,165 +30,228 @@ impl NearSwap {
             .expect("Pool for this token doesn't exist")
     }
 
+    /// Saves the new `token` pool `p` created by `creator`, assigning its pool id.
+    pub(crate) fn insert_new_pool(&mut self, token: &AccountId, p: &Pool, creator: &AccountId) {
+        self.set_pool(token, p);
+        self.assign_pool_id(token);
+        self.pool_metadata.insert(
+            token,
+            &PoolMetadata {
+                creator: creator.clone(),
+                description: String::new(),
+                reference: None,
+                creator_campaigns_only: false,
+            },
+        );
+    }
+
     #[inline]
     pub(crate) fn set_pool(&mut self, ref token: &AccountId, pool: &Pool) {
+        assert!(
+            pool.ynear >= pool.staked_ynear + pool.unstaking_ynear,
+            "{}",
+            ERR118_NOT_ENOUGH_LIQUID_NEAR
+        );
+        assert!(
+            pool.tokens >= pool.lent_tokens,
+            "{}",
+            ERR121_NOT_ENOUGH_LIQUID_TOKENS
+        );
         self.pools.insert(token, pool);
+        #[cfg(feature = "invariant-checks")]
+        self.assert_invariants(token, pool);
+    }
+
+    /// Checks the pool accounting after every pool update: reserves are consistent with
+    /// the total shares and the caller doesn't own more than all shares.
+    /// Every `INVARIANT_CHECK_PERIOD` events, it also checks that the contract holds at
+    /// least the liquid NEAR accounted in all pools, and that the holdings of all the pool
+    /// `holders` plus the shares escrowed by votes sum to `total_shares`, as `audit_shares`
+    /// does. The pool must be saved after the farm and delegation updates moving its shares.
+    #[cfg(feature = "invariant-checks")]
+    fn assert_invariants(&self, token: &AccountId, p: &Pool) {
+        assert!(
+            p.reserves_consistent(),
+            "{} (ynear={}, tokens={}, total_shares={})",
+            ERR32_INVARIANT_VIOLATION,
+            p.ynear,
+            p.tokens,
+            p.total_shares
+        );
+        let caller_shares = p.shares.get(&env::predecessor_account_id()).unwrap_or(0);
+        assert!(
+            caller_shares <= p.total_shares,
+            "{} (shares={}, total_shares={})",
+            ERR32_INVARIANT_VIOLATION,
+            caller_shares,
+            p.total_shares
+        );
+        if self.event_seq % INVARIANT_CHECK_PERIOD == 0 {
+            let pools_ynear: u128 = self.pools.values().map(|p| p.liquid_ynear()).sum();
+            assert!(
+                pools_ynear <= env::account_balance(),
+                "{} (pools ynear={}, balance={})",
+                ERR32_INVARIANT_VIOLATION,
+                pools_ynear,
+                env::account_balance()
+            );
+            let f = self.get_farm(token);
+            let holdings = p.vote_escrow
+                + p.holders
+                    .iter()
+                    .map(|h| self.holder_shares(token, p, &f, &h))
+                    .sum::<Balance>();
+            assert!(
+                holdings == p.total_shares,
+                "{} (holdings={}, total_shares={})",
+                ERR32_INVARIANT_VIOLATION,
+                holdings,
+                p.total_shares
+            );
+        }
     }
 
     /// Calculates amout of tokens a user buys for `in_amount` tokens, when a total balance
     /// in the pool is `in_bal` and `out_bal` of paid tokens and buying tokens respectively.
     #[inline]
-    #[allow(non_snake_case)]
     pub(crate) fn calc_out_amount(&self, in_amount: u128, in_bal: u128, out_bal: u128) -> u128 {
-        // formula: y = (x * Y * X) / (x + X)^2
-        let x = u256::from(in_amount);
-        let X = u256::from(in_bal);
-        let numerator = x * u256::from(out_bal) * X;
-        let mut denominator = x + X;
-        denominator *= denominator;
-
-        let r = numerator / denominator;
-        return r.as_u128();
+        clp_math::calc_out_amount(in_amount, in_bal, out_bal)
     }
 
-    /// returns swap out amount and fee.
+    /// returns swap out amount and fee for a `fee_bps` pool fee.
     #[allow(non_snake_case)]
-    pub(crate) fn calc_out_with_fee(&self, mut x: u128, X: u128, Y: u128) -> (u128, u128) {
-        if x == 0 {
-            return (0, 0);
-        }
-        let fee = (x * 3) / 1000; // 0.3% x
-        x = x - fee;
-        (self.calc_out_amount(x, X, Y), fee)
-    }
-
-    pub(crate) fn _price_n2t_in(&self, token: &AccountId, ynear_in: u128) -> (Pool, u128) {
-        assert!(ynear_in > 0, "E2: balance arguments must be >0");
-        let p = self.get_pool(&token);
-        let (out, _) = self.calc_out_with_fee(ynear_in, p.ynear, p.tokens).into();
-        (p, out)
+    pub(crate) fn calc_out_with_fee(
+        &self,
+        x: u128,
+        X: u128,
+        Y: u128,
+        fee_bps: u16,
+    ) -> (u128, u128) {
+        let (out, fee) = clp_math::calc_out_with_fee_bps(x, X, Y, fee_bps);
+        metrics_log!("calc_out x={} X={} Y={} fee={} out={}", x, X, Y, fee, out);
+        (out, fee)
     }
 
-    pub(crate) fn _price_swap_tokens_in(
+    /// Returns the amount of `token_out` bought for `amount_in` of `token_in`.
+    /// `None` token denotes NEAR.
+    pub(crate) fn internal_quote(
         &self,
-        t_in: &AccountId,
-        t_out: &AccountId,
-        tokens_in: Balance,
+        token_in: &Option<AccountId>,
+        token_out: &Option<AccountId>,
+        amount_in: Balance,
     ) -> Balance {
-        assert!(tokens_in > 0, "E2: balance arguments must be >0");
-        assert_ne!(t_in, t_out, "E9: can't swap same tokens");
-        let p_in = self.get_pool(t_in);
-        let p_out = self.get_pool(t_out);
-        let (near_swap, _) = self.calc_out_with_fee(tokens_in, p_in.tokens, p_in.ynear);
-        let (tokens2_out, _) = self.calc_out_with_fee(near_swap, p_out.ynear, p_out.tokens);
-        println!(
-            "Swapping_in {} {} -> {} ynear -> {} {}",
-            tokens_in, t_in, near_swap, tokens2_out, t_out
-        );
-        return tokens2_out;
+        assert!(amount_in > 0, "E2: balance arguments must be >0");
+        let mut out = amount_in;
+        for (t, near_in) in swap_legs(token_in, token_out) {
+            let p = self.get_pool(t);
+            out = self.calc_leg(&p, near_in, out).0;
+        }
+        out
     }
 
-    /// Should be at least `min_tokens_out` or swap will fail
-    /// (prevents front running and other slippage issues).
-    pub(crate) fn _swap_n2t(
+    /**
+    Swaps `amount_in` of `token_in` from the `user` deposit to `token_out`. `None` token
+    denotes NEAR. Should be at least `min_out` or swap will fail (prevents front running and
+    other slippage issues).
+    NEAR is handled as any other pool asset: a swap is a sequence of legs through the
+    NEAR-token pools, see `swap_legs`. Each leg first fills the pool order book orders
+    with a better price than the pool, see `match_book`. */
+    pub(crate) fn internal_swap(
         &mut self,
-        p: &mut Pool,
-        ynear_in: Balance,
-        token: &AccountId,
-        min_tokens_out: Balance,
+        user: &AccountId,
+        token_in: &Option<AccountId>,
+        token_out: &Option<AccountId>,
+        amount_in: Balance,
+        min_out: Balance,
     ) -> Balance {
-        let in_bal = p.ynear;
-        let out_bal = p.tokens;
-        let in_amount = ynear_in;
+        let legs = swap_legs(token_in, token_out);
+        let mut pools = Vec::with_capacity(legs.len());
+        for &(t, _) in legs.iter() {
+            let mut p = self.get_pool(t);
+            self.assert_pool_active(&p);
+            assert!(p.batch_window == 0, "{}", ERR100_BATCH_AUCTION_POOL);
+            self.run_twamm(t, &mut p);
+            p.record_snapshot();
+            pools.push(p);
+        }
 
-        let (out_amount, _fee) = self.calc_out_with_fee(in_amount, in_bal, out_bal);
-        assert!(out_amount >= min_tokens_out, "{}", ERR25_MIN_AMOUNT);
-        println!(
-            "User purchased {} {} for {} yNEAR",
-            out_amount, token, ynear_in
+        // `volume` and `fees` are denominated in NEAR, the side every leg shares.
+        let (mut out, mut volume, mut fees) = (amount_in, 0, 0);
+        for (&(t, near_in), p) in legs.iter().zip(pools.iter_mut()) {
+            let leg_in = out;
+            // the pool order book orders with a better price are filled first.
+            let (book_in, book_out) = self.match_book(t, p, near_in, leg_in);
+            let amm_in = leg_in - book_in;
+            let (amm_out, fee) = self.calc_leg(p, near_in, amm_in);
+            self.assert_trade_size(p, if near_in { amm_in } else { amm_out });
+            let leg_out = book_out + amm_out;
+            if near_in {
+                assert!(
+                    amm_out <= p.liquid_tokens(),
+                    "{} (out={}, liquid={})",
+                    ERR151_OVER_LIQUID_TOKENS,
+                    amm_out,
+                    p.liquid_tokens()
+                );
+                p.ynear += amm_in - self.take_fees(p, fee, true);
+                p.tokens -= amm_out;
+                volume = leg_in;
+                fees += fee;
+            } else {
+                assert!(
+                    amm_out <= p.liquid_ynear(),
+                    "{} (out={}, liquid={})",
+                    ERR150_OVER_LIQUID_NEAR,
+                    amm_out,
+                    p.liquid_ynear()
+                );
+                p.tokens += amm_in - self.take_fees(p, fee, false);
+                p.ynear -= amm_out;
+                volume = leg_out;
+                fees += token_fee_in_ynear(fee, amm_in, amm_out);
+            }
+            p.record_trade(volume);
+            out = leg_out;
+        }
+        assert!(
+            out >= min_out,
+            "{} (out={}, min={})",
+            ERR25_MIN_AMOUNT,
+            out,
+            min_out
         );
-
-        p.tokens -= out_amount;
-        p.ynear += ynear_in;
-
-        let user = env::predecessor_account_id();
-        let mut d = self.get_deposit(&user);
-        d.remove_near(ynear_in);
-        d.add(token, out_amount);
-
-        self.set_pool(token, p);
-        self.deposits.insert(&user, &d.into());
-        out_amount
-    }
-
-    // Should be at least `min_ynear_out` or swap will fail
-    // (prevents front running and other slippage issues).
-    pub(crate) fn _swap_t2n(
-        &mut self,
-        p: &mut Pool,
-        token: &AccountId,
-        token_in: Balance,
-        min_ynear_out: Balance,
-    ) -> Balance {
-        let user = env::predecessor_account_id();
-
-        let in_bal = p.tokens;
-        let out_bal = p.ynear;
-        let in_amount = token_in;
-
-        let (out_amount, _) = self.calc_out_with_fee(in_amount, in_bal, out_bal);
-        assert!(out_amount >= min_ynear_out, "{}", ERR25_MIN_AMOUNT);
         println!(
-            "User {} purchased {} NEAR tokens for {} tokens",
-            user, out_amount, token_in
+            "User {} purchased {} {} for {} {}",
+            user,
+            out,
+            token_out.as_deref().unwrap_or("yNEAR"),
+            amount_in,
+            token_in.as_deref().unwrap_or("yNEAR"),
         );
+        self.record_trader_stats(user, volume, fees);
+        for (&(t, _), p) in legs.iter().zip(pools.iter_mut()) {
+            self.forward_protocol_fees(t, p);
+        }
 
-        p.tokens += in_amount;
-        p.ynear -= out_amount;
-
-        let mut d = self.get_deposit(&user);
-        d.remove(token, in_amount);
-        d.ynear += out_amount;
-
-        self.set_pool(&token, p);
-        self.deposits.insert(&user, &d.into());
-        out_amount
+        let mut d = self.get_deposit(user);
+        d.remove_asset(token_in, amount_in);
+        d.add_asset(token_out, out);
+        for (&(t, _), p) in legs.iter().zip(pools.iter()) {
+            self.set_pool(t, p);
+        }
+        self.deposits.insert(user, &d.into());
+        out
     }
 
-    // Should be at least min_amount_out or swap will fail
-    // (prevents front running and other slippage issues).
-    pub(crate) fn _swap_tokens(
-        &mut self,
-        p1: &mut Pool,
-        p2: &mut Pool,
-        token1: &AccountId,
-        token1_in: Balance,
-        token2: &AccountId,
-        min_token2_out: Balance,
-    ) -> Balance {
-        let user = env::predecessor_account_id();
-        let (swap_amount, _) = self.calc_out_with_fee(token1_in, p1.tokens, p1.ynear);
-        let (out, _) = self.calc_out_with_fee(swap_amount, p2.ynear, p2.tokens);
-
-        assert!(out >= min_token2_out, "{}", ERR25_MIN_AMOUNT);
-        println!(
-            "User purchased {} {} tokens for {} {} tokens",
-            out, token2, token1_in, token1,
-        );
-
-        p1.tokens += token1_in;
-        p1.ynear -= swap_amount;
-        p2.tokens -= out;
-        p2.ynear += swap_amount;
-
-        let mut d = self.get_deposit(&user);
-        d.remove(token1, token1_in);
-        d.add(token2, out);
-
-        self.set_pool(&token1, p1);
-        self.set_pool(&token2, p2);
-        self.deposits.insert(&user, &d.into());
-        out
+    /// Returns the out amount and the fee of a swap leg through the `p` pool. With `near_in`
+    /// NEAR is sold for the pool token, otherwise the pool token is sold for NEAR.
+    fn calc_leg(&self, p: &Pool, near_in: bool, amount_in: Balance) -> (Balance, Balance) {
+        let fee_bps = p.swap_fee_bps(near_in);
+        if near_in {
+            self.calc_out_with_fee(amount_in, p.ynear, p.curve_tokens(), fee_bps)
+        } else {
+            self.calc_out_with_fee(amount_in, p.curve_tokens(), p.ynear, fee_bps)
+        }
     }
 
     /// Helper function for LP shares transfer implementing NEP-MFT standard.
@@ -199,12 +273,16 @@ impl NearSwap {
         assert!(amount_u > 0, "E2: amount must be >0");
         let mut p = self.get_pool(&token);
         let shares = p.shares.get(&sender).unwrap_or(0);
-        assert!(shares >= amount_u, "{}", ERR11_NOT_ENOUGH_SHARES);
-        p.shares.insert(&sender, &(shares - amount_u));
-        p.shares.insert(
-            &recipient,
-            &(p.shares.get(&recipient).unwrap_or(0) + amount_u),
+        assert!(
+            shares >= amount_u,
+            "{} (owned {}, requested {})",
+            ERR11_NOT_ENOUGH_SHARES,
+            shares,
+            amount_u
         );
+        p.set_shares(&sender, shares - amount_u);
+        let recipient_shares = p.shares.get(&recipient).unwrap_or(0);
+        p.set_shares(&recipient, recipient_shares + amount_u);
 
         if is_contract {
             // TODO: We should do it before modifiying local state to avoid exploits.
@@ -215,7 +293,7 @@ impl NearSwap {
                 msg,
                 &recipient,
                 0,
-                env::prepaid_gas() / 4,
+                self.gas_with_unused(GasOp::MftReceive, 0),
             );
         }
         self.set_pool(&token, &p);
@@ -229,8 +307,44 @@ pub(crate) fn assert_min_buy(to_buy: u128, min: u128) {
         to_buy >= min,
         "{}",
         format!(
-            "E7: buying {} tokens is smaller than required minimum",
-            to_buy
+            "E7: buying {} tokens is smaller than required minimum {}",
+            to_buy, min
         )
     );
 }
+
+/// Returns true if the two promise results are a valid NEP-141 `ft_metadata` and
+/// `ft_total_supply`, the probes of a token before its pool creation.
+pub(crate) fn nep141_probes_ok() -> bool {
+    let metadata_ok = match env::promise_result(0) {
+        PromiseResult::Successful(data) => {
+            near_sdk::serde_json::from_slice::<FungibleTokenMetadata>(&data).is_ok()
+        }
+        _ => false,
+    };
+    let supply_ok = match env::promise_result(1) {
+        PromiseResult::Successful(data) => {
+            near_sdk::serde_json::from_slice::<U128>(&data).is_ok()
+        }
+        _ => false,
+    };
+    metadata_ok && supply_ok
+}
+
+/// Returns the pools a `token_in` -> `token_out` swap goes through, with the leg direction:
+/// true when the leg sells NEAR. `None` token denotes NEAR. Every pool pairs a token with
+/// NEAR, so a token to token swap has two legs: token_in -> NEAR -> token_out.
+pub(crate) fn swap_legs<'a>(
+    token_in: &'a Option<AccountId>,
+    token_out: &'a Option<AccountId>,
+) -> Vec<(&'a AccountId, bool)> {
+    match (token_in, token_out) {
+        (None, Some(t)) => vec![(t, true)],
+        (Some(t), None) => vec![(t, false)],
+        (Some(t1), Some(t2)) => {
+            assert_ne!(t1, t2, "E9: can't swap same tokens");
+            vec![(t1, false), (t2, true)]
+        }
+        (None, None) => panic!("{}", ERR28_INVALID_SWAP_ASSETS),
+    }
+}
@@ -0,0 +1,322 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  BONDING CURVE LAUNCHES
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance, Promise};
+
+use crate::amounts::{NearAmount, Shares, TokenAmount};
+use crate::constants::*;
+use crate::ft_token::*;
+use crate::*;
+
+/// Single sided token launch along a linear bonding curve. Prices are in yNEAR for `1e24`
+/// token units.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LaunchConfig {
+    /// tokens sold along the curve.
+    pub sale_tokens: U128,
+    /// price of the first and of the last sold token.
+    pub start_price: U128,
+    pub end_price: U128,
+    /// part of the proceeds seeded into the token pool on completion, in basis points.
+    pub seed_bps: u16,
+    /// tokens reserved for the pool seeding. The pool is seeded at the `end_price`, the
+    /// unused tokens are returned to the creator.
+    pub seed_tokens: U128,
+}
+
+impl LaunchConfig {
+    pub fn assert_valid(&self) {
+        assert!(
+            self.sale_tokens.0 > 0
+                && self.start_price.0 > 0
+                && self.start_price.0 <= self.end_price.0
+                && self.seed_bps <= 10_000,
+            "{}",
+            ERR142_INVALID_LAUNCH
+        );
+    }
+}
+
+/// Bonding curve launch in progress, see `create_launch`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Launch {
+    pub creator: AccountId,
+    pub config: LaunchConfig,
+    /// tokens sold so far.
+    pub sold: U128,
+    /// NEAR paid by the buyers, in yNEAR.
+    pub proceeds: U128,
+    /// pool creation bond, see `PoolBondConfig`. It becomes the pool bond on completion.
+    pub bond: U128,
+    /// the token passed the NEP-141 probes, the launch is open for purchases.
+    pub active: bool,
+}
+
+impl Launch {
+    /// Returns the curve price after `sold` tokens were sold.
+    pub fn price_at(&self, sold: Balance) -> Balance {
+        let c = &self.config;
+        c.start_price.0
+            + clp_math::mul_div_floor(c.end_price.0 - c.start_price.0, sold, c.sale_tokens.0)
+    }
+
+    /// Returns the yNEAR cost of the next `amount` tokens: the area under the curve.
+    pub fn cost(&self, amount: Balance) -> Balance {
+        let sold = self.sold.0;
+        let prices = self.price_at(sold) + self.price_at(sold + amount);
+        clp_math::mul_div_ceil(amount, prices, 2 * NDENOM)
+    }
+
+    pub fn remaining(&self) -> Balance {
+        self.config.sale_tokens.0 - self.sold.0
+    }
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Starts a bonding curve launch of `token`, for a token without a pool. Only the token
+    contract or the owner can launch a token. The `sale_tokens` and the `seed_tokens` are
+    moved from the caller deposit. Buyers purchase the tokens with `buy_launch` along the
+    curve from `start_price` to `end_price`. When all `sale_tokens` are sold, the `token` pool
+    is created and seeded with `seed_bps` of the proceeds and the matching seed tokens, see
+    `LaunchConfig`. The pool shares, the rest of the proceeds and the unused seed tokens are
+    credited to the caller.
+    As with `create_pool`, the pool creation bond must be attached, otherwise exactly one
+    yNEAR to enforce wallet confirmation, and the launch opens in the `resolve_create_launch`
+    callback once the token answers the `ft_metadata` and `ft_total_supply` probes. */
+    #[payable]
+    pub fn create_launch(&mut self, token: AccountId, config: LaunchConfig) -> Promise {
+        let attached = env::attached_deposit();
+        let bond = if self.pool_bond_config.amount.0 == 0 {
+            assert_one_yocto();
+            0
+        } else {
+            self.assert_pool_bond(attached);
+            attached
+        };
+        config.assert_valid();
+        let creator = env::predecessor_account_id();
+        assert!(
+            creator == token || creator == self.owner,
+            "{}",
+            ERR147_NOT_LAUNCH_CREATOR
+        );
+        assert!(self.pools.get(&token).is_none(), "E1: pool already exists");
+        assert!(self.launches.get(&token).is_none(), "{}", ERR145_TOKEN_IN_LAUNCH);
+        let start_storage = env::storage_usage();
+        let mut d = self.get_deposit(&creator);
+        d.remove(&token, config.sale_tokens.0 + config.seed_tokens.0);
+        self.deposits.insert(&creator, &d.into());
+        self.launches.insert(
+            &token,
+            &Launch {
+                creator,
+                config,
+                sold: U128(0),
+                proceeds: U128(0),
+                bond: bond.into(),
+                active: false,
+            },
+        );
+        self.unsafe_storage_check(start_storage);
+        let gas = self.gas_for(GasOp::FtMetadata);
+        ext_fungible_token::ft_metadata(&token, 0, gas)
+            .and(ext_fungible_token::ft_total_supply(&token, 0, gas))
+            .then(ext_self::resolve_create_launch(
+                token,
+                &env::current_account_id(),
+                0,
+                self.gas_for(GasOp::ResolveCreatePool),
+            ))
+    }
+
+    /// Callback for the `create_launch` probes. Opens the `token` launch if the token
+    /// returned valid NEP-141 metadata and total supply, otherwise cancels it, returning the
+    /// tokens and the bond to the creator. Returns true if the launch was opened.
+    #[private]
+    pub fn resolve_create_launch(&mut self, token: AccountId) -> bool {
+        assert_eq!(
+            env::promise_results_count(),
+            2,
+            "Contract expected 2 results on the callback"
+        );
+        let mut l = match self.launches.get(&token) {
+            Some(l) => l,
+            None => return false,
+        };
+        if !nep141_probes_ok() {
+            self.close_launch(&token, l);
+            event_log!(self, "Launch {} not created, tokens and bond refunded", token);
+            return false;
+        }
+        l.active = true;
+        self.launches.insert(&token, &l);
+        event_log!(
+            self,
+            "Launch {} created: {} tokens from {} to {} yNEAR",
+            token,
+            l.config.sale_tokens.0,
+            l.config.start_price.0,
+            l.config.end_price.0
+        );
+        true
+    }
+
+    /**
+    Launch creator: cancels the opened `token` launch before the first purchase, returning
+    the tokens to the caller deposit and the bond to the caller.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation. */
+    #[payable]
+    pub fn cancel_launch(&mut self, token: AccountId) {
+        assert_one_yocto();
+        let l = self.launches.get(&token).expect(ERR143_NO_LAUNCH);
+        assert!(
+            l.creator == env::predecessor_account_id() && l.active && l.sold.0 == 0,
+            "{}",
+            ERR146_CANNOT_CANCEL_LAUNCH
+        );
+        self.close_launch(&token, l);
+        event_log!(self, "Launch {} cancelled", token);
+    }
+
+    /**
+    Buys `amount` of `token` from its launch, paying from the caller NEAR deposit at most
+    `max_cost` yNEAR. Buys the remaining tokens if less than `amount` is left. The purchase
+    completing the sale creates and seeds the `token` pool, see `create_launch`.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the paid yNEAR. */
+    #[payable]
+    pub fn buy_launch(&mut self, token: AccountId, amount: U128, max_cost: U128) -> U128 {
+        let _m = MethodMetrics::start("buy_launch");
+        assert_one_yocto();
+        assert!(!self.paused, "{}", ERR80_PAUSED);
+        assert!(amount.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        let mut l = self.launches.get(&token).expect(ERR143_NO_LAUNCH);
+        assert!(l.active, "{}", ERR148_LAUNCH_NOT_ACTIVE);
+        let amount = amount.0.min(l.remaining());
+        let cost = l.cost(amount);
+        assert!(
+            cost <= max_cost.0,
+            "{} (cost={}, max={})",
+            ERR144_LAUNCH_COST,
+            cost,
+            max_cost.0
+        );
+        l.sold.0 += amount;
+        l.proceeds.0 += cost;
+
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let mut d = self.get_deposit(&user);
+        d.remove_near(cost);
+        d.add(&token, amount);
+        self.deposits.insert(&user, &d.into());
+        self.launches.insert(&token, &l);
+        self.unsafe_storage_check(start_storage);
+        event_log!(self, "Launch {}: bought {} for {} yNEAR", token, amount, cost);
+        if l.remaining() == 0 {
+            self.complete_launch(&token, l);
+        }
+        cost.into()
+    }
+
+    /// Returns the yNEAR cost of the next `amount` tokens of the `token` launch, capped at
+    /// the remaining tokens.
+    pub fn launch_cost(&self, token: AccountId, amount: U128) -> U128 {
+        let l = self.launches.get(&token).expect(ERR143_NO_LAUNCH);
+        l.cost(amount.0.min(l.remaining())).into()
+    }
+
+    pub fn launch(&self, token: AccountId) -> Option<Launch> {
+        self.launches.get(&token)
+    }
+
+    /// Returns the launches in progress with their tokens.
+    pub fn list_launches(&self) -> Vec<(AccountId, Launch)> {
+        self.launches.iter().collect()
+    }
+}
+
+impl NearSwap {
+    /// Removes the `token` launch `l` without a purchase: the tokens are returned to the
+    /// creator deposit and the bond to the creator.
+    fn close_launch(&mut self, token: &AccountId, l: Launch) {
+        let start_storage = env::storage_usage();
+        self.launches.remove(token);
+        let mut d = self.get_deposit(&l.creator);
+        d.add(token, l.config.sale_tokens.0 + l.config.seed_tokens.0);
+        d.update_storage(start_storage);
+        self.deposits.insert(&l.creator, &d.into());
+        if l.bond.0 > 0 {
+            Promise::new(l.creator).transfer(l.bond.0);
+        }
+    }
+
+    /// Closes the sold out `token` launch `l`: creates the `token` pool, with the default
+    /// pool template, seeded with the `seed_bps` part of the proceeds at the curve end
+    /// price, with the launch bond as the pool bond. The creator gets the pool shares, the
+    /// rest of the proceeds and the unused seed tokens, and pays for the pool storage.
+    fn complete_launch(&mut self, token: &AccountId, l: Launch) {
+        let start_storage = env::storage_usage();
+        self.launches.remove(token);
+        let c = &l.config;
+        let seed_ynear = clp_math::mul_div_floor(l.proceeds.0, c.seed_bps as u128, 10_000);
+        let seed_tokens =
+            clp_math::mul_div_floor(seed_ynear, NDENOM, c.end_price.0).min(c.seed_tokens.0);
+        let (mut ynear, mut tokens, mut shares) = (l.proceeds.0, c.seed_tokens.0, 0);
+        // the bond is returned with the proceeds if no pool is created.
+        let mut bond_refund = l.bond.0;
+        if seed_ynear > 0 && seed_tokens > 0 {
+            let mut p = Pool::new(token);
+            if let Some(t) = self.select_pool_template(&None) {
+                t.apply(&mut p);
+            }
+            let (NearAmount(added_ynear), TokenAmount(added_tokens), Shares(minted)) = p
+                .add_liquidity(
+                    &l.creator,
+                    NearAmount(seed_ynear),
+                    TokenAmount(seed_tokens),
+                    Shares(0),
+                );
+            self.record_liquidity_event(
+                &l.creator,
+                token,
+                LiquidityEventKind::Add,
+                (added_ynear, added_tokens, minted),
+                &p,
+            );
+            self.insert_new_pool(token, &p, &l.creator);
+            if bond_refund > 0 {
+                let b = PoolBond {
+                    creator: l.creator.clone(),
+                    amount: l.bond,
+                };
+                self.pool_bonds.insert(token, &b);
+                bond_refund = 0;
+            }
+            ynear -= added_ynear;
+            tokens -= added_tokens;
+            shares = minted;
+        }
+        let mut d = self.get_deposit(&l.creator);
+        d.add_near(ynear + bond_refund);
+        d.add(token, tokens);
+        d.update_storage(start_storage);
+        self.deposits.insert(&l.creator, &d.into());
+        event_log!(
+            self,
+            "Launch {} completed: {} yNEAR raised, {} shares minted",
+            token,
+            l.proceeds.0,
+            shares
+        );
+    }
+}
@@ -0,0 +1,284 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  LENDING MARKET
+  (BOOSTED POOLS)
+***********************/
+
+use std::convert::TryInto;
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, ext_contract, near_bindgen, AccountId, Gas, Promise, PromiseResult};
+
+use crate::constants::*;
+use crate::ft_token::*;
+use crate::*;
+
+/// Amount of gas for the lending market `withdraw` (including its token transfer) and
+/// `get_supplied` calls.
+pub const GAS_FOR_LENDING_CALL: Gas = 40 * TGAS;
+
+/// Amount of gas for the lending market calls callbacks.
+pub const GAS_FOR_RESOLVE_LENDING: Gas = 10 * TGAS;
+
+/// Message of the `ft_transfer_call` supplying tokens to the lending market.
+pub const LENDING_SUPPLY_MSG: &str = "supply";
+
+/**
+Lending market adapter interface. Tokens are supplied with the token `ft_transfer_call` to
+the lending market with the `LENDING_SUPPLY_MSG` message. `withdraw` transfers the tokens
+back with `ft_transfer` and fails if the market doesn't have enough liquidity. */
+#[ext_contract(ext_lending_market)]
+pub trait LendingMarket {
+    fn withdraw(&mut self, token_id: AccountId, amount: U128);
+
+    /// Returns the `token_id` amount supplied by `account_id`, including the interest.
+    fn get_supplied(&self, account_id: AccountId, token_id: AccountId) -> U128;
+}
+
+/// Lending market call made by `rebalance_lending`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum LendingOp {
+    Supply,
+    Withdraw,
+}
+
+/// Pool token reserve split returned by `pool_lending`. `tokens = liquid + lent`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct PoolLendingInfo {
+    /// tokens available for swaps and withdrawals.
+    pub liquid: U128,
+    /// tokens supplied to the lending market, including the harvested interest.
+    pub lent: U128,
+    /// lent part of the pool token reserve `rebalance_lending` moves to, in basis points.
+    pub lending_bps: u16,
+    /// a lending market call is in progress.
+    pub pending: bool,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: sets the approved lending market. It can be changed only when no pool tokens
+    /// are lent. `None` disables lending.
+    pub fn set_lending_market(&mut self, market: Option<ValidAccountId>) {
+        self.assert_owner();
+        let market: Option<AccountId> = market.map(|m| m.into());
+        assert!(
+            market == self.lending_market || self.pools.values().all(|p| p.lent_tokens == 0),
+            "{}",
+            ERR119_INVALID_LENDING_CONFIG
+        );
+        self.log_admin_action("set_lending_market", &market);
+        self.lending_market = market;
+    }
+
+    pub fn lending_market(&self) -> Option<AccountId> {
+        self.lending_market.clone()
+    }
+
+    /// Owner: sets the part of the `token` pool token reserve supplied to the lending market,
+    /// in basis points, at most `MAX_LENT_BPS`. Applied by `rebalance_lending`.
+    pub fn set_pool_lending_share(&mut self, token: AccountId, lending_bps: u16) {
+        self.assert_owner();
+        assert!(lending_bps <= MAX_LENT_BPS, "{}", ERR119_INVALID_LENDING_CONFIG);
+        let mut p = self.get_pool(&token);
+        p.lending_bps = lending_bps;
+        self.set_pool(&token, &p);
+        self.log_admin_action("set_pool_lending_share", &(token, lending_bps));
+    }
+
+    /// Returns the split of the `token` pool token reserve between the liquid and the lent
+    /// tokens.
+    pub fn pool_lending(&self, token: AccountId) -> PoolLendingInfo {
+        let p = self.get_pool(&token);
+        PoolLendingInfo {
+            liquid: p.liquid_tokens().into(),
+            lent: p.lent_tokens.into(),
+            lending_bps: p.lending_bps,
+            pending: p.lending_pending,
+        }
+    }
+
+    /**
+    Moves the `token` pool lent tokens towards `lending_bps` of the pool token reserve with a
+    single lending market call. Lent tokens above the target are withdrawn, eg when swaps
+    bought the pool tokens, otherwise liquid tokens above the target are supplied. Changes
+    smaller than `LENDING_REBALANCE_THRESHOLD_BPS` of the reserve are skipped, unless the
+    target is 0. Only one call per pool can be in progress. Can be called by anyone.
+    Returns true if a lending market call was scheduled. */
+    pub fn rebalance_lending(&mut self, token: AccountId) -> bool {
+        self.internal_rebalance_lending(&token, false).is_some()
+    }
+
+    /// Owner or guardian: stops lending the `token` pool tokens and withdraws all lent
+    /// tokens, eg when the pool needs the liquidity or the lending market is at risk.
+    /// Returns true if a withdraw was scheduled.
+    pub fn force_lending_withdraw(&mut self, token: AccountId) -> bool {
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == self.owner || self.guardian.as_ref() == Some(&caller),
+            "{}",
+            ERR77_NOT_GUARDIAN
+        );
+        let mut p = self.get_pool(&token);
+        p.lending_bps = 0;
+        self.set_pool(&token, &p);
+        self.log_admin_action("force_lending_withdraw", &token);
+        self.internal_rebalance_lending(&token, true).is_some()
+    }
+
+    /// Callback for the `rebalance_lending` lending market calls. Reverts the unused part of
+    /// a supply and records a successful withdraw.
+    #[private]
+    pub fn resolve_lending(&mut self, token: AccountId, op: LendingOp, amount: U128) -> bool {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Contract expected a result on the callback"
+        );
+        let amount = amount.0;
+        let mut p = self.get_pool(&token);
+        p.lending_pending = false;
+        let ok = match (op, env::promise_result(0)) {
+            // `ft_transfer_call` returns the amount used by the lending market.
+            (LendingOp::Supply, PromiseResult::Successful(data)) => {
+                let used = near_sdk::serde_json::from_slice::<U128>(&data).map_or(0, |u| u.0);
+                let unused = amount - used.min(amount);
+                p.lent_tokens -= unused;
+                self.record_token_in(&token, unused);
+                unused == 0
+            }
+            (LendingOp::Supply, _) => {
+                p.lent_tokens -= amount;
+                self.record_token_in(&token, amount);
+                false
+            }
+            (LendingOp::Withdraw, PromiseResult::Successful(_)) => {
+                p.lent_tokens -= amount;
+                self.record_token_in(&token, amount);
+                true
+            }
+            (LendingOp::Withdraw, _) => false,
+        };
+        self.set_pool(&token, &p);
+        event_log!(
+            self,
+            "Pool {} lending {:?} {} tokens: {}",
+            token,
+            op,
+            amount,
+            if ok { "done" } else { "failed" }
+        );
+        ok
+    }
+
+    /// Queries the `token` amount supplied to the lending market and adds the interest to
+    /// the pool token reserve, so it's earned by the LPs pro rata to their shares. The
+    /// interest stays lent. Can be called by anyone.
+    pub fn harvest_lending_interest(&mut self, token: AccountId) -> Promise {
+        let market = self.lending_market.clone().expect(ERR120_LENDING_NOT_SET);
+        self.get_pool(&token);
+        ext_lending_market::get_supplied(
+            env::current_account_id(),
+            token.clone(),
+            &market,
+            0,
+            self.gas_for(GasOp::LendingCall),
+        )
+        .then(ext_self::resolve_harvest_lending_interest(
+            token,
+            &env::current_account_id(),
+            0,
+            self.gas_for(GasOp::ResolveLending),
+        ))
+    }
+
+    /// Callback for `harvest_lending_interest`. Returns the harvested interest.
+    #[private]
+    pub fn resolve_harvest_lending_interest(&mut self, token: AccountId) -> U128 {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Contract expected a result on the callback"
+        );
+        let supplied = match env::promise_result(0) {
+            PromiseResult::Successful(data) => {
+                near_sdk::serde_json::from_slice::<U128>(&data).map_or(0, |s| s.0)
+            }
+            _ => 0,
+        };
+        let mut p = self.get_pool(&token);
+        let interest = supplied.saturating_sub(p.lent_tokens);
+        if interest == 0 {
+            return U128(0);
+        }
+        p.lent_tokens += interest;
+        p.tokens += interest;
+        self.set_pool(&token, &p);
+        event_log!(self, "Pool {} lending interest: {} tokens", token, interest);
+        interest.into()
+    }
+}
+
+impl NearSwap {
+    /// Schedules the lending market call of `rebalance_lending`, if any. With `force`, the
+    /// threshold is not applied.
+    fn internal_rebalance_lending(&mut self, token: &AccountId, force: bool) -> Option<Promise> {
+        let market = self.lending_market.clone()?;
+        let mut p = self.get_pool(token);
+        if p.lending_pending {
+            return None;
+        }
+        let target = clp_math::mul_div_floor(p.tokens, p.lending_bps as u128, 10_000);
+        let threshold = if force || target == 0 {
+            1
+        } else {
+            clp_math::mul_div_floor(p.tokens, LENDING_REBALANCE_THRESHOLD_BPS as u128, 10_000)
+                .max(1)
+        };
+        let (op, amount) = if p.lent_tokens >= target + threshold {
+            (LendingOp::Withdraw, p.lent_tokens - target)
+        } else if target >= p.lent_tokens + threshold {
+            (LendingOp::Supply, target - p.lent_tokens)
+        } else {
+            return None;
+        };
+
+        let call = match op {
+            LendingOp::Supply => {
+                // the tokens leave the contract now, so the supply is recorded upfront.
+                p.lent_tokens += amount;
+                self.record_token_out(token, amount);
+                ext_fungible_token::ft_transfer_call(
+                    market.try_into().unwrap(),
+                    amount.into(),
+                    None,
+                    LENDING_SUPPLY_MSG.to_string(),
+                    token,
+                    1,
+                    self.gas_for(GasOp::FtTransferCall),
+                )
+            }
+            LendingOp::Withdraw => ext_lending_market::withdraw(
+                token.clone(),
+                amount.into(),
+                &market,
+                1,
+                self.gas_for(GasOp::LendingCall),
+            ),
+        };
+        p.lending_pending = true;
+        self.set_pool(token, &p);
+        Some(call.then(ext_self::resolve_lending(
+            token.clone(),
+            op,
+            amount.into(),
+            &env::current_account_id(),
+            0,
+            self.gas_for(GasOp::ResolveLending),
+        )))
+    }
+}
@@ -2,40 +2,189 @@
 // Copyright (C) 2020 Robert Zaremba and contributors
 
 use internal::assert_min_buy;
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet};
+use near_sdk::collections::{LookupMap, LookupSet, UnorderedMap, UnorderedSet, Vector};
 use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::{
-    assert_one_yocto, env, near_bindgen, AccountId, Balance, PanicOnDefault, Promise, StorageUsage,
+    assert_one_yocto, env, near_bindgen, AccountId, Balance, BorshStorageKey, CryptoHash,
+    PanicOnDefault, Promise, PromiseResult, StorageUsage,
 };
 
+pub mod accumulator;
+pub mod adapters;
+pub mod amounts;
+pub mod admin_log;
+pub mod auction;
+pub mod audit;
+pub mod bonds;
+pub mod book;
+pub mod candles;
+pub mod commit_reveal;
 mod constants;
+pub mod council;
+pub mod croncat;
+pub mod deadline;
+pub mod delegation;
+pub mod dynamic_fee;
 mod deposit;
 pub mod errors;
+pub mod escrow;
+pub mod farming;
 mod ft_token;
+pub mod gas;
+pub mod gov_staking;
+pub mod governance;
+pub mod launch;
+pub mod lending;
 mod internal;
+pub mod locks;
+pub mod method_groups;
+pub mod migration;
+pub mod orders;
 pub mod pool;
+pub mod pool_creator;
+pub mod pool_ids;
+pub mod pool_templates;
+pub mod position_history;
+pub mod price_history;
+pub mod protocol_fees;
+pub mod quote_locks;
+pub mod rate_provider;
+pub mod rebates;
+mod reserves;
+pub mod rfq;
+pub mod router;
+pub mod slippage;
+pub mod source_metadata;
+pub mod staking;
+mod stats;
 mod storage_management;
+#[cfg(feature = "testnet")]
+mod testnet;
+pub mod timelock;
+pub mod trade_limits;
+pub mod twamm;
 pub mod twap;
 pub mod types;
 pub mod util;
 mod view;
 
+use crate::adapters::*;
+use crate::amounts::*;
+use crate::admin_log::*;
+use crate::auction::*;
+use crate::bonds::*;
+use crate::book::*;
+use crate::commit_reveal::*;
+use crate::council::*;
+use crate::croncat::*;
+use crate::deadline::*;
+use crate::delegation::*;
 use crate::deposit::*;
 use crate::errors::*;
+use crate::escrow::*;
+use crate::farming::*;
+use crate::ft_token::*;
+use crate::gas::*;
+use crate::gov_staking::*;
+use crate::governance::*;
+use crate::launch::*;
+use crate::locks::*;
+use crate::migration::*;
+use crate::orders::*;
+use crate::pool_creator::*;
+use crate::pool_templates::*;
+use crate::position_history::*;
+use crate::protocol_fees::*;
+use crate::quote_locks::*;
+use crate::rate_provider::*;
 pub use crate::pool::*;
+use crate::rebates::*;
+use crate::staking::*;
+use crate::stats::*;
+use crate::timelock::*;
+use crate::twamm::*;
 use crate::types::*;
 use crate::util::*;
 
 // a way to optimize memory management
 near_sdk::setup_alloc!();
 
+/// Storage prefixes of the contract collections. Each prefix starts with a unique variant
+/// byte. Per pool collections are suffixed with the hash of the pool token account id (see
+/// `pool_hash`), so a crafted token account id can't collide with other collections.
+#[derive(BorshStorageKey, BorshSerialize)]
+pub(crate) enum StorageKey {
+    Pools,
+    Deposits,
+    WhitelistedTokens,
+    SwapRequests,
+    Orders,
+    TriggerOrders,
+    DcaOrders,
+    TraderStats,
+    CronTasks,
+    GasOverrides,
+    FailedTransfers,
+    Farms,
+    FeeProposals,
+    FeeVotes,
+    PoolFeeProposals,
+    Delegations,
+    Delegators,
+    QueuedActions,
+    CouncilConfirmations,
+    TokenBalances,
+    PoolShares { pool: CryptoHash },
+    PoolTwap { pool: CryptoHash },
+    PoolHistory { pool: CryptoHash },
+    PoolCandles1h { pool: CryptoHash },
+    PoolCandles1d { pool: CryptoHash },
+    PoolLocks { pool: CryptoHash },
+    PoolHolders { pool: CryptoHash },
+    FarmStakes { pool: CryptoHash },
+    PoolBonds,
+    RfqMakers,
+    RfqInventory,
+    RfqUsedQuotes,
+    Books,
+    Twamm,
+    TwammBoundaries,
+    TwammOrders,
+    Batches,
+    SwapCommitments,
+    LockedQuotes,
+    QuoteLockFunds,
+    PoolMetadata,
+    TokenProjects,
+    ProtocolFees,
+    PoolTopLps { pool: CryptoHash },
+    AdminLog,
+    GasEstimates,
+    MigrationSources,
+    PositionHistory,
+    PositionEvents { position: CryptoHash },
+    AmmAdapters,
+    ExternalSwaps,
+    PoolTokens,
+    PoolIds,
+    PoolPriceHistory { pool: CryptoHash },
+    PoolShareCheckpoints { pool: CryptoHash },
+    GovStakes,
+    GovActivationAcc,
+    FeeForwardThresholds,
+    PoolTemplates,
+    Launches,
+}
+
 /// NearSwap is the main contract for managing the swap pools and liquidity.
 /// It implements the NEARswap functionality.
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct NearSwap {
-    pub fee_dst: AccountId,
+    /// protocol fees recipients, see `distribute_fees`.
+    pub fee_destinations: Vec<FeeDestination>,
     pub owner: AccountId,
     // we are using unordered map because it allows to iterate over the pools
     pools: UnorderedMap<AccountId, Pool>,
@@ -45,6 +194,148 @@ pub struct NearSwap {
 
     // Set of whitelisted tokens by "owner".
     whitelisted_tokens: UnorderedSet<AccountId>,
+
+    // escrowed swaps, see `request_swap`.
+    swap_requests: LookupMap<u64, SwapRequest>,
+    next_swap_request: u64,
+
+    // limit orders, see `place_limit_order`.
+    orders: LookupMap<u64, LimitOrder>,
+    // stop-loss / take-profit orders, see `place_trigger_order`. Share ids with `orders`.
+    trigger_orders: LookupMap<u64, TriggerOrder>,
+    // recurring (DCA) orders, see `place_dca_order`. Share ids with `orders`.
+    dca_orders: LookupMap<u64, DcaOrder>,
+    next_order: u64,
+
+    // per account cumulative trading statistics, see `trader_stats`.
+    trader_stats: LookupMap<AccountId, TraderStats>,
+    // fee rebate tiers, ordered by `min_volume`, see `set_rebate_tiers`.
+    rebate_tiers: Vec<RebateTier>,
+    // NEAR available to pay rebate claims.
+    rebate_fund: Balance,
+
+    // croncat manager contract allowed to call `croncat_tick`.
+    croncat_manager: Option<AccountId>,
+    // croncat tasks registered by `croncat_create_task`, by task hash.
+    cron_tasks: UnorderedMap<Vec<u8>, CronTask>,
+
+    // owner overrides of the cross-contract calls gas, see `set_gas_override`.
+    gas_overrides: LookupMap<GasOp, Gas>,
+    // owner calibrations of the contract gas per action, see `set_gas_estimate`.
+    gas_estimates: LookupMap<GasAction, Gas>,
+
+    // amounts owed from failed transfers, by (account, token), see `claim_failed_transfers`.
+    failed_transfers: LookupMap<(AccountId, Option<AccountId>), Balance>,
+
+    // pool farms (staked LP shares and reward campaigns), see `create_reward_campaign`.
+    farms: LookupMap<AccountId, Farm>,
+    // minimum staking period and early unstake penalty of all farms.
+    farm_unstake_policy: FarmUnstakePolicy,
+
+    // pool fee proposals, see `propose_pool_fee`.
+    fee_proposals: LookupMap<u64, FeeProposal>,
+    // shares escrowed by the fee proposal votes, by (proposal id, account).
+    fee_votes: LookupMap<(u64, AccountId), FeeVote>,
+    // fee proposal in progress, by pool.
+    pool_fee_proposals: LookupMap<AccountId, u64>,
+    next_proposal: u64,
+    // range of the pool fees LPs can vote for.
+    pool_fee_bounds: FeeBounds,
+    // pool vote delegations, by (pool, delegator), see `delegate_votes`.
+    delegations: LookupMap<(AccountId, AccountId), Delegation>,
+    // delegators, by (pool, delegate).
+    delegators: LookupMap<(AccountId, AccountId), Vec<AccountId>>,
+
+    // approved governance actions waiting for execution, see `execute`.
+    queued_actions: UnorderedMap<u64, QueuedAction>,
+    next_action: u64,
+    // account allowed to cancel queued governance actions.
+    guardian: Option<AccountId>,
+
+    // emergency council, see `council_confirm`.
+    council: Council,
+    // member confirmations of the pending council motions.
+    council_confirmations: UnorderedMap<CouncilMotion, Vec<AccountId>>,
+    // swaps and adding liquidity are disabled, see `CouncilMotion::Pause`.
+    paused: bool,
+
+    // tokens the contract should hold, by token, see `verify_reserves`.
+    token_balances: LookupMap<AccountId, Balance>,
+
+    // anti-spam bond required by `create_pool`.
+    pool_bond_config: PoolBondConfig,
+    // bonds of the pool creators, by pool.
+    pool_bonds: LookupMap<AccountId, PoolBond>,
+
+    // ed25519 public keys of the RFQ makers, see `swap_rfq`.
+    rfq_makers: UnorderedMap<AccountId, Vec<u8>>,
+    // RFQ makers inventory, by (maker, token).
+    rfq_inventory: LookupMap<(AccountId, Option<AccountId>), Balance>,
+    // hashes of the taken RFQ quotes.
+    rfq_used_quotes: LookupSet<CryptoHash>,
+
+    // pool order books, see `place_book_order`. Share ids with `orders`.
+    books: LookupMap<AccountId, OrderBook>,
+
+    // long-term orders virtual sale, by pool, see `place_twamm_order`.
+    twamm: LookupMap<AccountId, TwammPool>,
+    // long-term orders ending at an interval boundary, by (pool, boundary).
+    twamm_boundaries: LookupMap<(AccountId, u64), TwammBoundary>,
+    // long-term orders. Share ids with `orders`.
+    twamm_orders: LookupMap<u64, TwammOrder>,
+
+    // pending batch auctions, by pool, see `submit_batch_swap`.
+    batches: LookupMap<AccountId, Batch>,
+
+    // hidden swaps, see `commit_swap`. Share ids with `orders`.
+    swap_commitments: LookupMap<u64, SwapCommitment>,
+
+    // quotes locked with `lock_quote`. Share ids with `orders`.
+    locked_quotes: LookupMap<u64, LockedQuote>,
+    // funds covering adverse price moves of the locked quotes, by pool.
+    quote_lock_funds: LookupMap<AccountId, QuoteLockFund>,
+
+    // pool creators and pool information, by pool, see `set_pool_metadata`.
+    pool_metadata: LookupMap<AccountId, PoolMetadata>,
+
+    // protocol part of the swap fees, in basis points of the fee.
+    protocol_fee_bps: u16,
+    // token projects sharing the protocol fees of their pool, see `register_token_project`.
+    token_projects: LookupMap<AccountId, TokenProject>,
+    // protocol fees split from the pools, by asset (`None` for NEAR).
+    protocol_fees: UnorderedMap<Option<AccountId>, Balance>,
+    // accrued protocol fees thresholds of the forwarding to the fee destinations, by asset,
+    // see `set_fee_forward_threshold`.
+    fee_forward_thresholds: LookupMap<Option<AccountId>, Balance>,
+    // append-only log of the privileged actions.
+    admin_log: Vector<AdminLogEntry>,
+    // delegation of the pools NEAR to a staking pool, see `rebalance_stake`.
+    staking: Staking,
+    // approved lending market for the pools tokens, see `rebalance_lending`.
+    lending_market: Option<AccountId>,
+    // external AMM pools approved for `migrate_in`, by LP token.
+    migration_sources: UnorderedMap<AccountId, MigrationSource>,
+    // liquidity events of the LP positions, by (account, pool token).
+    position_history: LookupMap<(AccountId, AccountId), Vector<LiquidityEvent>>,
+    // max slippage of the swaps without a minimum out amount, see `set_default_max_slippage`.
+    default_max_slippage_bps: u16,
+    // registered external AMM adapters with their names, see `swap_external`.
+    amm_adapters: UnorderedMap<AccountId, String>,
+    // swaps through the adapters waiting for the bought tokens. Share ids with `orders`.
+    external_swaps: LookupMap<u64, ExternalSwap>,
+    // pool tokens by pool id, and the reverse, see `pool_id`.
+    pool_tokens: LookupMap<u32, AccountId>,
+    pool_ids: LookupMap<AccountId, u32>,
+    next_pool_id: u32,
+    // governance token staking for a share of the protocol fees, see `stake_gov`.
+    gov_staking: GovStaking,
+    // owner defined pool parameters sets, by name, see `create_pool`.
+    pool_templates: UnorderedMap<String, PoolTemplate>,
+    // bonding curve launches in progress, by token, see `create_launch`.
+    launches: UnorderedMap<AccountId, Launch>,
+
+    // sequence number of the last logged event.
+    event_seq: u64,
 }
 
 //-------------------------
@@ -56,26 +347,105 @@ impl NearSwap {
     pub fn new(owner: ValidAccountId) -> Self {
         let o = AccountId::from(owner);
         Self {
-            fee_dst: o.clone(),
+            fee_destinations: vec![FeeDestination {
+                account: o.clone(),
+                weight: 1,
+            }],
             owner: o,
-            pools: UnorderedMap::new(b"p".to_vec()),
-            deposits: LookupMap::new(b"d".to_vec()),
-            whitelisted_tokens: UnorderedSet::new(b"w".to_vec()),
+            pools: UnorderedMap::new(StorageKey::Pools),
+            deposits: LookupMap::new(StorageKey::Deposits),
+            whitelisted_tokens: UnorderedSet::new(StorageKey::WhitelistedTokens),
+            swap_requests: LookupMap::new(StorageKey::SwapRequests),
+            next_swap_request: 0,
+            orders: LookupMap::new(StorageKey::Orders),
+            trigger_orders: LookupMap::new(StorageKey::TriggerOrders),
+            dca_orders: LookupMap::new(StorageKey::DcaOrders),
+            next_order: 0,
+            trader_stats: LookupMap::new(StorageKey::TraderStats),
+            rebate_tiers: Vec::new(),
+            rebate_fund: 0,
+            croncat_manager: None,
+            cron_tasks: UnorderedMap::new(StorageKey::CronTasks),
+            gas_overrides: LookupMap::new(StorageKey::GasOverrides),
+            gas_estimates: LookupMap::new(StorageKey::GasEstimates),
+            failed_transfers: LookupMap::new(StorageKey::FailedTransfers),
+            farms: LookupMap::new(StorageKey::Farms),
+            farm_unstake_policy: FarmUnstakePolicy {
+                min_period: 0.into(),
+                penalty_bps: 0,
+            },
+            fee_proposals: LookupMap::new(StorageKey::FeeProposals),
+            fee_votes: LookupMap::new(StorageKey::FeeVotes),
+            pool_fee_proposals: LookupMap::new(StorageKey::PoolFeeProposals),
+            next_proposal: 0,
+            pool_fee_bounds: FeeBounds {
+                min_bps: constants::POOL_FEE_BOUNDS.0,
+                max_bps: constants::POOL_FEE_BOUNDS.1,
+            },
+            delegations: LookupMap::new(StorageKey::Delegations),
+            delegators: LookupMap::new(StorageKey::Delegators),
+            queued_actions: UnorderedMap::new(StorageKey::QueuedActions),
+            next_action: 0,
+            guardian: None,
+            council: Council {
+                members: Vec::new(),
+                threshold: 0,
+            },
+            council_confirmations: UnorderedMap::new(StorageKey::CouncilConfirmations),
+            paused: false,
+            token_balances: LookupMap::new(StorageKey::TokenBalances),
+            pool_bond_config: PoolBondConfig {
+                amount: 0.into(),
+                min_liquidity: constants::POOL_BOND_MIN_LIQUIDITY.into(),
+            },
+            pool_bonds: LookupMap::new(StorageKey::PoolBonds),
+            rfq_makers: UnorderedMap::new(StorageKey::RfqMakers),
+            rfq_inventory: LookupMap::new(StorageKey::RfqInventory),
+            rfq_used_quotes: LookupSet::new(StorageKey::RfqUsedQuotes),
+            books: LookupMap::new(StorageKey::Books),
+            twamm: LookupMap::new(StorageKey::Twamm),
+            twamm_boundaries: LookupMap::new(StorageKey::TwammBoundaries),
+            twamm_orders: LookupMap::new(StorageKey::TwammOrders),
+            batches: LookupMap::new(StorageKey::Batches),
+            swap_commitments: LookupMap::new(StorageKey::SwapCommitments),
+            locked_quotes: LookupMap::new(StorageKey::LockedQuotes),
+            quote_lock_funds: LookupMap::new(StorageKey::QuoteLockFunds),
+            pool_metadata: LookupMap::new(StorageKey::PoolMetadata),
+            protocol_fee_bps: 0,
+            token_projects: LookupMap::new(StorageKey::TokenProjects),
+            protocol_fees: UnorderedMap::new(StorageKey::ProtocolFees),
+            fee_forward_thresholds: LookupMap::new(StorageKey::FeeForwardThresholds),
+            admin_log: Vector::new(StorageKey::AdminLog),
+            staking: Staking {
+                staking_pool: None,
+                max_staked_bps: 0,
+                total_staked: 0,
+                total_unstaking: 0,
+                reward_acc: accumulator::RewardAcc::default(),
+            },
+            lending_market: None,
+            migration_sources: UnorderedMap::new(StorageKey::MigrationSources),
+            position_history: LookupMap::new(StorageKey::PositionHistory),
+            default_max_slippage_bps: 0,
+            amm_adapters: UnorderedMap::new(StorageKey::AmmAdapters),
+            external_swaps: LookupMap::new(StorageKey::ExternalSwaps),
+            pool_tokens: LookupMap::new(StorageKey::PoolTokens),
+            pool_ids: LookupMap::new(StorageKey::PoolIds),
+            next_pool_id: 0,
+            gov_staking: GovStaking::new(),
+            pool_templates: UnorderedMap::new(StorageKey::PoolTemplates),
+            launches: UnorderedMap::new(StorageKey::Launches),
+            event_seq: 0,
         }
     }
 
-    /// Updates the fee destination destination account
-    pub fn set_fee_dst(&mut self, fee_dst: ValidAccountId) {
-        self.assert_owner();
-        self.fee_dst = fee_dst.into();
-    }
-
     /// Owner is an account (can be a multisig) who has management rights to update
     /// fee size.
     pub fn change_owner(&mut self, new_owner: ValidAccountId) {
         self.assert_owner();
         let o = AccountId::from(new_owner);
-        env_log!("Changing owner from {} to {}", self.owner, o);
+        event_log!(self, "Changing owner from {} to {}", self.owner, o);
+        self.log_admin_action("change_owner", &o);
         self.owner = o;
     }
 
@@ -83,6 +453,7 @@ impl NearSwap {
     #[payable]
     pub fn extend_whitelisted_tokens(&mut self, tokens: Vec<ValidAccountId>) {
         self.assert_owner();
+        self.log_admin_action("extend_whitelisted_tokens", &tokens);
         for token in tokens {
             self.whitelisted_tokens.insert(token.as_ref());
         }
@@ -92,6 +463,7 @@ impl NearSwap {
     pub fn remove_whitelisted_token(&mut self, token: ValidAccountId) {
         self.assert_owner();
         self.whitelisted_tokens.remove(token.as_ref());
+        self.log_admin_action("remove_whitelisted_token", &token);
     }
 
     /**********************
@@ -101,16 +473,83 @@ impl NearSwap {
     /// Allows any user to creat a new near-token pool. Each pool is identified by the `token`
     /// account - which we call the Pool Token.
     /// If a pool for give token exists then "E1" assert exception is thrown.
+    /// `price_bounds` is an optional range of the expected initial price (yNEAR per 1e24
+    /// token units). When set, a first deposit with a price outside of it is rejected.
+    /// When the owner set a pool creation bond, at least the bond `amount` must be attached.
+    /// It's refunded with `refund_pool_bond`, see `PoolBondConfig`.
+    /// The caller is recorded as the pool creator, see `PoolMetadata`.
+    /// The `token` must implement NEP-141: the pool is created by the `resolve_create_pool`
+    /// callback once the token answers the `ft_metadata` and `ft_total_supply` probes.
+    /// The pool parameters are set from the `template` pool template, or the
+    /// `DEFAULT_POOL_TEMPLATE` one if defined, see `set_pool_template`.
     /// TODO: charge user for a storage created!
     #[payable]
-    pub fn create_pool(&mut self, token: ValidAccountId) {
+    pub fn create_pool(
+        &mut self,
+        token: ValidAccountId,
+        price_bounds: Option<PriceBounds>,
+        template: Option<String>,
+    ) -> Promise {
         let token = AccountId::from(token);
-        assert!(
-            self.pools
-                .insert(&token, &Pool::new(token.as_bytes().to_vec()))
-                .is_none(),
-            "E1: pool already exists"
+        if let Some(b) = &price_bounds {
+            b.assert_valid();
+        }
+        assert!(self.pools.get(&token).is_none(), "E1: pool already exists");
+        assert!(self.launches.get(&token).is_none(), "{}", ERR145_TOKEN_IN_LAUNCH);
+        let template = self.select_pool_template(&template);
+        let attached = env::attached_deposit();
+        self.assert_pool_bond(attached);
+        let gas = self.gas_for(GasOp::FtMetadata);
+        ext_fungible_token::ft_metadata(&token, 0, gas)
+            .and(ext_fungible_token::ft_total_supply(&token, 0, gas))
+            .then(ext_self::resolve_create_pool(
+                token,
+                env::predecessor_account_id(),
+                price_bounds,
+                attached.into(),
+                template,
+                &env::current_account_id(),
+                0,
+                self.gas_for(GasOp::ResolveCreatePool),
+            ))
+    }
+
+    /// Callback for the `create_pool` probes. Creates the `token` pool with the `template`
+    /// parameters if the token returned valid NEP-141 metadata and total supply, otherwise
+    /// refunds the `attached` deposit to the `creator`. Returns true if the pool was created.
+    #[private]
+    pub fn resolve_create_pool(
+        &mut self,
+        token: AccountId,
+        creator: AccountId,
+        price_bounds: Option<PriceBounds>,
+        attached: U128,
+        template: Option<PoolTemplate>,
+    ) -> bool {
+        assert_eq!(
+            env::promise_results_count(),
+            2,
+            "Contract expected 2 results on the callback"
         );
+        // a concurrent `create_pool` or `create_launch` call could take the token in the meantime.
+        if !nep141_probes_ok()
+            || self.pools.get(&token).is_some()
+            || self.launches.get(&token).is_some()
+        {
+            event_log!(self, "Pool {} not created, attached deposit refunded", token);
+            if attached.0 > 0 {
+                Promise::new(creator).transfer(attached.0);
+            }
+            return false;
+        }
+        let mut p = Pool::new(&token);
+        p.init_price = price_bounds;
+        if let Some(t) = &template {
+            t.apply(&mut p);
+        }
+        self.insert_new_pool(&token, &p, &creator);
+        self.take_pool_bond(&token, creator, attached.0);
+        true
     }
 
     /// Extracts public information of the `token` pool.
@@ -121,6 +560,18 @@ impl NearSwap {
         }
     }
 
+    /// Updates the `token` pool `top_lps` index with the current shares of up to
+    /// `MAX_TOP_LPS` `accounts`, eg LPs missed by the index. Can be called by anyone.
+    pub fn refresh_top_lps(&mut self, token: AccountId, accounts: Vec<AccountId>) {
+        let mut p = self.get_pool(&token);
+        let start_storage = env::storage_usage();
+        for a in accounts.iter().take(constants::MAX_TOP_LPS) {
+            p.update_top_lps(a, p.shares.get(a).unwrap_or(0));
+        }
+        p.update_storage(start_storage);
+        self.set_pool(&token, &p);
+    }
+
     /// Returns list of pools identified by token AccountId.
     pub fn list_pools(&self) -> Vec<AccountId> {
         return self.pools.keys().collect();
@@ -151,9 +602,12 @@ impl NearSwap {
         max_tokens: U128,
         min_shares: U128,
     ) -> U128 {
+        let _m = MethodMetrics::start("add_liquidity");
         assert_one_yocto();
         let start_storage = env::storage_usage();
         let mut p = self.get_pool(&token);
+        self.assert_pool_active(&p);
+        self.run_twamm(&token, &mut p);
         let caller = env::predecessor_account_id();
         let ynear: Balance = ynear.into();
         let max_tokens: Balance = max_tokens.into();
@@ -162,15 +616,28 @@ impl NearSwap {
             ynear > 0 && max_tokens > 0,
             "E2: added liquidity must be >0"
         );
-        let (ynear, added_tokens, shares_minted) =
-            p.add_liquidity(&caller, ynear, max_tokens, min_shares.into());
+        let (NearAmount(ynear), TokenAmount(added_tokens), Shares(shares_minted)) = p
+            .add_liquidity(
+                &caller,
+                NearAmount(ynear),
+                TokenAmount(max_tokens),
+                min_shares.into(),
+            );
         d.remove(&token, added_tokens);
         d.remove_near(ynear);
+        self.record_liquidity_event(
+            &caller,
+            &token,
+            LiquidityEventKind::Add,
+            (ynear, added_tokens, shares_minted),
+            &p,
+        );
         d.update_storage(start_storage);
         self.deposits.insert(&caller, &d.into());
         self.set_pool(&token, &p);
 
-        env_log!(
+        event_log!(
+            self,
             "Minting {} of shares for {} yNEAR and {} tokens",
             shares_minted,
             ynear,
@@ -182,6 +649,8 @@ impl NearSwap {
     /// Redeems `shares` for liquidity stored in this pool with condition of getting at least
     /// `min_ynear` of Near and `min_tokens` of tokens. Shares are not
     /// exchagable between different pools.
+    /// Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    #[payable]
     pub fn withdraw_liquidity(
         &mut self,
         token: AccountId,
@@ -189,6 +658,8 @@ impl NearSwap {
         min_ynear: U128,
         min_tokens: U128,
     ) {
+        let _m = MethodMetrics::start("withdraw_liquidity");
+        assert_one_yocto();
         let start_storage = env::storage_usage();
         let shares: u128 = shares.into();
         let min_ynear: u128 = min_ynear.into();
@@ -200,20 +671,27 @@ impl NearSwap {
 
         let caller = env::predecessor_account_id();
         let mut p = self.get_pool(&token);
+        self.run_twamm(&token, &mut p);
         let current_shares = p.shares.get(&caller).unwrap_or(0);
         assert!(
             current_shares >= shares,
             "{}",
             format!(
-                "E5: can't withdraw more shares then currently owned ({})",
-                current_shares
+                "E5: can't withdraw more shares then currently owned ({}), requested {}",
+                current_shares, shares
             )
         );
 
         let mut d = self.get_deposit(&caller);
-        let (ynear, token_amount) = p.withdraw_liquidity(&caller, min_ynear, min_tokens, shares);
+        let (NearAmount(ynear), TokenAmount(token_amount)) = p.withdraw_liquidity(
+            &caller,
+            NearAmount(min_ynear),
+            TokenAmount(min_tokens),
+            Shares(shares),
+        );
 
-        env_log!(
+        event_log!(
+            self,
             "Reedeming {:?} shares for {} NEAR and {} tokens",
             shares,
             ynear,
@@ -222,18 +700,41 @@ impl NearSwap {
 
         d.add(&token, token_amount);
         d.add_near(ynear);
+        self.record_liquidity_event(
+            &caller,
+            &token,
+            LiquidityEventKind::Withdraw,
+            (ynear, token_amount, shares),
+            &p,
+        );
         d.update_storage(start_storage);
         self.deposits.insert(&caller, &d.into());
         self.set_pool(&token, &p);
     }
 
+    /// Donates the attached NEAR to the `token` pool reserves. No shares are minted, so the
+    /// donation raises the value of the existing shares. Tokens are donated with
+    /// `ft_transfer_call` and the `DONATE_MSG` message.
+    #[payable]
+    pub fn donate_near(&mut self, token: AccountId) {
+        let ynear = env::attached_deposit();
+        assert!(ynear > 0, "{}", ERR02_POSITIVE_ARGS);
+        let mut p = self.get_pool(&token);
+        p.donate(ynear, 0);
+        self.set_pool(&token, &p);
+        event_log!(self, "Donated {} yNEAR to {} pool", ynear, token);
+    }
+
     /**********************
      AMM functions
     **********************/
 
     /// Swaps NEAR to `token` and transfers the tokens to the caller.
     /// Caller attaches near tokens he wants to swap to the transacion under a condition of
-    /// receving at least `min_tokens` of `token`.
+    /// receving at least `min_tokens` of `token`. With `min_tokens = 0`, the default max
+    /// slippage applies, see `set_default_max_slippage`.
+    /// Panics if the `deadline` expired.
+    /// Can be called with a function-call access key, see `get_method_groups`.
     /// Returns amount of bought tokens.
     #[payable]
     pub fn swap_near_to_token_exact_in(
@@ -241,24 +742,30 @@ impl NearSwap {
         ynear_in: U128,
         token: AccountId,
         min_tokens: U128,
+        deadline: Option<Deadline>,
     ) -> U128 {
+        let _m = MethodMetrics::start("swap_near_to_token_exact_in");
         let start_storage = env::storage_usage();
-        assert_one_yocto();
+        assert_at_most_one_yocto();
+        assert_deadline(&deadline);
         let ynear: u128 = ynear_in.into();
-        let min_tokens: u128 = min_tokens.into();
-        assert!(ynear > 0 && min_tokens > 0, "{}", ERR02_POSITIVE_ARGS);
+        assert!(ynear > 0, "{}", ERR02_POSITIVE_ARGS);
+        let token = Some(token);
+        let min_tokens = self.min_out_or_default(&None, &token, ynear, min_tokens.into());
 
-        let (mut p, tokens_out) = self._price_n2t_in(&token, ynear);
-        assert_min_buy(tokens_out, min_tokens);
-        let tokens_swap_out = self._swap_n2t(&mut p, ynear, &token, tokens_out);
+        let user = env::predecessor_account_id();
+        let tokens_swap_out = self.internal_swap(&user, &None, &token, ynear, 0);
+        assert_min_buy(tokens_swap_out, min_tokens);
         self.unsafe_storage_check(start_storage);
         return tokens_swap_out.into();
     }
 
     /// Swaps `tokens_paid` of `token` to NEAR and transfers NEAR to the caller under acc
-    /// condition of receving at least `min_ynear` yocto NEARs.
+    /// condition of receving at least `min_ynear` yocto NEARs. With `min_ynear = 0`, the
+    /// default max slippage applies, see `set_default_max_slippage`.
     /// Preceeding to this transaction, caller has to create sufficient allowance of `token`
     /// for this contract (at least `tokens_paid`).
+    /// Panics if the `deadline` expired.
     /// Returns amount of yNEAR bought.
     #[payable]
     pub fn swap_token_to_near_exact_in(
@@ -266,27 +773,32 @@ impl NearSwap {
         token: AccountId,
         tokens_paid: U128,
         min_ynear: U128,
+        deadline: Option<Deadline>,
     ) -> U128 {
+        let _m = MethodMetrics::start("swap_token_to_near_exact_in");
         let start_storage = env::storage_usage();
-        assert_one_yocto();
+        assert_at_most_one_yocto();
+        assert_deadline(&deadline);
         let tokens_paid: u128 = tokens_paid.into();
-        let min_ynear: u128 = min_ynear.into();
-        assert!(tokens_paid > 0 && min_ynear > 0, "{}", ERR02_POSITIVE_ARGS);
+        assert!(tokens_paid > 0, "{}", ERR02_POSITIVE_ARGS);
+        let token = Some(token);
+        let min_ynear = self.min_out_or_default(&token, &None, tokens_paid, min_ynear.into());
 
-        let mut p = self.get_pool(&token);
-        let (near_out, _) = self.calc_out_with_fee(tokens_paid, p.tokens, p.ynear);
-        assert_min_buy(near_out, min_ynear);
-        let near_swap_out = self._swap_t2n(&mut p, &token, tokens_paid, near_out);
+        let user = env::predecessor_account_id();
+        let near_swap_out = self.internal_swap(&user, &token, &None, tokens_paid, 0);
+        assert_min_buy(near_swap_out, min_ynear);
         self.unsafe_storage_check(start_storage);
         return near_swap_out.into();
     }
 
     /// Swaps two different tokens.
     /// Caller defines the amount of tokens he wants to swap under a condition of
-    /// receving at least `min_tokens_out`.
+    /// receving at least `min_tokens_out`. With `min_tokens_out = 0`, the default max
+    /// slippage applies, see `set_default_max_slippage`.
     /// Preceeding to this transaction, caller has to create a sufficient allowance of
     /// `from` token for this contract.
-    /// Transaction will panic if a caller doesn't provide enough allowance.
+    /// Transaction will panic if a caller doesn't provide enough allowance or the `deadline`
+    /// expired.
     /// Returns amount tokens bought.
     #[payable]
     pub fn swap_tokens_exact_in(
@@ -295,20 +807,21 @@ impl NearSwap {
         tokens_in: U128,
         token_out: AccountId,
         min_tokens_out: U128,
+        deadline: Option<Deadline>,
     ) -> U128 {
+        let _m = MethodMetrics::start("swap_tokens_exact_in");
         let start_storage = env::storage_usage();
-        assert_one_yocto();
+        assert_at_most_one_yocto();
+        assert_deadline(&deadline);
         let tokens_in: u128 = tokens_in.into();
-        let min_tokens_out: u128 = min_tokens_out.into();
-        assert!(min_tokens_out > 0 && tokens_in > 0, "{}", ERR02_POSITIVE_ARGS);
+        assert!(tokens_in > 0, "{}", ERR02_POSITIVE_ARGS);
 
-        let mut p1 = self.get_pool(&token_in);
-        let mut p2 = self.get_pool(&token_out);
-        let tokens_out = self._price_swap_tokens_in(&token_in, &token_out, tokens_in);
-        assert_min_buy(tokens_out, min_tokens_out);
-        let tokens_swap_out = self._swap_tokens(
-            &mut p1, &mut p2, &token_in, tokens_in, &token_out, tokens_out,
-        );
+        let (token_in, token_out) = (Some(token_in), Some(token_out));
+        let min_tokens_out =
+            self.min_out_or_default(&token_in, &token_out, tokens_in, min_tokens_out.into());
+        let user = env::predecessor_account_id();
+        let tokens_swap_out = self.internal_swap(&user, &token_in, &token_out, tokens_in, 0);
+        assert_min_buy(tokens_swap_out, min_tokens_out);
         self.unsafe_storage_check(start_storage);
         return tokens_swap_out.into();
     }
@@ -332,21 +845,19 @@ impl NearSwap {
     /// Calculates amount of tokens user will recieve when swapping `ynear_in` for `token`
     /// assets
     pub fn price_near_to_token_in(&self, token: AccountId, ynear_in: U128) -> U128 {
-        self._price_n2t_in(&token, ynear_in.into()).1.into()
+        self.internal_quote(&None, &Some(token), ynear_in.into())
+            .into()
     }
 
     /// Calculates amount of NEAR user will recieve when swapping `tokens_in` for NEAR.
     pub fn price_token_to_near_in(&self, token: AccountId, tokens_in: U128) -> U128 {
-        let tokens_in: u128 = tokens_in.into();
-        assert!(tokens_in > 0, "E2: balance arguments must be >0");
-        let p = self.get_pool(&token);
-        let (out, _) = self.calc_out_with_fee(tokens_in, p.tokens, p.ynear).into();
-        return U128(out);
+        self.internal_quote(&Some(token), &None, tokens_in.into())
+            .into()
     }
 
     /// Calculates amount of tokens `to` user will receive when swapping `tokens_in` of `from`
     pub fn price_token_to_token_in(&self, from: AccountId, to: AccountId, tokens_in: U128) -> U128 {
-        self._price_swap_tokens_in(&from, &to, tokens_in.into())
+        self.internal_quote(&Some(from), &Some(to), tokens_in.into())
             .into()
     }
 
@@ -402,16 +913,18 @@ impl NearSwap {
     ///     external data. If referencing a binary data, it should use base64 serialization.
     /// The function panics if the token doesn't refer to any registered pool or the predecessor
     /// doesn't have sufficient amount of shares.
+    /// Requires payment of exactly one yNEAR to enforce wallet confirmation.
     #[payable]
     pub fn transfer(
         &mut self,
         token: String,
-        recipient: AccountId,
+        recipient: ValidAccountId,
         amount: U128,
         msg: String,
         memo: String,
     ) -> bool {
-        self._transfer(token, recipient, amount, msg, memo, false)
+        assert_one_yocto();
+        self._transfer(token, recipient.into(), amount, msg, memo, false)
     }
 
     /// Transfer `amount` of LP Shares (Liquidity Provider Shares) of a pool identified
@@ -425,16 +938,18 @@ impl NearSwap {
     ///     external event. If referencing a binary data, it should use base64 serialization.
     /// The function panics if the token doesn't refer to any registered pool or the predecessor
     /// doesn't have sufficient amount of shares.
+    /// Requires payment of exactly one yNEAR to enforce wallet confirmation.
     #[payable]
     pub fn transfer_call(
         &mut self,
         token: String,
-        recipient: AccountId,
+        recipient: ValidAccountId,
         amount: U128,
         msg: String,
         memo: String,
     ) -> bool {
-        self._transfer(token, recipient, amount, msg, memo, true)
+        assert_one_yocto();
+        self._transfer(token, recipient.into(), amount, msg, memo, true)
     }
 
     /**********************
@@ -445,7 +960,10 @@ impl NearSwap {
     pub fn remove_pool(&mut self, token: AccountId) {
         self.assert_owner();
         if let Some(p) = self.pools.remove(&token) {
-            env_log!(
+            self.remove_pool_id(&token);
+            self.log_admin_action("remove_pool", &token);
+            event_log!(
+                self,
                 "killing {} pool and transferring {} to {}",
                 token,
                 p.ynear,
@@ -464,10 +982,17 @@ mod tests {
     use crate::twap::Twap;
 
     use super::*;
-    use near_sdk::{testing_env, MockedBlockchain, VMContext};
-    use near_sdk_sim::to_yocto;
+    use near_sdk::{
+        testing_env, MockedBlockchain, PromiseOrValue, PromiseResult, RuntimeFeesConfig, VMConfig, VMContext,
+    };
     use std::convert::{TryFrom, TryInto};
+    use near_sdk::json_types::U64;
     use crate::constants::*;
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use crate::rfq::RfqQuote;
+    use ed25519_dalek::{Keypair, SecretKey, Signer};
+    use near_sdk::json_types::{Base58PublicKey, Base64VecU8};
+    use std::collections::HashMap;
 
     struct Accounts {
         current: AccountId,
@@ -543,6 +1068,21 @@ mod tests {
         return (ctx, contract);
     }
 
+    fn new_pool(
+        ynear: Balance,
+        tokens: Balance,
+        total_shares: Balance,
+        shares: LookupMap<AccountId, Balance>,
+    ) -> Pool {
+        let mut p = Pool::new(&"test".to_string());
+        p.ynear = ynear;
+        p.tokens = tokens;
+        p.total_shares = total_shares;
+        p.shares = shares;
+        p.twap = Twap::new(b"twap".to_vec(), 10);
+        return p;
+    }
+
     fn to_va(a: AccountId) -> ValidAccountId {
         ValidAccountId::try_from(a).unwrap()
     }
@@ -556,79 +1096,3097 @@ mod tests {
     }
 
     #[test]
-    fn add_to_whitelist_works() {
-        let (ctx, mut c) = init_with_owner();
+    fn add_to_whitelist_works() {
+        let (ctx, mut c) = init_with_owner();
+        let a = ctx.accounts.predecessor.clone();
+        // Add to nearswap whitelist
+        c.extend_whitelisted_tokens(vec![to_va("token1".into()), to_va("token2".into())]);
+
+        // Add to account deposit list
+        let mut d = account_deposit();
+        d.add_to_whitelist(&vec![to_va("token1".into()), to_va("token2".into())]);
+        c.deposits.insert(&a, &d.into());
+
+        // deposit should work
+        c.deposit_token(&a, &"token1".into(), 10);
+        let res = c.get_deposit(&a);
+        assert_eq!(res.tokens.get(&"token1".to_string()), Some(&10))
+    }
+
+    #[test]
+    #[should_panic(expected = r#"E23: Token is not whitelisted"#)]
+    fn add_to_whitelist_failure_1() {
+        let (ctx, mut c) = init_with_owner();
+        let a = ctx.accounts.predecessor.clone();
+        // Add to nearswap whitelist
+        c.extend_whitelisted_tokens(vec![to_va("token1".into()), to_va("token2".into())]);
+        let d = account_deposit();
+        c.deposits.insert(&a, &d.into());
+
+        // deposit should not work
+        // because token not whitelisted in account deposit
+        c.deposit_token(&a, &"token1".into(), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = r#"E23: Token is not whitelisted"#)]
+    fn add_to_whitelist_failure_2() {
+        let (ctx, mut c) = init_with_owner();
+        let a = ctx.accounts.predecessor.clone();
+
+        let d = account_deposit();
+        c.deposits.insert(&a, &d.into());
+
+        // deposit should not work
+        // because token not whitelisted in global list
+        c.deposit_token(&a.clone(), &"token1".into(), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = r#"E23: Token is not whitelisted"#)]
+    fn remove_from_whitelist_works_1() {
+        let (ctx, mut c) = init_with_owner();
+        let a = ctx.accounts.predecessor.clone();
+        c.extend_whitelisted_tokens(vec![to_va("token1".into()), to_va("token2".into())]);
+        let mut d = account_deposit();
+        d.add_to_whitelist(&vec![to_va("token1".into()), to_va("token2".into())]);
+        c.deposits.insert(&a, &d.into());
+        c.remove_whitelisted_token(to_va("token1".into()));
+
+        c.deposit_token(&a.clone(), &"token1".into(), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = r#"E23: Token is not whitelisted"#)]
+    fn remove_from_whitelist_works_2() {
+        let (ctx, mut c) = init_with_owner();
+        let a = ctx.accounts.predecessor.clone();
+        c.extend_whitelisted_tokens(vec![to_va("token1".into()), to_va("token2".into())]);
+        let mut d = account_deposit();
+        d.add_to_whitelist(&vec![to_va("token1".into()), to_va("token2".into())]);
+
+        d.remove_from_whitelist(&to_va("token1".into()));
+        c.deposits.insert(&a, &d.into());
+
+        c.deposit_token(&a.clone(), &"token1".into(), 10);
+    }
+
+    fn set_promise_result(ctx: &Ctx, result: PromiseResult) {
+        set_promise_results(ctx, vec![result]);
+    }
+
+    fn set_promise_results(ctx: &Ctx, results: Vec<PromiseResult>) {
+        // storage is carried over to the new mocked blockchain, so must be its usage.
+        let mut vm = ctx.vm.clone();
+        vm.storage_usage = env::storage_usage();
+        testing_env!(
+            vm,
+            VMConfig::default(),
+            RuntimeFeesConfig::default(),
+            Default::default(),
+            results
+        );
+    }
+
+    /// Creates the `token` pool as the `ctx` predecessor, resolving the NEP-141 probes.
+    fn create_pool_resolved(
+        ctx: &Ctx,
+        c: &mut NearSwap,
+        token: &AccountId,
+        price_bounds: Option<PriceBounds>,
+        template: Option<String>,
+    ) {
+        c.create_pool(to_va(token.clone()), price_bounds.clone(), template.clone());
+        let metadata = br#"{"spec":"ft-1.0.0","name":"Token","symbol":"TKN","icon":null,
+            "reference":null,"reference_hash":null,"decimals":24}"#;
+        set_promise_results(
+            ctx,
+            vec![
+                PromiseResult::Successful(metadata.to_vec()),
+                PromiseResult::Successful(b"\"1000\"".to_vec()),
+            ],
+        );
+        let creator = ctx.vm.predecessor_account_id.clone();
+        let attached = U128(ctx.vm.attached_deposit);
+        let template = c.select_pool_template(&template);
+        assert!(c.resolve_create_pool(token.clone(), creator, price_bounds, attached, template));
+    }
+
+    fn promise_value(p: PromiseOrValue<U128>) -> U128 {
+        match p {
+            PromiseOrValue::Value(v) => v,
+            PromiseOrValue::Promise(_) => panic!("expected a value"),
+        }
+    }
+
+    #[test]
+    fn resolve_withdraw_token_failed() {
+        let (ctx, mut c) = init();
+        let a = ctx.accounts.predecessor.clone();
+        // the deposit doesn't cover the transfer retry fee.
+        let mut d = account_deposit();
+        d.ynear = TRANSFER_RETRY_FEE - 1;
+        c.deposits.insert(&a, &d.into());
+
+        set_promise_result(&ctx, PromiseResult::Failed);
+        let used = c.resolve_withdraw_token(a.clone(), a.clone(), "eth".into(), U128(5), false);
+        assert_eq!(promise_value(used), U128(0));
+        assert_eq!(c.get_deposit(&a).tokens.get("eth"), Some(&16));
+    }
+
+    #[test]
+    fn resolve_withdraw_token_retry() {
+        let (ctx, mut c) = init();
+        let a = ctx.accounts.predecessor.clone();
+        c.deposits.insert(&a, &account_deposit().into());
+
+        set_promise_result(&ctx, PromiseResult::Failed);
+        let r = c.resolve_withdraw_token(a.clone(), "bob".into(), "eth".into(), U128(5), false);
+        assert!(matches!(r, PromiseOrValue::Promise(_)), "transfer should be retried");
+        let d = c.get_deposit(&a);
+        assert_eq!(d.ynear, NDENOM - TRANSFER_RETRY_FEE);
+        assert_eq!(d.tokens.get("eth"), Some(&11));
+
+        // the retry failed: the amount is claimable.
+        set_promise_result(&ctx, PromiseResult::Failed);
+        assert_eq!(c.resolve_retry_transfer(a.clone(), "eth".into(), U128(5)), U128(0));
+        assert_eq!(c.failed_transfers(a.clone(), Some("eth".into())), U128(5));
+        assert_eq!(c.get_deposit(&a).tokens.get("eth"), Some(&11));
+    }
+
+    #[test]
+    fn resolve_withdraw_token_call_refund() {
+        let (ctx, mut c) = init();
+        let a = ctx.accounts.predecessor.clone();
+        c.deposits.insert(&a, &account_deposit().into());
+
+        // receiver used only 3 out of 5 tokens
+        set_promise_result(&ctx, PromiseResult::Successful(b"\"3\"".to_vec()));
+        let used = c.resolve_withdraw_token(a.clone(), a.clone(), "eth".into(), U128(5), true);
+        assert_eq!(promise_value(used), U128(3));
+        assert_eq!(c.get_deposit(&a).tokens.get("eth"), Some(&13));
+        assert_eq!(c.recorded_token_balance("eth".into()), U128(2));
+
+        set_promise_result(&ctx, PromiseResult::Successful(vec![]));
+        let used = c.resolve_withdraw_token(a.clone(), a.clone(), "eth".into(), U128(5), false);
+        assert_eq!(promise_value(used), U128(5));
+        assert_eq!(c.get_deposit(&a).tokens.get("eth"), Some(&13));
+    }
+
+    #[test]
+    fn resolve_withdraw_near_failed() {
+        let (ctx, mut c) = init();
+        let a = ctx.accounts.predecessor.clone();
+        c.deposits.insert(&a, &account_deposit().into());
+
+        set_promise_result(&ctx, PromiseResult::Failed);
+        c.resolve_withdraw_near(a.clone(), U128(100));
+        assert_eq!(c.get_deposit(&a).ynear, NDENOM + 100);
+    }
+
+    #[test]
+    fn failed_transfer_for_unregistered_is_claimable() {
+        let (ctx, mut c) = _init(1);
+        let a = ctx.accounts.predecessor.clone();
+
+        set_promise_result(&ctx, PromiseResult::Failed);
+        c.resolve_withdraw_token(a.clone(), a.clone(), "eth".into(), U128(5), false);
+        c.resolve_withdraw_near(a.clone(), U128(100));
+        assert_eq!(c.failed_transfers(a.clone(), Some("eth".into())), U128(5));
+        assert_eq!(c.failed_transfers(a.clone(), None), U128(100));
+
+        c.claim_failed_transfers(Some("eth".into()));
+        assert_eq!(c.failed_transfers(a.clone(), Some("eth".into())), U128(0));
+        // the retried transfer failed again
+        c.resolve_claim_failed_transfer(a.clone(), Some("eth".into()), U128(5));
+        assert_eq!(c.failed_transfers(a, Some("eth".into())), U128(5));
+    }
+
+    fn set_block(ctx: &mut Ctx, block_index: u64) {
+        ctx.vm.block_index = block_index;
+        ctx.vm.storage_usage = env::storage_usage();
+        testing_env!(ctx.vm.clone());
+    }
+
+    fn prepare_for_swap_request() -> (Ctx, NearSwap) {
+        let (ctx, mut c) = _init(1);
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let p = new_pool(10 * NDENOM, 20 * NDENOM, 0, LookupMap::new(b"1".to_vec()));
+        c.set_pool(&t, &p);
+        let d = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: [(t.clone(), 2 * NDENOM)].iter().cloned().collect(),
+        };
+        c.deposits.insert(&a, &d.into());
+        (ctx, c)
+    }
+
+    #[test]
+    fn escrowed_swap_in_parts() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+
+        let id = c.request_swap(Some(t.clone()), U128(2 * NDENOM), None, U128(1), 2);
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&0));
+        let ynear_before = c.get_deposit(&a).ynear;
+
+        set_block(&mut ctx, SWAP_ESCROW_DELAY);
+        let out1 = c.execute_swap(id).0;
+        let r = c.swap_request(id).expect("swap request should exist");
+        assert_eq!(r.amount_in, U128(NDENOM));
+        assert_eq!(r.parts_left, 1);
+
+        set_block(&mut ctx, SWAP_ESCROW_DELAY + 1);
+        let out2 = c.execute_swap(id).0;
+        assert!(out2 < out1, "second chunk should get a worse price");
+        assert!(c.swap_request(id).is_none(), "swap request should be removed");
+        assert_eq!(c.get_deposit(&a).ynear, ynear_before + out1 + out2);
+        assert_eq!(c.get_pool(&t).tokens, 22 * NDENOM);
+    }
+
+    #[test]
+    #[should_panic(expected = "E29: Swap request can't be executed in this block yet")]
+    fn escrowed_swap_too_early() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+
+        let id = c.request_swap(Some(t), U128(2 * NDENOM), None, U128(1), 2);
+        set_block(&mut ctx, SWAP_ESCROW_DELAY);
+        c.execute_swap(id);
+        // next chunk must be executed in another block
+        c.execute_swap(id);
+    }
+
+    #[test]
+    fn escrowed_swap_cancel() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+
+        let id = c.request_swap(Some(t.clone()), U128(NDENOM), None, U128(1), 1);
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&NDENOM));
+        assert_eq!(c.cancel_swap(id), U128(NDENOM));
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(2 * NDENOM)));
+        assert!(c.swap_request(id).is_none(), "swap request should be removed");
+    }
+
+    fn set_predecessor(ctx: &mut Ctx, account: &AccountId) {
+        ctx.vm.predecessor_account_id = account.clone();
+        ctx.vm.storage_usage = env::storage_usage();
+        testing_env!(ctx.vm.clone());
+    }
+
+    #[test]
+    fn limit_order_partial_fills() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+
+        // pool price is 0.5 NEAR per token, limit: at least 0.4 NEAR per token.
+        let id = c.place_limit_order(
+            Some(t.clone()),
+            U128(2 * NDENOM),
+            None,
+            U128(8 * NDENOM / 10),
+        );
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&0));
+        let ynear_before = c.get_deposit(&a).ynear;
+
+        // keeper fills the order in two parts.
+        let keeper = ctx.accounts.alice.clone();
+        set_predecessor(&mut ctx, &keeper);
+        let out1 = c.fill_limit_order(id, Some(U128(NDENOM / 2))).0;
+        let o = c.limit_order(id).expect("order should exist");
+        assert_eq!(o.filled_in, U128(NDENOM / 2));
+        assert_eq!(o.filled_out, U128(out1));
+        assert_eq!(o.remaining(), 3 * NDENOM / 2);
+
+        let out2 = c.fill_limit_order(id, None).0;
+        assert!(c.limit_order(id).is_none(), "filled order should be removed");
+        assert_eq!(c.get_deposit(&a).ynear, ynear_before + out1 + out2);
+        assert_eq!(c.get_pool(&t).tokens, 22 * NDENOM);
+    }
+
+    #[test]
+    #[should_panic(expected = "E34: Pool price doesn't reach the order limit price")]
+    fn limit_order_price_not_reached() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+
+        // pool price is 0.5 NEAR per token, limit: at least 0.6 NEAR per token.
+        let id = c.place_limit_order(Some(t), U128(NDENOM), None, U128(6 * NDENOM / 10));
+        c.fill_limit_order(id, None);
+    }
+
+    #[test]
+    fn limit_order_cancel() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+
+        let id =
+            c.place_limit_order(Some(t.clone()), U128(2 * NDENOM), None, U128(NDENOM / 2));
+        c.fill_limit_order(id, Some(U128(NDENOM)));
+        assert_eq!(c.cancel_limit_order(id), U128(NDENOM));
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&NDENOM));
+        assert!(c.limit_order(id).is_none(), "order should be removed");
+    }
+
+    /// Records the `token` pool spot price in its TWAP oracle.
+    fn poke_oracle(c: &mut NearSwap, token: &AccountId) {
+        let mut p = c.get_pool(token);
+        p.poke_oracle();
+        c.set_pool(token, &p);
+    }
+
+    #[test]
+    fn trigger_order_stop_loss() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        // pool price is 0.5 NEAR per token.
+        poke_oracle(&mut c, &t);
+
+        let id = c.place_trigger_order(
+            Some(t.clone()),
+            U128(NDENOM),
+            None,
+            TriggerKind::StopLoss,
+            U128(6 * NDENOM / 10),
+            1500,
+        );
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&NDENOM));
+        let ynear_before = c.get_deposit(&a).ynear;
+
+        let keeper = ctx.accounts.alice.clone();
+        set_predecessor(&mut ctx, &keeper);
+        let out = c.execute_trigger_order(id).0;
+        assert!(out >= NDENOM / 2 * 85 / 100, "out {} is below the slippage bound", out);
+        assert_eq!(c.get_deposit(&a).ynear, ynear_before + out);
+        assert!(c.trigger_order(id).is_none(), "order should be removed");
+    }
+
+    #[test]
+    #[should_panic(expected = "E35: Pool price didn't cross the order trigger price")]
+    fn trigger_order_take_profit_not_reached() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        poke_oracle(&mut c, &t);
+
+        let id = c.place_trigger_order(
+            Some(t),
+            U128(NDENOM),
+            None,
+            TriggerKind::TakeProfit,
+            U128(NDENOM),
+            100,
+        );
+        let keeper = ctx.accounts.alice.clone();
+        set_predecessor(&mut ctx, &keeper);
+        c.execute_trigger_order(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "E35: Pool price didn't cross the order trigger price")]
+    fn trigger_order_ignores_spot_price() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        // TWAP price is 0.5 NEAR per token.
+        poke_oracle(&mut c, &t);
+
+        let id = c.place_trigger_order(
+            Some(t.clone()),
+            U128(NDENOM),
+            None,
+            TriggerKind::StopLoss,
+            U128(46 * NDENOM / 100),
+            1500,
+        );
+        // selling tokens pushes the spot price to ~0.45, below the trigger price.
+        c.swap_token_to_near_exact_in(t.clone(), U128(NDENOM), U128(1), None);
+        assert!(c.get_pool(&t).spot_price() < 46 * NDENOM / 100);
+        c.execute_trigger_order(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "E149: Pool oracle has no observations")]
+    fn trigger_order_without_twap() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        let id = c.place_trigger_order(
+            Some(t),
+            U128(NDENOM),
+            None,
+            TriggerKind::StopLoss,
+            U128(NDENOM),
+            100,
+        );
+        c.execute_trigger_order(id);
+    }
+
+    #[test]
+    fn trigger_order_token_to_token() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let (t1, t2) = (ctx.accounts.token1.clone(), ctx.accounts.token2.clone());
+        let mut p2 = new_pool(10 * NDENOM, 10 * NDENOM, 0, LookupMap::new(b"2".to_vec()));
+        p2.twap = Twap::new(b"twap2".to_vec(), 10);
+        c.set_pool(&t2, &p2);
+        poke_oracle(&mut c, &t1);
+        poke_oracle(&mut c, &t2);
+        // token1 is worth 0.5 token2.
+        assert_eq!(c.trigger_price(&Some(t1.clone()), &Some(t2.clone())), NDENOM / 2);
+
+        let id = c.place_trigger_order(
+            Some(t1.clone()),
+            U128(NDENOM),
+            Some(t2.clone()),
+            TriggerKind::TakeProfit,
+            U128(NDENOM / 2),
+            1500,
+        );
+        let keeper = ctx.accounts.alice.clone();
+        set_predecessor(&mut ctx, &keeper);
+        let out = c.execute_trigger_order(id).0;
+        assert!(out >= NDENOM / 2 * 85 / 100, "out {} is below the slippage bound", out);
+        assert_eq!(c.get_deposit(&a).tokens.get(&t2), Some(&out));
+    }
+
+    #[test]
+    fn dca_order_executes_on_schedule() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let keeper = ctx.accounts.alice.clone();
+        let k = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: [(t.clone(), 0)].iter().cloned().collect(),
+        };
+        c.deposits.insert(&keeper, &k.into());
+        poke_oracle(&mut c, &t);
+
+        let id =
+            c.place_dca_order(Some(t.clone()), U128(NDENOM), None, U128(NDENOM / 2), 10, 1000);
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&NDENOM));
+        let ynear_before = c.get_deposit(&a).ynear;
+
+        set_predecessor(&mut ctx, &keeper);
+        let out1 = c.execute_dca(id).0;
+        let fee = NDENOM / 2 * DCA_KEEPER_FEE_BPS / 10_000;
+        assert_eq!(c.get_deposit(&keeper).tokens.get(&t), Some(&fee));
+        assert_eq!(c.get_deposit(&a).ynear, ynear_before + out1);
+        let o = c.dca_order(id).expect("order should exist");
+        assert_eq!(o.budget, U128(NDENOM / 2));
+        assert_eq!(o.next_block, 10);
+
+        set_block(&mut ctx, 10);
+        let out2 = c.execute_dca(id).0;
+        assert!(out2 < out1, "price moves against the order");
+        assert_eq!(c.get_deposit(&keeper).tokens.get(&t), Some(&(2 * fee)));
+        assert!(c.dca_order(id).is_none(), "order should be removed");
+    }
+
+    #[test]
+    #[should_panic(expected = "E25: Swap output amount is less than required amount")]
+    fn dca_chunk_bounded_by_twap() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        // TWAP price is 0.5 NEAR per token.
+        poke_oracle(&mut c, &t);
+        let id = c.place_dca_order(Some(t.clone()), U128(NDENOM), None, U128(NDENOM / 2), 10, 100);
+
+        // a keeper sells tokens ahead of the chunk: the spot price drops ~10%.
+        let keeper = ctx.accounts.alice.clone();
+        let k = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: [(t.clone(), NDENOM)].iter().cloned().collect(),
+        };
+        c.deposits.insert(&keeper, &k.into());
+        set_predecessor(&mut ctx, &keeper);
+        c.swap_token_to_near_exact_in(t, U128(NDENOM), U128(1), None);
+        c.execute_dca(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "E37: DCA order chunk is not due yet")]
+    fn dca_order_not_due() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        poke_oracle(&mut c, &t);
+
+        let id = c.place_dca_order(Some(t), U128(NDENOM), None, U128(NDENOM / 2), 10, 1000);
+        c.execute_dca(id);
+        set_block(&mut ctx, 9);
+        c.execute_dca(id);
+    }
+
+    #[test]
+    fn trader_stats_accumulate() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        assert_eq!(c.trader_stats(a.clone()).swaps, 0);
+
+        let ynear_in = NDENOM / 2;
+        c.swap_near_to_token_exact_in(U128(ynear_in), t.clone(), U128(1), None);
+        let s = c.trader_stats(a.clone());
+        assert_eq!(s.swaps, 1);
+        assert_eq!(s.volume, U128(ynear_in));
+        assert_eq!(s.fees, U128(clp_math::calc_fee(ynear_in)));
+
+        let ynear_out = c.swap_token_to_near_exact_in(t, U128(NDENOM / 2), U128(1), None).0;
+        let s = c.trader_stats(a);
+        assert_eq!(s.swaps, 2);
+        assert_eq!(s.volume, U128(ynear_in + ynear_out));
+        // the token fee is ~0.3% of the NEAR leg.
+        let fee2 = s.fees.0 - clp_math::calc_fee(ynear_in);
+        assert!(fee2 > 0 && fee2 <= clp_math::calc_fee(ynear_out), "fee2 = {}", fee2);
+    }
+
+    #[test]
+    fn fee_rebates() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_rebate_tiers(vec![
+            RebateTier {
+                min_volume: U128(NDENOM / 4),
+                rebate_bps: 1000,
+            },
+            RebateTier {
+                min_volume: U128(NDENOM),
+                rebate_bps: 5000,
+            },
+        ]);
+
+        set_predecessor(&mut ctx, &a);
+        let ynear_in = NDENOM / 2;
+        c.swap_near_to_token_exact_in(U128(ynear_in), t, U128(1), None);
+        let fee = clp_math::calc_fee(ynear_in);
+        let rebate = fee / 10;
+        assert_eq!(c.trader_stats(a.clone()).rebate, U128(rebate));
+        assert_eq!(c.trader_volume_30d(a.clone()), U128(ynear_in));
+
+        // the fund limits the claim.
+        ctx.vm.attached_deposit = rebate / 2;
+        testing_env!(ctx.vm.clone());
+        c.fund_rebates();
+        ctx.vm.attached_deposit = 1;
+        testing_env!(ctx.vm.clone());
+        let ynear_before = c.get_deposit(&a).ynear;
+        assert_eq!(c.claim_rebate(), U128(rebate / 2));
+        assert_eq!(c.get_deposit(&a).ynear, ynear_before + rebate / 2);
+        assert_eq!(c.trader_stats(a).rebate, U128(rebate - rebate / 2));
+        assert_eq!(c.rebate_fund(), U128(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "E38: Rebate tiers must be ordered by min volume")]
+    fn rebate_tiers_must_be_ordered() {
+        let (mut ctx, mut c) = init();
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_rebate_tiers(vec![
+            RebateTier {
+                min_volume: U128(NDENOM),
+                rebate_bps: 1000,
+            },
+            RebateTier {
+                min_volume: U128(NDENOM / 4),
+                rebate_bps: 5000,
+            },
+        ]);
+    }
+
+    #[test]
+    fn liquidity_lock_boost() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &(5 * NDENOM));
+        let p = new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares);
+        c.set_pool(&t, &p);
+
+        c.lock_liquidity(t.clone(), U128(5 * NDENOM), 0);
+        assert_eq!(c.get_pool(&t).shares.get(&a), Some(0));
+        let l = c.liquidity_lock(t.clone(), a.clone()).expect("lock should exist");
+        assert_eq!(l.boost_bps, 12_500);
+
+        // boost weight: 5 * 0.25 = 1.25; fee cut: fee * 1.25 / (10 + 1.25)
+        let ynear_in = NDENOM / 2;
+        let fee = clp_math::calc_fee(ynear_in);
+        c.swap_near_to_token_exact_in(U128(ynear_in), t.clone(), U128(1), None);
+        let p = c.get_pool(&t);
+        assert_eq!(p.ynear, 10 * NDENOM + ynear_in - fee / 9);
+        let pending = c.liquidity_lock(t.clone(), a.clone()).unwrap().pending_ynear.0;
+        // accumulator rounding error is below boost_weight / ACC_DENOM.
+        assert!(
+            fee / 9 - pending <= 2 * NDENOM / ACC_DENOM,
+            "pending {}, expected {}",
+            pending,
+            fee / 9
+        );
+
+        ctx.vm.block_timestamp = LOCK_TIERS[0].0;
+        testing_env!(ctx.vm.clone());
+        let ynear_before = c.get_deposit(&a).ynear;
+        assert_eq!(c.unlock_liquidity(t.clone()), U128(5 * NDENOM));
+        assert_eq!(c.get_pool(&t).shares.get(&a), Some(5 * NDENOM));
+        assert_eq!(c.get_pool(&t).boost_weight, 0);
+        assert_eq!(c.get_deposit(&a).ynear, ynear_before + pending);
+    }
+
+    #[test]
+    #[should_panic(expected = "E44: Liquidity lock didn't expire yet")]
+    fn liquidity_lock_not_expired() {
+        let (_ctx, mut c) = prepare_for_swap_request();
+        let a = "predecessor".to_string();
+        let t = "token1".to_string();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &NDENOM);
+        c.set_pool(&t, &new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares));
+
+        c.lock_liquidity(t.clone(), U128(NDENOM), 2);
+        c.unlock_liquidity(t);
+    }
+
+    #[test]
+    fn croncat_tick_executes_dca() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        poke_oracle(&mut c, &t);
+        let id =
+            c.place_dca_order(Some(t.clone()), U128(NDENOM), None, U128(NDENOM / 2), 10, 1000);
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_croncat_manager(Some(to_va("croncat".to_string())));
+
+        set_predecessor(&mut ctx, &"croncat".to_string());
+        let ynear_before = c.get_deposit(&a).ynear;
+        c.croncat_tick(CronTask::ExecuteDca { order_id: id });
+        let o = c.dca_order(id).expect("order should exist");
+        assert_eq!(o.budget, U128(NDENOM / 2));
+        // no keeper fee: the whole chunk is swapped.
+        assert_eq!(c.get_deposit(&a).ynear, ynear_before + o.filled_out.0);
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&NDENOM));
+    }
+
+    #[test]
+    #[should_panic(expected = "E47: Only the croncat manager can call this function")]
+    fn croncat_tick_only_manager() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_croncat_manager(Some(to_va("croncat".to_string())));
+        c.croncat_tick(CronTask::PokeOracle { token: t });
+    }
+
+    #[test]
+    fn gas_overrides() {
+        let (mut ctx, mut c) = init();
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        let ft_transfer_gas = |c: &NearSwap| {
+            let (_, gas) = c.gas_config()[0];
+            gas.0
+        };
+        let default_gas = GasOp::FtTransfer.default_gas();
+        assert_eq!(ft_transfer_gas(&c), default_gas);
+
+        c.set_gas_override(GasOp::FtTransfer, Some((30 * TGAS).into()));
+        assert_eq!(ft_transfer_gas(&c), 30 * TGAS);
+        c.set_gas_override(GasOp::FtTransfer, None);
+        assert_eq!(ft_transfer_gas(&c), default_gas);
+    }
+
+    #[test]
+    fn estimate_gas() {
+        let (mut ctx, mut c) = init();
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        assert_eq!(c.estimate_gas(GasAction::Swap).0, GAS_EXEC_SWAP + GAS_RESERVE);
+        let withdraw = GAS_EXEC_WITHDRAW + GAS_RESERVE + GasOp::ResolveWithdraw.default_gas();
+        assert_eq!(
+            c.estimate_gas(GasAction::WithdrawTokenCall).0,
+            withdraw + GasOp::FtTransferCall.default_gas()
+        );
+        // includes the transfer retry.
+        assert_eq!(
+            c.estimate_gas(GasAction::WithdrawToken).0,
+            withdraw + GasOp::FtTransfer.default_gas() + c.retry_transfer_gas()
+        );
+
+        // owner overrides and calibrations are included.
+        c.set_gas_override(GasOp::FtTransferCall, Some((30 * TGAS).into()));
+        c.set_gas_estimate(GasAction::WithdrawTokenCall, Some((40 * TGAS).into()));
+        assert_eq!(
+            c.estimate_gas(GasAction::WithdrawTokenCall).0,
+            withdraw - GAS_EXEC_WITHDRAW + 70 * TGAS
+        );
+        c.set_gas_estimate(GasAction::WithdrawTokenCall, None);
+        assert_eq!(c.estimate_gas(GasAction::WithdrawTokenCall).0, withdraw + 30 * TGAS);
+    }
+
+    #[test]
+    #[should_panic(expected = "E48: Gas override is out of the allowed range")]
+    fn gas_override_out_of_range() {
+        let (mut ctx, mut c) = init();
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_gas_override(GasOp::FtTransfer, Some(TGAS.into()));
+    }
+
+    #[test]
+    fn batch_swap() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let quote_n2t = c.price_near_to_token_in(t.clone(), U128(NDENOM / 2)).0;
+
+        let outs = c.batch_swap(
+            vec![
+                SwapAction {
+                    token_in: None,
+                    amount_in: U128(NDENOM / 2),
+                    token_out: Some(t.clone()),
+                    min_out: U128(quote_n2t),
+                },
+                SwapAction {
+                    token_in: Some(t.clone()),
+                    amount_in: U128(NDENOM),
+                    token_out: None,
+                    min_out: U128(1),
+                },
+            ],
+            None,
+        );
+        assert_eq!(outs.len(), 2);
+        assert_eq!(outs[0], U128(quote_n2t));
+        let d = c.get_deposit(&a);
+        assert_eq!(d.tokens.get(&t), Some(&(NDENOM + quote_n2t)));
+        assert_eq!(d.ynear, NDENOM / 2 + outs[1].0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E25: Swap output amount is less than required amount")]
+    fn batch_swap_min_out_fails_whole_batch() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        let s = SwapAction {
+            token_in: None,
+            amount_in: U128(NDENOM / 2),
+            token_out: Some(t),
+            min_out: U128(NDENOM),
+        };
+        c.batch_swap(vec![s], None);
+    }
+
+    #[test]
+    fn swap_split() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let alice = ctx.accounts.alice.clone();
+        let d = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: HashMap::new(),
+        };
+        c.deposits.insert(&alice, &d.into());
+        let quote = c.price_near_to_token_in(t.clone(), U128(NDENOM / 2)).0;
+
+        let outs = c.swap_split(
+            None,
+            Some(t.clone()),
+            U128(NDENOM / 2),
+            U128(quote),
+            vec![(to_va(a.clone()), 7500), (to_va(alice.clone()), 2500)],
+            None,
+        );
+        let alice_out = quote / 4;
+        assert_eq!(outs, vec![U128(quote - alice_out), U128(alice_out)]);
+        let d = c.get_deposit(&a);
+        assert_eq!(d.ynear, NDENOM / 2);
+        assert_eq!(d.tokens.get(&t), Some(&(2 * NDENOM + quote - alice_out)));
+        assert_eq!(c.get_deposit(&alice).tokens.get(&t), Some(&alice_out));
+    }
+
+    #[test]
+    #[should_panic(expected = "E115")]
+    fn swap_split_invalid_bps() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        c.swap_split(None, Some(t), U128(NDENOM / 2), U128(1), vec![(to_va(a), 9000)], None);
+    }
+
+    #[test]
+    fn rebalance_to_target_weights() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+
+        // deposit: ~1 NEAR and 2 tokens worth 1 NEAR. Target: 25% NEAR, 75% tokens.
+        let swaps = c.rebalance(
+            vec![
+                TargetWeight {
+                    token: None,
+                    weight_bps: 2500,
+                },
+                TargetWeight {
+                    token: Some(t.clone()),
+                    weight_bps: 7500,
+                },
+            ],
+            1500,
+            None,
+        );
+        assert_eq!(swaps.len(), 1);
+        let s = &swaps[0];
+        assert!(s.token_in.is_none() && s.token_out == Some(t.clone()));
+        let d = c.get_deposit(&a);
+        assert_eq!(d.ynear, NDENOM - s.amount_in.0);
+        assert_eq!(d.tokens.get(&t), Some(&(2 * NDENOM + s.amount_out.0)));
+        let storage = d.storage_usage();
+        assert!(NDENOM / 2 - s.amount_in.0 <= storage, "spent {}", s.amount_in.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E51")]
+    fn rebalance_weights_must_sum_to_10000() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        c.rebalance(
+            vec![TargetWeight {
+                token: Some(t),
+                weight_bps: 5000,
+            }],
+            100,
+            None,
+        );
+    }
+
+    #[test]
+    fn zap_out_to_near() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &NDENOM);
+        c.set_pool(&t, &new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares));
+
+        // shares redeem 1 NEAR and 2 tokens, tokens are swapped in the pool left with 9 NEAR
+        // and 18 tokens.
+        let swapped = c.calc_out_with_fee(2 * NDENOM, 18 * NDENOM, 9 * NDENOM, clp_math::FEE_BPS).0;
+        let out = c.zap_out(t.clone(), U128(NDENOM), None, U128(NDENOM), None).0;
+        assert_eq!(out, NDENOM + swapped);
+        let d = c.get_deposit(&a);
+        assert_eq!(d.ynear, NDENOM + out);
+        assert_eq!(d.tokens.get(&t), Some(&(2 * NDENOM)));
+        assert_eq!(c.get_pool(&t).shares.get(&a), Some(0));
+    }
+
+    #[test]
+    fn farm_campaign_rewards() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &(2 * NDENOM));
+        c.set_pool(&t, &new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares));
+
+        // 0.01 tokens per second for 100 seconds.
+        c.create_reward_campaign(t.clone(), t.clone(), U128(NDENOM / 100), U64(100));
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&NDENOM));
+        c.farm_stake(t.clone(), U128(NDENOM));
+        assert_eq!(c.get_pool(&t).shares.get(&a), Some(NDENOM));
+
+        ctx.vm.block_timestamp = 50 * 1_000_000_000;
+        ctx.vm.storage_usage = env::storage_usage();
+        testing_env!(ctx.vm.clone());
+        let st = c.farm_stake_info(t.clone(), a.clone()).expect("stake should exist");
+        assert_eq!(st.rewards, vec![(t.clone(), U128(NDENOM / 2))]);
+        c.farm_unstake(t.clone(), U128(NDENOM));
+        assert_eq!(c.get_pool(&t).shares.get(&a), Some(2 * NDENOM));
+
+        // no stakers: the second half of the campaign goes back to the funder.
+        ctx.vm.block_timestamp = 200 * 1_000_000_000;
+        ctx.vm.storage_usage = env::storage_usage();
+        testing_env!(ctx.vm.clone());
+        assert_eq!(c.claim_rewards(t.clone()), vec![(t.clone(), U128(NDENOM / 2))]);
+        assert!(c.farm_stake_info(t.clone(), a.clone()).is_none());
+        assert_eq!(c.close_reward_campaign(t.clone(), t.clone()), U128(NDENOM / 2));
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(2 * NDENOM)));
+    }
+
+    #[test]
+    fn farm_concurrent_reward_tokens() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let eth = "eth".to_string();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &NDENOM);
+        c.set_pool(&t, &new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares));
+        let mut d = c.get_deposit(&a);
+        d.add(&eth, NDENOM);
+        c.deposits.insert(&a, &d.into());
+
+        c.create_reward_campaign(t.clone(), t.clone(), U128(NDENOM / 100), U64(100));
+        c.create_reward_campaign(t.clone(), eth.clone(), U128(NDENOM / 10), U64(10));
+        assert_eq!(c.farm_info(t.clone()).campaigns.len(), 2);
+        c.farm_stake(t.clone(), U128(NDENOM));
+
+        ctx.vm.block_timestamp = 20 * 1_000_000_000;
+        ctx.vm.storage_usage = env::storage_usage();
+        testing_env!(ctx.vm.clone());
+        let mut rewards = c.claim_rewards(t.clone());
+        rewards.sort_by(|x, y| x.0.cmp(&y.0));
+        assert_eq!(
+            rewards,
+            vec![(eth.clone(), U128(NDENOM)), (t.clone(), U128(NDENOM / 5))]
+        );
+        let d = c.get_deposit(&a);
+        assert_eq!(d.tokens.get(&eth), Some(&NDENOM));
+        assert_eq!(d.tokens.get(&t), Some(&(NDENOM + NDENOM / 5)));
+    }
+
+    #[test]
+    fn farm_early_unstake_penalty() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let alice = ctx.accounts.alice.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &NDENOM);
+        shares.insert(&alice, &NDENOM);
+        c.set_pool(&t, &new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares));
+        let d = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: [(t.clone(), 0)].iter().cloned().collect(),
+        };
+        c.deposits.insert(&alice, &d.into());
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_farm_unstake_policy(FarmUnstakePolicy {
+            min_period: (100 * 1_000_000_000).into(),
+            penalty_bps: 5000,
+        });
+
+        set_predecessor(&mut ctx, &a);
+        c.create_reward_campaign(t.clone(), t.clone(), U128(NDENOM / 100), U64(100));
+        c.farm_stake(t.clone(), U128(NDENOM));
+        set_predecessor(&mut ctx, &alice);
+        c.farm_stake(t.clone(), U128(NDENOM));
+
+        // both stakers accrued 0.25, half of it is forfeited by the early unstake.
+        ctx.vm.block_timestamp = 50 * 1_000_000_000;
+        set_predecessor(&mut ctx, &a);
+        c.farm_unstake(t.clone(), U128(NDENOM));
+        assert_eq!(c.claim_rewards(t.clone()), vec![(t.clone(), U128(NDENOM / 8))]);
+
+        ctx.vm.block_timestamp = 100 * 1_000_000_000;
+        set_predecessor(&mut ctx, &alice);
+        let expected = NDENOM / 4 + NDENOM / 8 + NDENOM / 2;
+        assert_eq!(c.claim_rewards(t.clone()), vec![(t.clone(), U128(expected))]);
+    }
+
+    #[test]
+    #[should_panic(expected = "E58: Farm stake is within the minimum staking period")]
+    fn farm_claim_within_min_period() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &NDENOM);
+        c.set_pool(&t, &new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares));
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_farm_unstake_policy(FarmUnstakePolicy {
+            min_period: 100.into(),
+            penalty_bps: 1000,
+        });
+
+        set_predecessor(&mut ctx, &a);
+        c.farm_stake(t.clone(), U128(NDENOM));
+        c.claim_rewards(t);
+    }
+
+    #[test]
+    #[should_panic(expected = "E52: Farm reward campaign is still active")]
+    fn farm_one_campaign_per_reward_token() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        c.create_reward_campaign(t.clone(), t.clone(), U128(1000), U64(100));
+        c.create_reward_campaign(t.clone(), t.clone(), U128(1000), U64(100));
+    }
+
+    #[test]
+    fn pool_fee_vote() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &(3 * NDENOM));
+        c.set_pool(&t, &new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares));
+        assert_eq!(c.pool_fee(t.clone()), clp_math::FEE_BPS);
+
+        let id = c.propose_pool_fee(t.clone(), 50);
+        assert_eq!(c.pool_fee_proposal(t.clone()), Some(id));
+        c.vote_pool_fee(id, true, U128(3 * NDENOM));
+        assert_eq!(c.get_pool(&t).shares.get(&a), Some(0));
+
+        let fp = c.fee_proposal(id).expect("proposal should exist");
+        ctx.vm.block_timestamp = fp.voting_end.0;
+        set_predecessor(&mut ctx, &a);
+        assert_eq!(c.withdraw_fee_vote(id), U128(3 * NDENOM));
+        assert_eq!(c.get_pool(&t).shares.get(&a), Some(3 * NDENOM));
+        assert_eq!(c.finalize_fee_proposal(id), ProposalStatus::Queued);
+        let action_id = c.fee_proposal(id).unwrap().action_id.expect("action should be queued");
+        assert_eq!(c.queued_actions(0, 10).len(), 1);
+
+        ctx.vm.block_timestamp = fp.voting_end.0 + GOV_TIMELOCK;
+        set_predecessor(&mut ctx, &a);
+        c.execute(action_id);
+        assert_eq!(c.fee_proposal(id).unwrap().status, ProposalStatus::Applied);
+        assert!(c.queued_actions(0, 10).is_empty());
+        assert_eq!(c.pool_fee(t.clone()), 50);
+        assert!(c.pool_fee_proposal(t.clone()).is_none());
+        let expected = clp_math::calc_out_with_fee_bps(NDENOM, 10 * NDENOM, 20 * NDENOM, 50).0;
+        assert_eq!(c.price_near_to_token_in(t, U128(NDENOM)), U128(expected));
+    }
+
+    #[test]
+    fn pool_fee_vote_without_quorum() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &NDENOM);
+        c.set_pool(&t, &new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares));
+
+        let id = c.propose_pool_fee(t.clone(), 50);
+        c.vote_pool_fee(id, true, U128(NDENOM));
+        ctx.vm.block_timestamp = FEE_VOTING_PERIOD;
+        set_predecessor(&mut ctx, &a);
+        assert_eq!(c.finalize_fee_proposal(id), ProposalStatus::Rejected);
+        assert_eq!(c.pool_fee(t), clp_math::FEE_BPS);
+    }
+
+    #[test]
+    #[should_panic(expected = "E65: Queued action timelock didn't expire yet")]
+    fn pool_fee_vote_timelock() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &(3 * NDENOM));
+        c.set_pool(&t, &new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares));
+
+        let id = c.propose_pool_fee(t, 50);
+        c.vote_pool_fee(id, true, U128(3 * NDENOM));
+        ctx.vm.block_timestamp = FEE_VOTING_PERIOD;
+        set_predecessor(&mut ctx, &a);
+        c.finalize_fee_proposal(id);
+        c.execute(c.fee_proposal(id).unwrap().action_id.unwrap());
+    }
+
+    #[test]
+    fn guardian_cancels_queued_action() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &(3 * NDENOM));
+        c.set_pool(&t, &new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares));
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_guardian(Some(to_va("guardian".to_string())));
+
+        set_predecessor(&mut ctx, &a);
+        let id = c.propose_pool_fee(t.clone(), 50);
+        c.vote_pool_fee(id, true, U128(3 * NDENOM));
+        ctx.vm.block_timestamp = FEE_VOTING_PERIOD;
+        set_predecessor(&mut ctx, &a);
+        c.finalize_fee_proposal(id);
+
+        set_predecessor(&mut ctx, &"guardian".to_string());
+        c.cancel_queued(c.fee_proposal(id).unwrap().action_id.unwrap());
+        assert_eq!(c.fee_proposal(id).unwrap().status, ProposalStatus::Cancelled);
+        assert!(c.pool_fee_proposal(t.clone()).is_none());
+        assert_eq!(c.pool_fee(t), clp_math::FEE_BPS);
+    }
+
+    fn prepare_delegated_votes(ctx: &mut Ctx, c: &mut NearSwap) -> u64 {
+        let a = ctx.accounts.predecessor.clone();
+        let alice = ctx.accounts.alice.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &NDENOM);
+        shares.insert(&alice, &(2 * NDENOM));
+        c.set_pool(&t, &new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares));
+        c.deposits.insert(&alice, &account_deposit().into());
+
+        set_predecessor(ctx, &alice);
+        c.delegate_votes(t.clone(), to_va(a.clone()), U128(2 * NDENOM));
+        set_predecessor(ctx, &a);
+        let id = c.propose_pool_fee(t.clone(), 50);
+        let vp = c.voting_power(t.clone(), a.clone());
+        assert_eq!((vp.shares, vp.delegated), (U128(NDENOM), U128(2 * NDENOM)));
+        c.vote_pool_fee(id, true, U128(NDENOM));
+        id
+    }
+
+    #[test]
+    fn pool_fee_vote_delegated() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let alice = ctx.accounts.alice.clone();
+        let t = ctx.accounts.token1.clone();
+        let id = prepare_delegated_votes(&mut ctx, &mut c);
+        let fp = c.fee_proposal(id).expect("proposal should exist");
+        assert_eq!(fp.yes, U128(3 * NDENOM));
+        assert_eq!(c.delegators(t.clone(), a), vec![alice.clone()]);
+
+        ctx.vm.block_timestamp = fp.voting_end.0;
+        set_predecessor(&mut ctx, &alice);
+        assert_eq!(c.undelegate_votes(t.clone()), U128(2 * NDENOM));
+        assert_eq!(c.get_pool(&t).shares.get(&alice), Some(2 * NDENOM));
+        assert!(c.delegation(t, alice).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "E74: Delegation was counted in a voting in progress")]
+    fn undelegate_during_voting() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let alice = ctx.accounts.alice.clone();
+        let t = ctx.accounts.token1.clone();
+        prepare_delegated_votes(&mut ctx, &mut c);
+        set_predecessor(&mut ctx, &alice);
+        c.undelegate_votes(t);
+    }
+
+    #[test]
+    #[should_panic(expected = "E135")]
+    fn pool_fee_vote_shares_after_proposal() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let alice = ctx.accounts.alice.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &NDENOM);
+        shares.insert(&alice, &NDENOM);
+        c.set_pool(&t, &new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares));
+        c.deposits.insert(&alice, &account_deposit().into());
+        let id = c.propose_pool_fee(t.clone(), 50);
+
+        // alice adds liquidity right after the proposal creation.
+        let mut p = c.get_pool(&t);
+        p.set_shares(&alice, 5 * NDENOM);
+        p.set_shares(&alice, 6 * NDENOM);
+        assert_eq!(p.voting_shares(&alice, id), NDENOM);
+        assert_eq!(p.voting_shares(&a, id), NDENOM);
+        c.set_pool(&t, &p);
+        set_predecessor(&mut ctx, &alice);
+        c.vote_pool_fee(id, true, U128(2 * NDENOM));
+    }
+
+    #[test]
+    fn pool_fee_vote_delegated_after_proposal() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let alice = ctx.accounts.alice.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &NDENOM);
+        c.set_pool(&t, &new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares));
+        c.deposits.insert(&alice, &account_deposit().into());
+        let id = c.propose_pool_fee(t.clone(), 50);
+
+        // alice acquires shares during the voting and delegates them.
+        let mut p = c.get_pool(&t);
+        p.set_shares(&alice, 2 * NDENOM);
+        c.set_pool(&t, &p);
+        set_predecessor(&mut ctx, &alice);
+        c.delegate_votes(t.clone(), to_va(a.clone()), U128(2 * NDENOM));
+        set_predecessor(&mut ctx, &a);
+        c.vote_pool_fee(id, true, U128(NDENOM));
+        assert_eq!(c.fee_proposal(id).unwrap().yes, U128(NDENOM));
+    }
+
+    #[test]
+    fn council_veto_and_pause() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let alice = ctx.accounts.alice.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &(3 * NDENOM));
+        c.set_pool(&t, &new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares));
+        c.deposits.insert(&alice, &account_deposit().into());
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_council(vec![to_va(a.clone()), to_va(alice.clone())], 2);
+
+        set_predecessor(&mut ctx, &a);
+        let id = c.propose_pool_fee(t.clone(), 50);
+        c.vote_pool_fee(id, true, U128(3 * NDENOM));
+        ctx.vm.block_timestamp = FEE_VOTING_PERIOD;
+        set_predecessor(&mut ctx, &a);
+        c.finalize_fee_proposal(id);
+        let veto = CouncilMotion::Veto {
+            action_id: c.fee_proposal(id).unwrap().action_id.unwrap(),
+        };
+        assert!(!c.council_confirm(veto.clone()));
+        assert_eq!(c.council_confirmations(veto.clone()), vec![a.clone()]);
+        assert_eq!(c.fee_proposal(id).unwrap().status, ProposalStatus::Queued);
+        set_predecessor(&mut ctx, &alice);
+        assert!(c.council_confirm(veto.clone()));
+        assert_eq!(c.fee_proposal(id).unwrap().status, ProposalStatus::Cancelled);
+        assert!(c.council_confirmations(veto).is_empty());
+
+        assert!(!c.council_confirm(CouncilMotion::Pause));
+        c.council_revoke(CouncilMotion::Pause);
+        assert!(c.council_motions().is_empty());
+        assert!(!c.council_confirm(CouncilMotion::Pause));
+        set_predecessor(&mut ctx, &a);
+        assert!(c.council_confirm(CouncilMotion::Pause));
+        assert!(c.is_paused());
+        set_predecessor(&mut ctx, &owner);
+        c.unpause();
+        assert!(!c.is_paused());
+    }
+
+    #[test]
+    #[should_panic(expected = "E80: Contract is paused")]
+    fn swap_when_paused() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_council(vec![to_va(a.clone())], 1);
+        set_predecessor(&mut ctx, &a);
+        assert!(c.council_confirm(CouncilMotion::Pause));
+        let t = ctx.accounts.token1.clone();
+        c.swap_near_to_token_exact_in(U128(NDENOM / 10), t, U128(1), None);
+    }
+
+    #[test]
+    fn health_check_flags() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let h = c.health_check(0, 10);
+        assert_eq!(h.len(), 1);
+        assert!(!h[0].reserves_consistent);
+        assert_eq!(h[0].flags, vec![HealthFlag::Empty, HealthFlag::StaleOracle]);
+
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&a, &(3 * NDENOM));
+        let mut p = new_pool(10 * NDENOM, 20 * NDENOM, 10 * NDENOM, shares);
+        p.poke_oracle();
+        c.set_pool(&t, &p);
+        c.propose_pool_fee(t.clone(), 50);
+        let h = c.health_check(0, 10);
+        assert!(h[0].reserves_consistent);
+        assert_eq!(h[0].flags, vec![HealthFlag::FeeVoting]);
+
+        ctx.vm.block_timestamp = ORACLE_STALE_PERIOD + 1;
+        set_predecessor(&mut ctx, &a);
+        assert_eq!(
+            c.health_check(0, 10)[0].flags,
+            vec![HealthFlag::StaleOracle, HealthFlag::FeeVoting]
+        );
+        assert!(c.health_check(1, 10).is_empty());
+    }
+
+    fn prepare_audit(ctx: &mut Ctx, c: &mut NearSwap) {
+        let a = ctx.accounts.predecessor.clone();
+        let alice = ctx.accounts.alice.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut p = new_pool(10 * NDENOM, 20 * NDENOM, 6 * NDENOM, LookupMap::new(b"1".to_vec()));
+        p.set_shares(&a, 3 * NDENOM);
+        p.set_shares(&alice, 3 * NDENOM);
+        c.set_pool(&t, &p);
+        c.deposits.insert(&alice, &account_deposit().into());
+
+        set_predecessor(ctx, &alice);
+        c.delegate_votes(t.clone(), to_va(a.clone()), U128(NDENOM));
+        c.farm_stake(t.clone(), U128(NDENOM));
+        set_predecessor(ctx, &a);
+        c.lock_liquidity(t.clone(), U128(NDENOM), 0);
+        let id = c.propose_pool_fee(t.clone(), 50);
+        c.vote_pool_fee(id, true, U128(NDENOM));
+    }
+
+    #[test]
+    fn liquidity_providers_views() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let (a, alice) = (ctx.accounts.predecessor.clone(), ctx.accounts.alice.clone());
+        let t = ctx.accounts.token1.clone();
+        let mut p = new_pool(10 * NDENOM, 20 * NDENOM, 6 * NDENOM, LookupMap::new(b"1".to_vec()));
+        p.set_shares(&a, 3 * NDENOM);
+        p.set_shares(&alice, 3 * NDENOM);
+        p.set_shares(&a, 2 * NDENOM);
+        c.set_pool(&t, &p);
+        assert_eq!(c.n_liquidity_providers(t.clone()), 2);
+        assert_eq!(
+            c.get_liquidity_providers(t.clone(), 1, 10),
+            vec![(alice.clone(), U128(3 * NDENOM))]
+        );
+
+        p.set_shares(&alice, 0);
+        c.set_pool(&t, &p);
+        assert_eq!(c.n_liquidity_providers(t.clone()), 1);
+        assert_eq!(
+            c.get_liquidity_providers(t, 0, 10),
+            vec![(a, U128(2 * NDENOM))]
+        );
+    }
+
+    #[test]
+    fn top_lps_index() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let (a, alice) = (ctx.accounts.predecessor.clone(), ctx.accounts.alice.clone());
+        let t = ctx.accounts.token1.clone();
+        let mut p = new_pool(10 * NDENOM, 20 * NDENOM, 6 * NDENOM, LookupMap::new(b"1".to_vec()));
+        p.set_shares(&a, 3 * NDENOM);
+        p.set_shares(&alice, 4 * NDENOM);
+        c.set_pool(&t, &p);
+        assert_eq!(
+            c.top_lps(t.clone(), 10),
+            vec![(alice.clone(), U128(4 * NDENOM)), (a.clone(), U128(3 * NDENOM))]
+        );
+        assert_eq!(c.top_lps(t.clone(), 1).len(), 1);
+
+        p.set_shares(&alice, NDENOM);
+        p.set_shares(&a, 0);
+        c.set_pool(&t, &p);
+        assert_eq!(c.top_lps(t, 10), vec![(alice, U128(NDENOM))]);
+    }
+
+    #[test]
+    fn refresh_top_lps_adds_missed_lps() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let (a, alice) = (ctx.accounts.predecessor.clone(), ctx.accounts.alice.clone());
+        let t = ctx.accounts.token1.clone();
+        let mut shares = LookupMap::new(b"1".to_vec());
+        shares.insert(&alice, &NDENOM);
+        let mut p = new_pool(10 * NDENOM, 20 * NDENOM, 3 * NDENOM, shares);
+        p.set_shares(&a, 2 * NDENOM);
+        c.set_pool(&t, &p);
+        assert_eq!(c.top_lps(t.clone(), 10).len(), 1);
+
+        c.refresh_top_lps(t.clone(), vec![alice.clone()]);
+        assert_eq!(
+            c.top_lps(t, 10),
+            vec![(a, U128(2 * NDENOM)), (alice, U128(NDENOM))]
+        );
+    }
+
+    #[test]
+    fn audit_shares_in_chunks() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        prepare_audit(&mut ctx, &mut c);
+        assert_eq!(c.get_pool(&t).vote_escrow, NDENOM);
+
+        let s = c.audit_shares(t.clone(), 0, 1);
+        assert_eq!((s.cursor, s.holders, s.result), (1, 2, None));
+        let s = c.audit_shares(t.clone(), s.cursor, 10);
+        assert_eq!((s.cursor, s.sum, s.result), (2, U128(6 * NDENOM), Some(true)));
+        assert_eq!(c.health_check(0, 1)[0].shares_audit, Some(true));
+
+        let mut p = c.get_pool(&t);
+        p.total_shares += 1;
+        c.set_pool(&t, &p);
+        assert_eq!(c.audit_shares(t.clone(), 0, 10).result, Some(false));
+        assert_eq!(c.health_check(0, 1)[0].shares_audit, Some(false));
+    }
+
+    #[test]
+    #[should_panic(expected = "E84: Pool shares changed during the audit")]
+    fn audit_shares_outdated() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        let alice = ctx.accounts.alice.clone();
+        prepare_audit(&mut ctx, &mut c);
+        c.audit_shares(t.clone(), 0, 1);
+        set_predecessor(&mut ctx, &alice);
+        c.farm_unstake(t.clone(), U128(NDENOM));
+        c.audit_shares(t, 1, 10);
+    }
+
+    #[test]
+    fn verify_reserves_underfunded() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        c.record_token_in(&t, 22 * NDENOM);
+        c.record_token_out(&t, NDENOM);
+        assert_eq!(c.recorded_token_balance(t.clone()), U128(21 * NDENOM));
+
+        let balance = format!("\"{}\"", 21 * NDENOM).into_bytes();
+        set_promise_result(&ctx, PromiseResult::Successful(balance));
+        let r = c.resolve_verify_reserves(t.clone(), true).unwrap();
+        assert!(!r.underfunded);
+        assert!(!c.get_pool(&t).paused);
+
+        let balance = format!("\"{}\"", 20 * NDENOM).into_bytes();
+        set_promise_result(&ctx, PromiseResult::Successful(balance));
+        let r = c.resolve_verify_reserves(t.clone(), true).unwrap();
+        assert!(r.underfunded);
+        assert_eq!((r.recorded, r.pool_tokens), (U128(21 * NDENOM), U128(20 * NDENOM)));
+        assert!(c.get_pool(&t).paused);
+        assert!(c.health_check(0, 1)[0].flags.contains(&HealthFlag::Paused));
+
+        set_promise_result(&ctx, PromiseResult::Failed);
+        assert!(c.resolve_verify_reserves(t.clone(), true).is_none());
+
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.unpause_pool(t.clone());
+        assert!(!c.get_pool(&t).paused);
+    }
+
+    #[test]
+    #[should_panic(expected = "E85: Pool is paused")]
+    fn swap_in_paused_pool() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        let mut p = c.get_pool(&t);
+        p.paused = true;
+        c.set_pool(&t, &p);
+        c.swap_near_to_token_exact_in(U128(NDENOM / 10), t, U128(1), None);
+    }
+
+    #[test]
+    fn guardian_pauses_pool() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_guardian(Some(to_va("guardian".to_string())));
+        set_predecessor(&mut ctx, &"guardian".to_string());
+        c.pause_pool(t.clone());
+        assert!(c.get_pool(&t).paused);
+        assert!(!c.is_paused());
+
+        set_predecessor(&mut ctx, &owner);
+        c.unpause_pool(t.clone());
+        assert!(!c.get_pool(&t).paused);
+    }
+
+    #[test]
+    #[should_panic(expected = "E85: Pool is paused")]
+    fn deposit_to_paused_pool() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_guardian(Some(to_va("guardian".to_string())));
+        set_predecessor(&mut ctx, &"guardian".to_string());
+        c.pause_pool(t.clone());
+        set_predecessor(&mut ctx, &t);
+        c.ft_on_transfer(to_va(a), U128(NDENOM), String::new());
+    }
+
+    #[test]
+    fn donate_to_pool() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let mut p = c.get_pool(&t);
+        p.total_shares = 10 * NDENOM;
+        c.set_pool(&t, &p);
+
+        ctx.vm.attached_deposit = NDENOM;
+        set_predecessor(&mut ctx, &a);
+        c.donate_near(t.clone());
+        set_predecessor(&mut ctx, &t);
+        c.ft_on_transfer(to_va(a.clone()), U128(2 * NDENOM), DONATE_MSG.to_string());
+        let p = c.get_pool(&t);
+        assert_eq!(
+            (p.ynear, p.tokens, p.total_shares),
+            (11 * NDENOM, 22 * NDENOM, 10 * NDENOM)
+        );
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(2 * NDENOM)));
+        assert_eq!(c.recorded_token_balance(t), U128(2 * NDENOM));
+    }
+
+    #[test]
+    #[should_panic(expected = "E86: Can't donate to a pool without liquidity")]
+    fn donate_to_empty_pool() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        set_predecessor(&mut ctx, &t);
+        c.ft_on_transfer(to_va(ctx.accounts.alice.clone()), U128(NDENOM), DONATE_MSG.to_string());
+    }
+
+    #[test]
+    fn pool_collections_are_separated() {
+        let (ctx, _c) = init();
+        let a = ctx.accounts.predecessor.clone();
+        let mut p1 = Pool::new(&"t1".to_string());
+        let mut p2 = Pool::new(&"t2".to_string());
+        p1.set_shares(&a, NDENOM);
+        p1.twap.log_observation(1, 1, 1);
+        assert_eq!(p2.shares.get(&a), None);
+        assert!(p2.twap.last_observation().is_none());
+        p2.set_shares(&a, 2 * NDENOM);
+        assert_eq!(p1.shares.get(&a), Some(NDENOM));
+    }
+
+    #[test]
+    fn pool_storage_tracking() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let s = c.pool_storage(t.clone());
+        assert_eq!(s.collections, U64(0));
+        assert!(s.entry.0 > 3 * STORAGE_RECORD_OVERHEAD);
+
+        let mut p = c.get_pool(&t);
+        let start = env::storage_usage();
+        p.set_shares(&a, NDENOM);
+        let used = env::storage_usage() - start;
+        assert!(used > 0);
+        p.set_shares(&a, 2 * NDENOM);
+        c.set_pool(&t, &p);
+        let s = c.pool_storage(t.clone());
+        assert_eq!(s.collections, U64(used));
+        assert_eq!(s.total.0, s.entry.0 + used);
+    }
+
+    fn set_pool_bond(ctx: &mut Ctx, c: &mut NearSwap) {
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(ctx, &owner);
+        c.set_pool_bond_config(PoolBondConfig {
+            amount: U128(NDENOM),
+            min_liquidity: U128(5 * NDENOM),
+        });
+        c.set_guardian(Some(to_va("guardian".to_string())));
+    }
+
+    #[test]
+    fn pool_creation_bond() {
+        let (mut ctx, mut c) = init();
+        let alice = ctx.accounts.alice.clone();
+        let t = ctx.accounts.token1.clone();
+        set_pool_bond(&mut ctx, &mut c);
+        ctx.vm.attached_deposit = NDENOM;
+        set_predecessor(&mut ctx, &alice);
+        create_pool_resolved(&ctx, &mut c, &t, None, None);
+        let b = c.pool_bond(t.clone()).expect("bond should be recorded");
+        assert_eq!((b.creator, b.amount), (alice, U128(NDENOM)));
+
+        let mut p = c.get_pool(&t);
+        p.ynear = 5 * NDENOM;
+        c.set_pool(&t, &p);
+        c.refund_pool_bond(t.clone());
+        assert!(c.pool_bond(t).is_none());
+    }
+
+    #[test]
+    fn slash_junk_pool_bond() {
+        let (mut ctx, mut c) = init();
+        let t = ctx.accounts.token1.clone();
+        set_pool_bond(&mut ctx, &mut c);
+        ctx.vm.attached_deposit = 2 * NDENOM;
+        let alice = ctx.accounts.alice.clone();
+        set_predecessor(&mut ctx, &alice);
+        create_pool_resolved(&ctx, &mut c, &t, None, None);
+
+        set_predecessor(&mut ctx, &"guardian".to_string());
+        c.slash_pool_bond(t.clone(), true);
+        assert!(c.pool_bond(t).is_none());
+        assert!(c.list_pools().is_empty());
+        assert_eq!(c.protocol_fees(None), U128(2 * NDENOM));
+    }
+
+    #[test]
+    #[should_panic(expected = "E89: Pool liquidity is below the bond refund threshold")]
+    fn refund_pool_bond_low_liquidity() {
+        let (mut ctx, mut c) = init();
+        let t = ctx.accounts.token1.clone();
+        set_pool_bond(&mut ctx, &mut c);
+        ctx.vm.attached_deposit = NDENOM;
+        let alice = ctx.accounts.alice.clone();
+        set_predecessor(&mut ctx, &alice);
+        create_pool_resolved(&ctx, &mut c, &t, None, None);
+        c.refund_pool_bond(t);
+    }
+
+    #[test]
+    #[should_panic(expected = "E87: Attached deposit is below the pool creation bond")]
+    fn create_pool_without_bond() {
+        let (mut ctx, mut c) = init();
+        set_pool_bond(&mut ctx, &mut c);
+        c.create_pool(to_va(ctx.accounts.token1.clone()), None, None);
+    }
+
+    fn create_alice_pool(ctx: &mut Ctx, c: &mut NearSwap) -> AccountId {
+        let (t, alice) = (ctx.accounts.token1.clone(), ctx.accounts.alice.clone());
+        set_predecessor(ctx, &alice);
+        create_pool_resolved(ctx, c, &t, None, None);
+        let d = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: HashMap::new(),
+        };
+        c.deposits.insert(&alice, &d.into());
+        t
+    }
+
+    #[test]
+    fn pool_creator_controls() {
+        let (mut ctx, mut c) = init();
+        let t = create_alice_pool(&mut ctx, &mut c);
+        let alice = ctx.accounts.alice.clone();
+        c.set_pool_metadata(t.clone(), "Alice token".into(), Some("https://a.b".into()));
+        assert_eq!(
+            c.pool_metadata(t.clone()),
+            Some(PoolMetadata {
+                creator: alice,
+                description: "Alice token".into(),
+                reference: Some("https://a.b".into()),
+                creator_campaigns_only: false,
+            })
+        );
+        c.set_pool_campaign_policy(t.clone(), true);
+        assert!(c.pool_metadata(t.clone()).unwrap().creator_campaigns_only);
+        c.pause_own_pool(t.clone());
+        assert!(c.get_pool(&t).paused);
+    }
+
+    #[test]
+    #[should_panic(expected = "E109: Only the pool creator can do this")]
+    fn pool_creator_controls_other_account() {
+        let (mut ctx, mut c) = init();
+        let t = create_alice_pool(&mut ctx, &mut c);
+        let a = ctx.accounts.predecessor.clone();
+        set_predecessor(&mut ctx, &a);
+        c.pause_own_pool(t);
+    }
+
+    #[test]
+    #[should_panic(expected = "E109: Only the pool creator can do this")]
+    fn pool_creator_campaigns_only() {
+        let (mut ctx, mut c) = init();
+        let t = create_alice_pool(&mut ctx, &mut c);
+        c.set_pool_campaign_policy(t.clone(), true);
+        let a = ctx.accounts.predecessor.clone();
+        ctx.vm.attached_deposit = 1;
+        set_predecessor(&mut ctx, &a);
+        c.create_reward_campaign(t, "reward".into(), U128(1), U64(10));
+    }
+
+    fn set_rfq_maker(ctx: &mut Ctx, c: &mut NearSwap) -> Keypair {
+        let secret = SecretKey::from_bytes(&[7; 32]).unwrap();
+        let public: ed25519_dalek::PublicKey = (&secret).into();
+        let mut key = vec![0];
+        key.extend(public.as_bytes());
+        let (owner, alice) = (ctx.accounts.owner.clone(), ctx.accounts.alice.clone());
+        set_predecessor(ctx, &owner);
+        c.set_rfq_maker(to_va(alice.clone()), Some(Base58PublicKey(key)));
+        let d = DepositV1 {
+            ynear: 6 * NDENOM,
+            storage_used: 84,
+            tokens: HashMap::new(),
+        };
+        c.deposits.insert(&alice, &d.into());
+        set_predecessor(ctx, &alice);
+        c.rfq_deposit(None, U128(5 * NDENOM));
+        Keypair { secret, public }
+    }
+
+    fn rfq_quote(ctx: &Ctx, expiry: u64) -> RfqQuote {
+        RfqQuote {
+            maker: ctx.accounts.alice.clone(),
+            taker: None,
+            token_in: Some(ctx.accounts.token1.clone()),
+            token_out: None,
+            amount_in: U128(NDENOM),
+            amount_out: U128(NDENOM / 2),
+            expiry: U64(expiry),
+            nonce: U64(1),
+        }
+    }
+
+    fn sign_quote(ctx: &Ctx, kp: &Keypair, q: &RfqQuote) -> Base64VecU8 {
+        let mut msg = ctx.accounts.current.try_to_vec().unwrap();
+        msg.extend(q.try_to_vec().unwrap());
+        Base64VecU8(kp.sign(&env::sha256(&msg)).to_bytes().to_vec())
+    }
+
+    #[test]
+    fn rfq_quote_swap() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let maker = ctx.accounts.alice.clone();
+        let kp = set_rfq_maker(&mut ctx, &mut c);
+        assert_eq!(c.rfq_inventory(maker.clone(), None), U128(5 * NDENOM));
+
+        let q = rfq_quote(&ctx, 1000);
+        let sig = sign_quote(&ctx, &kp, &q);
+        set_predecessor(&mut ctx, &a);
+        assert_eq!(c.swap_rfq(q, sig, U128(1)), U128(NDENOM / 2));
+        let d = c.get_deposit(&a);
+        assert_eq!((d.ynear, d.tokens.get(&t)), (NDENOM * 3 / 2, Some(&NDENOM)));
+        assert_eq!(c.rfq_inventory(maker.clone(), None), U128(NDENOM * 9 / 2));
+        assert_eq!(c.rfq_inventory(maker, Some(t.clone())), U128(NDENOM));
+        // pool is not touched
+        assert_eq!(c.get_pool(&t).tokens, 20 * NDENOM);
+    }
+
+    #[test]
+    #[should_panic(expected = "E93: Quote was already taken")]
+    fn rfq_quote_taken_once() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let kp = set_rfq_maker(&mut ctx, &mut c);
+        let q = rfq_quote(&ctx, 1000);
+        let sig = sign_quote(&ctx, &kp, &q);
+        set_predecessor(&mut ctx, &a);
+        c.swap_rfq(q.clone(), sig.clone(), U128(1));
+        c.swap_rfq(q, sig, U128(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "E92: Invalid quote signature")]
+    fn rfq_quote_invalid_signature() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let kp = set_rfq_maker(&mut ctx, &mut c);
+        let mut q = rfq_quote(&ctx, 1000);
+        let sig = sign_quote(&ctx, &kp, &q);
+        q.amount_out = U128(NDENOM);
+        set_predecessor(&mut ctx, &a);
+        c.swap_rfq(q, sig, U128(1));
+    }
+
+    #[test]
+    fn rfq_expired_quote_swaps_in_pool() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let kp = set_rfq_maker(&mut ctx, &mut c);
+        let q = rfq_quote(&ctx, 0);
+        let sig = sign_quote(&ctx, &kp, &q);
+        let amm_out = c.price_token_to_near_in(t.clone(), U128(NDENOM));
+        set_predecessor(&mut ctx, &a);
+        assert_eq!(c.swap_rfq(q, sig, U128(1)), amm_out);
+        assert_eq!(c.get_pool(&t).tokens, 21 * NDENOM);
+        assert_eq!(c.rfq_inventory(ctx.accounts.alice.clone(), None), U128(5 * NDENOM));
+    }
+
+    fn place_ask(ctx: &mut Ctx, c: &mut NearSwap, price: Balance, amount: Balance) -> u64 {
+        let (alice, t) = (ctx.accounts.alice.clone(), ctx.accounts.token1.clone());
+        if c.deposits.get(&alice).is_none() {
+            let d = DepositV1 {
+                ynear: NDENOM,
+                storage_used: 84,
+                tokens: [(t.clone(), 10 * NDENOM)].iter().cloned().collect(),
+            };
+            c.deposits.insert(&alice, &d.into());
+        }
+        set_predecessor(ctx, &alice);
+        c.place_book_order(t, BookSide::Ask, U128(price), U128(amount))
+    }
+
+    #[test]
+    fn book_order_filled_before_pool() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let alice = ctx.accounts.alice.clone();
+        // pool price is 0.5 NEAR per token.
+        let id = place_ask(&mut ctx, &mut c, 4 * NDENOM / 10, 2 * NDENOM);
+        let alice_ynear = c.get_deposit(&alice).ynear;
+
+        set_predecessor(&mut ctx, &a);
+        let out = c.swap_near_to_token_exact_in(U128(NDENOM / 2), t.clone(), U128(1), None);
+        assert_eq!(out, U128(NDENOM * 5 / 4));
+        assert_eq!(c.get_pool(&t).tokens, 20 * NDENOM);
+        assert_eq!(c.get_deposit(&alice).ynear, alice_ynear + NDENOM / 2);
+        let asks = c.book_orders(t.clone(), BookSide::Ask);
+        assert_eq!((asks[0].id, asks[0].amount), (id, U128(NDENOM * 3 / 4)));
+
+        // the rest of the order is filled and the swap continues in the pool.
+        let amm_out = c.price_near_to_token_in(t.clone(), U128(NDENOM / 10));
+        let out = c.swap_near_to_token_exact_in(U128(NDENOM * 4 / 10), t.clone(), U128(1), None);
+        assert_eq!(out.0, NDENOM * 3 / 4 + amm_out.0);
+        assert!(c.book_orders(t, BookSide::Ask).is_empty());
+    }
+
+    #[test]
+    fn book_order_worse_than_pool() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        place_ask(&mut ctx, &mut c, 6 * NDENOM / 10, 2 * NDENOM);
+        let amm_out = c.price_near_to_token_in(t.clone(), U128(NDENOM / 2));
+        set_predecessor(&mut ctx, &a);
+        let out = c.swap_near_to_token_exact_in(U128(NDENOM / 2), t.clone(), U128(1), None);
+        assert_eq!(out, amm_out);
+        assert_eq!(c.book_orders(t, BookSide::Ask)[0].amount, U128(2 * NDENOM));
+    }
+
+    #[test]
+    fn book_depth_and_cancel() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        let alice = ctx.accounts.alice.clone();
+        let id1 = place_ask(&mut ctx, &mut c, 6 * NDENOM / 10, 2 * NDENOM);
+        let id2 = place_ask(&mut ctx, &mut c, 5 * NDENOM / 10, NDENOM);
+        let id3 = place_ask(&mut ctx, &mut c, 6 * NDENOM / 10, NDENOM);
+        let ids: Vec<u64> = c.book_orders(t.clone(), BookSide::Ask).iter().map(|o| o.id).collect();
+        assert_eq!(ids, vec![id2, id1, id3]);
+        assert_eq!(
+            c.book_depth(t.clone(), BookSide::Ask, 10),
+            vec![
+                BookLevel {
+                    price: U128(5 * NDENOM / 10),
+                    amount: U128(NDENOM),
+                    orders: 1
+                },
+                BookLevel {
+                    price: U128(6 * NDENOM / 10),
+                    amount: U128(3 * NDENOM),
+                    orders: 2
+                },
+            ]
+        );
+        assert_eq!(c.book_depth(t.clone(), BookSide::Ask, 1).len(), 1);
+
+        assert_eq!(c.cancel_book_order(t.clone(), id1), U128(2 * NDENOM));
+        assert_eq!(c.get_deposit(&alice).tokens.get(&t), Some(&(8 * NDENOM)));
+        assert_eq!(c.book_orders(t, BookSide::Ask).len(), 2);
+    }
+
+    fn set_time(ctx: &mut Ctx, seconds: u64) {
+        ctx.vm.block_timestamp = seconds * 1_000_000_000;
+        ctx.vm.storage_usage = env::storage_usage();
+        testing_env!(ctx.vm.clone());
+    }
+
+    #[test]
+    fn twamm_order_sells_over_time() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let id = c.place_twamm_order(t.clone(), false, U128(2 * NDENOM), 1);
+        let rate = 2 * NDENOM / TWAMM_INTERVAL as u128;
+        assert_eq!(c.twamm_rates(t.clone()), (U128(0), U128(rate)));
+        let escrow = rate * TWAMM_INTERVAL as u128;
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(2 * NDENOM - escrow)));
+
+        // half of the order is sold in the pool.
+        set_time(&mut ctx, TWAMM_INTERVAL / 2);
+        c.execute_twamm(t.clone());
+        let half = rate * (TWAMM_INTERVAL / 2) as u128;
+        let p = c.get_pool(&t);
+        let (out1, _) = clp_math::calc_out_with_fee_bps(half, 20 * NDENOM, 10 * NDENOM, p.fee_bps);
+        assert_eq!((p.tokens, p.ynear), (20 * NDENOM + half, 10 * NDENOM - out1));
+
+        // the order ends at the interval boundary.
+        set_time(&mut ctx, 2 * TWAMM_INTERVAL);
+        let (out2, _) = clp_math::calc_out_with_fee_bps(half, p.tokens, p.ynear, p.fee_bps);
+        let ynear = c.get_deposit(&a).ynear;
+        let s = c.cancel_twamm_order(id);
+        assert_eq!(s.refunded, U128(0));
+        assert!(s.bought.0 <= out1 + out2 && s.bought.0 + 1_000_000 > out1 + out2);
+        assert_eq!(c.get_deposit(&a).ynear, ynear + s.bought.0);
+        assert_eq!(c.get_pool(&t).tokens, 20 * NDENOM + escrow);
+        assert_eq!(c.twamm_rates(t.clone()), (U128(0), U128(0)));
+        assert!(c.twamm_order(id).is_none());
+    }
+
+    #[test]
+    fn twamm_opposite_orders_are_matched() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        // equal value at the pool price: 0.5 NEAR per token.
+        let id1 = c.place_twamm_order(t.clone(), true, U128(NDENOM / 2), 1);
+        let id2 = c.place_twamm_order(t.clone(), false, U128(NDENOM), 1);
+
+        set_time(&mut ctx, TWAMM_INTERVAL / 2);
+        let s1 = c.cancel_twamm_order(id1);
+        let s2 = c.cancel_twamm_order(id2);
+        let p = c.get_pool(&t);
+        assert!(p.ynear.max(10 * NDENOM) - p.ynear.min(10 * NDENOM) < 1_000_000);
+        assert!(s1.bought.0 + 1_000_000 > NDENOM / 2 && s1.bought.0 <= NDENOM / 2);
+        assert!(s2.bought.0 + 1_000_000 > NDENOM / 4 && s2.bought.0 <= NDENOM / 4);
+        assert!(s1.refunded.0 + 1_000_000 > NDENOM / 4);
+        assert!(s2.refunded.0 + 1_000_000 > NDENOM / 2);
+    }
+
+    fn prepare_batch_auction(ctx: &mut Ctx, c: &mut NearSwap) -> AccountId {
+        let (t, alice) = (ctx.accounts.token1.clone(), ctx.accounts.alice.clone());
+        let mut p = c.get_pool(&t);
+        p.batch_window = 5;
+        c.set_pool(&t, &p);
+        let d = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: [(t, NDENOM)].iter().cloned().collect(),
+        };
+        c.deposits.insert(&alice, &d.into());
+        alice
+    }
+
+    #[test]
+    fn batch_auction_nets_opposite_swaps() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let alice = prepare_batch_auction(&mut ctx, &mut c);
+        c.submit_batch_swap(t.clone(), true, U128(NDENOM / 2), U128(1));
+        set_predecessor(&mut ctx, &alice);
+        // equal value at the pool price: 0.5 NEAR per token.
+        c.submit_batch_swap(t.clone(), false, U128(NDENOM), U128(1));
+        assert_eq!(c.batch(t.clone()).unwrap().orders.len(), 2);
+
+        set_block(&mut ctx, 5);
+        c.settle_batch(t.clone());
+        assert!(c.batch(t.clone()).is_none());
+        let p = c.get_pool(&t);
+        assert_eq!((p.ynear, p.tokens), (10 * NDENOM, 20 * NDENOM));
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(3 * NDENOM)));
+        assert_eq!(c.get_deposit(&alice).ynear, NDENOM + NDENOM / 2);
+        assert_eq!(c.get_deposit(&alice).tokens.get(&t), Some(&0));
+    }
+
+    #[test]
+    fn batch_auction_swaps_excess_in_pool() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let alice = prepare_batch_auction(&mut ctx, &mut c);
+        c.submit_batch_swap(t.clone(), true, U128(NDENOM / 2), U128(1));
+        set_predecessor(&mut ctx, &alice);
+        // can't be filled at the batch price.
+        c.submit_batch_swap(t.clone(), false, U128(NDENOM), U128(NDENOM));
+
+        assert_eq!(c.batch(t.clone()).unwrap().start, 0);
+        set_block(&mut ctx, 6);
+        c.settle_batch(t.clone());
+        let fee_bps = c.get_pool(&t).fee_bps;
+        let (out, _) = clp_math::calc_out_with_fee_bps(NDENOM / 2, 10 * NDENOM, 20 * NDENOM, fee_bps);
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(2 * NDENOM + out)));
+        assert_eq!(c.get_pool(&t).tokens, 20 * NDENOM - out);
+        assert_eq!(c.get_deposit(&alice).tokens.get(&t), Some(&NDENOM));
+        assert_eq!(c.get_deposit(&alice).ynear, NDENOM);
+    }
+
+    #[test]
+    #[should_panic(expected = "E100: Pool settles swaps in batch auctions")]
+    fn batch_auction_pool_blocks_swaps() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        prepare_batch_auction(&mut ctx, &mut c);
+        c.swap_near_to_token_exact_in(U128(NDENOM / 2), t, U128(1), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E101: Batch auction window is not over yet")]
+    fn batch_auction_settles_after_window() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        prepare_batch_auction(&mut ctx, &mut c);
+        c.submit_batch_swap(t.clone(), true, U128(NDENOM / 2), U128(1));
+        set_block(&mut ctx, 4);
+        c.settle_batch(t);
+    }
+
+    #[test]
+    fn commit_reveal_swap() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = Some(ctx.accounts.token1.clone());
+        let salt = vec![1, 2, 3];
+        let hash = swap_commitment_hash(&None, &t, NDENOM / 2, 1, salt.clone());
+        let id = c.commit_swap(Base64VecU8(hash.to_vec()));
+        assert_eq!(c.swap_commitment(id).unwrap().reveal_block, SWAP_REVEAL_DELAY);
+
+        set_block(&mut ctx, SWAP_REVEAL_DELAY);
+        let expected = c.price_near_to_token_in(ctx.accounts.token1.clone(), U128(NDENOM / 2));
+        let out = c.reveal_swap(id, None, t.clone(), U128(NDENOM / 2), U128(1), Base64VecU8(salt));
+        assert_eq!(out, expected);
+        assert_eq!(c.get_deposit(&a).ynear, NDENOM / 2);
+        assert!(c.swap_commitment(id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "E105: Swap commitment can't be revealed in this block yet")]
+    fn commit_reveal_swap_same_block() {
+        let (_ctx, mut c) = prepare_for_swap_request();
+        let hash = swap_commitment_hash(&None, &None, 1, 1, vec![]);
+        let id = c.commit_swap(Base64VecU8(hash.to_vec()));
+        c.reveal_swap(id, None, None, U128(1), U128(1), Base64VecU8(vec![]));
+    }
+
+    #[test]
+    #[should_panic(expected = "E106: Revealed swap doesn't match the commitment")]
+    fn commit_reveal_swap_other_params() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = Some(ctx.accounts.token1.clone());
+        let hash = swap_commitment_hash(&None, &t, NDENOM / 2, 1, vec![1]);
+        let id = c.commit_swap(Base64VecU8(hash.to_vec()));
+        set_block(&mut ctx, SWAP_REVEAL_DELAY);
+        c.reveal_swap(id, None, t, U128(NDENOM), U128(1), Base64VecU8(vec![1]));
+    }
+
+    /// Locks a quote of 0.1 NEAR for tokens and makes alice swap `ynear` for tokens after it.
+    fn lock_quote_and_move(ctx: &mut Ctx, c: &mut NearSwap, ynear: Balance) -> (u64, Balance) {
+        let (a, alice) = (ctx.accounts.predecessor.clone(), ctx.accounts.alice.clone());
+        let t = ctx.accounts.token1.clone();
+        let d = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: [(t.clone(), NDENOM)].iter().cloned().collect(),
+        };
+        c.deposits.insert(&alice, &d.into());
+        let id = c.lock_quote(t.clone(), U128(NDENOM / 10), true);
+        let q = c.locked_quote(id).unwrap();
+        assert_eq!(c.get_deposit(&a).ynear, NDENOM - NDENOM / 10_000);
+        assert_eq!(c.quote_lock_fund(t.clone()).ynear, U128(NDENOM / 10_000));
+
+        set_predecessor(ctx, &alice);
+        c.swap_near_to_token_exact_in(U128(ynear), t.clone(), U128(1), None);
+        set_predecessor(ctx, &a);
+        set_block(ctx, QUOTE_LOCK_BLOCKS);
+        (id, q.amount_out.0)
+    }
+
+    #[test]
+    fn locked_quote_covers_adverse_move() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = ctx.accounts.token1.clone();
+        let fund = QuoteLockFund {
+            ynear: U128(0),
+            tokens: U128(NDENOM),
+        };
+        c.quote_lock_funds.insert(&t, &fund);
+        let (id, quoted) = lock_quote_and_move(&mut ctx, &mut c, NDENOM / 100);
+        let out = c.price_near_to_token_in(t.clone(), U128(NDENOM / 10)).0;
+        assert!(out < quoted);
+
+        assert_eq!(c.execute_locked_quote(id), U128(quoted));
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(2 * NDENOM + quoted)));
+        let fund = c.quote_lock_fund(t);
+        assert_eq!(fund.tokens, U128(NDENOM - (quoted - out)));
+        assert!(c.locked_quote(id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "E108: Price moved more than the quote lock fund covers")]
+    fn locked_quote_large_move() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        let fund = QuoteLockFund {
+            ynear: U128(0),
+            tokens: U128(NDENOM),
+        };
+        c.quote_lock_funds.insert(&t, &fund);
+        let (id, _) = lock_quote_and_move(&mut ctx, &mut c, NDENOM / 2);
+        c.execute_locked_quote(id);
+    }
+
+    #[test]
+    #[should_panic(expected = "E107: Locked quote expired")]
+    fn locked_quote_expires() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let id = c.lock_quote(ctx.accounts.token1.clone(), U128(NDENOM / 10), true);
+        set_block(&mut ctx, QUOTE_LOCK_BLOCKS + 1);
+        c.execute_locked_quote(id);
+    }
+
+    fn register_alice_project(ctx: &mut Ctx, c: &mut NearSwap) {
+        let (owner, alice) = (ctx.accounts.owner.clone(), ctx.accounts.alice.clone());
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        set_predecessor(ctx, &owner);
+        c.set_protocol_fee(5_000);
+        set_predecessor(ctx, &t);
+        c.register_token_project(to_va(alice.clone()));
+        let d = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: HashMap::new(),
+        };
+        c.deposits.insert(&alice, &d.into());
+        set_predecessor(ctx, &a);
+    }
+
+    #[test]
+    fn protocol_fee_shared_with_token_project() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        register_alice_project(&mut ctx, &mut c);
+        let fee_bps = c.get_pool(&t).fee_bps;
+        let (out, fee) = clp_math::calc_out_with_fee_bps(NDENOM / 2, 10 * NDENOM, 20 * NDENOM, fee_bps);
+        c.swap_near_to_token_exact_in(U128(NDENOM / 2), t.clone(), U128(1), None);
+        let protocol = fee / 2;
+        let p = c.get_pool(&t);
+        assert_eq!((p.ynear, p.tokens), (10 * NDENOM + NDENOM / 2 - protocol, 20 * NDENOM - out));
+        let project = protocol / 5;
+        assert_eq!(c.token_project(t.clone()).unwrap().ynear, U128(project));
+
+        let alice = ctx.accounts.alice.clone();
+        set_predecessor(&mut ctx, &alice);
+        assert_eq!(c.claim_project_revenue(t.clone()), (U128(project), U128(0)));
+        assert_eq!(c.get_deposit(&alice).ynear, NDENOM + project);
+        assert_eq!(c.protocol_fees(None), U128(protocol - project));
+        assert_eq!(c.token_project(t).unwrap().ynear, U128(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "E112: Account is not the token project beneficiary")]
+    fn claim_project_revenue_not_beneficiary() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        register_alice_project(&mut ctx, &mut c);
+        c.claim_project_revenue(t);
+    }
+
+    #[test]
+    fn distribute_fees_by_weight() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let (owner, alice) = (ctx.accounts.owner.clone(), ctx.accounts.alice.clone());
+        let d = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: HashMap::new(),
+        };
+        c.deposits.insert(&alice, &d.into());
+        set_predecessor(&mut ctx, &owner);
+        let dsts = vec![
+            FeeDestination {
+                account: a.clone(),
+                weight: 3,
+            },
+            FeeDestination {
+                account: alice.clone(),
+                weight: 1,
+            },
+        ];
+        c.set_fee_destinations(dsts.clone());
+        assert_eq!(c.fee_destinations(), dsts);
+
+        c.add_protocol_fees(&None, 1000);
+        c.add_protocol_fees(&Some(t.clone()), 10);
+        c.distribute_fees();
+        assert_eq!(c.get_deposit(&a).ynear, NDENOM + 750);
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(2 * NDENOM + 8)));
+        assert_eq!(c.get_deposit(&alice).ynear, NDENOM + 250);
+        assert_eq!(c.get_deposit(&alice).tokens.get(&t), Some(&2));
+        assert_eq!(c.protocol_fees(None), U128(0));
+        assert_eq!(c.protocol_fees(Some(t)), U128(0));
+    }
+
+    #[test]
+    fn protocol_fees_forwarded_on_threshold() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let (owner, alice) = (ctx.accounts.owner.clone(), ctx.accounts.alice.clone());
+        let d = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: HashMap::new(),
+        };
+        c.deposits.insert(&alice, &d.into());
+        set_predecessor(&mut ctx, &owner);
+        c.set_protocol_fee(5_000);
+        c.set_fee_destinations(vec![FeeDestination {
+            account: alice.clone(),
+            weight: 1,
+        }]);
+        let fee_bps = c.get_pool(&t).fee_bps;
+        let fee = clp_math::calc_fee_bps(NDENOM / 2, fee_bps);
+        c.set_fee_forward_threshold(None, Some(U128(fee / 2 + 1)));
+        c.set_fee_forward_threshold(Some(t.clone()), Some(U128(1)));
+        assert_eq!(c.fee_forward_threshold(None), Some(U128(fee / 2 + 1)));
+
+        set_predecessor(&mut ctx, &a);
+        c.swap_near_to_token_exact_in(U128(NDENOM / 2), t.clone(), U128(1), None);
+        assert_eq!(c.get_pool(&t).protocol_ynear, fee / 2);
+        assert_eq!(c.get_deposit(&alice).ynear, NDENOM);
+
+        // the second swap crosses the threshold.
+        c.swap_near_to_token_exact_in(U128(NDENOM / 2), t.clone(), U128(1), None);
+        assert_eq!(c.get_pool(&t).protocol_ynear, 0);
+        assert_eq!(c.protocol_fees(None), U128(0));
+        assert_eq!(c.get_deposit(&alice).ynear, NDENOM + fee / 2 * 2);
+
+        // alice doesn't have a token deposit: the token fees stay for `distribute_fees`.
+        c.swap_token_to_near_exact_in(t.clone(), U128(NDENOM), U128(1), None);
+        assert_eq!(c.get_pool(&t).protocol_tokens, 0);
+        assert!(c.protocol_fees(Some(t.clone())).0 > 0);
+        assert!(c.get_deposit(&alice).tokens.get(&t).is_none());
+    }
+
+    #[test]
+    fn protocol_fees_not_forwarded_to_unregistered_destination() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let (owner, alice) = (ctx.accounts.owner.clone(), ctx.accounts.alice.clone());
+        let d = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: HashMap::new(),
+        };
+        c.deposits.insert(&alice, &d.into());
+        set_predecessor(&mut ctx, &owner);
+        c.set_protocol_fee(5_000);
+        c.set_fee_destinations(vec![FeeDestination {
+            account: alice.clone(),
+            weight: 1,
+        }]);
+        c.set_fee_forward_threshold(None, Some(U128(1)));
+        // the destination unregisters its storage.
+        c.deposits.remove(&alice);
+
+        set_predecessor(&mut ctx, &a);
+        c.swap_near_to_token_exact_in(U128(NDENOM / 2), t.clone(), U128(1), None);
+        let fee = clp_math::calc_fee_bps(NDENOM / 2, c.get_pool(&t).fee_bps);
+        assert_eq!(c.protocol_fees(None), U128(fee / 2));
+        assert!(c.deposits.get(&alice).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "E20")]
+    fn fee_forward_threshold_unregistered_destination() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        // the default destination, the owner, has no deposit.
+        c.set_fee_forward_threshold(None, Some(U128(1)));
+    }
+
+    #[test]
+    fn gov_staking_fee_share() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let (owner, alice) = (ctx.accounts.owner.clone(), ctx.accounts.alice.clone());
+        let d = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: [(t.clone(), 3 * NDENOM)].iter().cloned().collect(),
+        };
+        c.deposits.insert(&alice, &d.into());
+        set_predecessor(&mut ctx, &owner);
+        c.set_fee_destinations(vec![FeeDestination {
+            account: alice.clone(),
+            weight: 1,
+        }]);
+        c.set_gov_staking(to_va(t.clone()), 5_000);
+
+        set_predecessor(&mut ctx, &a);
+        c.stake_gov(U128(NDENOM));
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&NDENOM));
+        c.add_protocol_fees(&None, 2 * NDENOM);
+        c.distribute_fees();
+        assert_eq!(c.get_deposit(&alice).ynear, 2 * NDENOM);
+        assert_eq!(c.gov_staking_info().epoch_fees, U128(NDENOM));
+
+        // the fees of an epoch without active stake wait for the next payout.
+        ctx.vm.epoch_height += 1;
+        set_predecessor(&mut ctx, &alice);
+        c.stake_gov(U128(3 * NDENOM));
+        c.add_protocol_fees(&None, 2 * NDENOM);
+        c.distribute_fees();
+        let i = c.gov_staking_info();
+        assert_eq!((i.total_staked, i.total_pending), (U128(NDENOM), U128(3 * NDENOM)));
+
+        // alice stake was pending during the paid epoch.
+        ctx.vm.epoch_height += 1;
+        set_predecessor(&mut ctx, &a);
+        assert_eq!(c.claim_fee_share(), U128(2 * NDENOM));
+        let s = c.gov_stake(alice.clone()).unwrap();
+        assert_eq!((s.staked, s.fees), (U128(3 * NDENOM), U128(0)));
+
+        c.add_protocol_fees(&None, 8 * NDENOM);
+        c.distribute_fees();
+        ctx.vm.epoch_height += 1;
+        set_predecessor(&mut ctx, &alice);
+        assert_eq!(c.claim_fee_share(), U128(3 * NDENOM));
+        c.unstake_gov(U128(3 * NDENOM));
+        assert_eq!(c.get_deposit(&alice).tokens.get(&t), Some(&(3 * NDENOM)));
+        set_predecessor(&mut ctx, &a);
+        assert_eq!(c.claim_fee_share(), U128(NDENOM));
+        assert_eq!(c.get_deposit(&a).ynear, NDENOM + 3 * NDENOM);
+    }
+
+    #[test]
+    fn admin_actions_logged() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (owner, alice) = (ctx.accounts.owner.clone(), ctx.accounts.alice.clone());
+        set_predecessor(&mut ctx, &owner);
+        set_time(&mut ctx, 10);
+        c.set_protocol_fee(1_000);
+        set_time(&mut ctx, 20);
+        c.change_owner(to_va(alice.clone()));
+        assert_eq!(c.admin_log_len(), 2);
+        assert_eq!(
+            c.admin_log(0, 10),
+            vec![
+                AdminLogEntry {
+                    timestamp: U64(10_000_000_000),
+                    actor: owner.clone(),
+                    action: "set_protocol_fee".to_string(),
+                    args: "1000".to_string(),
+                },
+                AdminLogEntry {
+                    timestamp: U64(20_000_000_000),
+                    actor: owner,
+                    action: "change_owner".to_string(),
+                    args: format!("\"{}\"", alice),
+                },
+            ]
+        );
+        assert_eq!(c.admin_log(1, 10).len(), 1);
+        assert!(c.admin_log(2, 10).is_empty());
+    }
+
+    #[test]
+    fn contract_source_metadata() {
+        let (_ctx, c) = init();
+        let m = c.contract_source_metadata();
+        assert_eq!(m.version, Some(env!("CARGO_PKG_VERSION").to_string()));
+        assert_eq!(m.link, Some("https://github.com/robert-zaremba/near-clp".to_string()));
+    }
+
+    #[cfg(feature = "testnet")]
+    #[test]
+    fn seed_demo_pool() {
+        let (mut ctx, mut c) = init();
+        let owner = ctx.accounts.owner.clone();
+        let token = "demo.token".to_string();
+        let mut tokens = HashMap::new();
+        tokens.insert(token.clone(), 3 * NDENOM);
+        let d = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens,
+        };
+        c.deposits.insert(&owner, &d.into());
+        set_predecessor(&mut ctx, &owner);
+        ctx.vm.attached_deposit = NDENOM;
+        testing_env!(ctx.vm.clone());
+        let shares = c.seed_demo_pool(to_va(token.clone()), U128(2 * NDENOM));
+        let p = c.get_pool(&token);
+        assert_eq!((p.ynear, p.tokens), (NDENOM, 2 * NDENOM));
+        assert_eq!(p.shares.get(&owner), Some(shares.0));
+        assert_eq!(c.pool_metadata(token.clone()).unwrap().creator, owner);
+        let d = c.get_deposit(&owner);
+        assert_eq!(d.tokens.get(&token), Some(&NDENOM));
+    }
+
+    #[test]
+    fn trading_methods_without_deposit() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        // function-call access keys can't attach a deposit.
+        ctx.vm.attached_deposit = 0;
+        testing_env!(ctx.vm.clone());
+        assert!(c.swap_near_to_token_exact_in(U128(NDENOM / 10), t, U128(1), None).0 > 0);
+
+        let groups = c.get_method_groups();
+        let trading = groups.iter().find(|g| g.name == "trading").unwrap();
+        assert!(!trading.requires_one_yocto);
+        assert!(trading.methods.contains(&"swap_tokens_exact_in".to_string()));
+        assert!(groups
+            .iter()
+            .filter(|g| g.methods.contains(&"withdraw_liquidity".to_string()))
+            .all(|g| g.requires_one_yocto));
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+    fn withdraw_liquidity_requires_one_yocto() {
+        let (t, mut c) = prepare_for_withdraw();
+        let mut ctx = Ctx::new(vec![], false);
+        ctx.vm.attached_deposit = 0;
+        ctx.vm.storage_usage = env::storage_usage();
+        testing_env!(ctx.vm.clone());
+        c.withdraw_liquidity(t, U128(NDENOM), U128(1), U128(1));
+    }
+
+    #[test]
+    fn stake_pool_near() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_staking_config(Some("validator.near".try_into().unwrap()), 3000);
+        assert_eq!(c.staking_config().max_staked_bps, 3000);
+
+        // stake 30% of the pool NEAR.
+        assert!(c.rebalance_stake(t.clone()));
+        let s = c.pool_staking(t.clone());
+        assert_eq!((s.staked, s.liquid), (U128(3 * NDENOM), U128(7 * NDENOM)));
+        assert!(s.pending);
+        assert!(!c.rebalance_stake(t.clone()));
+        set_promise_result(&ctx, PromiseResult::Successful(vec![]));
+        assert!(c.resolve_staking(t.clone(), StakingOp::Stake, U128(3 * NDENOM)));
+        assert!(!c.pool_staking(t.clone()).pending);
+
+        // rewards are added to the pool NEAR reserve.
+        let balance = format!("\"{}\"", 33 * NDENOM / 10).into_bytes();
+        set_promise_result(&ctx, PromiseResult::Successful(balance));
+        assert_eq!(c.resolve_harvest_staking_rewards(), U128(3 * NDENOM / 10));
+        assert_eq!(c.pool_staking(t.clone()).staked, U128(33 * NDENOM / 10));
+        set_predecessor(&mut ctx, &owner);
+        c.set_staking_config(Some("validator.near".try_into().unwrap()), 1000);
+        let out = c.internal_swap(&a, &Some(t.clone()), &None, 2 * NDENOM, 1);
+        let ynear = 103 * NDENOM / 10 - out;
+
+        // the reserve dropped below the target: the staked NEAR above it is unstaked.
+        assert!(c.rebalance_stake(t.clone()));
+        let excess = 33 * NDENOM / 10 - ynear / 10;
+        set_promise_result(&ctx, PromiseResult::Successful(vec![]));
+        assert!(c.resolve_staking(t.clone(), StakingOp::Unstake, U128(excess)));
+        let s = c.pool_staking(t.clone());
+        assert_eq!((s.staked, s.unstaking), (U128(ynear / 10), U128(excess)));
+        assert_eq!(c.get_pool(&t).ynear, ynear);
+        assert!(!c.rebalance_stake(t.clone()));
+
+        ctx.vm.epoch_height = s.unstake_available_epoch.0;
+        set_predecessor(&mut ctx, &owner);
+        assert!(c.rebalance_stake(t.clone()));
+        set_promise_result(&ctx, PromiseResult::Failed);
+        assert!(!c.resolve_staking(t.clone(), StakingOp::Withdraw, U128(excess)));
+        assert_eq!(c.pool_staking(t.clone()).unstaking, U128(excess));
+        assert!(c.rebalance_stake(t.clone()));
+        set_promise_result(&ctx, PromiseResult::Successful(vec![]));
+        assert!(c.resolve_staking(t.clone(), StakingOp::Withdraw, U128(excess)));
+        let s = c.pool_staking(t.clone());
+        assert_eq!((s.unstaking, s.liquid), (U128(0), U128(ynear - ynear / 10)));
+    }
+
+    #[test]
+    #[should_panic(expected = "E150")]
+    fn swap_over_liquid_near() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_staking_config(Some("validator.near".try_into().unwrap()), 5000);
+        c.rebalance_stake(t.clone());
+        let mut d = c.get_deposit(&a);
+        d.add(&t, 140 * NDENOM);
+        c.deposits.insert(&a, &d.into());
+        // 5 NEAR are staked, the swaps buy more than the other 5 NEAR.
+        for x in [20, 40, 80].iter() {
+            c.internal_swap(&a, &Some(t.clone()), &None, x * NDENOM, 1);
+        }
+    }
+
+    #[test]
+    fn lend_pool_tokens() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_lending_market(Some("lending.near".try_into().unwrap()));
+        c.set_pool_lending_share(t.clone(), 3000);
+        c.record_token_in(&t, 20 * NDENOM);
+
+        assert!(c.rebalance_lending(t.clone()));
+        let l = c.pool_lending(t.clone());
+        assert_eq!((l.lent, l.liquid), (U128(6 * NDENOM), U128(14 * NDENOM)));
+        assert!(l.pending);
+        assert_eq!(c.recorded_token_balance(t.clone()), U128(14 * NDENOM));
+        // the lending market used only 5 tokens.
+        let used = format!("\"{}\"", 5 * NDENOM).into_bytes();
+        set_promise_result(&ctx, PromiseResult::Successful(used));
+        assert!(!c.resolve_lending(t.clone(), LendingOp::Supply, U128(6 * NDENOM)));
+        assert_eq!(c.pool_lending(t.clone()).lent, U128(5 * NDENOM));
+        assert_eq!(c.recorded_token_balance(t.clone()), U128(15 * NDENOM));
+
+        // the interest is added to the pool token reserve.
+        let supplied = format!("\"{}\"", 55 * NDENOM / 10).into_bytes();
+        set_promise_result(&ctx, PromiseResult::Successful(supplied));
+        assert_eq!(c.resolve_harvest_lending_interest(t.clone()), U128(NDENOM / 2));
+        assert_eq!(c.get_pool(&t).tokens, 205 * NDENOM / 10);
+        assert_eq!(c.pool_lending(t.clone()).lent, U128(55 * NDENOM / 10));
+
+        set_predecessor(&mut ctx, &owner);
+        assert!(c.force_lending_withdraw(t.clone()));
+        set_promise_result(&ctx, PromiseResult::Successful(vec![]));
+        assert!(c.resolve_lending(t.clone(), LendingOp::Withdraw, U128(55 * NDENOM / 10)));
+        let l = c.pool_lending(t.clone());
+        assert_eq!((l.lent, l.lending_bps), (U128(0), 0));
+        assert_eq!(c.recorded_token_balance(t), U128(205 * NDENOM / 10));
+    }
+
+    #[test]
+    #[should_panic(expected = "E151")]
+    fn swap_over_liquid_tokens() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_lending_market(Some("lending.near".try_into().unwrap()));
+        c.set_pool_lending_share(t.clone(), 5000);
+        c.rebalance_lending(t.clone());
+        let mut d = c.get_deposit(&a);
+        d.add_near(70 * NDENOM);
+        c.deposits.insert(&a, &d.into());
+        // 10 tokens are lent, the swaps buy more than the other 10 tokens.
+        for x in [10, 20, 40].iter() {
+            c.internal_swap(&a, &None, &Some(t.clone()), x * NDENOM, 1);
+        }
+    }
+
+    #[test]
+    fn exchange_rate_pricing() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        let provider = RateProvider {
+            contract: "rates.near".into(),
+            method: RateMethod::GetStNearPrice,
+        };
+        c.set_rate_provider(t.clone(), Some(provider.clone()));
+        let quote = c.price_near_to_token_in(t.clone(), U128(NDENOM)).0;
+
+        // the first rate doesn't change the pricing.
+        let rate = format!("\"{}\"", NDENOM).into_bytes();
+        set_promise_result(&ctx, PromiseResult::Successful(rate));
+        assert_eq!(c.resolve_update_rate(t.clone()), Some(U128(NDENOM)));
+        assert_eq!(c.price_near_to_token_in(t.clone(), U128(NDENOM)).0, quote);
+        assert_eq!(c.get_pool(&t).spot_price(), NDENOM / 2);
+
+        // the token value accrued 0.5%: the pool price follows it.
+        let rate = format!("\"{}\"", 1005 * NDENOM / 1000).into_bytes();
+        set_promise_result(&ctx, PromiseResult::Successful(rate));
+        c.resolve_update_rate(t.clone()).unwrap();
+        let p = c.get_pool(&t);
+        assert_eq!(p.tokens, 20 * NDENOM);
+        assert_eq!(p.curve_tokens(), 20 * NDENOM * 1000 / 1005);
+        assert_eq!(p.spot_price(), clp_math::spot_price(10 * NDENOM, p.curve_tokens()).as_u128());
+        assert!(c.price_near_to_token_in(t.clone(), U128(NDENOM)).0 < quote);
+
+        // a rate jump above MAX_RATE_CHANGE_BPS is rejected.
+        let rate = format!("\"{}\"", 11 * NDENOM / 10).into_bytes();
+        set_promise_result(&ctx, PromiseResult::Successful(rate));
+        assert_eq!(c.resolve_update_rate(t.clone()), None);
+        let r = c.pool_rate(t.clone()).unwrap();
+        assert_eq!((r.provider, r.rate), (provider, U128(1005 * NDENOM / 1000)));
+
+        // the allowed change grows with the time since the last update.
+        ctx.vm.block_timestamp += 10 * RATE_CHANGE_PERIOD;
+        let rate = format!("\"{}\"", 11 * NDENOM / 10).into_bytes();
+        set_promise_result(&ctx, PromiseResult::Successful(rate));
+        assert_eq!(c.resolve_update_rate(t.clone()), Some(U128(11 * NDENOM / 10)));
+        assert_eq!(c.pool_rate(t.clone()).unwrap().updated, U64(ctx.vm.block_timestamp));
+
+        set_predecessor(&mut ctx, &owner);
+        c.set_rate_provider(t.clone(), None);
+        assert!(c.pool_rate(t.clone()).is_none());
+        assert_eq!(c.price_near_to_token_in(t, U128(NDENOM)).0, quote);
+    }
+
+    #[test]
+    fn migrate_in_liquidity() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let (owner, lp) = (ctx.accounts.owner.clone(), "lp.amm.near".to_string());
+        let mut p = c.get_pool(&t);
+        p.total_shares = 10 * NDENOM;
+        c.set_pool(&t, &p);
+        set_predecessor(&mut ctx, &owner);
+        let source = MigrationSource {
+            amm: "amm.near".into(),
+            token: t.clone(),
+        };
+        c.set_migration_source(to_va(lp.clone()), Some(source.clone()));
+        assert_eq!(c.migration_sources(), vec![(lp.clone(), source)]);
+
+        set_predecessor(&mut ctx, &lp);
+        let msg = format!("{}:{}", MIGRATE_IN_MSG, NDENOM);
+        match c.ft_on_transfer(to_va(a.clone()), U128(5 * NDENOM), msg) {
+            PromiseOrValue::Promise(_) => {}
+            PromiseOrValue::Value(_) => panic!("expected a promise"),
+        }
+
+        // the redeem failed: the LP tokens are refunded.
+        set_promise_result(&ctx, PromiseResult::Failed);
+        let unused =
+            c.resolve_migrate_in(a.clone(), lp.clone(), t.clone(), U128(5 * NDENOM), U128(NDENOM));
+        assert_eq!(unused, U128(5 * NDENOM));
+
+        let proceeds = format!("[\"{}\",\"{}\"]", NDENOM, 2 * NDENOM).into_bytes();
+        set_promise_result(&ctx, PromiseResult::Successful(proceeds));
+        let unused =
+            c.resolve_migrate_in(a.clone(), lp.clone(), t.clone(), U128(5 * NDENOM), U128(NDENOM));
+        assert_eq!(unused, U128(0));
+        let p = c.get_pool(&t);
+        assert_eq!((p.ynear, p.tokens), (11 * NDENOM, 22 * NDENOM));
+        assert_eq!(p.shares.get(&a), Some(NDENOM));
+        let d = c.get_deposit(&a);
+        assert_eq!((d.ynear, d.tokens.get(&t)), (NDENOM, Some(&(2 * NDENOM))));
+
+        // not enough shares minted: the proceeds stay in the deposit.
+        let proceeds = format!("[\"{}\",\"{}\"]", NDENOM, 2 * NDENOM).into_bytes();
+        set_promise_result(&ctx, PromiseResult::Successful(proceeds));
+        c.resolve_migrate_in(a.clone(), lp.clone(), t.clone(), U128(5 * NDENOM), U128(2 * NDENOM));
+        assert_eq!(c.get_pool(&t).shares.get(&a), Some(NDENOM));
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(4 * NDENOM)));
+
+        // the source was removed during the redeem: the proceeds are still credited.
+        set_predecessor(&mut ctx, &owner);
+        c.set_migration_source(to_va(lp.clone()), None);
+        let proceeds = format!("[\"{}\",\"{}\"]", NDENOM, 2 * NDENOM).into_bytes();
+        set_promise_result(&ctx, PromiseResult::Successful(proceeds));
+        let unused = c.resolve_migrate_in(a.clone(), lp, t.clone(), U128(5 * NDENOM), U128(NDENOM));
+        assert_eq!(unused, U128(0));
+        assert_eq!(c.get_pool(&t).shares.get(&a), Some(2 * NDENOM));
+    }
+
+    #[test]
+    fn swap_external() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let (owner, t2) = (ctx.accounts.owner.clone(), ctx.accounts.token2.clone());
+        let adapter = "adapter.amm.near".to_string();
+        set_predecessor(&mut ctx, &owner);
+        c.set_amm_adapter(to_va(adapter.clone()), Some("amm".into()));
+        c.extend_whitelisted_tokens(vec![to_va(t2.clone())]);
+        assert_eq!(c.amm_adapters(), vec![(adapter.clone(), "amm".to_string())]);
+
+        set_predecessor(&mut ctx, &a);
+        c.add_to_account_whitelist(&vec![to_va(t2.clone())]);
+        let id =
+            c.swap_external(adapter.clone(), t.clone(), U128(NDENOM), t2.clone(), U128(1), None);
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&NDENOM));
+
+        // the adapter returns the bought tokens before its transfer is resolved.
+        set_predecessor(&mut ctx, &t2);
+        let msg = format!("{}:{}", ADAPTER_RETURN_MSG, id);
+        promise_value(c.ft_on_transfer(to_va(adapter.clone()), U128(3 * NDENOM), msg));
+        assert_eq!(c.get_deposit(&a).tokens.get(&t2), Some(&(3 * NDENOM)));
+        assert_eq!(c.external_swap(id).unwrap().amount_out, Some(U128(3 * NDENOM)));
+
+        // a half of the sold tokens was unused.
+        let used = format!("\"{}\"", NDENOM / 2).into_bytes();
+        set_promise_result(&ctx, PromiseResult::Successful(used));
+        assert_eq!(c.resolve_adapter_swap(id), U128(NDENOM / 2));
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(3 * NDENOM / 2)));
+        assert!(c.external_swap(id).is_none());
+    }
+
+    #[test]
+    fn swap_external_failed() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let (owner, t2) = (ctx.accounts.owner.clone(), ctx.accounts.token2.clone());
+        let adapter = "adapter.amm.near".to_string();
+        set_predecessor(&mut ctx, &owner);
+        c.set_amm_adapter(to_va(adapter.clone()), Some("amm".into()));
+        c.extend_whitelisted_tokens(vec![to_va(t2.clone())]);
+
+        set_predecessor(&mut ctx, &a);
+        c.add_to_account_whitelist(&vec![to_va(t2.clone())]);
+        let id = c.swap_external(adapter, t.clone(), U128(NDENOM), t2, U128(1), None);
+        set_promise_result(&ctx, PromiseResult::Failed);
+        assert_eq!(c.resolve_adapter_swap(id), U128(NDENOM));
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(2 * NDENOM)));
+        assert!(c.external_swap(id).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "E131: Account is not a registered AMM adapter")]
+    fn swap_external_not_adapter() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let (t, t2) = (ctx.accounts.token1.clone(), ctx.accounts.token2.clone());
+        c.swap_external("amm.near".into(), t, U128(NDENOM), t2, U128(1), None);
+    }
+
+    #[test]
+    fn position_history() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let mut p = c.get_pool(&t);
+        p.total_shares = 10 * NDENOM;
+        c.set_pool(&t, &p);
+        assert_eq!(c.position_history_len(a.clone(), t.clone()), 0);
+
+        c.add_liquidity(t.clone(), U128(NDENOM / 2), U128(NDENOM), U128(0));
+        c.withdraw_liquidity(t.clone(), U128(NDENOM / 4), U128(1), U128(1));
+        assert_eq!(c.position_history_len(a.clone(), t.clone()), 2);
+
+        let events = c.position_history(a.clone(), t.clone(), 0, 10);
+        let amounts: Vec<_> = events
+            .iter()
+            .map(|e| (e.kind, e.ynear.0, e.tokens.0, e.shares.0))
+            .collect();
+        assert_eq!(
+            amounts,
+            vec![
+                (LiquidityEventKind::Add, NDENOM / 2, NDENOM, NDENOM / 2),
+                (LiquidityEventKind::Withdraw, NDENOM / 4, NDENOM / 2, NDENOM / 4),
+            ]
+        );
+        // the pool keeps the 1:2 reserves ratio, a share is backed by 1 NEAR and 2 tokens.
+        for e in &events {
+            assert_eq!((e.token_price.0, e.share_price.0), (NDENOM / 2, 2 * NDENOM));
+        }
+        assert_eq!(c.position_history(a.clone(), t.clone(), 1, 10), events[1..].to_vec());
+        assert!(c.position_history(a, t, 2, 10).is_empty());
+    }
+
+    #[test]
+    fn spot_value_conversion() {
+        let (ctx, c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        // pool reserves: 10 NEAR and 20 tokens, no fee and no price impact.
+        assert_eq!(c.to_near_value(t.clone(), U128(2 * NDENOM)), U128(NDENOM));
+        assert_eq!(c.from_near_value(t.clone(), U128(NDENOM)), U128(2 * NDENOM));
+        assert_eq!(c.to_near_value(t, U128(3)), U128(1));
+    }
+
+    #[test]
+    fn pool_invariant() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let mut p = c.get_pool(&t);
+        p.total_shares = 10 * NDENOM;
+        c.set_pool(&t, &p);
+        let k = |y: u128, t: u128| (u256::from(y) * u256::from(t)).to_string();
+        assert_eq!(c.pool_invariant(t.clone()).k, k(10 * NDENOM, 20 * NDENOM));
+
+        c.add_liquidity(t.clone(), U128(NDENOM / 2), U128(NDENOM), U128(0));
+        let inv = c.pool_invariant(t.clone());
+        let k_added = k(21 * NDENOM / 2, 21 * NDENOM);
+        assert_eq!((&inv.k, &inv.k_last_liquidity), (&k_added, &k_added));
+
+        c.internal_swap(&a, &None, &Some(t.clone()), NDENOM / 10, 0);
+        let inv = c.pool_invariant(t);
+        assert_eq!(inv.k_last_liquidity, k_added);
+        let k_now = u256::from_dec_str(&inv.k).unwrap();
+        assert!(k_now > u256::from_dec_str(&k_added).unwrap());
+    }
+
+    #[test]
+    fn pool_imbalance() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        assert_eq!(c.pool_imbalance(t.clone()), None);
+
+        let mut p = c.get_pool(&t);
+        p.poke_oracle();
+        c.set_pool(&t, &p);
+        let i = c.pool_imbalance(t.clone()).unwrap();
+        assert_eq!((i.spot_price, i.twap_price), (U128(NDENOM / 2), U128(NDENOM / 2)));
+        assert_eq!(i.deviation_bps, 0);
+
+        p.ynear = 11 * NDENOM;
+        c.set_pool(&t, &p);
+        assert_eq!(c.pool_imbalance(t.clone()).unwrap().deviation_bps, 1000);
+        p.ynear = 9 * NDENOM;
+        c.set_pool(&t, &p);
+        assert_eq!(c.pool_imbalance(t).unwrap().deviation_bps, -1000);
+    }
+
+    #[test]
+    fn twap_window() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (t, owner) = (ctx.accounts.token1.clone(), ctx.accounts.owner.clone());
+        let mut p = c.get_pool(&t);
+        p.twap.log_observation(to_nanoseconds(10_000), NDENOM, 0);
+        p.twap.log_observation(to_nanoseconds(10_120), NDENOM / 2, 0);
+        c.set_pool(&t, &p);
+        assert_eq!(c.twap_window(t.clone()), DEFAULT_TWAP_WINDOW);
+        let twap = c.pool_imbalance(t.clone()).unwrap().twap_price;
+        assert_eq!(twap, U128(NDENOM * 3 / 4));
+
+        // the 1 minute window only covers the last observation.
+        set_predecessor(&mut ctx, &owner);
+        c.set_twap_window(t.clone(), 60);
+        assert_eq!(c.twap_window(t.clone()), 60);
+        let i = c.pool_imbalance(t).unwrap();
+        assert_eq!((i.twap_price, i.deviation_bps), (U128(NDENOM / 2), 0));
+    }
+
+    #[test]
+    fn gm_twap() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        assert_eq!(c.gm_twap(t.clone(), 3600), None);
+        let mut p = c.get_pool(&t);
+        for i in 0..9 {
+            p.twap.log_observation(to_nanoseconds(10_000 + i * 100), NDENOM, NDENOM);
+        }
+        // an outlier block.
+        p.twap.log_observation(to_nanoseconds(10_900), 1000 * NDENOM, NDENOM);
+        c.set_pool(&t, &p);
+        let gm = c.gm_twap(t.clone(), 3600).unwrap().0;
+        // 1000^(1/10) ~ 1.995, while the arithmetic mean is 100.9.
+        assert!(gm > 1_990 * NDENOM / 1000 && gm < 2 * NDENOM, "gm={}", gm);
+        assert_eq!(c.get_pool(&t).twap.mean_over(to_nanoseconds(3600)).0, 1009 * NDENOM / 10);
+        // the window only covers the outlier.
+        let gm = c.gm_twap(t, 60).unwrap().0;
+        assert!(1000 * NDENOM - gm < NDENOM / 1_000_000, "gm={}", gm);
+    }
+
+    #[test]
+    fn convert_fees() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        let asset = Some(t.clone());
+        assert_eq!(c.convert_fees(t.clone()), U128(0));
+        let mut p = c.get_pool(&t);
+        p.twap.log_observation(to_nanoseconds(10_000), NDENOM / 2, 2 * NDENOM);
+        c.set_pool(&t, &p);
+        c.add_protocol_fees(&asset, 3 * NDENOM / 20);
+
+        // at most 0.5% of the 20N token reserve is sold.
+        let out = c.convert_fees(t.clone()).0;
+        let (expected, _) =
+            clp_math::calc_out_with_fee_bps(NDENOM / 10, 20 * NDENOM, 10 * NDENOM, 30);
+        assert_eq!(out, expected);
+        assert_eq!(c.protocol_fees(asset.clone()), U128(NDENOM / 20));
+        assert_eq!(c.protocol_fees(None), U128(out));
+        let p = c.get_pool(&t);
+        assert_eq!((p.ynear, p.tokens), (10 * NDENOM - out, 20 * NDENOM + NDENOM / 10));
+
+        let out2 = c.convert_fees(t.clone()).0;
+        assert!(out2 > 0);
+        assert_eq!(c.protocol_fees(asset), U128(0));
+        assert_eq!(c.protocol_fees(None), U128(out + out2));
+    }
+
+    #[test]
+    #[should_panic(expected = "E139")]
+    fn convert_fees_price_off_twap() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        let mut p = c.get_pool(&t);
+        // the spot price was pushed 10% down from the TWAP.
+        p.twap.log_observation(to_nanoseconds(10_000), 11 * NDENOM / 20, 20 * NDENOM / 11);
+        c.set_pool(&t, &p);
+        c.add_protocol_fees(&Some(t.clone()), NDENOM / 100);
+        c.convert_fees(t);
+    }
+
+    #[test]
+    fn twap_window_pool_creator() {
+        let (mut ctx, mut c) = init();
+        let t = create_alice_pool(&mut ctx, &mut c);
+        c.set_twap_window(t.clone(), MAX_TWAP_WINDOW);
+        assert_eq!(c.twap_window(t), MAX_TWAP_WINDOW);
+    }
+
+    #[test]
+    #[should_panic(expected = "E109: Only the pool creator can do this")]
+    fn twap_window_other_account() {
+        let (mut ctx, mut c) = init();
+        let t = create_alice_pool(&mut ctx, &mut c);
         let a = ctx.accounts.predecessor.clone();
-        // Add to nearswap whitelist
-        c.extend_whitelisted_tokens(vec![to_va("token1".into()), to_va("token2".into())]);
+        set_predecessor(&mut ctx, &a);
+        c.set_twap_window(t, 600);
+    }
 
-        // Add to account deposit list
-        let mut d = account_deposit();
-        d.add_to_whitelist(&vec![to_va("token1".into()), to_va("token2".into())]);
-        c.deposits.insert(&a, &d.into());
+    #[test]
+    #[should_panic(expected = "E134")]
+    fn twap_window_out_of_bounds() {
+        let (mut ctx, mut c) = init();
+        let t = create_alice_pool(&mut ctx, &mut c);
+        c.set_twap_window(t, MIN_TWAP_WINDOW - 1);
+    }
 
-        // deposit should work
-        c.deposit_token(&a, &"token1".into(), 10);
-        let res = c.get_deposit(&a);
-        assert_eq!(res.tokens.get(&"token1".to_string()), Some(&10))
+    #[test]
+    fn dynamic_fee() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        let owner = ctx.accounts.owner.clone();
+        let mut p = c.get_pool(&t);
+        p.poke_oracle();
+        c.set_pool(&t, &p);
+        let fee = p.fee_bps;
+        set_predecessor(&mut ctx, &owner);
+        let f = DynamicFee {
+            surcharge_bps: 30,
+            discount_bps: 20,
+        };
+        c.set_pool_dynamic_fee(t.clone(), Some(f));
+        assert_eq!(c.pool_dynamic_fee(t.clone()), Some(f));
+        // balanced pool: the flat fee.
+        assert_eq!(c.pool_swap_fee(t.clone(), true), fee);
+
+        // the spot price is 10% above the TWAP: selling NEAR moves it further.
+        let mut p = c.get_pool(&t);
+        p.ynear = 11 * NDENOM;
+        c.set_pool(&t, &p);
+        assert_eq!(c.pool_swap_fee(t.clone(), true), fee + 30);
+        assert_eq!(c.pool_swap_fee(t.clone(), false), fee - 20);
+        let (out, _) = clp_math::calc_out_with_fee_bps(NDENOM, 11 * NDENOM, 20 * NDENOM, fee + 30);
+        assert_eq!(c.price_near_to_token_in(t.clone(), U128(NDENOM)), U128(out));
+
+        c.set_pool_dynamic_fee(t.clone(), None);
+        assert_eq!(c.pool_swap_fee(t, true), fee);
     }
 
     #[test]
-    #[should_panic(expected = r#"E23: Token is not whitelisted"#)]
-    fn add_to_whitelist_failure_1() {
-        let (ctx, mut c) = init_with_owner();
-        let a = ctx.accounts.predecessor.clone();
-        // Add to nearswap whitelist
-        c.extend_whitelisted_tokens(vec![to_va("token1".into()), to_va("token2".into())]);
-        let d = account_deposit();
-        c.deposits.insert(&a, &d.into());
+    #[should_panic(expected = "E125")]
+    fn dynamic_fee_surcharge_limit() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (t, owner) = (ctx.accounts.token1.clone(), ctx.accounts.owner.clone());
+        set_predecessor(&mut ctx, &owner);
+        let f = DynamicFee {
+            surcharge_bps: MAX_FEE_SURCHARGE_BPS + 1,
+            discount_bps: 0,
+        };
+        c.set_pool_dynamic_fee(t, Some(f));
+    }
 
-        // deposit should not work
-        // because token not whitelisted in account deposit
-        c.deposit_token(&a, &"token1".into(), 10);
+    #[test]
+    fn pool_max_trade() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let owner = ctx.accounts.owner.clone();
+        assert_eq!(c.pool_max_trade_ynear(t.clone()), None);
+        set_predecessor(&mut ctx, &owner);
+        let m = MaxTrade::ReserveBps { bps: 500 };
+        c.set_pool_max_trade(t.clone(), Some(m));
+        assert_eq!(c.pool_max_trade(t.clone()), Some(m));
+        assert_eq!(c.pool_max_trade_ynear(t.clone()), Some(U128(NDENOM / 2)));
+
+        set_predecessor(&mut ctx, &a);
+        c.internal_swap(&a, &None, &Some(t.clone()), NDENOM / 2, 1);
+        // the limit follows the NEAR reserve.
+        let ynear = c.get_pool(&t).ynear;
+        assert_eq!(c.pool_max_trade_ynear(t), Some(U128(ynear / 20)));
     }
 
     #[test]
-    #[should_panic(expected = r#"E23: Token is not whitelisted"#)]
-    fn add_to_whitelist_failure_2() {
-        let (ctx, mut c) = init_with_owner();
-        let a = ctx.accounts.predecessor.clone();
+    #[should_panic(expected = "E126")]
+    fn pool_max_trade_exceeded() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        let m = MaxTrade::Ynear {
+            amount: U128(NDENOM / 2),
+        };
+        c.set_pool_max_trade(t.clone(), Some(m));
+        set_predecessor(&mut ctx, &a);
+        // sells 2 tokens for ~0.9 NEAR.
+        c.internal_swap(&a, &Some(t), &None, 2 * NDENOM, 1);
+    }
 
-        let d = account_deposit();
-        c.deposits.insert(&a, &d.into());
+    #[test]
+    fn default_max_slippage() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_default_max_slippage(100);
+        assert_eq!(c.default_max_slippage_bps(), 100);
+
+        // 0.3% fee and a small price impact, within the 1% default.
+        set_predecessor(&mut ctx, &a);
+        let expected = c.price_token_to_near_in(t.clone(), U128(NDENOM / 100));
+        let out = c.swap_token_to_near_exact_in(t.clone(), U128(NDENOM / 100), U128(0), None);
+        assert_eq!(out, expected);
+        assert!(out.0 > NDENOM / 200 * 99 / 100);
+    }
 
-        // deposit should not work
-        // because token not whitelisted in global list
-        c.deposit_token(&a.clone(), &"token1".into(), 10);
+    #[test]
+    #[should_panic(expected = "E7")]
+    fn default_max_slippage_exceeded() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_default_max_slippage(100);
+        set_predecessor(&mut ctx, &a);
+        // ~9% price impact.
+        c.swap_token_to_near_exact_in(t, U128(2 * NDENOM), U128(0), None);
     }
 
     #[test]
-    #[should_panic(expected = r#"E23: Token is not whitelisted"#)]
-    fn remove_from_whitelist_works_1() {
-        let (ctx, mut c) = init_with_owner();
-        let a = ctx.accounts.predecessor.clone();
-        c.extend_whitelisted_tokens(vec![to_va("token1".into()), to_va("token2".into())]);
-        let mut d = account_deposit();
-        d.add_to_whitelist(&vec![to_va("token1".into()), to_va("token2".into())]);
-        c.deposits.insert(&a, &d.into());
-        c.remove_whitelisted_token(to_va("token1".into()));
+    #[should_panic(expected = "E2")]
+    fn zero_min_out_without_default_slippage() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        c.swap_token_to_near_exact_in(t, U128(NDENOM / 100), U128(0), None);
+    }
 
-        c.deposit_token(&a.clone(), &"token1".into(), 10);
+    #[test]
+    fn swap_deadline() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        ctx.vm.block_timestamp = 1000;
+        set_block(&mut ctx, 100);
+        let d = Deadline {
+            timestamp: Some(1000.into()),
+            max_block_height: Some(100.into()),
+        };
+        assert!(!d.expired());
+        c.swap_near_to_token_exact_in(U128(NDENOM / 10), t, U128(1), Some(d));
+
+        // the stricter limit applies.
+        ctx.vm.block_timestamp = 999;
+        set_block(&mut ctx, 101);
+        assert!(d.expired());
+        let d = Deadline {
+            timestamp: Some(1000.into()),
+            max_block_height: None,
+        };
+        assert!(!d.expired());
     }
 
     #[test]
-    #[should_panic(expected = r#"E23: Token is not whitelisted"#)]
-    fn remove_from_whitelist_works_2() {
-        let (ctx, mut c) = init_with_owner();
-        let a = ctx.accounts.predecessor.clone();
-        c.extend_whitelisted_tokens(vec![to_va("token1".into()), to_va("token2".into())]);
-        let mut d = account_deposit();
-        d.add_to_whitelist(&vec![to_va("token1".into()), to_va("token2".into())]);
+    #[should_panic(expected = "E129")]
+    fn swap_block_height_deadline_expired() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        set_block(&mut ctx, 101);
+        let d = Deadline {
+            timestamp: None,
+            max_block_height: Some(100.into()),
+        };
+        c.swap_token_to_near_exact_in(t, U128(NDENOM / 10), U128(1), Some(d));
+    }
 
-        d.remove_from_whitelist(&to_va("token1".into()));
-        c.deposits.insert(&a, &d.into());
+    #[test]
+    fn find_route_and_swap_path() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        let amount = U128(NDENOM / 2);
+        assert_eq!(c.find_route(None, None, amount), None);
+        assert_eq!(c.find_route(None, Some("unknown.near".into()), amount), None);
+
+        let r = c.find_route(None, Some(t.clone()), amount).unwrap();
+        assert_eq!(r.path, vec![None, Some(t.clone())]);
+        assert_eq!(r.amount_out, c.price_near_to_token_in(t.clone(), amount));
+
+        let out = c.swap_path(r.path, amount, r.amount_out, None);
+        assert_eq!(out, r.amount_out);
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(2 * NDENOM + out.0)));
+    }
 
-        c.deposit_token(&a.clone(), &"token1".into(), 10);
+    #[test]
+    #[should_panic(expected = "E130")]
+    fn swap_path_invalid() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let t = ctx.accounts.token1.clone();
+        c.swap_path(vec![None, Some(t), None], U128(NDENOM / 2), U128(1), None);
     }
 
     // TODO - fix this test.
@@ -652,6 +4210,22 @@ mod tests {
         assert_eq!(c.owner, "new_owner_near");
     }
 
+    #[test]
+    fn event_seq_increments() {
+        let (ctx, mut c) = init();
+        let a = ctx.accounts.predecessor.clone();
+        c.deposits.insert(&a, &account_deposit().into());
+        assert_eq!(c.event_seq(), 0);
+
+        c.deposit_near();
+        c.deposit_near();
+        assert_eq!(c.event_seq(), 2);
+        assert_eq!(
+            near_sdk::test_utils::get_logs().last().unwrap(),
+            "seq=2, Deposit, 0 yNEAR"
+        );
+    }
+
     #[test]
     #[should_panic(expected = "E22: Only owner can call this function")]
     fn change_owner_other_account() {
@@ -664,7 +4238,7 @@ mod tests {
     #[should_panic(expected = "E1: pool already exists")]
     fn create_twice_same_pool_fails() {
         let (ctx, mut c) = init();
-        c.create_pool("token1".try_into().unwrap());
+        create_pool_resolved(&ctx, &mut c, &ctx.accounts.token1, None, None);
 
         // let's check firstly the pool is there
         let pools = c.list_pools();
@@ -672,11 +4246,275 @@ mod tests {
         assert_eq!(pools, expected);
 
         //
-        c.create_pool("token1".try_into().unwrap());
+        c.create_pool("token1".try_into().unwrap(), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E27: Price bounds must satisfy 0 < min <= max")]
+    fn create_pool_invalid_price_bounds() {
+        let (_, mut c) = init();
+        let bounds = PriceBounds {
+            min: U128(2 * NDENOM),
+            max: U128(NDENOM),
+        };
+        c.create_pool("token1".try_into().unwrap(), Some(bounds), None);
+    }
+
+    #[test]
+    fn create_pool_not_nep141() {
+        let (mut ctx, mut c) = init();
+        let (t, alice) = (ctx.accounts.token1.clone(), ctx.accounts.alice.clone());
+        set_pool_bond(&mut ctx, &mut c);
+        ctx.vm.attached_deposit = NDENOM;
+        set_predecessor(&mut ctx, &alice);
+        c.create_pool(to_va(t.clone()), None, None);
+
+        // the account doesn't implement `ft_metadata`.
+        let results = vec![
+            PromiseResult::Failed,
+            PromiseResult::Successful(b"\"1000\"".to_vec()),
+        ];
+        set_promise_results(&ctx, results);
+        assert!(!c.resolve_create_pool(t.clone(), alice, None, U128(NDENOM), None));
+        assert!(c.list_pools().is_empty());
+        assert!(c.pool_bond(t).is_none());
+    }
+
+    fn stable_template() -> PoolTemplate {
+        PoolTemplate {
+            fee_bps: 5,
+            dynamic_fee: Some(DynamicFee {
+                surcharge_bps: 10,
+                discount_bps: 5,
+            }),
+            twap_window: 600,
+            max_trade: Some(MaxTrade::ReserveBps { bps: 1000 }),
+            batch_window: 0,
+        }
+    }
+
+    #[test]
+    fn create_pool_with_template() {
+        let (mut ctx, mut c) = init();
+        let (t1, t2) = (ctx.accounts.token1.clone(), ctx.accounts.token2.clone());
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.set_pool_template("stable".to_string(), Some(stable_template()));
+        assert_eq!(c.pool_templates(), vec![("stable".to_string(), stable_template())]);
+
+        create_pool_resolved(&ctx, &mut c, &t1, None, Some("stable".to_string()));
+        let p = c.get_pool(&t1);
+        assert_eq!(p.fee_bps, 5);
+        assert_eq!(p.dynamic_fee, stable_template().dynamic_fee);
+        assert_eq!(p.twap_window, 600);
+        assert_eq!(p.max_trade, Some(MaxTrade::ReserveBps { bps: 1000 }));
+
+        // the default template applies to the pools created without a template.
+        let default = PoolTemplate {
+            fee_bps: 100,
+            batch_window: 5,
+            ..stable_template()
+        };
+        c.set_pool_template(DEFAULT_POOL_TEMPLATE.to_string(), Some(default));
+        create_pool_resolved(&ctx, &mut c, &t2, None, None);
+        let p = c.get_pool(&t2);
+        assert_eq!((p.fee_bps, p.batch_window), (100, 5));
+    }
+
+    /// Starts a `token2` launch by the token contract, with the `ctx` attached deposit: 4N
+    /// tokens from 0.1 to 0.3 yNEAR per token unit, half of the proceeds seeded.
+    fn create_token_launch(ctx: &mut Ctx, c: &mut NearSwap) -> AccountId {
+        let t = ctx.accounts.token2.clone();
+        let d = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: [(t.clone(), 10 * NDENOM)].iter().cloned().collect(),
+        };
+        c.deposits.insert(&t, &d.into());
+        set_predecessor(ctx, &t);
+        c.create_launch(
+            t.clone(),
+            LaunchConfig {
+                sale_tokens: U128(4 * NDENOM),
+                start_price: U128(NDENOM / 10),
+                end_price: U128(3 * NDENOM / 10),
+                seed_bps: 5_000,
+                seed_tokens: U128(4 * NDENOM),
+            },
+        );
+        assert_eq!(c.get_deposit(&t).tokens.get(&t), Some(&(2 * NDENOM)));
+        assert!(!c.launch(t.clone()).unwrap().active);
+        t
+    }
+
+    /// Starts a `token2` launch, see `create_token_launch`, and resolves the NEP-141 probes.
+    fn token_launch(ctx: &mut Ctx, c: &mut NearSwap) -> AccountId {
+        let t = create_token_launch(ctx, c);
+        let metadata = br#"{"spec":"ft-1.0.0","name":"Token","symbol":"TKN","icon":null,
+            "reference":null,"reference_hash":null,"decimals":24}"#;
+        set_promise_results(
+            ctx,
+            vec![
+                PromiseResult::Successful(metadata.to_vec()),
+                PromiseResult::Successful(b"\"1000\"".to_vec()),
+            ],
+        );
+        assert!(c.resolve_create_launch(t.clone()));
+        t
+    }
+
+    #[test]
+    fn launch_sold_out_seeds_pool() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = token_launch(&mut ctx, &mut c);
+
+        set_predecessor(&mut ctx, &a);
+        assert_eq!(c.launch_cost(t.clone(), U128(2 * NDENOM)), U128(3 * NDENOM / 10));
+        c.buy_launch(t.clone(), U128(2 * NDENOM), U128(3 * NDENOM / 10));
+        assert_eq!(c.launch(t.clone()).unwrap().sold, U128(2 * NDENOM));
+        assert!(c.pools.get(&t).is_none());
+        // the purchase is capped at the remaining tokens.
+        assert_eq!(c.buy_launch(t.clone(), U128(10 * NDENOM), U128(NDENOM)), U128(NDENOM / 2));
+        let d = c.get_deposit(&a);
+        assert_eq!(d.tokens.get(&t), Some(&(4 * NDENOM)));
+        assert_eq!(d.ynear, NDENOM / 5);
+
+        // 0.4N of the 0.8N proceeds seed the pool at the curve end price.
+        assert!(c.launch(t.clone()).is_none());
+        let p = c.get_pool(&t);
+        let seed_tokens = 4 * NDENOM / 3;
+        assert_eq!((p.ynear, p.tokens), (2 * NDENOM / 5, seed_tokens));
+        assert_eq!(p.shares.get(&t), Some(p.total_shares));
+        assert_eq!(c.pool_metadata(t.clone()).unwrap().creator, t);
+        assert!(c.pool_id(t.clone()).is_some());
+        assert!(c.pool_bond(t.clone()).is_none());
+        let d = c.get_deposit(&t);
+        assert_eq!(d.tokens.get(&t), Some(&(6 * NDENOM - seed_tokens)));
+        assert!(d.ynear > NDENOM + NDENOM / 3);
+    }
+
+    #[test]
+    fn launch_bond_becomes_pool_bond() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        set_pool_bond(&mut ctx, &mut c);
+        ctx.vm.attached_deposit = NDENOM;
+        let t = token_launch(&mut ctx, &mut c);
+        assert_eq!(c.launch(t.clone()).unwrap().bond, U128(NDENOM));
+
+        ctx.vm.attached_deposit = 1;
+        set_predecessor(&mut ctx, &a);
+        c.buy_launch(t.clone(), U128(4 * NDENOM), U128(NDENOM));
+        let b = c.pool_bond(t.clone()).unwrap();
+        assert_eq!((b.creator, b.amount), (t, U128(NDENOM)));
     }
 
-    fn check_and_create_pool(c: &mut NearSwap, token: &AccountId) {
-        c.create_pool(token.to_string().try_into().unwrap());
+    #[test]
+    #[should_panic(expected = "E87")]
+    fn launch_without_bond() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        set_pool_bond(&mut ctx, &mut c);
+        create_token_launch(&mut ctx, &mut c);
+    }
+
+    #[test]
+    #[should_panic(expected = "E147")]
+    fn launch_by_token_holder() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let (alice, t) = (ctx.accounts.alice.clone(), ctx.accounts.token2.clone());
+        let d = DepositV1 {
+            ynear: NDENOM,
+            storage_used: 84,
+            tokens: [(t.clone(), 10 * NDENOM)].iter().cloned().collect(),
+        };
+        c.deposits.insert(&alice, &d.into());
+        set_predecessor(&mut ctx, &alice);
+        c.create_launch(
+            t,
+            LaunchConfig {
+                sale_tokens: U128(NDENOM),
+                start_price: U128(NDENOM / 10),
+                end_price: U128(NDENOM / 10),
+                seed_bps: 0,
+                seed_tokens: U128(0),
+            },
+        );
+    }
+
+    #[test]
+    fn launch_not_nep141() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = create_token_launch(&mut ctx, &mut c);
+        let results = vec![
+            PromiseResult::Failed,
+            PromiseResult::Successful(b"\"1000\"".to_vec()),
+        ];
+        set_promise_results(&ctx, results);
+        assert!(!c.resolve_create_launch(t.clone()));
+        assert!(c.launch(t.clone()).is_none());
+        assert_eq!(c.get_deposit(&t).tokens.get(&t), Some(&(10 * NDENOM)));
+    }
+
+    #[test]
+    #[should_panic(expected = "E148")]
+    fn buy_launch_before_probes() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = create_token_launch(&mut ctx, &mut c);
+        set_predecessor(&mut ctx, &a);
+        c.buy_launch(t, U128(NDENOM), U128(NDENOM));
+    }
+
+    #[test]
+    #[should_panic(expected = "E145")]
+    fn create_pool_during_launch() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = create_token_launch(&mut ctx, &mut c);
+        c.create_pool(to_va(t), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E144")]
+    fn buy_launch_above_max_cost() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let a = ctx.accounts.predecessor.clone();
+        let t = token_launch(&mut ctx, &mut c);
+        set_predecessor(&mut ctx, &a);
+        c.buy_launch(t, U128(2 * NDENOM), U128(3 * NDENOM / 10 - 1));
+    }
+
+    #[test]
+    fn cancel_launch() {
+        let (mut ctx, mut c) = prepare_for_swap_request();
+        let t = token_launch(&mut ctx, &mut c);
+        c.cancel_launch(t.clone());
+        assert!(c.list_launches().is_empty());
+        assert_eq!(c.get_deposit(&t).tokens.get(&t), Some(&(10 * NDENOM)));
+    }
+
+    #[test]
+    #[should_panic(expected = "E141")]
+    fn create_pool_unknown_template() {
+        let (_, mut c) = init();
+        c.create_pool("token1".try_into().unwrap(), None, Some("stable".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "E134")]
+    fn invalid_pool_template() {
+        let (mut ctx, mut c) = init();
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        let t = PoolTemplate {
+            twap_window: 1,
+            ..stable_template()
+        };
+        c.set_pool_template("stable".to_string(), Some(t));
+    }
+
+    fn check_and_create_pool(ctx: &Ctx, c: &mut NearSwap, token: &AccountId) {
+        create_pool_resolved(ctx, c, token, None, None);
         match c.pool_info(token) {
             None => panic!("Pool for {} token is expected", token),
             Some(p) => assert_eq!(
@@ -693,8 +4531,8 @@ mod tests {
     #[test]
     fn anyone_create_pool() {
         let (ctx, mut c) = init();
-        check_and_create_pool(&mut c, &ctx.accounts.token1);
-        check_and_create_pool(&mut c, &ctx.accounts.token2);
+        check_and_create_pool(&ctx, &mut c, &ctx.accounts.token1);
+        check_and_create_pool(&ctx, &mut c, &ctx.accounts.token2);
 
         let mut pools = c.list_pools();
         let mut expected = [ctx.accounts.token1, ctx.accounts.token2];
@@ -703,6 +4541,44 @@ mod tests {
         assert_eq!(pools, expected);
     }
 
+    #[test]
+    fn pool_ids() {
+        let (mut ctx, mut c) = init();
+        let (t1, t2) = (ctx.accounts.token1.clone(), ctx.accounts.token2.clone());
+        create_pool_resolved(&ctx, &mut c, &t1, None, None);
+        create_pool_resolved(&ctx, &mut c, &t2, None, None);
+        assert_eq!((c.pool_id(t1.clone()), c.pool_id(t2.clone())), (Some(0), Some(1)));
+        assert_eq!(c.pool_token(1), Some(t2.clone()));
+        assert_eq!(c.pool_info_by_id(0), c.pool_info(&t1));
+        assert_eq!(c.assign_pool_ids(0, 10), 0);
+
+        let owner = ctx.accounts.owner.clone();
+        set_predecessor(&mut ctx, &owner);
+        c.remove_pool(t2.clone());
+        assert!(c.pool_token(1).is_none() && c.pool_id(t2).is_none());
+    }
+
+    #[test]
+    fn swap_by_pool_id() {
+        let (ctx, mut c) = prepare_for_swap_request();
+        let (a, t) = (ctx.accounts.predecessor.clone(), ctx.accounts.token1.clone());
+        // the pool was created before the pool ids.
+        assert!(c.pool_id(t.clone()).is_none());
+        assert_eq!(c.assign_pool_ids(0, 10), 1);
+        assert_eq!(c.pool_id(t.clone()), Some(0));
+
+        let quote = c.price_near_to_token_in(t.clone(), U128(NDENOM / 2));
+        assert_eq!(c.swap_by_pool_id(0, true, U128(NDENOM / 2), quote, None), quote);
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(2 * NDENOM + quote.0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "E133: Pool id not found")]
+    fn swap_by_unknown_pool_id() {
+        let (_, mut c) = prepare_for_swap_request();
+        c.swap_by_pool_id(7, true, U128(NDENOM / 2), U128(1), None);
+    }
+
     #[test]
     fn add_liquidity_happy_path() {
         let ynear_deposit = 3 * NDENOM;
@@ -714,7 +4590,7 @@ mod tests {
         let a = ctx.accounts.predecessor.clone();
 
         // in unit tests we can't do cross contract calls, so we can't check token1 updates.
-        check_and_create_pool(&mut c, &t);
+        check_and_create_pool(&ctx, &mut c, &t);
 
         let d = DepositV1 {
             ynear: 2 * ynear_deposit + NDENOM,
@@ -801,13 +4677,7 @@ mod tests {
         let initial_ynear = 30 * NDENOM;
         let mut shares_map = LookupMap::new("123".as_bytes().to_vec());
         shares_map.insert(&a, &initial_ynear);
-        let p = Pool {
-            ynear: initial_ynear,
-            tokens: 10 * NDENOM,
-            total_shares: initial_ynear,
-            shares: shares_map,
-            twap: Twap::new(10),
-        };
+        let p = new_pool(initial_ynear, 10 * NDENOM, initial_ynear, shares_map);
         c.pools.insert(&t, &p);
 
         c.add_liquidity(
@@ -820,7 +4690,7 @@ mod tests {
         let p_info = c.pool_info(&t).expect("Pool should exist");
         let expected_pool = PoolInfo {
             ynear: (ynear_deposit + p.ynear).into(),
-            tokens: (token_deposit + p.tokens + 1).into(), // 1 is added as a minimum token transfer
+            tokens: (token_deposit + p.tokens).into(),
             total_shares: (ynear_deposit + p.ynear).into(),
         };
         assert_eq!(p_info, expected_pool, "pool_info should be correct");
@@ -853,13 +4723,7 @@ mod tests {
         let mut shares_map = LookupMap::new("123".as_bytes().to_vec());
         shares_map.insert(&a, &initial_ynear);
         // Pool Ratio: 3:1
-        let p = Pool {
-            ynear: initial_ynear,
-            tokens: 10 * NDENOM,
-            total_shares: initial_ynear,
-            shares: shares_map,
-            twap: Twap::new(10),
-        };
+        let p = new_pool(initial_ynear, 10 * NDENOM, initial_ynear, shares_map);
         c.pools.insert(&t, &p);
 
         let max_tokens = token_deposit / 2;
@@ -881,9 +4745,42 @@ mod tests {
         assert_eq!(p_info, expected_pool, "pool_info should be correct");
     }
 
+    #[test]
+    fn simulate_add_liquidity_matches() {
+        let (ctx, mut c) = _init(1);
+        let t = ctx.accounts.token1.clone();
+        let a = ctx.accounts.predecessor.clone();
+
+        let d = DepositV1 {
+            ynear: 31 * NDENOM,
+            storage_used: 84,
+            tokens: [(t.clone(), 110 * NDENOM)].iter().cloned().collect(),
+        };
+        c.deposits.insert(&a, &d.into());
+        let mut shares_map = LookupMap::new("123".as_bytes().to_vec());
+        shares_map.insert(&a, &(30 * NDENOM));
+        let p = new_pool(30 * NDENOM, 10 * NDENOM, 30 * NDENOM, shares_map);
+        c.pools.insert(&t, &p);
+
+        let ynear = U128(30 * NDENOM);
+        let max_tokens = U128(5 * NDENOM);
+        let preview = c.simulate_add_liquidity(t.clone(), ynear, max_tokens);
+        let shares = c.add_liquidity(t.clone(), ynear, max_tokens, U128(0));
+
+        assert_eq!(preview.shares, shares);
+        assert_eq!(preview.tokens, max_tokens);
+        assert_eq!(preview.pool, c.pool_info(&t).unwrap());
+        assert_eq!(
+            preview.ynear.0,
+            expected_adjusted_near(max_tokens.0, p.ynear, p.tokens)
+        );
+    }
+
     fn expected_adjusted_near(max_tokens: u128, ynear_pool: u128, tokens_pool: u128) -> u128 {
-        let p_ynear_256 = u256::from(ynear_pool);
-        return ((u256::from(max_tokens) * p_ynear_256) / u256::from(tokens_pool) + 1).as_u128();
+        // rounded up
+        let n = u256::from(max_tokens) * u256::from(ynear_pool);
+        let d = u256::from(tokens_pool);
+        return ((n + d - 1) / d).as_u128();
     }
 
     #[test]
@@ -897,7 +4794,7 @@ mod tests {
         let a = ctx.accounts.predecessor.clone();
 
         // in unit tests we can't do cross contract calls, so we can't check token1 updates.
-        check_and_create_pool(&mut c, &t);
+        check_and_create_pool(&ctx, &mut c, &t);
 
         let d = DepositV1 {
             ynear: 2 * ynear_deposit + NDENOM,
@@ -940,7 +4837,7 @@ mod tests {
 
         println!(">> adding liquidity - second time with minted shares");
 
-        let min_shares = to_yocto("30");
+        let min_shares = 30 * NDENOM;
 
         c.add_liquidity(
             t.clone(),
@@ -969,20 +4866,14 @@ mod tests {
 
     #[test]
     fn withdraw_happy_path() {
-        let (ctx, mut c) = init_with_storage_deposit();
+        let (ctx, mut c) = _init(1);
         let a = ctx.accounts.predecessor.clone();
         let t = ctx.accounts.token1.clone();
 
         let shares_bal = 12 * NDENOM;
         let mut shares_map = LookupMap::new("123".as_bytes().to_vec());
         shares_map.insert(&a, &shares_bal);
-        let p = Pool {
-            ynear: shares_bal,
-            tokens: 3 * NDENOM,
-            total_shares: shares_bal,
-            shares: shares_map,
-            twap: Twap::new(10),
-        };
+        let p = new_pool(shares_bal, 3 * NDENOM, shares_bal, shares_map);
         c.set_pool(&t, &p);
 
         let d = DepositV1 {
@@ -1016,21 +4907,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn simulate_withdraw_liquidity_matches() {
+        let (t, mut c) = prepare_for_withdraw();
+        let a = env::predecessor_account_id();
+        // make the pool amounts not divisible by shares to check rounding
+        let mut p = c.get_pool(&t);
+        p.tokens += 7;
+        c.set_pool(&t, &p);
+
+        let shares = U128(5 * NDENOM + 1);
+        let preview = c.simulate_withdraw_liquidity(t.clone(), shares);
+        let d_before = c.get_deposit(&a);
+        c.withdraw_liquidity(t.clone(), shares, U128(1), U128(1));
+        let d_after = c.get_deposit(&a);
+
+        assert_eq!(preview.pool, c.pool_info(&t).unwrap());
+        assert_eq!(preview.ynear.0, d_after.ynear - d_before.ynear);
+        assert_eq!(
+            preview.tokens.0,
+            d_after.tokens.get(&t).unwrap() - d_before.tokens.get(&t).unwrap()
+        );
+    }
+
     fn prepare_for_withdraw() -> (AccountId, NearSwap) {
-        let (ctx, mut c) = init_with_storage_deposit();
+        let (ctx, mut c) = _init(1);
         let a = ctx.accounts.predecessor.clone();
         let t = ctx.accounts.token1.clone();
 
         let shares_bal = 12 * NDENOM;
         let mut shares_map = LookupMap::new("123".as_bytes().to_vec());
         shares_map.insert(&a, &shares_bal);
-        let p = Pool {
-            ynear: shares_bal,
-            tokens: 3 * NDENOM,
-            total_shares: shares_bal,
-            shares: shares_map,
-            twap: Twap::new(10),
-        };
+        let p = new_pool(shares_bal, 3 * NDENOM, shares_bal, shares_map);
         c.set_pool(&t, &p);
 
         let d = DepositV1 {
@@ -1077,29 +4985,123 @@ mod tests {
         c.withdraw_liquidity(t.clone(), shares.into(), min_near, min_token);
     }
 
+    /// Stakes half of the `prepare_for_withdraw` pool NEAR: 6 NEAR staked, 6 NEAR liquid.
+    fn prepare_for_staked_withdraw() -> (Ctx, AccountId, NearSwap) {
+        let (t, mut c) = prepare_for_withdraw();
+        let mut ctx = Ctx::new(vec![], false);
+        ctx.vm.attached_deposit = 1;
+        let (a, owner) = (ctx.accounts.predecessor.clone(), ctx.accounts.owner.clone());
+        set_predecessor(&mut ctx, &owner);
+        c.set_staking_config(Some("validator.near".try_into().unwrap()), 5000);
+        assert!(c.rebalance_stake(t.clone()));
+        set_promise_result(&ctx, PromiseResult::Successful(vec![]));
+        assert!(c.resolve_staking(t.clone(), StakingOp::Stake, U128(6 * NDENOM)));
+        set_predecessor(&mut ctx, &a);
+        (ctx, t, c)
+    }
+
+    #[test]
+    #[should_panic(expected = "E150")]
+    fn withdraw_over_liquid_near() {
+        let (_ctx, t, mut c) = prepare_for_staked_withdraw();
+        c.withdraw_liquidity(t, U128(12 * NDENOM), U128(1), U128(1));
+    }
+
+    #[test]
+    fn withdraw_while_staked() {
+        let (mut ctx, t, mut c) = prepare_for_staked_withdraw();
+        let a = ctx.accounts.predecessor.clone();
+        let ynear_before = c.get_deposit(&a).ynear;
+
+        // the redemption is capped at the liquid NEAR.
+        c.withdraw_liquidity(t.clone(), U128(6 * NDENOM), U128(1), U128(1));
+        assert_eq!(c.get_deposit(&a).ynear, ynear_before + 6 * NDENOM);
+        let s = c.pool_staking(t.clone());
+        assert_eq!((s.staked, s.liquid), (U128(6 * NDENOM), U128(0)));
+
+        // the reserve dropped below the target: the staked NEAR above it is unstaked and,
+        // after the unlock period, withdrawn for the next redemption.
+        assert!(c.rebalance_stake(t.clone()));
+        set_promise_result(&ctx, PromiseResult::Successful(vec![]));
+        assert!(c.resolve_staking(t.clone(), StakingOp::Unstake, U128(3 * NDENOM)));
+        ctx.vm.epoch_height = c.pool_staking(t.clone()).unstake_available_epoch.0;
+        set_predecessor(&mut ctx, &a);
+        assert!(c.rebalance_stake(t.clone()));
+        set_promise_result(&ctx, PromiseResult::Successful(vec![]));
+        assert!(c.resolve_staking(t.clone(), StakingOp::Withdraw, U128(3 * NDENOM)));
+        assert_eq!(c.pool_staking(t.clone()).liquid, U128(3 * NDENOM));
+
+        set_predecessor(&mut ctx, &a);
+        c.withdraw_liquidity(t.clone(), U128(3 * NDENOM), U128(1), U128(1));
+        assert_eq!(c.get_deposit(&a).ynear, ynear_before + 9 * NDENOM);
+        assert_eq!(c.get_pool(&t).liquid_ynear(), 0);
+    }
+
+    /// Lends half of the `prepare_for_withdraw` pool tokens: 1.5 tokens lent, 1.5 liquid.
+    fn prepare_for_lent_withdraw() -> (Ctx, AccountId, NearSwap) {
+        let (t, mut c) = prepare_for_withdraw();
+        let mut ctx = Ctx::new(vec![], false);
+        ctx.vm.attached_deposit = 1;
+        let (a, owner) = (ctx.accounts.predecessor.clone(), ctx.accounts.owner.clone());
+        set_predecessor(&mut ctx, &owner);
+        c.set_lending_market(Some("lending.near".try_into().unwrap()));
+        c.set_pool_lending_share(t.clone(), 5000);
+        c.record_token_in(&t, 3 * NDENOM);
+        assert!(c.rebalance_lending(t.clone()));
+        let used = format!("\"{}\"", 15 * NDENOM / 10).into_bytes();
+        set_promise_result(&ctx, PromiseResult::Successful(used));
+        assert!(c.resolve_lending(t.clone(), LendingOp::Supply, U128(15 * NDENOM / 10)));
+        set_predecessor(&mut ctx, &a);
+        (ctx, t, c)
+    }
+
+    #[test]
+    #[should_panic(expected = "E151")]
+    fn withdraw_over_liquid_tokens() {
+        let (_ctx, t, mut c) = prepare_for_lent_withdraw();
+        c.withdraw_liquidity(t, U128(12 * NDENOM), U128(1), U128(1));
+    }
+
+    #[test]
+    fn withdraw_while_lent() {
+        let (mut ctx, t, mut c) = prepare_for_lent_withdraw();
+        let a = ctx.accounts.predecessor.clone();
+
+        // the redemption is capped at the liquid tokens.
+        c.withdraw_liquidity(t.clone(), U128(6 * NDENOM), U128(1), U128(1));
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(15 * NDENOM / 10 + 11)));
+        let l = c.pool_lending(t.clone());
+        assert_eq!((l.lent, l.liquid), (U128(15 * NDENOM / 10), U128(0)));
+
+        // the lent tokens above the target are withdrawn for the next redemption.
+        assert!(c.rebalance_lending(t.clone()));
+        set_promise_result(&ctx, PromiseResult::Successful(vec![]));
+        assert!(c.resolve_lending(t.clone(), LendingOp::Withdraw, U128(75 * NDENOM / 100)));
+        assert_eq!(c.pool_lending(t.clone()).liquid, U128(75 * NDENOM / 100));
+
+        set_predecessor(&mut ctx, &a);
+        c.withdraw_liquidity(t.clone(), U128(3 * NDENOM), U128(1), U128(1));
+        assert_eq!(c.get_deposit(&a).tokens.get(&t), Some(&(225 * NDENOM / 100 + 11)));
+        assert_eq!(c.get_pool(&t).liquid_tokens(), 0);
+    }
+
     #[test]
     fn shares_transfer() {
-        let (ctx, mut c) = init_with_storage_deposit();
+        let (ctx, mut c) = _init(1);
         let acc = ctx.accounts.predecessor.clone();
         let t = ctx.accounts.token1.clone();
 
         let shares_bal = 12 * NDENOM;
         let mut shares_map = LookupMap::new("123".as_bytes().to_vec());
         shares_map.insert(&acc, &shares_bal);
-        let p = Pool {
-            ynear: shares_bal,
-            tokens: 22 * NDENOM,
-            total_shares: shares_bal,
-            shares: shares_map,
-            twap: Twap::new(10),
-        };
+        let p = new_pool(shares_bal, 22 * NDENOM, shares_bal, shares_map);
         c.set_pool(&t, &p);
 
         let amount = shares_bal / 3;
         let alice = ctx.accounts.alice.clone();
         c.transfer(
             t.clone(),
-            alice.clone(),
+            to_va(alice.clone()),
             amount.into(),
             "msg".to_string(),
             "reference".to_string(),
@@ -1165,7 +5167,7 @@ mod tests {
         const G: u128 = 1_000_000_000;
         let assert_out = |in_amount, in_bal, out_bal| {
             assert_eq!(
-                c.calc_out_with_fee(in_amount, in_bal, out_bal).0,
+                c.calc_out_with_fee(in_amount, in_bal, out_bal, clp_math::FEE_BPS).0,
                 expected_calc_price_fee(in_amount, in_bal, out_bal)
             )
         };
@@ -1203,13 +5205,14 @@ mod tests {
         let (_, c) = init();
         const G: u128 = 1_000_000_000;
         let x = c.calc_out_amount(1_000_000, G, G);
-        let (y, _) = c.calc_out_with_fee(1_000_000, G, G);
+        let (y, _) = c.calc_out_with_fee(1_000_000, G, G, clp_math::FEE_BPS);
         assert!(x > y, "Tokens output incorrect");
     }
 
     #[allow(non_snake_case)]
     fn expected_calc_price_fee(amount: u128, in_bal: u128, out_bal: u128) -> u128 {
-        let x = u256::from(amount - (amount * 3) / 1000);
+        // fee is rounded up
+        let x = u256::from(amount - (amount * 3 + 999) / 1000);
         let X = u256::from(in_bal);
         let numerator = x * u256::from(out_bal) * X;
         let mut denominator = x + X;
@@ -1224,22 +5227,10 @@ mod tests {
         let t1: AccountId = "token1".to_string();
         let t2: AccountId = "token2".to_string();
         let p1_factor = 4;
-        let p1 = Pool {
-            // 1:4
-            ynear: G,
-            tokens: p1_factor * G,
-            total_shares: 0,
-            shares: LookupMap::new("1".as_bytes().to_vec()),
-            twap: Twap::new(10),
-        };
-        let p2 = Pool {
-            // 2:1
-            ynear: 2 * G,
-            tokens: G,
-            total_shares: 0,
-            shares: LookupMap::new("2".as_bytes().to_vec()),
-            twap: Twap::new(10),
-        };
+        // 1:4
+        let p1 = new_pool(G, p1_factor * G, 0, LookupMap::new("1".as_bytes().to_vec()));
+        // 2:1
+        let p2 = new_pool(2 * G, G, 0, LookupMap::new("2".as_bytes().to_vec()));
         c.set_pool(&t1, &p1);
         c.set_pool(&t2, &p2);
 
@@ -1265,6 +5256,99 @@ mod tests {
         assert_close(v, amount / 8, 1000);
     }
 
+    /// Reference Uniswap v2 `getAmountOut`, independent of `clp_math`: constant product
+    /// `X * Y = k` with the fee (rounded up) taken from the input `x`. Returns the exact out
+    /// amount as a fraction: (numerator, denominator).
+    #[allow(non_snake_case)]
+    fn ref_v2_out(x: u128, X: u128, Y: u128, fee_bps: u16) -> (u256, u256) {
+        let fee = (x * fee_bps as u128 + 9_999) / 10_000;
+        let x = u256::from(x - fee);
+        (x * u256::from(Y), u256::from(X) + x)
+    }
+
+    /// Reference CLP out amount: the constant product out amount scaled down by the slip
+    /// `X / (X + x)`, rounded down.
+    #[allow(non_snake_case)]
+    fn ref_clp_out(x: u128, X: u128, Y: u128, fee_bps: u16) -> u128 {
+        let (num, den) = ref_v2_out(x, X, Y, fee_bps);
+        (num * u256::from(X) / (den * den)).as_u128()
+    }
+
+    /// Deterministic xorshift64 generator of values in `1..=max`.
+    fn rand_amount(seed: &mut u64, max: u128) -> u128 {
+        let mut next = || {
+            *seed ^= *seed << 13;
+            *seed ^= *seed >> 7;
+            *seed ^= *seed << 17;
+            *seed as u128
+        };
+        let r = (next() << 64) | next();
+        r % max + 1
+    }
+
+    #[test]
+    fn pricing_matches_reference() {
+        let (_, mut c) = init_with_storage_deposit();
+        // x * X * Y must fit into u256.
+        const MAX_RESERVE: u128 = 1 << 80;
+        const MAX_AMOUNT: u128 = 1 << 70;
+        let (t1, t2): (AccountId, AccountId) = ("token1".into(), "token2".into());
+        let mut seed = 0x2545_f491_4f6c_dd1d;
+        for i in 0..200 {
+            let mut p1 = new_pool(
+                rand_amount(&mut seed, MAX_RESERVE),
+                rand_amount(&mut seed, MAX_RESERVE),
+                0,
+                LookupMap::new(format!("1-{}", i).into_bytes()),
+            );
+            p1.fee_bps = (rand_amount(&mut seed, 101) - 1) as u16;
+            let p2 = new_pool(
+                rand_amount(&mut seed, MAX_RESERVE),
+                rand_amount(&mut seed, MAX_RESERVE),
+                0,
+                LookupMap::new(format!("2-{}", i).into_bytes()),
+            );
+            c.set_pool(&t1, &p1);
+            c.set_pool(&t2, &p2);
+            let x = rand_amount(&mut seed, MAX_AMOUNT);
+
+            let out = to_num(c.price_near_to_token_in(t1.clone(), x.into()));
+            assert_eq!(out, ref_clp_out(x, p1.ynear, p1.tokens, p1.fee_bps), "case {}", i);
+            let (num, den) = ref_v2_out(x, p1.ynear, p1.tokens, p1.fee_bps);
+            assert!(u256::from(out) <= num / den, "case {}: CLP pays more than v2", i);
+
+            let out = to_num(c.price_token_to_near_in(t1.clone(), x.into()));
+            assert_eq!(out, ref_clp_out(x, p1.tokens, p1.ynear, p1.fee_bps), "case {}", i);
+            let (num, den) = ref_v2_out(x, p1.tokens, p1.ynear, p1.fee_bps);
+            assert!(u256::from(out) <= num / den, "case {}: CLP pays more than v2", i);
+
+            let ynear = ref_clp_out(x, p1.tokens, p1.ynear, p1.fee_bps);
+            let out = to_num(c.price_token_to_token_in(t1.clone(), t2.clone(), x.into()));
+            assert_eq!(out, ref_clp_out(ynear, p2.ynear, p2.tokens, p2.fee_bps), "case {}", i);
+        }
+    }
+
+    #[test]
+    fn best_quote() {
+        let (_, c) = prepare_for_swap_request();
+        let t = "token1".to_string();
+        let fee_bps = c.get_pool(&t).fee_bps;
+        let (out, _) = clp_math::calc_out_with_fee_bps(NDENOM, 10 * NDENOM, 20 * NDENOM, fee_bps);
+        let q = c.best_quote(None, Some(t.clone()), U128(NDENOM), 100);
+        assert_eq!(q.amount_out, U128(out));
+        assert_eq!(q.route, vec![None, Some(t.clone())]);
+        let spot_out = 2 * NDENOM;
+        assert_eq!(
+            q.price_impact_bps as u128,
+            clp_math::mul_div_ceil(spot_out - out, 10_000, spot_out)
+        );
+        assert_eq!(q.min_out, U128(out * 99 / 100));
+
+        let q = c.best_quote(Some(t.clone()), None, U128(NDENOM), 0);
+        assert_eq!(q.min_out, q.amount_out);
+        assert_eq!(q.route, vec![Some(t), None]);
+    }
+
     fn to_num(a: U128) -> u128 {
         a.into()
     }
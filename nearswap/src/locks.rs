@@ -0,0 +1,189 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  LIQUIDITY LOCKS
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance};
+
+use crate::constants::*;
+use crate::*;
+
+/// LP shares locked with `lock_liquidity` until `expiry`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LiquidityLock {
+    pub shares: Balance,
+    /// fee share boost, in basis points (10000 = no boost).
+    pub boost_bps: u16,
+    /// unlock timestamp, in nanoseconds.
+    pub expiry: u64,
+    /// pool boost accumulator checkpoints at the lock time.
+    acc_ynear: Balance,
+    acc_tokens: Balance,
+}
+
+impl LiquidityLock {
+    /// Extra (boost) weight of the locked shares in the pool fee distribution.
+    pub fn boost_weight(&self) -> Balance {
+        clp_math::mul_div_floor(self.shares, self.boost_bps as u128 - 10_000, 10_000)
+    }
+
+    /// Returns the boost fees (yNEAR, tokens) earned since the lock.
+    pub fn pending(&self, p: &Pool) -> (Balance, Balance) {
+        let w = self.boost_weight();
+        (
+            p.boost_acc_ynear.earned(w, self.acc_ynear),
+            p.boost_acc_tokens.earned(w, self.acc_tokens),
+        )
+    }
+}
+
+/// Lock duration with its fee share boost.
+#[derive(Serialize, Deserialize)]
+pub struct LockTier {
+    /// lock duration, in nanoseconds.
+    pub duration: U64,
+    /// fee share boost, in basis points (10000 = no boost).
+    pub boost_bps: u16,
+}
+
+/// Public information of a liquidity lock.
+#[derive(Serialize, Deserialize)]
+pub struct LockInfo {
+    pub shares: U128,
+    pub boost_bps: u16,
+    /// unlock timestamp, in nanoseconds.
+    pub expiry: U64,
+    /// boost fees earned so far, credited to the deposit on unlock.
+    pub pending_ynear: U128,
+    pub pending_tokens: U128,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Returns the available lock tiers. `lock_liquidity` takes the tier index.
+    pub fn lock_tiers(&self) -> Vec<LockTier> {
+        LOCK_TIERS
+            .iter()
+            .map(|(duration, boost_bps)| LockTier {
+                duration: (*duration).into(),
+                boost_bps: *boost_bps,
+            })
+            .collect()
+    }
+
+    /**
+    Locks `shares` of the caller `token` pool LP shares for the `tier` duration (see
+    `lock_tiers`). Locked shares can't be withdrawn nor transferred, and they get a boosted
+    share of the pool swap fees, taken from the unlocked shares part. The extra fees are
+    credited to the deposit with `unlock_liquidity`.
+    An account can have only one lock per pool.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation. */
+    #[payable]
+    pub fn lock_liquidity(&mut self, token: AccountId, shares: U128, tier: u8) {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let shares: Balance = shares.into();
+        assert!(shares >= MIN_LOCK_SHARES, "{}", ERR40_LOCK_TOO_SMALL);
+        let (duration, boost_bps) = *LOCK_TIERS
+            .get(tier as usize)
+            .expect(ERR41_INVALID_LOCK_TIER);
+        let user = env::predecessor_account_id();
+        let mut p = self.get_pool(&token);
+        assert!(p.locks.get(&user).is_none(), "{}", ERR42_LOCK_EXISTS);
+        let owned = p.shares.get(&user).unwrap_or(0);
+        assert!(
+            owned >= shares,
+            "{} (owned {}, requested {})",
+            ERR11_NOT_ENOUGH_SHARES,
+            owned,
+            shares
+        );
+
+        let l = LiquidityLock {
+            shares,
+            boost_bps,
+            expiry: env::block_timestamp() + duration,
+            acc_ynear: p.boost_acc_ynear.acc_reward_per_share,
+            acc_tokens: p.boost_acc_tokens.acc_reward_per_share,
+        };
+        p.set_shares(&user, owned - shares);
+        p.boost_weight += l.boost_weight();
+        let pool_storage = env::storage_usage();
+        p.locks.insert(&user, &l);
+        p.update_storage(pool_storage);
+        self.set_pool(&token, &p);
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Locked {} {} shares until {} with boost {} bps",
+            shares,
+            token,
+            l.expiry,
+            boost_bps
+        );
+    }
+
+    /**
+    Unlocks the caller expired `token` pool liquidity lock: moves the shares back to the
+    caller and credits the earned boost fees to the caller deposit.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the unlocked shares. */
+    #[payable]
+    pub fn unlock_liquidity(&mut self, token: AccountId) -> U128 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let mut p = self.get_pool(&token);
+        let l = p.locks.get(&user).expect(ERR43_LOCK_NOT_FOUND);
+        assert!(
+            env::block_timestamp() >= l.expiry,
+            "{} (expiry {})",
+            ERR44_LOCK_NOT_EXPIRED,
+            l.expiry
+        );
+
+        let (ynear, tokens) = l.pending(&p);
+        let pool_storage = env::storage_usage();
+        p.locks.remove(&user);
+        p.update_storage(pool_storage);
+        p.boost_weight -= l.boost_weight();
+        let owned = p.shares.get(&user).unwrap_or(0);
+        p.set_shares(&user, owned + l.shares);
+        self.set_pool(&token, &p);
+
+        let mut d = self.get_deposit(&user);
+        d.add_near(ynear);
+        d.add(&token, tokens);
+        d.update_storage(start_storage);
+        self.deposits.insert(&user, &d.into());
+        event_log!(
+            self,
+            "Unlocked {} {} shares, boost fees: {} yNEAR and {} tokens",
+            l.shares,
+            token,
+            ynear,
+            tokens
+        );
+        l.shares.into()
+    }
+
+    /// Returns the `account` liquidity lock in the `token` pool, if it exists.
+    pub fn liquidity_lock(&self, token: AccountId, account: AccountId) -> Option<LockInfo> {
+        let p = self.get_pool(&token);
+        p.locks.get(&account).map(|l| {
+            let (ynear, tokens) = l.pending(&p);
+            LockInfo {
+                shares: l.shares.into(),
+                boost_bps: l.boost_bps,
+                expiry: l.expiry.into(),
+                pending_ynear: ynear.into(),
+                pending_tokens: tokens.into(),
+            }
+        })
+    }
+}
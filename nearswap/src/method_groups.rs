@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  METHOD GROUPS
+***********************/
+
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Group of related contract methods, eg the method names of a function-call access key.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct MethodGroup {
+    pub name: String,
+    pub methods: Vec<String>,
+    /// the methods move the caller funds (deposit, liquidity shares or transfers out).
+    pub mutates_funds: bool,
+    /// the change methods require exactly one attached yNEAR (wallet confirmation), so they
+    /// can't be called with a function-call access key, which can't attach a deposit.
+    pub requires_one_yocto: bool,
+}
+
+/// (name, methods, mutates_funds, requires_one_yocto)
+const METHOD_GROUPS: [(&str, &[&str], bool, bool); 4] = [
+    (
+        "quotes",
+        &[
+            "price_near_to_token_in",
+            "price_token_to_near_in",
+            "price_token_to_token_in",
+            "best_quote",
+            "estimate_gas",
+            "pool_info",
+            "get_deposit_near",
+            "get_deposit_token",
+        ],
+        false,
+        false,
+    ),
+    // swaps within the caller deposit, protected by the minimum bought amount. Allowed for
+    // a trading bot function-call access key.
+    (
+        "trading",
+        &[
+            "swap_near_to_token_exact_in",
+            "swap_token_to_near_exact_in",
+            "swap_tokens_exact_in",
+        ],
+        true,
+        false,
+    ),
+    (
+        "liquidity",
+        &["add_liquidity", "withdraw_liquidity", "transfer", "transfer_call"],
+        true,
+        true,
+    ),
+    (
+        "withdrawals",
+        &["withdraw_near", "withdraw_token", "claim_failed_transfers"],
+        true,
+        true,
+    ),
+];
+
+#[near_bindgen]
+impl NearSwap {
+    /// Returns the trader facing method groups. A trading bot can be given a function-call
+    /// access key limited to the `trading` methods: swaps and quotes, without withdrawals.
+    pub fn get_method_groups(&self) -> Vec<MethodGroup> {
+        METHOD_GROUPS
+            .iter()
+            .map(|(name, methods, mutates_funds, requires_one_yocto)| MethodGroup {
+                name: name.to_string(),
+                methods: methods.iter().map(|m| m.to_string()).collect(),
+                mutates_funds: *mutates_funds,
+                requires_one_yocto: *requires_one_yocto,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,210 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  LIQUIDITY MIGRATION
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    env, ext_contract, near_bindgen, AccountId, Balance, Gas, PromiseOrValue, PromiseResult,
+};
+
+use crate::constants::*;
+use crate::*;
+
+/// Amount of gas for the migration source `redeem_lp` call, including its transfers.
+pub const GAS_FOR_MIGRATION_REDEEM: Gas = 50 * TGAS;
+
+/// Amount of gas for the `migrate_in` callback, which adds the liquidity.
+pub const GAS_FOR_RESOLVE_MIGRATE_IN: Gas = 30 * TGAS;
+
+/**
+External AMM adapter interface. `redeem_lp` burns `amount` of the caller LP tokens of a
+NEAR-token pool, transfers the redeemed NEAR and the redeemed tokens (with `ft_transfer`)
+to the caller and returns the (yNEAR, tokens) amounts. */
+#[ext_contract(ext_migration_source)]
+pub trait MigrationSourceAmm {
+    fn redeem_lp(&mut self, amount: U128) -> (U128, U128);
+}
+
+/// External AMM pool, identified by its LP token, whose liquidity can be migrated to the
+/// `token` pool, see `migrate_in`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct MigrationSource {
+    /// contract implementing `redeem_lp`.
+    pub amm: AccountId,
+    /// token paired with NEAR in the external pool.
+    pub token: AccountId,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: approves the `lp_token` external AMM pool as a liquidity migration source.
+    /// `None` removes the approval.
+    pub fn set_migration_source(
+        &mut self,
+        lp_token: ValidAccountId,
+        source: Option<MigrationSource>,
+    ) {
+        self.assert_owner();
+        let lp_token: AccountId = lp_token.into();
+        self.log_admin_action("set_migration_source", &(&lp_token, &source));
+        match source {
+            Some(s) => {
+                assert_account_is_valid(&s.amm);
+                self.get_pool(&s.token);
+                self.migration_sources.insert(&lp_token, &s);
+            }
+            None => {
+                self.migration_sources.remove(&lp_token);
+            }
+        }
+    }
+
+    /// Returns the approved migration sources, by LP token.
+    pub fn migration_sources(&self) -> Vec<(AccountId, MigrationSource)> {
+        self.migration_sources.iter().collect()
+    }
+
+    /**
+    Callback for the `migrate_in` redeem. Credits the redeemed NEAR and tokens to the
+    `sender` deposit and adds them as liquidity to the `token` pool, if it mints at least
+    `min_shares`. Otherwise, or if the pool is paused, empty or removed, the proceeds stay in
+    the deposit. The `token` is passed by `migrate_in`, so a migration source removed in the
+    meantime doesn't affect the redeem in flight. Returns the unused LP tokens: all of them
+    only if the redeem failed. */
+    #[private]
+    pub fn resolve_migrate_in(
+        &mut self,
+        sender: AccountId,
+        lp_token: AccountId,
+        token: AccountId,
+        amount: U128,
+        min_shares: U128,
+    ) -> U128 {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Contract expected a result on the callback"
+        );
+        let data = match env::promise_result(0) {
+            PromiseResult::Successful(data) => data,
+            _ => {
+                event_log!(self, "Migration of {} {} failed", amount.0, lp_token);
+                return amount;
+            }
+        };
+        // the LP tokens were redeemed, so they are never refunded from now on.
+        let (ynear, tokens) = match near_sdk::serde_json::from_slice::<(U128, U128)>(&data) {
+            Ok((y, t)) => (y.0, t.0),
+            Err(_) => {
+                event_log!(
+                    self,
+                    "Migration of {} {}: invalid redeem result",
+                    amount.0,
+                    lp_token
+                );
+                return U128(0);
+            }
+        };
+        self.record_token_in(&token, tokens);
+
+        let start_storage = env::storage_usage();
+        let mut d = self.get_deposit(&sender);
+        d.add_near(ynear);
+        d.add(&token, tokens);
+        let shares = match self.pools.get(&token) {
+            Some(mut p) if !self.paused && !p.paused && p.total_shares > 0 => {
+                let (_, _, Shares(shares)) =
+                    p.calc_add_liquidity(NearAmount(ynear), TokenAmount(tokens));
+                if shares < min_shares.0 {
+                    0
+                } else {
+                    self.run_twamm(&token, &mut p);
+                    let (NearAmount(added_near), TokenAmount(added_tokens), Shares(shares)) = p
+                        .add_liquidity(
+                            &sender,
+                            NearAmount(ynear),
+                            TokenAmount(tokens),
+                            min_shares.into(),
+                        );
+                    d.remove_near(added_near);
+                    d.remove(&token, added_tokens);
+                    self.record_liquidity_event(
+                        &sender,
+                        &token,
+                        LiquidityEventKind::Add,
+                        (added_near, added_tokens, shares),
+                        &p,
+                    );
+                    self.set_pool(&token, &p);
+                    shares
+                }
+            }
+            _ => 0,
+        };
+        d.update_storage(start_storage);
+        self.deposits.insert(&sender, &d.into());
+        event_log!(
+            self,
+            "Migrated {} {} for {} yNEAR and {} tokens: {} shares minted",
+            amount.0,
+            lp_token,
+            ynear,
+            tokens,
+            shares
+        );
+        U128(0)
+    }
+}
+
+impl NearSwap {
+    /// Starts the migration of `amount` of the external `lp_token` LP tokens received from
+    /// `sender`: redeems them with the migration source and adds the proceeds as liquidity,
+    /// see `resolve_migrate_in`. The sender must have a storage deposit.
+    pub(crate) fn migrate_in(
+        &mut self,
+        sender: AccountId,
+        lp_token: AccountId,
+        amount: Balance,
+        min_shares: Balance,
+    ) -> PromiseOrValue<U128> {
+        let source = self
+            .migration_sources
+            .get(&lp_token)
+            .expect(ERR123_NOT_MIGRATION_SOURCE);
+        assert!(amount > 0, "{}", ERR02_POSITIVE_ARGS);
+        self.get_deposit(&sender);
+        ext_migration_source::redeem_lp(
+            amount.into(),
+            &source.amm,
+            1,
+            self.gas_for(GasOp::MigrationRedeem),
+        )
+        .then(ext_self::resolve_migrate_in(
+            sender,
+            lp_token,
+            source.token,
+            amount.into(),
+            min_shares.into(),
+            &env::current_account_id(),
+            0,
+            self.gas_for(GasOp::ResolveMigrateIn),
+        ))
+        .into()
+    }
+}
+
+/// Parses the `ft_on_transfer` message of a liquidity migration: `MIGRATE_IN_MSG`, with
+/// optional minimum shares, eg `migrate_in:1000`. Returns the minimum shares.
+pub(crate) fn parse_migrate_in_msg(msg: &str) -> Option<Balance> {
+    let rest = msg.strip_prefix(MIGRATE_IN_MSG)?;
+    if rest.is_empty() {
+        return Some(0);
+    }
+    let min_shares = rest.strip_prefix(':')?.parse().expect(ERR124_INVALID_MIGRATE_MSG);
+    Some(min_shares)
+}
@@ -0,0 +1,625 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  LIMIT, TRIGGER AND
+  DCA ORDERS
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance, BlockHeight};
+
+use crate::constants::*;
+use crate::*;
+
+/// A limit order placed with `place_limit_order`. Assets are identified by the token account,
+/// `None` denotes NEAR.
+/// The order sells `amount_in` of `token_in` for at least `min_out` of `token_out`, which
+/// defines the limit price. It can be filled incrementally, each fill must respect the limit
+/// price pro rata.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+pub struct LimitOrder {
+    pub account: AccountId,
+    pub token_in: Option<AccountId>,
+    pub token_out: Option<AccountId>,
+    /// total amount of `token_in` to sell.
+    pub amount_in: U128,
+    /// minimum amount of `token_out` to buy for the whole `amount_in`.
+    pub min_out: U128,
+    /// amount of `token_in` sold so far.
+    pub filled_in: U128,
+    /// amount of `token_out` bought so far.
+    pub filled_out: U128,
+}
+
+impl LimitOrder {
+    /// Amount of `token_in` still held in escrow.
+    pub fn remaining(&self) -> Balance {
+        self.amount_in.0 - self.filled_in.0
+    }
+
+    /// Minimum amount of `token_out` for selling `amount` of `token_in` at the limit price,
+    /// rounded up.
+    pub fn min_out_for(&self, amount: Balance) -> Balance {
+        clp_math::mul_div_ceil(amount, self.min_out.0, self.amount_in.0)
+    }
+}
+
+/// Trigger order kind.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerKind {
+    /// executes when the price drops to or below the trigger price.
+    StopLoss,
+    /// executes when the price rises to or above the trigger price.
+    TakeProfit,
+}
+
+/// A stop-loss or take-profit order placed with `place_trigger_order`. Assets are identified
+/// by the token account, `None` denotes NEAR.
+/// The price is the pools TWAP price, see `trigger_price`: yNEAR for 1e24 units of
+/// `token_in`, `token_out` units for 1e24 units of `token_in` in a token to token order, or
+/// yNEAR for 1e24 units of `token_out` when selling NEAR.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+pub struct TriggerOrder {
+    pub account: AccountId,
+    pub token_in: Option<AccountId>,
+    pub token_out: Option<AccountId>,
+    /// amount of `token_in` held in escrow.
+    pub amount_in: U128,
+    pub kind: TriggerKind,
+    pub trigger_price: U128,
+    /// max slippage, in basis points, with respect to the TWAP price at the execution.
+    pub max_slippage_bps: u16,
+}
+
+/// A recurring (dollar-cost-averaging) order placed with `place_dca_order`. Assets are
+/// identified by the token account, `None` denotes NEAR.
+/// Every `interval` blocks `amount_per_interval` of the escrowed budget is swapped, minus the
+/// keeper fee (`DCA_KEEPER_FEE_BPS`), until the budget is exhausted.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+pub struct DcaOrder {
+    pub account: AccountId,
+    pub token_in: Option<AccountId>,
+    pub token_out: Option<AccountId>,
+    /// amount of `token_in` still held in escrow.
+    pub budget: U128,
+    pub amount_per_interval: U128,
+    /// number of blocks between executions.
+    pub interval: BlockHeight,
+    /// first block height at which the next execution can happen.
+    pub next_block: BlockHeight,
+    /// max slippage, in basis points, with respect to the TWAP price at the execution.
+    pub max_slippage_bps: u16,
+    /// amount of `token_out` bought so far.
+    pub filled_out: U128,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Places a limit order: escrows `amount_in` of `token_in` from the caller deposit to be
+    sold for at least `min_out` of `token_out`. `None` token denotes NEAR.
+    The order is filled, fully or in parts, by anyone (keepers) with `fill_limit_order`
+    when the pool price reaches the limit price. Bought tokens are credited to the caller
+    deposit.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the order id. */
+    #[payable]
+    pub fn place_limit_order(
+        &mut self,
+        token_in: Option<AccountId>,
+        amount_in: U128,
+        token_out: Option<AccountId>,
+        min_out: U128,
+    ) -> u64 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        assert!(amount_in.0 > 0 && min_out.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        assert!(
+            token_in != token_out && (token_in.is_some() || token_out.is_some()),
+            "{}",
+            ERR28_INVALID_SWAP_ASSETS
+        );
+        for t in token_in.iter().chain(token_out.iter()) {
+            assert!(self.pools.get(t).is_some(), "Pool for this token doesn't exist");
+        }
+
+        let user = env::predecessor_account_id();
+        let mut d = self.get_deposit(&user);
+        d.remove_asset(&token_in, amount_in.0);
+        // make sure fills don't need more deposit storage.
+        if let Some(t) = &token_out {
+            d.add(t, 0);
+        }
+        self.deposits.insert(&user, &d.into());
+
+        let id = self.next_order;
+        self.next_order += 1;
+        self.orders.insert(
+            &id,
+            &LimitOrder {
+                account: user,
+                token_in,
+                token_out,
+                amount_in,
+                min_out,
+                filled_in: U128(0),
+                filled_out: U128(0),
+            },
+        );
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Limit order {}, escrowed {} for min {}",
+            id,
+            amount_in.0,
+            min_out.0
+        );
+        id
+    }
+
+    /**
+    Fills the `id` limit order with up to `amount_in` (all remaining if not set) of the
+    escrowed `token_in`. Can be called by anyone. The bought amount is credited to the order
+    owner deposit. The order is removed once it's fully filled.
+    Panics if the pool price doesn't satisfy the order limit price for the filled amount.
+    Returns the amount bought by this fill. */
+    pub fn fill_limit_order(&mut self, id: u64, amount_in: Option<U128>) -> U128 {
+        let start_storage = env::storage_usage();
+        let mut o = self.orders.get(&id).expect(ERR33_ORDER_NOT_FOUND);
+        let remaining = o.remaining();
+        let chunk = amount_in.map_or(remaining, |a| a.0.min(remaining));
+        assert!(chunk > 0, "{}", ERR02_POSITIVE_ARGS);
+        let chunk_min = o.min_out_for(chunk).max(1);
+
+        // move the chunk back to the owner deposit, so it's swapped as a regular deposit swap.
+        let owner = o.account.clone();
+        let mut d = self.get_deposit(&owner);
+        d.add_asset(&o.token_in, chunk);
+        self.deposits.insert(&owner, &d.into());
+        // the limit price is checked on the swap result, so the pools are read only once.
+        let out = self.internal_swap(&owner, &o.token_in, &o.token_out, chunk, 0);
+        assert!(
+            out >= chunk_min,
+            "{} (out={}, min={})",
+            ERR34_LIMIT_PRICE_NOT_REACHED,
+            out,
+            chunk_min
+        );
+
+        o.filled_in = (o.filled_in.0 + chunk).into();
+        o.filled_out = (o.filled_out.0 + out).into();
+        let remaining = o.remaining();
+        if remaining == 0 {
+            self.orders.remove(&id);
+        } else {
+            self.orders.insert(&id, &o);
+        }
+        let mut d = self.get_deposit(&owner);
+        d.update_storage(start_storage);
+        self.deposits.insert(&owner, &d.into());
+        event_log!(
+            self,
+            "Limit order {} filled, sold {} for {}, remaining {}",
+            id,
+            chunk,
+            out,
+            remaining
+        );
+        out.into()
+    }
+
+    /// Cancels the `id` limit order and moves the unfilled escrowed amount back to the
+    /// caller deposit. Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    /// Returns the refunded amount.
+    #[payable]
+    pub fn cancel_limit_order(&mut self, id: u64) -> U128 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let o = self.orders.get(&id).expect(ERR33_ORDER_NOT_FOUND);
+        assert!(o.account == user, "{}", ERR33_ORDER_NOT_FOUND);
+        self.orders.remove(&id);
+
+        let remaining = o.remaining();
+        let mut d = self.get_deposit(&user);
+        d.add_asset(&o.token_in, remaining);
+        d.update_storage(start_storage);
+        self.deposits.insert(&user, &d.into());
+        event_log!(self, "Limit order {} cancelled, refunded {}", id, remaining);
+        remaining.into()
+    }
+
+    /// Returns the `id` limit order, if it exists.
+    pub fn limit_order(&self, id: u64) -> Option<LimitOrder> {
+        self.orders.get(&id)
+    }
+
+    /**
+    Places a stop-loss or take-profit order: escrows `amount_in` of `token_in` from the caller
+    deposit to be sold for `token_out` once the pools TWAP price crosses `trigger_price`, see
+    `TriggerOrder`. `None` token denotes NEAR. The order is executed by anyone (keepers) with
+    `execute_trigger_order`. The swap output must not be worse than the TWAP price at the
+    execution by more than `max_slippage_bps` basis points.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the order id. */
+    #[payable]
+    pub fn place_trigger_order(
+        &mut self,
+        token_in: Option<AccountId>,
+        amount_in: U128,
+        token_out: Option<AccountId>,
+        kind: TriggerKind,
+        trigger_price: U128,
+        max_slippage_bps: u16,
+    ) -> u64 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        assert!(amount_in.0 > 0 && trigger_price.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        assert!(max_slippage_bps <= 10_000, "{}", ERR36_INVALID_SLIPPAGE);
+        assert!(
+            token_in != token_out && (token_in.is_some() || token_out.is_some()),
+            "{}",
+            ERR28_INVALID_SWAP_ASSETS
+        );
+        for t in token_in.iter().chain(token_out.iter()) {
+            assert!(self.pools.get(t).is_some(), "Pool for this token doesn't exist");
+        }
+
+        let user = env::predecessor_account_id();
+        let mut d = self.get_deposit(&user);
+        d.remove_asset(&token_in, amount_in.0);
+        if let Some(t) = &token_out {
+            d.add(t, 0);
+        }
+        self.deposits.insert(&user, &d.into());
+
+        let id = self.next_order;
+        self.next_order += 1;
+        self.trigger_orders.insert(
+            &id,
+            &TriggerOrder {
+                account: user,
+                token_in,
+                token_out,
+                amount_in,
+                kind,
+                trigger_price,
+                max_slippage_bps,
+            },
+        );
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Trigger order {}, escrowed {} at trigger price {}",
+            id,
+            amount_in.0,
+            trigger_price.0
+        );
+        id
+    }
+
+    /**
+    Executes the `id` trigger order if the pools TWAP price crossed the trigger price. Can be
+    called by anyone. The TWAP, unlike the spot price, can't be moved by the caller in the
+    same transaction. The bought amount is credited to the order owner deposit.
+    Panics if the order is not triggered, a pool oracle has no observations or the slippage
+    is bigger than the order allows.
+    Returns the bought amount. */
+    pub fn execute_trigger_order(&mut self, id: u64) -> U128 {
+        let start_storage = env::storage_usage();
+        let o = self.trigger_orders.get(&id).expect(ERR33_ORDER_NOT_FOUND);
+        let price = self.trigger_price(&o.token_in, &o.token_out);
+        let triggered = match o.kind {
+            TriggerKind::StopLoss => price <= o.trigger_price.0,
+            TriggerKind::TakeProfit => price >= o.trigger_price.0,
+        };
+        assert!(
+            triggered,
+            "{} (price={}, trigger={})",
+            ERR35_TRIGGER_NOT_REACHED,
+            price,
+            o.trigger_price.0
+        );
+        let amount_in = o.amount_in.0;
+        let twap_out = self.twap_out(&o.token_in, &o.token_out, amount_in);
+        let min_out =
+            clp_math::mul_div_ceil(twap_out, 10_000 - o.max_slippage_bps as u128, 10_000).max(1);
+        self.trigger_orders.remove(&id);
+
+        let owner = o.account.clone();
+        let mut d = self.get_deposit(&owner);
+        d.add_asset(&o.token_in, amount_in);
+        self.deposits.insert(&owner, &d.into());
+        let out = self.internal_swap(&owner, &o.token_in, &o.token_out, amount_in, min_out);
+
+        let mut d = self.get_deposit(&owner);
+        d.update_storage(start_storage);
+        self.deposits.insert(&owner, &d.into());
+        event_log!(
+            self,
+            "Trigger order {} executed at price {}, sold {} for {}",
+            id,
+            price,
+            amount_in,
+            out
+        );
+        out.into()
+    }
+
+    /// Cancels the `id` trigger order and moves the escrowed amount back to the caller
+    /// deposit. Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    /// Returns the refunded amount.
+    #[payable]
+    pub fn cancel_trigger_order(&mut self, id: u64) -> U128 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let o = self.trigger_orders.get(&id).expect(ERR33_ORDER_NOT_FOUND);
+        assert!(o.account == user, "{}", ERR33_ORDER_NOT_FOUND);
+        self.trigger_orders.remove(&id);
+
+        let mut d = self.get_deposit(&user);
+        d.add_asset(&o.token_in, o.amount_in.0);
+        d.update_storage(start_storage);
+        self.deposits.insert(&user, &d.into());
+        event_log!(
+            self,
+            "Trigger order {} cancelled, refunded {}",
+            id,
+            o.amount_in.0
+        );
+        o.amount_in
+    }
+
+    /// Returns the `id` trigger order, if it exists.
+    pub fn trigger_order(&self, id: u64) -> Option<TriggerOrder> {
+        self.trigger_orders.get(&id)
+    }
+
+    /**
+    Places a recurring (DCA) order: escrows `budget` of `token_in` from the caller deposit,
+    which is swapped to `token_out` in `amount_per_interval` chunks, one every `interval`
+    blocks, starting now. `None` token denotes NEAR. Chunks are executed by anyone (keepers)
+    with `execute_dca`, who are paid `DCA_KEEPER_FEE_BPS` of every chunk. The swap output must
+    not be worse than the pools TWAP price at the execution by more than `max_slippage_bps`
+    basis points, so a keeper can't sandwich the chunks.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the order id. */
+    #[payable]
+    pub fn place_dca_order(
+        &mut self,
+        token_in: Option<AccountId>,
+        budget: U128,
+        token_out: Option<AccountId>,
+        amount_per_interval: U128,
+        interval: BlockHeight,
+        max_slippage_bps: u16,
+    ) -> u64 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        assert!(
+            budget.0 > 0 && amount_per_interval.0 > 0 && interval > 0,
+            "{}",
+            ERR02_POSITIVE_ARGS
+        );
+        assert!(max_slippage_bps <= 10_000, "{}", ERR36_INVALID_SLIPPAGE);
+        assert!(
+            token_in != token_out && (token_in.is_some() || token_out.is_some()),
+            "{}",
+            ERR28_INVALID_SWAP_ASSETS
+        );
+        for t in token_in.iter().chain(token_out.iter()) {
+            assert!(self.pools.get(t).is_some(), "Pool for this token doesn't exist");
+        }
+
+        let user = env::predecessor_account_id();
+        let mut d = self.get_deposit(&user);
+        d.remove_asset(&token_in, budget.0);
+        if let Some(t) = &token_out {
+            d.add(t, 0);
+        }
+        self.deposits.insert(&user, &d.into());
+
+        let id = self.next_order;
+        self.next_order += 1;
+        self.dca_orders.insert(
+            &id,
+            &DcaOrder {
+                account: user,
+                token_in,
+                token_out,
+                budget,
+                amount_per_interval,
+                interval,
+                next_block: env::block_index(),
+                max_slippage_bps,
+                filled_out: U128(0),
+            },
+        );
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "DCA order {}, escrowed {}, {} every {} blocks",
+            id,
+            budget.0,
+            amount_per_interval.0,
+            interval
+        );
+        id
+    }
+
+    /**
+    Executes the next chunk of the `id` DCA order. Can be called by anyone. The bought amount
+    is credited to the order owner deposit and the keeper fee, in `token_in`, to the caller
+    deposit (for tokens, the caller must have the token registered in the deposit).
+    The order is removed once the budget is exhausted.
+    Panics if the chunk is not due yet, a pool oracle has no observations or the slippage is
+    bigger than the order allows.
+    Returns the amount bought by this chunk. */
+    pub fn execute_dca(&mut self, order_id: u64) -> U128 {
+        let keeper = env::predecessor_account_id();
+        self.internal_execute_dca(order_id, Some(&keeper)).into()
+    }
+
+    /// Cancels the `id` DCA order and moves the remaining budget back to the caller deposit.
+    /// Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    /// Returns the refunded amount.
+    #[payable]
+    pub fn cancel_dca_order(&mut self, id: u64) -> U128 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let o = self.dca_orders.get(&id).expect(ERR33_ORDER_NOT_FOUND);
+        assert!(o.account == user, "{}", ERR33_ORDER_NOT_FOUND);
+        self.dca_orders.remove(&id);
+
+        let mut d = self.get_deposit(&user);
+        d.add_asset(&o.token_in, o.budget.0);
+        d.update_storage(start_storage);
+        self.deposits.insert(&user, &d.into());
+        event_log!(self, "DCA order {} cancelled, refunded {}", id, o.budget.0);
+        o.budget
+    }
+
+    /// Returns the `id` DCA order, if it exists.
+    pub fn dca_order(&self, id: u64) -> Option<DcaOrder> {
+        self.dca_orders.get(&id)
+    }
+}
+
+impl NearSwap {
+    /// Executes the next chunk of the `order_id` DCA order, see `execute_dca`. The keeper
+    /// fee is paid only if the `keeper` is set.
+    pub(crate) fn internal_execute_dca(
+        &mut self,
+        order_id: u64,
+        keeper: Option<&AccountId>,
+    ) -> Balance {
+        let start_storage = env::storage_usage();
+        let mut o = self.dca_orders.get(&order_id).expect(ERR33_ORDER_NOT_FOUND);
+        assert!(env::block_index() >= o.next_block, "{}", ERR37_DCA_NOT_DUE);
+        let chunk = o.amount_per_interval.0.min(o.budget.0);
+        let fee = match keeper {
+            Some(_) => clp_math::mul_div_floor(chunk, DCA_KEEPER_FEE_BPS, 10_000),
+            None => 0,
+        };
+        let amount_in = chunk - fee;
+        let twap_out = self.twap_out(&o.token_in, &o.token_out, amount_in);
+        let min_out =
+            clp_math::mul_div_ceil(twap_out, 10_000 - o.max_slippage_bps as u128, 10_000).max(1);
+
+        let owner = o.account.clone();
+        let mut d = self.get_deposit(&owner);
+        d.add_asset(&o.token_in, amount_in);
+        self.deposits.insert(&owner, &d.into());
+        let out = self.internal_swap(&owner, &o.token_in, &o.token_out, amount_in, min_out);
+
+        if let Some(keeper) = keeper {
+            let mut k = self.get_deposit(keeper);
+            if let Some(t) = &o.token_in {
+                assert!(k.tokens.contains_key(t), "{}", ERR23_TOKEN_NOT_WHITELISTED);
+            }
+            k.add_asset(&o.token_in, fee);
+            self.deposits.insert(keeper, &k.into());
+        }
+
+        o.budget = (o.budget.0 - chunk).into();
+        o.filled_out = (o.filled_out.0 + out).into();
+        if o.budget.0 == 0 {
+            self.dca_orders.remove(&order_id);
+        } else {
+            o.next_block = env::block_index() + o.interval;
+            self.dca_orders.insert(&order_id, &o);
+        }
+        let mut d = self.get_deposit(&owner);
+        d.update_storage(start_storage);
+        self.deposits.insert(&owner, &d.into());
+        event_log!(
+            self,
+            "DCA order {}, swapped {} for {}, keeper fee {}, remaining {}",
+            order_id,
+            amount_in,
+            out,
+            fee,
+            o.budget.0
+        );
+        out
+    }
+
+    /// Returns the `token` pool TWAP price. Panics if the pool oracle has no observations.
+    fn pool_twap_price(&self, token: &AccountId) -> Balance {
+        let price = self.get_pool(token).twap_price().expect(ERR149_NO_TWAP);
+        assert!(price > 0, "{}", ERR149_NO_TWAP);
+        price
+    }
+
+    /// Returns the trigger order price of the `token_in`, `token_out` pair from the pools TWAP
+    /// prices, see `TriggerOrder`. `None` token denotes NEAR.
+    pub(crate) fn trigger_price(
+        &self,
+        token_in: &Option<AccountId>,
+        token_out: &Option<AccountId>,
+    ) -> Balance {
+        match (token_in, token_out) {
+            (Some(t), None) | (None, Some(t)) => self.pool_twap_price(t),
+            (Some(t1), Some(t2)) => clp_math::mul_div_floor(
+                self.pool_twap_price(t1),
+                NDENOM,
+                self.pool_twap_price(t2),
+            ),
+            (None, None) => panic!("{}", ERR28_INVALID_SWAP_ASSETS),
+        }
+    }
+
+    /// Returns the amount of `token_out` bought for `amount_in` of `token_in` at the pools
+    /// TWAP prices: without fees and price impact. `None` token denotes NEAR.
+    /// Panics if a pool oracle has no observations.
+    pub(crate) fn twap_out(
+        &self,
+        token_in: &Option<AccountId>,
+        token_out: &Option<AccountId>,
+        amount_in: Balance,
+    ) -> Balance {
+        match (token_in, token_out) {
+            (None, Some(t)) => clp_math::mul_div_floor(amount_in, NDENOM, self.pool_twap_price(t)),
+            _ => clp_math::mul_div_floor(
+                amount_in,
+                self.trigger_price(token_in, token_out),
+                NDENOM,
+            ),
+        }
+    }
+
+    /// Returns the amount of `token_out` bought for `amount_in` of `token_in` at the pools
+    /// spot prices: without fees and price impact. `None` token denotes NEAR.
+    pub(crate) fn spot_out(
+        &self,
+        token_in: &Option<AccountId>,
+        token_out: &Option<AccountId>,
+        amount_in: Balance,
+    ) -> Balance {
+        let amount = u256::from(amount_in);
+        let out = match (token_in, token_out) {
+            (None, Some(t)) => {
+                let p = self.get_pool(t);
+                amount * u256::from(p.curve_tokens()) / u256::from(p.ynear)
+            }
+            (Some(t), None) => {
+                let p = self.get_pool(t);
+                amount * u256::from(p.ynear) / u256::from(p.curve_tokens())
+            }
+            (Some(t1), Some(t2)) => {
+                let p1 = self.get_pool(t1);
+                let p2 = self.get_pool(t2);
+                amount * u256::from(p1.ynear) * u256::from(p2.curve_tokens())
+                    / (u256::from(p1.curve_tokens()) * u256::from(p2.ynear))
+            }
+            (None, None) => panic!("{}", ERR28_INVALID_SWAP_ASSETS),
+        };
+        out.min(u256::from(u128::MAX)).as_u128()
+    }
+}
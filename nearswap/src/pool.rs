@@ -2,13 +2,24 @@
 // Copyright (C) 2020 Robert Zaremba and contributors
 
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LookupMap};
-use near_sdk::json_types::U128;
+use near_sdk::collections::{LazyOption, LookupMap, UnorderedSet, Vector};
+use near_sdk::json_types::{U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::{AccountId, Balance};
+use near_sdk::{env, AccountId, Balance, BlockHeight, EpochHeight, StorageUsage};
 
 // use std::fmt;
 
+use crate::accumulator::RewardAcc;
+use crate::amounts::{NearAmount, Shares, TokenAmount};
+use crate::audit::SharesAudit;
+use crate::constants::{
+    DEFAULT_TWAP_WINDOW, DYNAMIC_FEE_NEUTRAL_BPS, MAX_TOP_LPS, POOL_HISTORY_LENGTH,
+};
+use crate::dynamic_fee::DynamicFee;
+use crate::rate_provider::RateProvider;
+use crate::candles::Candles;
+use crate::price_history::PriceHistory;
+use crate::trade_limits::MaxTrade;
 use crate::twap::*;
 use crate::*;
 
@@ -46,6 +57,119 @@ impl fmt::Display for PoolInfo {
     }
 }
 
+/// Result of a liquidity operation simulation. Amounts are the ones which would be moved
+/// between the caller deposit and the pool, `pool` is the resulting pool state.
+#[derive(Serialize, Deserialize)]
+pub struct LiquidityPreview {
+    pub ynear: U128,
+    pub tokens: U128,
+    pub shares: U128,
+    pub pool: PoolInfo,
+}
+
+/// Pool condition reported by `health_check`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthFlag {
+    /// the pool has no liquidity.
+    Empty,
+    /// swaps and adding liquidity are paused, see `CouncilMotion::Pause` and
+    /// `verify_reserves`.
+    Paused,
+    /// the TWAP oracle wasn't updated in the last `ORACLE_STALE_PERIOD`.
+    StaleOracle,
+    /// a pool fee proposal is in progress.
+    FeeVoting,
+}
+
+/// Pool consistency report, see `health_check`.
+#[derive(Serialize, Deserialize)]
+pub struct PoolHealth {
+    pub token: AccountId,
+    /// both reserves are zero when there are no shares, and non zero otherwise.
+    pub reserves_consistent: bool,
+    /// result of the last completed `audit_shares`: true if the holdings matched the total
+    /// shares. None if the pool was never audited.
+    pub shares_audit: Option<bool>,
+    pub flags: Vec<HealthFlag>,
+}
+
+/// Storage used by a pool, in bytes, see `pool_storage`.
+#[derive(Serialize, Deserialize)]
+pub struct PoolStorage {
+    /// records of the pool entry in the pools map.
+    pub entry: U64,
+    /// records of the pool collections: shares, holders, locks, history, candles, price
+    /// samples and oracle observations.
+    pub collections: U64,
+    pub total: U64,
+}
+
+/// Deviation of the pool reserves ratio from the TWAP oracle fair ratio, returned by
+/// `pool_imbalance`. Prices are yNEAR for 1e24 token units.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct PoolImbalance {
+    pub spot_price: U128,
+    /// mean of the TWAP oracle observations over the pool TWAP window, see `set_twap_window`.
+    pub twap_price: U128,
+    /// `(spot_price - twap_price) / twap_price`, in basis points. Positive when the pool
+    /// holds more NEAR than the fair ratio: the token is overpriced.
+    pub deviation_bps: i64,
+}
+
+/// Pool reserves product returned by `pool_invariant`. Swaps only grow `k`, it changes
+/// otherwise only with the liquidity and reserves of the pool. Values are decimal strings
+/// (`u256`).
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct PoolInvariant {
+    /// current `ynear * tokens`.
+    pub k: String,
+    /// `ynear * tokens` right after the last liquidity add or withdrawal.
+    pub k_last_liquidity: String,
+}
+
+/// Pool reserves recorded at the first pool update in the `epoch`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct PoolSnapshot {
+    pub epoch: EpochHeight,
+    pub ynear: U128,
+    pub tokens: U128,
+    pub total_shares: U128,
+}
+
+/// Expected price range of the very first deposit, declared by the pool creator.
+/// Price is expressed as amount of yNEAR paid for `1e24` token units (NEAR denomination).
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub struct PriceBounds {
+    pub min: U128,
+    pub max: U128,
+}
+
+impl PriceBounds {
+    /// Panics if the bounds don't define a valid (non empty) price range.
+    pub fn assert_valid(&self) {
+        assert!(
+            self.min.0 > 0 && self.min.0 <= self.max.0,
+            "{}",
+            ERR27_INVALID_PRICE_BOUNDS
+        );
+    }
+
+    /// Panics if the `ynear / tokens` price is outside of the bounds.
+    pub fn assert_within(&self, ynear: Balance, tokens: Balance) {
+        let price = clp_math::spot_price(ynear, tokens);
+        assert!(
+            price >= u256::from(self.min.0) && price <= u256::from(self.max.0),
+            "{}",
+            format!(
+                "{} (price={}, min={}, max={})",
+                ERR26_INIT_PRICE_OUT_OF_BOUNDS, price, self.min.0, self.max.0
+            )
+        );
+    }
+}
+
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct Pool {
     pub ynear: Balance,
@@ -55,19 +179,236 @@ pub struct Pool {
     pub total_shares: Balance,
 
     pub twap: Twap,
+    /// Price bounds enforced when the pool is empty and receives a first deposit.
+    pub init_price: Option<PriceBounds>,
+
+    /// Ring buffer of per epoch snapshots. Holds at most `POOL_HISTORY_LENGTH` last records.
+    history: Vector<PoolSnapshot>,
+    /// number of snapshots recorded since the pool creation.
+    history_count: u64,
+    /// epoch of the most recent snapshot.
+    history_epoch: EpochHeight,
+    /// OHLC price candles built from the pool swaps.
+    pub candles: Candles,
+    /// periodic spot price samples, see `price_history`.
+    pub price_history: PriceHistory,
+
+    /// locked liquidity positions, see `lock_liquidity`. Locked shares are held outside of
+    /// `shares`, but they are included in `total_shares`.
+    pub locks: LookupMap<AccountId, LiquidityLock>,
+    /// sum of the extra (boost) weight of all locked shares: `shares * (boost - 1)`.
+    pub boost_weight: Balance,
+    /// accumulated boost fees, in yNEAR and tokens, per unit of boost weight.
+    pub boost_acc_ynear: RewardAcc,
+    pub boost_acc_tokens: RewardAcc,
+
+    /// swap fee, in basis points. Set by the pool LPs, see `propose_pool_fee`.
+    pub fee_bps: u16,
+
+    /// accounts which ever held the pool shares, enumerated by `audit_shares`.
+    pub holders: UnorderedSet<AccountId>,
+    /// number of accounts with non zero `shares`.
+    pub n_lps: u64,
+    /// largest `shares` holders, in descending order of shares. Bounded by `MAX_TOP_LPS`.
+    /// An account position is updated when its shares change, see `refresh_top_lps`.
+    pub top_lps: LazyOption<Vec<(AccountId, Balance)>>,
+    /// incremented on every `shares` update.
+    pub shares_nonce: u64,
+    /// shares escrowed by the fee proposal votes, see `vote_pool_fee`.
+    pub vote_escrow: Balance,
+    /// fee proposal in voting, with its voting end, see `share_checkpoints`.
+    pub vote_checkpoint: Option<(u64, u64)>,
+    /// shares of the accounts before their first shares update during the `vote_checkpoint`
+    /// proposal voting, with the proposal id. Votes can't use shares acquired after the
+    /// proposal creation, see `voting_shares`.
+    pub share_checkpoints: LookupMap<AccountId, (u64, Balance)>,
+    /// shares audit progress and result, see `audit_shares`.
+    pub audit: SharesAudit,
+    /// swaps and adding liquidity are paused, see `verify_reserves`.
+    pub paused: bool,
+    /// bytes used by the pool collections records, see `PoolStorage`.
+    pub storage_usage: StorageUsage,
+    /// batch auction window, in blocks. When set, swaps are settled in batch auctions, see
+    /// `submit_batch_swap`.
+    pub batch_window: BlockHeight,
+    /// protocol part of the swap fees, in yNEAR and tokens, not split yet, see
+    /// `take_protocol_fee`.
+    pub protocol_ynear: Balance,
+    pub protocol_tokens: Balance,
+    /// NEAR delegated to the staking pool, included in `ynear`, see `rebalance_stake`.
+    pub staked_ynear: Balance,
+    /// NEAR unstaked from the staking pool and not withdrawn yet, included in `ynear`.
+    pub unstaking_ynear: Balance,
+    /// epoch of the last unstake.
+    pub unstake_epoch: EpochHeight,
+    /// staking rewards accumulator value at the last settlement, see
+    /// `settle_staking_rewards`.
+    pub staking_checkpoint: Balance,
+    /// a staking pool call is in progress.
+    pub staking_pending: bool,
+    /// tokens supplied to the lending market, included in `tokens`, see `rebalance_lending`.
+    pub lent_tokens: Balance,
+    /// lent part of `tokens` targeted by `rebalance_lending`, in basis points.
+    pub lending_bps: u16,
+    /// a lending market call is in progress.
+    pub lending_pending: bool,
+    /// exchange rate of an interest-bearing token, see `update_rate`.
+    pub rate_provider: Option<RateProvider>,
+    /// last fetched and first fetched exchange rate. The pricing uses `curve_tokens`.
+    pub rate: Balance,
+    pub rate_base: Balance,
+    /// block timestamp of the last rate update.
+    pub rate_updated: u64,
+    /// imbalance penalizing swap fee, see `set_pool_dynamic_fee`.
+    pub dynamic_fee: Option<DynamicFee>,
+    /// maximum size of a single swap, see `set_pool_max_trade`.
+    pub max_trade: Option<MaxTrade>,
+    /// reserves right after the last liquidity add or withdrawal, see `pool_invariant`.
+    pub liquidity_ynear: Balance,
+    pub liquidity_tokens: Balance,
+    /// period, in seconds, over which the TWAP oracle views average the observations, see
+    /// `set_twap_window`.
+    pub twap_window: u64,
 }
 
 impl Pool {
-    pub fn new(pool_id: Vec<u8>) -> Self {
+    pub fn new(token: &AccountId) -> Self {
+        let pool = pool_hash(token);
         Self {
             ynear: 0,
             tokens: 0,
-            shares: LookupMap::new(pool_id),
+            shares: LookupMap::new(StorageKey::PoolShares { pool }),
             total_shares: 0,
-            twap: Twap::new(65535),
+            twap: Twap::new(StorageKey::PoolTwap { pool }, 65535),
+            init_price: None,
+            history: Vector::new(StorageKey::PoolHistory { pool }),
+            history_count: 0,
+            history_epoch: 0,
+            candles: Candles::new(&pool),
+            price_history: PriceHistory::new(StorageKey::PoolPriceHistory { pool }),
+            locks: LookupMap::new(StorageKey::PoolLocks { pool }),
+            boost_weight: 0,
+            boost_acc_ynear: RewardAcc::default(),
+            boost_acc_tokens: RewardAcc::default(),
+            fee_bps: clp_math::FEE_BPS,
+            holders: UnorderedSet::new(StorageKey::PoolHolders { pool }),
+            n_lps: 0,
+            top_lps: LazyOption::new(StorageKey::PoolTopLps { pool }, None),
+            shares_nonce: 0,
+            vote_escrow: 0,
+            vote_checkpoint: None,
+            share_checkpoints: LookupMap::new(StorageKey::PoolShareCheckpoints { pool }),
+            audit: SharesAudit::default(),
+            paused: false,
+            storage_usage: 0,
+            batch_window: 0,
+            protocol_ynear: 0,
+            protocol_tokens: 0,
+            staked_ynear: 0,
+            unstaking_ynear: 0,
+            unstake_epoch: 0,
+            staking_checkpoint: 0,
+            staking_pending: false,
+            lent_tokens: 0,
+            lending_bps: 0,
+            lending_pending: false,
+            rate_provider: None,
+            rate: 0,
+            rate_base: 0,
+            rate_updated: 0,
+            dynamic_fee: None,
+            max_trade: None,
+            liquidity_ynear: 0,
+            liquidity_tokens: 0,
+            twap_window: DEFAULT_TWAP_WINDOW,
         }
     }
 
+    /// NEAR reserve available for swaps and withdrawals: not staked nor unstaking.
+    pub fn liquid_ynear(&self) -> Balance {
+        self.ynear - self.staked_ynear - self.unstaking_ynear
+    }
+
+    /// Token reserve available for swaps and withdrawals: not lent.
+    pub fn liquid_tokens(&self) -> Balance {
+        self.tokens - self.lent_tokens
+    }
+
+    /// Token reserve used by the pricing. For exchange rate tokens, the reserve is scaled by
+    /// the rate accrued since the first rate update, so the pool price follows the rate and
+    /// the accrued value isn't arbitraged away. The difference stays in the pool reserve.
+    pub fn curve_tokens(&self) -> Balance {
+        if self.rate == 0 {
+            self.tokens
+        } else {
+            clp_math::mul_div_floor(self.tokens, self.rate_base, self.rate)
+        }
+    }
+
+    /// Accounts the storage used (or released) by the pool collections since
+    /// `start_storage`. Must be called right after the collections update.
+    pub(crate) fn update_storage(&mut self, start_storage: StorageUsage) {
+        let now = env::storage_usage();
+        if now >= start_storage {
+            self.storage_usage += now - start_storage;
+        } else {
+            self.storage_usage = self.storage_usage.saturating_sub(start_storage - now);
+        }
+    }
+
+    /// Sets the `account` LP shares held in `shares` and records the account as a holder.
+    pub(crate) fn set_shares(&mut self, account: &AccountId, amount: Balance) {
+        let start_storage = env::storage_usage();
+        if let Some((id, voting_end)) = self.vote_checkpoint {
+            if env::block_timestamp() < voting_end
+                && self.share_checkpoints.get(account).map_or(true, |(i, _)| i != id)
+            {
+                let old = self.shares.get(account).unwrap_or(0);
+                self.share_checkpoints.insert(account, &(id, old));
+            }
+        }
+        let old = self.shares.insert(account, &amount).unwrap_or(0);
+        if old == 0 && amount > 0 {
+            self.n_lps += 1;
+        } else if old > 0 && amount == 0 {
+            self.n_lps -= 1;
+        }
+        self.holders.insert(account);
+        self.update_top_lps(account, amount);
+        self.update_storage(start_storage);
+        self.shares_nonce += 1;
+    }
+
+    /// Returns the `account` shares at the `proposal_id` fee proposal creation, or the current
+    /// shares if they didn't change since. `proposal_id` must be the pool `vote_checkpoint`.
+    pub fn voting_shares(&self, account: &AccountId, proposal_id: u64) -> Balance {
+        match self.share_checkpoints.get(account) {
+            Some((id, shares)) if id == proposal_id => shares,
+            _ => self.shares.get(account).unwrap_or(0),
+        }
+    }
+
+    /// Updates the `account` position in `top_lps` with its `amount` of shares. An account
+    /// outside of a full index enters it only with more shares than the last one.
+    pub(crate) fn update_top_lps(&mut self, account: &AccountId, amount: Balance) {
+        let mut top = self.top_lps.get().unwrap_or_default();
+        let pos = top.iter().position(|(a, _)| a == account);
+        if pos.is_none()
+            && (amount == 0 || (top.len() >= MAX_TOP_LPS && amount <= top[top.len() - 1].1))
+        {
+            return;
+        }
+        if let Some(i) = pos {
+            top.remove(i);
+        }
+        if amount > 0 {
+            let i = top.iter().position(|(_, s)| *s < amount).unwrap_or(top.len());
+            top.insert(i, (account.clone(), amount));
+            top.truncate(MAX_TOP_LPS);
+        }
+        self.top_lps.set(&top);
+    }
+
     pub fn pool_info(&self) -> PoolInfo {
         PoolInfo {
             ynear: self.ynear.into(),
@@ -76,6 +417,197 @@ impl Pool {
         }
     }
 
+    /// Returns true if both reserves are zero when there are no shares, and non zero
+    /// otherwise.
+    pub fn reserves_consistent(&self) -> bool {
+        if self.total_shares == 0 {
+            self.ynear == 0 && self.tokens == 0
+        } else {
+            self.ynear > 0 && self.tokens > 0
+        }
+    }
+
+    /// Records the pool reserves if this is the first pool update in the current epoch.
+    /// Must be called before the pool is modified. Empty pools are not recorded.
+    pub(crate) fn record_snapshot(&mut self) {
+        let epoch = env::epoch_height();
+        if self.history_epoch == epoch {
+            return;
+        }
+        self.history_epoch = epoch;
+        if self.total_shares == 0 {
+            return;
+        }
+        let s = PoolSnapshot {
+            epoch,
+            ynear: self.ynear.into(),
+            tokens: self.tokens.into(),
+            total_shares: self.total_shares.into(),
+        };
+        let start_storage = env::storage_usage();
+        if self.history.len() < POOL_HISTORY_LENGTH {
+            self.history.push(&s);
+        } else {
+            self.history
+                .replace(self.history_count % POOL_HISTORY_LENGTH, &s);
+        }
+        self.update_storage(start_storage);
+        self.history_count += 1;
+    }
+
+    /// Records a swap of `volume` yNEAR in the price candles and the price history, using the
+    /// pool spot price. Must be called after the pool reserves are updated.
+    pub(crate) fn record_trade(&mut self, volume: u128) {
+        if self.tokens == 0 {
+            return;
+        }
+        let price = self.spot_price();
+        let start_storage = env::storage_usage();
+        self.candles.record(env::block_timestamp(), price, volume);
+        self.price_history.record(env::block_timestamp(), price);
+        self.update_storage(start_storage);
+    }
+
+    /**
+    Takes the boosted liquidity part of a swap `fee` (charged in yNEAR if `is_near`, tokens
+    otherwise) and accounts it to the locked positions. Fees are distributed by shares weighted
+    by their boost, so locked positions get `fee * boost_weight / (total_shares + boost_weight)`
+    on top of the share they get from the pool reserves.
+    Returns the amount which must not be added to the pool reserves. */
+    pub(crate) fn take_boost_fee(&mut self, fee: Balance, is_near: bool) -> Balance {
+        if self.boost_weight == 0 || fee == 0 {
+            return 0;
+        }
+        let cut = clp_math::mul_div_floor(
+            fee,
+            self.boost_weight,
+            self.total_shares + self.boost_weight,
+        );
+        if is_near {
+            self.boost_acc_ynear.distribute(cut, self.boost_weight);
+        } else {
+            self.boost_acc_tokens.distribute(cut, self.boost_weight);
+        }
+        cut
+    }
+
+    /// Takes the `bps` protocol part of a swap `fee` (charged in yNEAR if `is_near`, tokens
+    /// otherwise). Returns the amount which must not be added to the pool reserves.
+    pub(crate) fn take_protocol_fee(&mut self, fee: Balance, is_near: bool, bps: u16) -> Balance {
+        let cut = clp_math::mul_div_floor(fee, bps as u128, 10_000);
+        if is_near {
+            self.protocol_ynear += cut;
+        } else {
+            self.protocol_tokens += cut;
+        }
+        cut
+    }
+
+    /// Records the current spot prices (yNEAR per token and tokens per NEAR) in the TWAP
+    /// oracle.
+    pub(crate) fn poke_oracle(&mut self) {
+        let price = self.spot_price();
+        let inverse = clp_math::spot_price(self.curve_tokens(), self.ynear);
+        let inverse = inverse.min(u256::from(u128::MAX)).as_u128();
+        let start_storage = env::storage_usage();
+        self.twap
+            .log_observation(env::block_timestamp(), price, inverse);
+        self.price_history.record(env::block_timestamp(), price);
+        self.update_storage(start_storage);
+    }
+
+    /// Returns the spot price: yNEAR for 1e24 token units, capped at u128::MAX.
+    /// Panics if the pool is empty.
+    pub fn spot_price(&self) -> u128 {
+        assert!(self.tokens > 0, "E10: Pool is empty");
+        let price = clp_math::spot_price(self.ynear, self.curve_tokens());
+        price.min(u256::from(u128::MAX)).as_u128()
+    }
+
+    /// Returns the mean spot price (yNEAR for 1e24 token units) recorded by the TWAP oracle
+    /// over the pool TWAP window, or None if the oracle has no observations.
+    pub fn twap_price(&self) -> Option<u128> {
+        self.twap.last_observation()?;
+        Some(self.twap.mean_over(to_nanoseconds(self.twap_window)).0)
+    }
+
+    /// Returns the deviation of the spot price from the TWAP over the pool TWAP window, or
+    /// None if the pool is empty or the oracle has no observations.
+    pub fn imbalance(&self) -> Option<PoolImbalance> {
+        let twap_price = self.twap_price()?;
+        if self.tokens == 0 || twap_price == 0 {
+            return None;
+        }
+        let spot_price = self.spot_price();
+        let (diff, positive) = if spot_price >= twap_price {
+            (spot_price - twap_price, true)
+        } else {
+            (twap_price - spot_price, false)
+        };
+        let bps = u256::from(diff) * 10_000 / u256::from(twap_price);
+        let bps = bps.min(u256::from(i64::MAX as u64)).as_u64() as i64;
+        Some(PoolImbalance {
+            spot_price: spot_price.into(),
+            twap_price: twap_price.into(),
+            deviation_bps: if positive { bps } else { -bps },
+        })
+    }
+
+    /// Returns the swap fee, in basis points, of a swap selling NEAR (`near_in`) or selling
+    /// the pool token. Selling NEAR raises the spot price. With a dynamic fee, the fee depends
+    /// on the swap direction with respect to the pool imbalance, see `DynamicFee`.
+    pub fn swap_fee_bps(&self, near_in: bool) -> u16 {
+        let f = match self.dynamic_fee {
+            Some(f) => f,
+            None => return self.fee_bps,
+        };
+        let deviation = match self.imbalance() {
+            Some(i) if i.deviation_bps.abs() > DYNAMIC_FEE_NEUTRAL_BPS => i.deviation_bps,
+            _ => return self.fee_bps,
+        };
+        if (deviation > 0) == near_in {
+            (self.fee_bps + f.surcharge_bps).min(10_000)
+        } else {
+            self.fee_bps.saturating_sub(f.discount_bps)
+        }
+    }
+
+    /// Returns up to `limit` snapshots, in chronological order, recorded not earlier than
+    /// `from_epoch`.
+    pub fn history(&self, from_epoch: EpochHeight, limit: u64) -> Vec<PoolSnapshot> {
+        let len = self.history.len();
+        // index of the oldest snapshot
+        let start = if len < POOL_HISTORY_LENGTH {
+            0
+        } else {
+            self.history_count % POOL_HISTORY_LENGTH
+        };
+        (0..len)
+            .map(|i| self.history.get((start + i) % len).unwrap())
+            .filter(|s| s.epoch >= from_epoch)
+            .take(limit as usize)
+            .collect()
+    }
+
+    /**
+    Computes the liquidity to be added to the pool without modifying it.
+    Arguments: see `add_liquidity`.
+    Returns: (ynear added, tokens added, shares minted). */
+    pub fn calc_add_liquidity(
+        &self,
+        ynear: NearAmount,
+        max_tokens: TokenAmount,
+    ) -> (NearAmount, TokenAmount, Shares) {
+        let (added_near, added_tokens, shares) = clp_math::calc_add_liquidity(
+            self.ynear,
+            self.tokens,
+            self.total_shares,
+            ynear.0,
+            max_tokens.0,
+        );
+        (NearAmount(added_near), TokenAmount(added_tokens), Shares(shares))
+    }
+
     /**
     Rebalances the pool by assigning new liquidity. It doesn't perform any transfer.
     Liquidiyt must come from the contract deposits.
@@ -88,91 +620,142 @@ impl Pool {
     pub(crate) fn add_liquidity(
         &mut self,
         caller: &AccountId,
-        ynear: u128,
-        max_tokens: u128,
-        min_shares: u128,
-    ) -> (u128, u128, u128) {
-        let shares_minted;
-        let mut added_tokens;
-        let added_near;
+        ynear: NearAmount,
+        max_tokens: TokenAmount,
+        min_shares: Shares,
+    ) -> (NearAmount, TokenAmount, Shares) {
+        self.record_snapshot();
+        let (added_near, added_tokens, shares_minted) = self.calc_add_liquidity(ynear, max_tokens);
+        metrics_log!(
+            "add_liquidity pool=({}, {}, {}) ynear={} tokens={} shares={}",
+            self.ynear,
+            self.tokens,
+            self.total_shares,
+            added_near,
+            added_tokens,
+            shares_minted
+        );
         // the very first deposit -- we define the constant ratio
         if self.total_shares == 0 {
-            self.ynear = ynear;
-            shares_minted = self.ynear;
-            self.total_shares = shares_minted;
-            added_tokens = max_tokens;
-            added_near = ynear;
-            self.tokens = added_tokens;
-            self.shares.insert(caller, &shares_minted);
-        } else {
-            let ynear_256 = u256::from(ynear);
-            let p_ynear_256 = u256::from(self.ynear); // ynear in pool
-            added_tokens = (ynear_256 * u256::from(self.tokens) / p_ynear_256 + 1).as_u128();
-
-            // Adjust near according to max_tokens
-            if max_tokens < added_tokens {
-                added_near = ((u256::from(max_tokens) * p_ynear_256) / u256::from(self.tokens) + 1)
-                    .as_u128();
-                added_tokens = max_tokens;
-                shares_minted = (u256::from(added_near) * u256::from(self.total_shares)
-                    / p_ynear_256)
-                    .as_u128();
-            } else {
-                added_near = ynear;
-                shares_minted = (ynear_256 * u256::from(self.total_shares) / p_ynear_256).as_u128();
+            if let Some(b) = &self.init_price {
+                b.assert_within(ynear.0, max_tokens.0);
             }
+            self.ynear = added_near.0;
+            self.tokens = added_tokens.0;
+            self.total_shares = shares_minted.0;
+            self.set_shares(caller, shares_minted.0);
+        } else {
             assert!(
-                u128::from(min_shares) <= shares_minted,
-                "E4: amount minted shares ({}) is smaller then the required minimum",
-                shares_minted
+                min_shares <= shares_minted,
+                "E4: amount minted shares ({}) is smaller then the required minimum ({})",
+                shares_minted,
+                min_shares
             );
-            self.shares.insert(
-                caller,
-                &(self.shares.get(&caller).unwrap_or(0) + shares_minted),
-            );
-            self.tokens += added_tokens;
-            self.ynear += added_near;
-            self.total_shares += shares_minted;
+            self.set_shares(caller, self.shares.get(&caller).unwrap_or(0) + shares_minted.0);
+            self.tokens += added_tokens.0;
+            self.ynear += added_near.0;
+            self.total_shares += shares_minted.0;
         }
+        self.checkpoint_invariant();
         return (added_near, added_tokens, shares_minted);
     }
 
+    /// Adds the donated `ynear` and `tokens` to the reserves without minting shares.
+    /// Panics if the pool has no liquidity: the first liquidity provider would get the
+    /// donation.
+    pub(crate) fn donate(&mut self, ynear: Balance, tokens: Balance) {
+        assert!(self.total_shares > 0, "{}", ERR86_DONATION_TO_EMPTY_POOL);
+        self.record_snapshot();
+        self.ynear += ynear;
+        self.tokens += tokens;
+    }
+
+    /// Computes the amount of yNEAR and tokens redeemed for `shares` without modifying the pool.
+    /// Amounts are rounded down.
+    /// Returns: (ynear, tokens).
+    pub fn calc_withdraw_liquidity(&self, shares: Shares) -> (NearAmount, TokenAmount) {
+        let (ynear, tokens) =
+            clp_math::calc_withdraw_liquidity(self.ynear, self.tokens, self.total_shares, shares.0);
+        (NearAmount(ynear), TokenAmount(tokens))
+    }
+
     /// Withdraw `shares` for liquidity stored in this pool and transfer them to the caller deposit account. User can require
     /// getting at least `min_ynear` of Near and `min_tokens` of tokens. The function panic if the condition is not met.
     /// Shares are not exchangeable between different pools.
+    /// A single redemption is capped at the pool liquid NEAR and tokens: the staked NEAR and the
+    /// lent tokens are paid out only after `rebalance_stake` and `rebalance_lending` withdraw
+    /// them.
     pub(crate) fn withdraw_liquidity(
         &mut self,
         caller: &AccountId,
-        min_ynear: u128,
-        min_tokens: u128,
-        shares: u128,
-    ) -> (u128, u128) {
+        min_ynear: NearAmount,
+        min_tokens: TokenAmount,
+        shares: Shares,
+    ) -> (NearAmount, TokenAmount) {
+        self.record_snapshot();
         let current_shares = self.shares.get(&caller).unwrap_or(0);
-        let total_shares2 = u256::from(self.total_shares);
-        let shares2 = u256::from(shares);
-        let ynear = (shares2 * u256::from(self.ynear) / total_shares2).as_u128();
-        let token_amount = (shares2 * u256::from(self.tokens) / total_shares2).as_u128();
+        let (ynear, token_amount) = self.calc_withdraw_liquidity(shares);
+        metrics_log!(
+            "withdraw_liquidity pool=({}, {}, {}) shares={} ynear={} tokens={}",
+            self.ynear,
+            self.tokens,
+            self.total_shares,
+            shares,
+            ynear,
+            token_amount
+        );
         assert!(
             ynear >= min_ynear && token_amount >= min_tokens,
             "{}",
             format!(
-                "E6: redeeming (ynear={}, tokens={}), which is smaller than the required minimum",
-                ynear, token_amount
+                "E6: redeeming (ynear={}, tokens={}), which is smaller than the required minimum (min_ynear={}, min_tokens={})",
+                ynear, token_amount, min_ynear, min_tokens
             )
         );
 
-        self.shares.insert(caller, &(current_shares - shares));
-        self.total_shares -= shares;
-        self.tokens -= token_amount;
-        self.ynear -= ynear;
+        let (liquid_ynear, liquid_tokens) = (self.liquid_ynear(), self.liquid_tokens());
+        assert!(
+            ynear.0 <= liquid_ynear,
+            "{} (ynear={}, liquid={}, max_shares={})",
+            ERR150_OVER_LIQUID_NEAR,
+            ynear,
+            liquid_ynear,
+            clp_math::mul_div_floor(liquid_ynear, self.total_shares, self.ynear)
+        );
+        assert!(
+            token_amount.0 <= liquid_tokens,
+            "{} (tokens={}, liquid={}, max_shares={})",
+            ERR151_OVER_LIQUID_TOKENS,
+            token_amount,
+            liquid_tokens,
+            clp_math::mul_div_floor(liquid_tokens, self.total_shares, self.tokens)
+        );
+
+        self.set_shares(caller, current_shares - shares.0);
+        self.total_shares -= shares.0;
+        self.tokens -= token_amount.0;
+        self.ynear -= ynear.0;
+        self.checkpoint_invariant();
 
         return (ynear, token_amount);
     }
+
+    /// Returns the reserves product `ynear * tokens`.
+    pub fn invariant(&self) -> u256 {
+        u256::from(self.ynear) * u256::from(self.tokens)
+    }
+
+    /// Records the reserves after a liquidity change, see `PoolInvariant`.
+    fn checkpoint_invariant(&mut self) {
+        self.liquidity_ynear = self.ynear;
+        self.liquidity_tokens = self.tokens;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constants::NDENOM;
 
     use near_sdk::test_utils::{VMContextBuilder};
     use near_sdk::{testing_env, MockedBlockchain};
@@ -184,25 +767,25 @@ mod tests {
 
     fn setup_pool() -> Pool {
         let token = "eth".to_string();
-        return Pool::new(token.as_bytes().to_vec());
+        return Pool::new(&token);
     }
 
     fn expected_added_liquidity(
         ynear: u128, max_tokens: u128, pool: &Pool
     ) -> (u128, u128, u128) {
+        let ceil = |n: u256, d: u256| ((n + d - 1) / d).as_u128();
         let ynear_256 = u256::from(ynear);
         let p_ynear_256 = u256::from(pool.ynear);
-        let mut added_tokens = (ynear_256 * u256::from(pool.tokens) / p_ynear_256 + 1).as_u128();
+        let mut added_tokens = ceil(ynear_256 * u256::from(pool.tokens), p_ynear_256);
         let shares_minted;
         let added_near;
 
         // Adjust near according to max_tokens
         if max_tokens < added_tokens {
-            added_near = ((u256::from(max_tokens) * p_ynear_256) / u256::from(pool.tokens) + 1)
-                .as_u128();
+            added_near = ceil(u256::from(max_tokens) * p_ynear_256, u256::from(pool.tokens));
             added_tokens = max_tokens;
-            shares_minted = (u256::from(added_near) * u256::from(pool.total_shares)
-                / p_ynear_256)
+            shares_minted = (u256::from(max_tokens) * u256::from(pool.total_shares)
+                / u256::from(pool.tokens))
                 .as_u128();
         } else {
             added_near = ynear;
@@ -237,7 +820,7 @@ mod tests {
         let caller = "account".to_string();
         let mut pool: Pool = setup_pool();
 
-        pool.add_liquidity(&caller, 100, 200, 0);
+        pool.add_liquidity(&caller, NearAmount(100), TokenAmount(200), Shares(0));
 
         assert!(pool.ynear == 100, "liquidity added is incorrect");
         assert!(pool.tokens == 200, "liquidity added is incorrect");
@@ -246,21 +829,104 @@ mod tests {
         let (expected_near, expected_tokens, expected_shares) = expected_added_liquidity(200, 400, &pool);
         
         // add liquidity again
-        let (near_added, tokens_added, shares_minted) = pool.add_liquidity(&caller, 200, 400, 0);
+        let (near_added, tokens_added, shares_minted) =
+            pool.add_liquidity(&caller, NearAmount(200), TokenAmount(400), Shares(0));
 
-        assert!(near_added == expected_near, "liquidity added is incorrect");
-        assert!(tokens_added == expected_tokens, "liquidity added is incorrect");
-        assert!(shares_minted == expected_shares, "liquidity added is incorrect");
+        assert!(near_added.0 == expected_near, "liquidity added is incorrect");
+        assert!(tokens_added.0 == expected_tokens, "liquidity added is incorrect");
+        assert!(shares_minted.0 == expected_shares, "liquidity added is incorrect");
 
         let (expected_near2, expected_tokens2, expected_shares2) = expected_added_liquidity(100, 100, &pool);
         // add liquidity again with ratio 1:1(100:100)
-        let (near_added2, tokens_added2, shares_minted2) = pool.add_liquidity(&caller, 100, 100, 0);
+        let (near_added2, tokens_added2, shares_minted2) =
+            pool.add_liquidity(&caller, NearAmount(100), TokenAmount(100), Shares(0));
 
-        assert!(near_added2 == expected_near2, "liquidity added is incorrect");
+        assert!(near_added2.0 == expected_near2, "liquidity added is incorrect");
         // adjusted near because pool ratio is 1:2
-        assert_eq!(near_added2, 51, "liquidity added is incorrect");
-        assert!(tokens_added2 == expected_tokens2, "liquidity added is incorrect");
-        assert!(shares_minted2 == expected_shares2, "liquidity added is incorrect");
+        assert_eq!(near_added2.0, 50, "liquidity added is incorrect");
+        assert!(tokens_added2.0 == expected_tokens2, "liquidity added is incorrect");
+        assert!(shares_minted2.0 == expected_shares2, "liquidity added is incorrect");
+    }
+
+    #[test]
+    fn add_liquidity_within_price_bounds() {
+        init_blockchain();
+
+        let caller = "account".to_string();
+        let mut pool: Pool = setup_pool();
+        // first deposit price: 100 / 200 = 0.5 NEAR per token
+        pool.init_price = Some(PriceBounds {
+            min: U128(NDENOM * 4 / 10),
+            max: U128(NDENOM * 6 / 10),
+        });
+
+        pool.add_liquidity(&caller, NearAmount(100), TokenAmount(200), Shares(0));
+        assert!(pool.ynear == 100, "liquidity added is incorrect");
+        assert!(pool.tokens == 200, "liquidity added is incorrect");
+    }
+
+    #[test]
+    #[should_panic(expected = r#"E26: Initial deposit price is outside of the pool price bounds"#)]
+    fn add_liquidity_outside_price_bounds() {
+        init_blockchain();
+
+        let caller = "account".to_string();
+        let mut pool: Pool = setup_pool();
+        pool.init_price = Some(PriceBounds {
+            min: U128(NDENOM * 6 / 10),
+            max: U128(NDENOM),
+        });
+
+        pool.add_liquidity(&caller, NearAmount(100), TokenAmount(200), Shares(0));
+    }
+
+    fn set_epoch(epoch: u64) {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.epoch_height(epoch).build());
+    }
+
+    #[test]
+    fn history_records_once_per_epoch() {
+        set_epoch(1);
+        let caller = "account".to_string();
+        let mut pool: Pool = setup_pool();
+
+        // empty pool is not recorded
+        pool.add_liquidity(&caller, NearAmount(100), TokenAmount(200), Shares(0));
+        assert_eq!(pool.history(0, 10).len(), 0);
+
+        set_epoch(2);
+        pool.add_liquidity(&caller, NearAmount(100), TokenAmount(200), Shares(0));
+        pool.withdraw_liquidity(&caller, NearAmount(0), TokenAmount(0), Shares(50));
+        let (ynear, tokens) = (pool.ynear, pool.tokens);
+        set_epoch(4);
+        pool.withdraw_liquidity(&caller, NearAmount(0), TokenAmount(0), Shares(50));
+
+        let h = pool.history(0, 10);
+        assert_eq!(h.len(), 2);
+        assert_eq!(
+            h[0],
+            PoolSnapshot {
+                epoch: 2,
+                ynear: 100.into(),
+                tokens: 200.into(),
+                total_shares: 100.into(),
+            }
+        );
+        // the snapshot is taken before the epoch's first update
+        assert_eq!(
+            h[1],
+            PoolSnapshot {
+                epoch: 4,
+                ynear: ynear.into(),
+                tokens: tokens.into(),
+                total_shares: 150.into(),
+            }
+        );
+        let h_from_3 = pool.history(3, 10);
+        assert_eq!(h_from_3.len(), 1);
+        assert_eq!(h_from_3[0], h[1]);
+        assert_eq!(pool.history(0, 1).len(), 1);
     }
 
     #[test]
@@ -272,15 +938,20 @@ mod tests {
         let caller = "account".to_string();
         let mut pool: Pool = setup_pool();
 
-        pool.add_liquidity(&caller, 100, 200, 0);
+        pool.add_liquidity(&caller, NearAmount(100), TokenAmount(200), Shares(0));
 
         let min_ynear = 50;
         let min_tokens = 100;
         // withdraw liquidity with min required shares
-        let (ynear, token) = pool.withdraw_liquidity(&caller, min_ynear, min_tokens, 50);
+        let (ynear, token) = pool.withdraw_liquidity(
+            &caller,
+            NearAmount(min_ynear),
+            TokenAmount(min_tokens),
+            Shares(50),
+        );
 
-        assert!(ynear >= min_ynear, "Incorrect liquidity withdrawn");
-        assert!(token >= min_tokens, "Incorrect liquidity withdrawn");
+        assert!(ynear.0 >= min_ynear, "Incorrect liquidity withdrawn");
+        assert!(token.0 >= min_tokens, "Incorrect liquidity withdrawn");
     }
 
     #[test]
@@ -291,12 +962,24 @@ mod tests {
         let caller = "account".to_string();
         let mut pool: Pool = setup_pool();
 
-        pool.add_liquidity(&caller, 100, 200, 0);
+        pool.add_liquidity(&caller, NearAmount(100), TokenAmount(200), Shares(0));
 
         let min_ynear = 55;
         let min_tokens = 100;
         // withdraw liquidity with min required shares
-        pool.withdraw_liquidity(&caller, min_ynear, min_tokens, 50);
+        pool.withdraw_liquidity(&caller, NearAmount(min_ynear), TokenAmount(min_tokens), Shares(50));
+    }
+
+    #[test]
+    #[should_panic(expected = r#"E4: amount minted shares (50) is smaller then the required minimum (51)"#)]
+    fn add_liquidity_with_min_shares_fail() {
+        init_blockchain();
+
+        let caller = "account".to_string();
+        let mut pool: Pool = setup_pool();
+
+        pool.add_liquidity(&caller, NearAmount(100), TokenAmount(200), Shares(0));
+        pool.add_liquidity(&caller, NearAmount(50), TokenAmount(100), Shares(51));
     }
 
     #[test]
@@ -306,12 +989,12 @@ mod tests {
         let caller = "account".to_string();
         let mut pool: Pool = setup_pool();
 
-        pool.add_liquidity(&caller, 100, 200, 0);
+        pool.add_liquidity(&caller, NearAmount(100), TokenAmount(200), Shares(0));
 
         let (near_before, tokens_before, shares_before) = (pool.ynear, pool.tokens, pool.total_shares);
         let (expected_near, expected_tokens) = expected_withdraw(50, &pool);
 
-        pool.withdraw_liquidity(&caller, 0, 0, 50);
+        pool.withdraw_liquidity(&caller, NearAmount(0), TokenAmount(0), Shares(50));
 
         // withdraw shares
         assert!(pool.ynear == near_before - expected_near, "liquidity removed is incorrect");
@@ -329,10 +1012,10 @@ mod tests {
         let caller = "account".to_string();
         let mut pool: Pool = setup_pool();
 
-        pool.add_liquidity(&caller, 100, 200, 0);
+        pool.add_liquidity(&caller, NearAmount(100), TokenAmount(200), Shares(0));
 
         // tries to withdraw more liquidity than deposited
-        pool.withdraw_liquidity(&caller, 0, 0, 400);
+        pool.withdraw_liquidity(&caller, NearAmount(0), TokenAmount(0), Shares(400));
     }
 
     #[test]
@@ -345,9 +1028,9 @@ mod tests {
         let fake_caller = "fakecaller".to_string();
         let mut pool: Pool = setup_pool();
 
-        pool.add_liquidity(&caller, 100, 200, 0);
+        pool.add_liquidity(&caller, NearAmount(100), TokenAmount(200), Shares(0));
 
         // tries to withdraw liquidity by fakeCaller
-        pool.withdraw_liquidity(&fake_caller, 0, 0, 50);
+        pool.withdraw_liquidity(&fake_caller, NearAmount(0), TokenAmount(0), Shares(50));
     }
 }
@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  POOL CREATOR
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId};
+
+use crate::constants::*;
+use crate::*;
+
+/// Pool information managed by the pool creator.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PoolMetadata {
+    /// account which called `create_pool`.
+    pub creator: AccountId,
+    pub description: String,
+    /// URL of a JSON file with more pool (project) information.
+    pub reference: Option<String>,
+    /// only the creator can start reward campaigns on the pool farm.
+    pub creator_campaigns_only: bool,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Pool creator: sets the `token` pool description and reference URL. The storage is
+    /// charged to the creator deposit.
+    pub fn set_pool_metadata(
+        &mut self,
+        token: AccountId,
+        description: String,
+        reference: Option<String>,
+    ) {
+        let start_storage = env::storage_usage();
+        let mut m = self.assert_pool_creator(&token);
+        let len = description.len() + reference.as_ref().map_or(0, |r| r.len());
+        assert!(len <= MAX_POOL_METADATA_LEN, "{}", ERR110_METADATA_TOO_LONG);
+        m.description = description;
+        m.reference = reference;
+        self.pool_metadata.insert(&token, &m);
+        self.unsafe_storage_check(start_storage);
+    }
+
+    /// Pool creator: pauses the `token` pool into withdraw-only mode: swaps, adding
+    /// liquidity and token deposits are disabled. The owner resumes the pool with `unpause_pool`.
+    pub fn pause_own_pool(&mut self, token: AccountId) {
+        self.assert_pool_creator(&token);
+        let mut p = self.get_pool(&token);
+        if p.paused {
+            return;
+        }
+        p.paused = true;
+        self.set_pool(&token, &p);
+        self.log_admin_action("pause_own_pool", &token);
+        event_log!(self, "Pool {} paused by the creator", token);
+    }
+
+    /// Pool creator: when `creator_only` is set, only the creator can start reward campaigns
+    /// on the `token` pool farm. Doesn't affect running campaigns.
+    pub fn set_pool_campaign_policy(&mut self, token: AccountId, creator_only: bool) {
+        let mut m = self.assert_pool_creator(&token);
+        m.creator_campaigns_only = creator_only;
+        self.pool_metadata.insert(&token, &m);
+    }
+
+    /// Owner or pool creator: sets the period, in seconds, over which the `token` pool TWAP
+    /// oracle views (`pool_imbalance`, the dynamic fee) average the observations. Stable
+    /// pairs need a short window, volatile pairs a long one. Must be between
+    /// `MIN_TWAP_WINDOW` and `MAX_TWAP_WINDOW`.
+    pub fn set_twap_window(&mut self, token: AccountId, seconds: u64) {
+        let caller = env::predecessor_account_id();
+        if caller != self.owner {
+            self.assert_pool_creator(&token);
+        }
+        assert!(
+            seconds >= MIN_TWAP_WINDOW && seconds <= MAX_TWAP_WINDOW,
+            "{}",
+            ERR134_INVALID_TWAP_WINDOW
+        );
+        let mut p = self.get_pool(&token);
+        p.twap_window = seconds;
+        self.set_pool(&token, &p);
+        self.log_admin_action("set_twap_window", &(token, seconds));
+    }
+
+    /// Returns the `token` pool TWAP oracle window, in seconds.
+    pub fn twap_window(&self, token: AccountId) -> u64 {
+        self.get_pool(&token).twap_window
+    }
+
+    pub fn pool_metadata(&self, token: AccountId) -> Option<PoolMetadata> {
+        self.pool_metadata.get(&token)
+    }
+}
+
+impl NearSwap {
+    /// Panics if the caller is not the `token` pool creator. Returns the pool metadata.
+    fn assert_pool_creator(&self, token: &AccountId) -> PoolMetadata {
+        let m = self.pool_metadata.get(token).expect(ERR109_NOT_POOL_CREATOR);
+        assert!(
+            m.creator == env::predecessor_account_id(),
+            "{}",
+            ERR109_NOT_POOL_CREATOR
+        );
+        m
+    }
+
+    /// Panics if the `token` pool farm campaigns are restricted to the pool creator and the
+    /// `funder` is not the creator.
+    pub(crate) fn assert_campaign_funder(&self, token: &AccountId, funder: &AccountId) {
+        if let Some(m) = self.pool_metadata.get(token) {
+            assert!(
+                !m.creator_campaigns_only || &m.creator == funder,
+                "{}",
+                ERR109_NOT_POOL_CREATOR
+            );
+        }
+    }
+}
@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  POOL IDS
+***********************/
+
+use near_sdk::json_types::U128;
+use near_sdk::{near_bindgen, AccountId};
+
+use crate::*;
+
+#[near_bindgen]
+impl NearSwap {
+    /// Returns the numeric id of the `token` pool, assigned at the pool creation.
+    pub fn pool_id(&self, token: AccountId) -> Option<u32> {
+        self.pool_ids.get(&token)
+    }
+
+    /// Returns the token of the `pool_id` pool.
+    pub fn pool_token(&self, pool_id: u32) -> Option<AccountId> {
+        self.pool_tokens.get(&pool_id)
+    }
+
+    /// Returns public information of the `pool_id` pool, see `pool_info`.
+    pub fn pool_info_by_id(&self, pool_id: u32) -> Option<PoolInfo> {
+        self.pool_info(&self.pool_tokens.get(&pool_id)?)
+    }
+
+    /// Assigns ids to up to `limit` pools created before the pool ids, starting from the
+    /// `from_index` pool of `list_pools`. Can be called by anyone. Returns the number of
+    /// assigned ids.
+    pub fn assign_pool_ids(&mut self, from_index: u64, limit: u64) -> u32 {
+        let tokens: Vec<AccountId> = self
+            .pools
+            .keys()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .filter(|t| self.pool_ids.get(t).is_none())
+            .collect();
+        for t in &tokens {
+            self.assign_pool_id(t);
+        }
+        tokens.len() as u32
+    }
+
+    /**
+    Swaps in the `pool_id` pool: `amount_in` of NEAR to the pool token if `near_in`,
+    otherwise `amount_in` of the pool token to NEAR, for at least `min_out`. See
+    `swap_near_to_token_exact_in` and `swap_token_to_near_exact_in`.
+    Returns the bought amount. */
+    #[payable]
+    pub fn swap_by_pool_id(
+        &mut self,
+        pool_id: u32,
+        near_in: bool,
+        amount_in: U128,
+        min_out: U128,
+        deadline: Option<Deadline>,
+    ) -> U128 {
+        let token = self.pool_tokens.get(&pool_id).expect(ERR133_UNKNOWN_POOL_ID);
+        if near_in {
+            self.swap_near_to_token_exact_in(amount_in, token, min_out, deadline)
+        } else {
+            self.swap_token_to_near_exact_in(token, amount_in, min_out, deadline)
+        }
+    }
+}
+
+impl NearSwap {
+    /// Assigns the next pool id to the `token` pool.
+    pub(crate) fn assign_pool_id(&mut self, token: &AccountId) {
+        let id = self.next_pool_id;
+        self.next_pool_id += 1;
+        self.pool_tokens.insert(&id, token);
+        self.pool_ids.insert(token, &id);
+    }
+
+    /// Removes the id of the removed `token` pool. Ids are not reused.
+    pub(crate) fn remove_pool_id(&mut self, token: &AccountId) {
+        if let Some(id) = self.pool_ids.remove(token) {
+            self.pool_tokens.remove(&id);
+        }
+    }
+}
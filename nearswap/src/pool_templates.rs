@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  POOL TEMPLATES
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, BlockHeight};
+
+use crate::constants::*;
+use crate::*;
+
+/// Pool parameters set selected at the pool creation, see `create_pool`. The owner defines
+/// the templates with `set_pool_template`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PoolTemplate {
+    /// pool swap fee, in basis points.
+    pub fee_bps: u16,
+    pub dynamic_fee: Option<DynamicFee>,
+    /// TWAP oracle window, in seconds, see `set_twap_window`.
+    pub twap_window: u64,
+    pub max_trade: Option<MaxTrade>,
+    /// batch auction window, in blocks, see `set_batch_auction`. 0 for a continuous pool.
+    pub batch_window: BlockHeight,
+}
+
+impl PoolTemplate {
+    pub fn assert_valid(&self) {
+        assert!(self.fee_bps <= 10_000, "{}", ERR140_INVALID_POOL_TEMPLATE);
+        if let Some(f) = &self.dynamic_fee {
+            f.assert_valid();
+        }
+        assert!(
+            self.twap_window >= MIN_TWAP_WINDOW && self.twap_window <= MAX_TWAP_WINDOW,
+            "{}",
+            ERR134_INVALID_TWAP_WINDOW
+        );
+        if let Some(m) = &self.max_trade {
+            m.assert_valid();
+        }
+        assert!(self.batch_window <= MAX_BATCH_WINDOW, "{}", ERR104_INVALID_BATCH_WINDOW);
+    }
+
+    /// Sets the template parameters of a new pool `p`.
+    pub fn apply(&self, p: &mut Pool) {
+        p.fee_bps = self.fee_bps;
+        p.dynamic_fee = self.dynamic_fee;
+        p.twap_window = self.twap_window;
+        p.max_trade = self.max_trade;
+        p.batch_window = self.batch_window;
+    }
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: defines the `name` pool template, or removes it with `None`. The
+    /// `DEFAULT_POOL_TEMPLATE` template applies to the pools created without a template.
+    /// Existing pools are not affected.
+    pub fn set_pool_template(&mut self, name: String, template: Option<PoolTemplate>) {
+        self.assert_owner();
+        assert!(!name.is_empty(), "{}", ERR140_INVALID_POOL_TEMPLATE);
+        self.log_admin_action("set_pool_template", &(&name, &template));
+        match template {
+            Some(t) => {
+                t.assert_valid();
+                self.pool_templates.insert(&name, &t);
+            }
+            None => {
+                self.pool_templates.remove(&name);
+            }
+        }
+    }
+
+    pub fn pool_template(&self, name: String) -> Option<PoolTemplate> {
+        self.pool_templates.get(&name)
+    }
+
+    /// Returns the pool templates with their names.
+    pub fn pool_templates(&self) -> Vec<(String, PoolTemplate)> {
+        self.pool_templates.iter().collect()
+    }
+}
+
+impl NearSwap {
+    /// Returns the `name` pool template, or the default one, if defined, when `name` is
+    /// `None`. Panics if the named template doesn't exist.
+    pub(crate) fn select_pool_template(&self, name: &Option<String>) -> Option<PoolTemplate> {
+        match name {
+            Some(n) => Some(self.pool_templates.get(n).expect(ERR141_UNKNOWN_POOL_TEMPLATE)),
+            None => self.pool_templates.get(&DEFAULT_POOL_TEMPLATE.to_string()),
+        }
+    }
+}
@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  POSITION HISTORY
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::Vector;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance, CryptoHash};
+
+use crate::constants::*;
+use crate::*;
+
+/// Liquidity change recorded in the position history.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum LiquidityEventKind {
+    Add,
+    Withdraw,
+}
+
+/// Liquidity added to or withdrawn from a pool by an account, with the pool prices at the
+/// time, so the LP can compute the position cost basis and P&L, see `position_history`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LiquidityEvent {
+    /// block timestamp, in nanoseconds.
+    pub timestamp: U64,
+    pub kind: LiquidityEventKind,
+    /// added or withdrawn NEAR, in yNEAR.
+    pub ynear: U128,
+    /// added or withdrawn tokens.
+    pub tokens: U128,
+    /// minted or burned shares.
+    pub shares: U128,
+    /// pool spot price: yNEAR for 1 token unit, multiplied by `NDENOM`.
+    pub token_price: U128,
+    /// value of `NDENOM` shares in yNEAR, with the tokens valued at `token_price`.
+    pub share_price: U128,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Returns up to `limit` liquidity events of the `account` position in the `token` pool
+    /// starting from the `from_index` event, in chronological order.
+    pub fn position_history(
+        &self,
+        account: AccountId,
+        token: AccountId,
+        from_index: u64,
+        limit: u64,
+    ) -> Vec<LiquidityEvent> {
+        let events = match self.position_history.get(&(account, token)) {
+            Some(events) => events,
+            None => return vec![],
+        };
+        let end = events.len().min(from_index.saturating_add(limit));
+        (from_index..end).map(|i| events.get(i).unwrap()).collect()
+    }
+
+    pub fn position_history_len(&self, account: AccountId, token: AccountId) -> u64 {
+        self.position_history
+            .get(&(account, token))
+            .map_or(0, |events| events.len())
+    }
+}
+
+impl NearSwap {
+    /// Appends a liquidity event of the `account` position in the `token` pool. `p` is the
+    /// pool after the event. The storage is paid by the account: must be called before its
+    /// deposit storage update.
+    pub(crate) fn record_liquidity_event(
+        &mut self,
+        account: &AccountId,
+        token: &AccountId,
+        kind: LiquidityEventKind,
+        (ynear, tokens, shares): (Balance, Balance, Balance),
+        p: &Pool,
+    ) {
+        // a full withdrawal empties the pool, its amounts have the pool price.
+        let token_price = if p.tokens > 0 {
+            p.spot_price()
+        } else if tokens > 0 {
+            clp_math::spot_price(ynear, tokens).min(u256::from(u128::MAX)).as_u128()
+        } else {
+            0
+        };
+        let value = ynear + clp_math::mul_div_floor(tokens, token_price, NDENOM);
+        let share_price = if shares > 0 {
+            clp_math::mul_div_floor(value, NDENOM, shares)
+        } else {
+            0
+        };
+        let key = (account.clone(), token.clone());
+        let mut events = self.position_history.get(&key).unwrap_or_else(|| {
+            Vector::new(StorageKey::PositionEvents {
+                position: position_hash(account, token),
+            })
+        });
+        events.push(&LiquidityEvent {
+            timestamp: env::block_timestamp().into(),
+            kind,
+            ynear: ynear.into(),
+            tokens: tokens.into(),
+            shares: shares.into(),
+            token_price: token_price.into(),
+            share_price: share_price.into(),
+        });
+        self.position_history.insert(&key, &events);
+    }
+}
+
+/// Returns the hash of the `account` position in the `token` pool, used as the position
+/// events storage key suffix.
+fn position_hash(account: &AccountId, token: &AccountId) -> CryptoHash {
+    let mut h = CryptoHash::default();
+    h.copy_from_slice(&env::sha256(&(account, token).try_to_vec().unwrap()));
+    h
+}
@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  PRICE HISTORY
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::Vector;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId, IntoStorageKey};
+
+use crate::constants::{PRICE_HISTORY_LENGTH, PRICE_SAMPLE_PERIOD};
+use crate::*;
+
+/// Pool spot price sample: yNEAR for `1e24` token units.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct PriceSample {
+    /// block timestamp, in nanoseconds.
+    pub timestamp: U64,
+    pub price: U128,
+}
+
+/// Ring buffer of the last `PRICE_HISTORY_LENGTH` price samples of a pool. At most one sample
+/// is recorded per `PRICE_SAMPLE_PERIOD`: the first price update in the period.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct PriceHistory {
+    // number of samples recorded since the pool creation.
+    count: u64,
+    samples: Vector<PriceSample>,
+}
+
+impl PriceHistory {
+    pub fn new<S: IntoStorageKey>(prefix: S) -> Self {
+        Self {
+            count: 0,
+            samples: Vector::new(prefix),
+        }
+    }
+
+    /// Records the `price` at `timestamp` unless a sample was already recorded in the
+    /// `timestamp` sampling period.
+    pub fn record(&mut self, timestamp: u64, price: u128) {
+        let period = to_nanoseconds(PRICE_SAMPLE_PERIOD);
+        if self.count > 0 {
+            let last = self.samples.get((self.count - 1) % PRICE_HISTORY_LENGTH).unwrap();
+            if last.timestamp.0 / period == timestamp / period {
+                return;
+            }
+        }
+        let s = PriceSample {
+            timestamp: timestamp.into(),
+            price: price.into(),
+        };
+        if self.samples.len() < PRICE_HISTORY_LENGTH {
+            self.samples.push(&s);
+        } else {
+            self.samples.replace(self.count % PRICE_HISTORY_LENGTH, &s);
+        }
+        self.count += 1;
+    }
+
+    /// Returns up to `limit` samples, in chronological order, recorded not earlier than `from`.
+    pub fn list(&self, from: u64, limit: u64) -> Vec<PriceSample> {
+        let len = self.samples.len();
+        // index of the oldest sample
+        let first = if len < PRICE_HISTORY_LENGTH {
+            0
+        } else {
+            self.count % PRICE_HISTORY_LENGTH
+        };
+        (0..len)
+            .map(|i| self.samples.get((first + i) % len).unwrap())
+            .filter(|s| s.timestamp.0 >= from)
+            .take(limit as usize)
+            .collect()
+    }
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Returns up to `limit` spot price samples of the `token` pool recorded not earlier than
+    /// `from_ts` (timestamp in nanoseconds), in chronological order. The pool price is sampled
+    /// at most once per `PRICE_SAMPLE_PERIOD`, on swaps and oracle updates. Designed for
+    /// sparkline charts drawn straight from view calls.
+    pub fn price_history(&self, token: AccountId, from_ts: U64, limit: u64) -> Vec<PriceSample> {
+        self.get_pool(&token).price_history.list(from_ts.into(), limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    #[test]
+    fn record_samples_once_per_period() {
+        testing_env!(VMContextBuilder::new().build());
+        let mut h = PriceHistory::new(b"p".to_vec());
+        let period = to_nanoseconds(PRICE_SAMPLE_PERIOD);
+
+        h.record(period + 1, 10);
+        h.record(2 * period - 1, 11);
+        h.record(2 * period, 12);
+        let prices: Vec<u128> = h.list(0, 10).iter().map(|s| s.price.0).collect();
+        assert_eq!(prices, vec![10, 12]);
+        assert_eq!(h.list(period + 2, 10).len(), 1);
+
+        // only the last `PRICE_HISTORY_LENGTH` samples are kept
+        for i in 3..PRICE_HISTORY_LENGTH + 5 {
+            h.record(i * period, i as u128);
+        }
+        let s = h.list(0, PRICE_HISTORY_LENGTH + 10);
+        assert_eq!(s.len() as u64, PRICE_HISTORY_LENGTH);
+        assert_eq!(s[0].price.0, 5);
+        assert_eq!(s.last().unwrap().price.0, (PRICE_HISTORY_LENGTH + 4) as u128);
+    }
+}
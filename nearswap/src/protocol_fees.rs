@@ -0,0 +1,419 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  PROTOCOL FEES
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+
+use crate::constants::*;
+use crate::*;
+
+/// Token project registered with `register_token_project`, receiving a part of the protocol
+/// fees of its pool.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, PartialEq, Debug)]
+pub struct TokenProject {
+    /// account credited by `claim_project_revenue`.
+    pub beneficiary: AccountId,
+    /// project part of the pool protocol fees, in basis points.
+    pub share_bps: u16,
+    /// claimable revenue, in yNEAR and tokens.
+    pub ynear: U128,
+    pub tokens: U128,
+}
+
+/// Recipient of a `weight` part of the protocol fees, see `distribute_fees`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct FeeDestination {
+    pub account: AccountId,
+    pub weight: u32,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: sets the protocol fees recipients, eg treasury, insurance fund and buyback.
+    /// Every destination account must have a storage deposit.
+    pub fn set_fee_destinations(&mut self, destinations: Vec<FeeDestination>) {
+        self.assert_owner();
+        assert!(
+            !destinations.is_empty() && destinations.len() <= MAX_FEE_DESTINATIONS,
+            "{}",
+            ERR114_INVALID_FEE_DESTINATIONS
+        );
+        for (i, d) in destinations.iter().enumerate() {
+            assert_account_is_valid(&d.account);
+            assert!(
+                d.weight > 0 && destinations[..i].iter().all(|x| x.account != d.account),
+                "{}",
+                ERR114_INVALID_FEE_DESTINATIONS
+            );
+            self.get_deposit(&d.account);
+        }
+        self.log_admin_action("set_fee_destinations", &destinations);
+        self.fee_destinations = destinations;
+    }
+
+    pub fn fee_destinations(&self) -> Vec<FeeDestination> {
+        self.fee_destinations.clone()
+    }
+
+    /// Moves the protocol fees accrued in the `token` pool to the protocol fees, splitting
+    /// the token project part, see `distribute_fees`. Can be called by anyone.
+    pub fn collect_protocol_fees(&mut self, token: AccountId) {
+        let mut p = self.get_pool(&token);
+        self.split_protocol_fees(&token, &mut p);
+        self.set_pool(&token, &p);
+    }
+
+    /**
+    Credits the protocol fees to the deposits of the fee destinations, pro rata to their
+    weights. The governance token stakers part of the NEAR fees is taken first, see
+    `stake_gov`. Distributes at most `MAX_DISTRIBUTED_ASSETS` assets per call. Fees accrued
+    in a pool are distributed once collected with `collect_protocol_fees`. Can be called by
+    anyone. */
+    pub fn distribute_fees(&mut self) {
+        let fees: Vec<(Option<AccountId>, Balance)> = self
+            .protocol_fees
+            .iter()
+            .take(MAX_DISTRIBUTED_ASSETS)
+            .collect();
+        for (token, amount) in fees {
+            self.protocol_fees.remove(&token);
+            let amount = self.distribute_asset(&token, amount);
+            event_log!(
+                self,
+                "Protocol fees distributed: {} {}",
+                amount,
+                token.as_deref().unwrap_or("yNEAR")
+            );
+        }
+    }
+
+    /**
+    Converts the `token` protocol fees to NEAR by selling them in the `token` pool, so the
+    fee destinations receive a single asset. The pool token protocol fees are collected
+    first, see `collect_protocol_fees`. At most `FEE_CONVERSION_MAX_RESERVE_BPS` of the pool
+    token reserve is sold per call, and the bought NEAR must be within
+    `FEE_CONVERSION_MAX_SLIPPAGE_BPS` of the pool TWAP value of the sold tokens, so the
+    conversion can't be sandwiched. The swap pays the pool fee. Can be called by anyone.
+    Returns the bought yNEAR, added to the NEAR protocol fees. */
+    pub fn convert_fees(&mut self, token: AccountId) -> U128 {
+        let _m = MethodMetrics::start("convert_fees");
+        let mut p = self.get_pool(&token);
+        self.assert_pool_active(&p);
+        assert!(p.batch_window == 0, "{}", ERR100_BATCH_AUCTION_POOL);
+        self.run_twamm(&token, &mut p);
+        p.record_snapshot();
+        self.split_protocol_fees(&token, &mut p);
+        let asset = Some(token.clone());
+        let fees = self.protocol_fees.get(&asset).unwrap_or(0);
+        let max_in = clp_math::mul_div_floor(
+            p.tokens,
+            FEE_CONVERSION_MAX_RESERVE_BPS as u128,
+            10_000,
+        );
+        let amount_in = fees.min(max_in);
+        if amount_in == 0 {
+            self.set_pool(&token, &p);
+            return U128(0);
+        }
+
+        p.twap.last_observation().expect(ERR139_FEE_CONVERSION_SLIPPAGE);
+        let twap_price = p.twap.mean_over(to_nanoseconds(p.twap_window)).0;
+        let min_out = clp_math::mul_div_ceil(
+            clp_math::mul_div_floor(amount_in, twap_price, NDENOM),
+            (10_000 - FEE_CONVERSION_MAX_SLIPPAGE_BPS) as u128,
+            10_000,
+        );
+        let (out, fee) = self.calc_leg(&p, false, amount_in);
+        assert!(
+            out > 0 && out >= min_out,
+            "{} (out={}, min={})",
+            ERR139_FEE_CONVERSION_SLIPPAGE,
+            out,
+            min_out
+        );
+        p.tokens += amount_in - self.take_fees(&mut p, fee, false);
+        p.ynear -= out;
+        p.record_trade(out);
+        self.set_pool(&token, &p);
+
+        if fees == amount_in {
+            self.protocol_fees.remove(&asset);
+        } else {
+            self.protocol_fees.insert(&asset, &(fees - amount_in));
+        }
+        self.add_protocol_fees(&None, out);
+        event_log!(
+            self,
+            "Protocol fees converted: {} {} -> {} yNEAR",
+            amount_in,
+            token,
+            out
+        );
+        out.into()
+    }
+
+    /// Owner: sets the accrued protocol fees of `token` (`None` for NEAR) above which they
+    /// are forwarded to the fee destinations by the swap which crossed the threshold, see
+    /// `distribute_fees`. `None` disables the forwarding. All fee destinations must have a
+    /// storage deposit.
+    pub fn set_fee_forward_threshold(
+        &mut self,
+        token: Option<AccountId>,
+        threshold: Option<U128>,
+    ) {
+        self.assert_owner();
+        self.log_admin_action("set_fee_forward_threshold", &(&token, threshold));
+        match threshold {
+            Some(t) => {
+                assert!(t.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+                assert!(self.fee_destinations_registered(&None), "{}", ERR20_ACC_NOT_REGISTERED);
+                self.fee_forward_thresholds.insert(&token, &t.0);
+            }
+            None => {
+                self.fee_forward_thresholds.remove(&token);
+            }
+        }
+    }
+
+    pub fn fee_forward_threshold(&self, token: Option<AccountId>) -> Option<U128> {
+        self.fee_forward_thresholds.get(&token).map(U128)
+    }
+
+    /// Owner: sets the protocol part of the swap fees, in basis points of the fee. The rest
+    /// of the fee goes to the liquidity providers.
+    pub fn set_protocol_fee(&mut self, bps: u16) {
+        self.assert_owner();
+        assert!(bps <= MAX_PROTOCOL_FEE_BPS, "{}", ERR111_INVALID_PROTOCOL_FEE);
+        self.protocol_fee_bps = bps;
+        self.log_admin_action("set_protocol_fee", &bps);
+    }
+
+    pub fn protocol_fee_bps(&self) -> u16 {
+        self.protocol_fee_bps
+    }
+
+    /// Returns the protocol fees of `token` (`None` for NEAR) split from the pools.
+    pub fn protocol_fees(&self, token: Option<AccountId>) -> U128 {
+        self.protocol_fees.get(&token).unwrap_or(0).into()
+    }
+
+    /**
+    Registers the caller token project: the caller must be the token contract account, which
+    proves the control of the token. The `beneficiary` receives `PROJECT_REVENUE_SHARE_BPS`
+    of the protocol fees of the token pool generated from now, see `claim_project_revenue`.
+    Calling it again changes the beneficiary. */
+    pub fn register_token_project(&mut self, beneficiary: ValidAccountId) {
+        let token = env::predecessor_account_id();
+        let mut p = self.get_pool(&token);
+        self.split_protocol_fees(&token, &mut p);
+        self.set_pool(&token, &p);
+        let project = match self.token_projects.get(&token) {
+            Some(pr) => TokenProject {
+                beneficiary: beneficiary.into(),
+                ..pr
+            },
+            None => TokenProject {
+                beneficiary: beneficiary.into(),
+                share_bps: PROJECT_REVENUE_SHARE_BPS,
+                ynear: U128(0),
+                tokens: U128(0),
+            },
+        };
+        event_log!(
+            self,
+            "Token project {} registered, beneficiary {}",
+            token,
+            project.beneficiary
+        );
+        self.token_projects.insert(&token, &project);
+    }
+
+    /// Owner: sets the `token` project part of the pool protocol fees, in basis points.
+    /// Applies to the fees generated from now.
+    pub fn set_project_revenue_share(&mut self, token: AccountId, share_bps: u16) {
+        self.assert_owner();
+        assert!(share_bps <= 10_000, "{}", ERR113_INVALID_REVENUE_SHARE);
+        let mut p = self.get_pool(&token);
+        self.split_protocol_fees(&token, &mut p);
+        self.set_pool(&token, &p);
+        let mut pr = self.token_projects.get(&token).expect(ERR112_NOT_PROJECT_BENEFICIARY);
+        pr.share_bps = share_bps;
+        self.token_projects.insert(&token, &pr);
+        self.log_admin_action("set_project_revenue_share", &(token, share_bps));
+    }
+
+    /// Token project beneficiary: credits the `token` project revenue to the caller deposit.
+    /// Returns the claimed yNEAR and tokens.
+    pub fn claim_project_revenue(&mut self, token: AccountId) -> (U128, U128) {
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let mut p = self.get_pool(&token);
+        self.split_protocol_fees(&token, &mut p);
+        self.set_pool(&token, &p);
+        let mut pr = self.token_projects.get(&token).expect(ERR112_NOT_PROJECT_BENEFICIARY);
+        assert!(pr.beneficiary == user, "{}", ERR112_NOT_PROJECT_BENEFICIARY);
+        let (ynear, tokens) = (pr.ynear, pr.tokens);
+        pr.ynear = U128(0);
+        pr.tokens = U128(0);
+        self.token_projects.insert(&token, &pr);
+
+        let mut d = self.get_deposit(&user);
+        d.add_near(ynear.0);
+        d.add(&token, tokens.0);
+        self.deposits.insert(&user, &d.into());
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Token project {} revenue claimed: {} yNEAR, {} tokens",
+            token,
+            ynear.0,
+            tokens.0
+        );
+        (ynear, tokens)
+    }
+
+    /// Returns the `token` project with its revenue split up to now.
+    pub fn token_project(&self, token: AccountId) -> Option<TokenProject> {
+        let p = self.pools.get(&token)?;
+        self.token_projects.get(&token).map(|mut pr| {
+            let share = pr.share_bps as u128;
+            pr.ynear.0 += clp_math::mul_div_floor(p.protocol_ynear, share, 10_000);
+            pr.tokens.0 += clp_math::mul_div_floor(p.protocol_tokens, share, 10_000);
+            pr
+        })
+    }
+}
+
+impl NearSwap {
+    /// Takes the protocol and the boosted liquidity parts of a swap `fee` (charged in yNEAR
+    /// if `is_near`, tokens otherwise). Returns the amount which must not be added to the
+    /// pool reserves.
+    pub(crate) fn take_fees(&self, p: &mut Pool, fee: Balance, is_near: bool) -> Balance {
+        let protocol = p.take_protocol_fee(fee, is_near, self.protocol_fee_bps);
+        protocol + p.take_boost_fee(fee - protocol, is_near)
+    }
+
+    /// Splits the protocol fees accrued in the `token` pool `p` between the token project,
+    /// if registered, and the protocol. The caller must save the pool.
+    pub(crate) fn split_protocol_fees(&mut self, token: &AccountId, p: &mut Pool) {
+        let (mut ynear, mut tokens) = (p.protocol_ynear, p.protocol_tokens);
+        if ynear == 0 && tokens == 0 {
+            return;
+        }
+        p.protocol_ynear = 0;
+        p.protocol_tokens = 0;
+        if let Some(mut pr) = self.token_projects.get(token) {
+            let share = pr.share_bps as u128;
+            let (py, pt) = (
+                clp_math::mul_div_floor(ynear, share, 10_000),
+                clp_math::mul_div_floor(tokens, share, 10_000),
+            );
+            pr.ynear.0 += py;
+            pr.tokens.0 += pt;
+            self.token_projects.insert(token, &pr);
+            ynear -= py;
+            tokens -= pt;
+        }
+        self.add_protocol_fees(&None, ynear);
+        self.add_protocol_fees(&Some(token.clone()), tokens);
+    }
+
+    /// Forwards the protocol fees of the `token` pool assets to the fee destinations if the
+    /// fees accrued in the pool `p` and the collected ones reach the asset forward threshold.
+    /// An asset is forwarded only if all destinations are registered and, for a token,
+    /// already have its deposit, so the swap neither fails nor pays for their storage.
+    /// Otherwise the fees stay in the protocol fees. The caller must save the pool.
+    pub(crate) fn forward_protocol_fees(&mut self, token: &AccountId, p: &mut Pool) {
+        if self.fee_destinations.is_empty() {
+            return;
+        }
+        let due: Vec<Option<AccountId>> = vec![
+            (None, p.protocol_ynear),
+            (Some(token.clone()), p.protocol_tokens),
+        ]
+        .into_iter()
+        .filter(|(a, accrued)| {
+            self.fee_forward_thresholds.get(a).map_or(false, |threshold| {
+                accrued + self.protocol_fees.get(a).unwrap_or(0) >= threshold
+            })
+        })
+        .map(|(a, _)| a)
+        .collect();
+        if due.is_empty() {
+            return;
+        }
+        self.split_protocol_fees(token, p);
+        for a in due {
+            if !self.fee_destinations_registered(&a) {
+                continue;
+            }
+            let amount = match self.protocol_fees.remove(&a) {
+                Some(amount) => self.distribute_asset(&a, amount),
+                None => continue,
+            };
+            event_log!(
+                self,
+                "Protocol fees forwarded: {} {}",
+                amount,
+                a.as_deref().unwrap_or("yNEAR")
+            );
+        }
+    }
+
+    /// Returns true if all fee destinations have a deposit, with a `token` balance for a
+    /// token asset (`None` for NEAR).
+    fn fee_destinations_registered(&self, token: &Option<AccountId>) -> bool {
+        self.fee_destinations.iter().all(|dst| match self.deposits.get(&dst.account) {
+            Some(d) => {
+                let d: DepositV1 = d.into();
+                token.as_ref().map_or(true, |t| d.tokens.contains_key(t))
+            }
+            None => false,
+        })
+    }
+
+    /// Credits `amount` of `token` (`None` for NEAR) protocol fees to the deposits of the fee
+    /// destinations, pro rata to their weights, after taking the governance token stakers
+    /// part of the NEAR fees. Returns the credited amount.
+    fn distribute_asset(&mut self, token: &Option<AccountId>, amount: Balance) -> Balance {
+        let total: u128 = self.fee_destinations.iter().map(|d| d.weight as u128).sum();
+        let amount = if token.is_none() {
+            amount - self.take_gov_fee_share(amount)
+        } else {
+            amount
+        };
+        let dsts = self.fee_destinations.clone();
+        let mut left = amount;
+        for (i, dst) in dsts.iter().enumerate().rev() {
+            // the rounding remainder goes to the first destination.
+            let x = if i == 0 {
+                left
+            } else {
+                clp_math::mul_div_floor(amount, dst.weight as u128, total)
+            };
+            left -= x;
+            let start_storage = env::storage_usage();
+            let mut d = self.get_deposit(&dst.account);
+            d.add_asset(token, x);
+            self.deposits.insert(&dst.account, &d.into());
+            // a new deposit token is paid by the destination.
+            let mut d = self.get_deposit(&dst.account);
+            d.update_storage(start_storage);
+            self.deposits.insert(&dst.account, &d.into());
+        }
+        amount
+    }
+
+    pub(crate) fn add_protocol_fees(&mut self, token: &Option<AccountId>, amount: Balance) {
+        if amount > 0 {
+            let b = self.protocol_fees.get(token).unwrap_or(0);
+            self.protocol_fees.insert(token, &(b + amount));
+        }
+    }
+}
@@ -0,0 +1,178 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  QUOTE LOCKS
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance, BlockHeight};
+
+use crate::constants::*;
+use crate::*;
+
+/// Pool quote recorded by `lock_quote`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+pub struct LockedQuote {
+    pub account: AccountId,
+    pub token: AccountId,
+    /// sells NEAR for the pool token, otherwise sells the pool token for NEAR.
+    pub sell_near: bool,
+    pub amount_in: U128,
+    /// amount bought by `execute_locked_quote`.
+    pub amount_out: U128,
+    /// last block height at which the quote can be executed.
+    pub expires: BlockHeight,
+}
+
+/// Pool fund paying the adverse price moves of the locked quotes, filled with the lock fees
+/// and the favorable price moves.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Default, PartialEq, Debug)]
+pub struct QuoteLockFund {
+    pub ynear: U128,
+    pub tokens: U128,
+}
+
+impl QuoteLockFund {
+    fn add(&mut self, is_near: bool, amount: Balance) {
+        let b = if is_near { &mut self.ynear } else { &mut self.tokens };
+        b.0 += amount;
+    }
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Locks the current `token` pool quote for a swap of `amount_in` NEAR (`sell_near`) or
+    tokens from the caller deposit. The quote can be executed by the caller with
+    `execute_locked_quote` during `QUOTE_LOCK_BLOCKS` blocks. The lock fee of
+    `QUOTE_LOCK_FEE_BPS` of `amount_in` is taken now from the caller deposit, in the sold
+    asset, and added to the pool quote lock fund.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the lock id. */
+    #[payable]
+    pub fn lock_quote(&mut self, token: AccountId, amount_in: U128, sell_near: bool) -> u64 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        assert!(amount_in.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        let p = self.get_pool(&token);
+        self.assert_pool_active(&p);
+        let (token_in, token_out) = swap_assets(&token, sell_near);
+        let amount_out = self.internal_quote(&token_in, &token_out, amount_in.0);
+        assert!(amount_out > 0, "{}", ERR02_POSITIVE_ARGS);
+
+        let fee = clp_math::mul_div_ceil(amount_in.0, QUOTE_LOCK_FEE_BPS, 10_000);
+        let user = env::predecessor_account_id();
+        let mut d = self.get_deposit(&user);
+        d.remove_asset(&token_in, fee);
+        self.deposits.insert(&user, &d.into());
+        let mut fund = self.quote_lock_funds.get(&token).unwrap_or_default();
+        fund.add(sell_near, fee);
+        self.quote_lock_funds.insert(&token, &fund);
+
+        let id = self.next_order;
+        self.next_order += 1;
+        let expires = env::block_index() + QUOTE_LOCK_BLOCKS;
+        self.locked_quotes.insert(
+            &id,
+            &LockedQuote {
+                account: user,
+                token,
+                sell_near,
+                amount_in,
+                amount_out: amount_out.into(),
+                expires,
+            },
+        );
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Quote {} locked: {} for {} until {}",
+            id,
+            amount_in.0,
+            amount_out,
+            expires
+        );
+        id
+    }
+
+    /**
+    Executes the `id` locked quote of the caller: swaps the quote `amount_in` from the caller
+    deposit in the pool and credits exactly the quote `amount_out`. A better pool price
+    adds the surplus to the pool quote lock fund. A worse pool price is covered by the fund,
+    up to `QUOTE_LOCK_MAX_MOVE_BPS` of `amount_out`.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the amount bought. */
+    #[payable]
+    pub fn execute_locked_quote(&mut self, id: u64) -> U128 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let q = self.locked_quotes.get(&id).expect(ERR33_ORDER_NOT_FOUND);
+        assert!(q.account == user, "{}", ERR33_ORDER_NOT_FOUND);
+        assert!(env::block_index() <= q.expires, "{}", ERR107_QUOTE_LOCK_EXPIRED);
+        self.locked_quotes.remove(&id);
+
+        let (token_in, token_out) = swap_assets(&q.token, q.sell_near);
+        let quoted = q.amount_out.0;
+        let out = self.internal_swap(&user, &token_in, &token_out, q.amount_in.0, 0);
+        let mut fund = self.quote_lock_funds.get(&q.token).unwrap_or_default();
+        let mut d = self.get_deposit(&user);
+        if out >= quoted {
+            d.remove_asset(&token_out, out - quoted);
+            fund.add(!q.sell_near, out - quoted);
+        } else {
+            let shortfall = quoted - out;
+            let available = if q.sell_near { &mut fund.tokens } else { &mut fund.ynear };
+            assert!(
+                shortfall <= clp_math::mul_div_floor(quoted, QUOTE_LOCK_MAX_MOVE_BPS, 10_000)
+                    && shortfall <= available.0,
+                "{} (out={}, quoted={}, fund={})",
+                ERR108_QUOTE_LOCK_NOT_COVERED,
+                out,
+                quoted,
+                available.0
+            );
+            available.0 -= shortfall;
+            d.add_asset(&token_out, shortfall);
+        }
+        self.deposits.insert(&user, &d.into());
+        self.quote_lock_funds.insert(&q.token, &fund);
+        self.unsafe_storage_check(start_storage);
+        event_log!(self, "Locked quote {} executed, pool out {}", id, out);
+        quoted.into()
+    }
+
+    /// Removes the `id` locked quote of the caller. The lock fee is not refunded.
+    /// Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    #[payable]
+    pub fn cancel_locked_quote(&mut self, id: u64) {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let q = self.locked_quotes.get(&id).expect(ERR33_ORDER_NOT_FOUND);
+        assert!(q.account == user, "{}", ERR33_ORDER_NOT_FOUND);
+        self.locked_quotes.remove(&id);
+        self.unsafe_storage_check(start_storage);
+    }
+
+    pub fn locked_quote(&self, id: u64) -> Option<LockedQuote> {
+        self.locked_quotes.get(&id)
+    }
+
+    /// Returns the `token` pool quote lock fund.
+    pub fn quote_lock_fund(&self, token: AccountId) -> QuoteLockFund {
+        self.quote_lock_funds.get(&token).unwrap_or_default()
+    }
+}
+
+/// Returns the sold and the bought asset of a `token` pool swap. `None` denotes NEAR.
+fn swap_assets(token: &AccountId, sell_near: bool) -> (Option<AccountId>, Option<AccountId>) {
+    if sell_near {
+        (None, Some(token.clone()))
+    } else {
+        (Some(token.clone()), None)
+    }
+}
@@ -0,0 +1,163 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  EXCHANGE RATE TOKENS
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, ext_contract, near_bindgen, AccountId, Balance, Gas, Promise, PromiseResult};
+
+use crate::constants::*;
+use crate::*;
+
+/// Amount of gas for the rate provider view call.
+pub const GAS_FOR_RATE_PROVIDER: Gas = 5 * TGAS;
+
+/// Amount of gas for the `update_rate` callback.
+pub const GAS_FOR_RESOLVE_UPDATE_RATE: Gas = 10 * TGAS;
+
+/**
+Exchange rate provider interface: view methods returning the exchange rate of a token whose
+value accrues over time (eg a staked NEAR derivative), in yNEAR per 1e24 token units. A
+provider implements one of them, see `RateMethod`. */
+#[ext_contract(ext_rate_provider)]
+pub trait RateProviderContract {
+    /// Meta Pool stNEAR price.
+    fn get_st_near_price(&self) -> U128;
+
+    /// LiNEAR price.
+    fn ft_price(&self) -> U128;
+}
+
+/// `ext_rate_provider` view method called by `update_rate`.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum RateMethod {
+    GetStNearPrice,
+    FtPrice,
+}
+
+/// Exchange rate provider of a pool token.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct RateProvider {
+    pub contract: AccountId,
+    pub method: RateMethod,
+}
+
+/// Exchange rate of a pool token returned by `pool_rate`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct PoolRate {
+    pub provider: RateProvider,
+    /// last rate fetched by `update_rate`, 0 before the first update.
+    pub rate: U128,
+    /// rate of the first update, when the pool pricing started to follow the rate.
+    pub rate_base: U128,
+    /// block timestamp of the last update, in nanoseconds.
+    pub updated: U64,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Owner: sets the exchange rate provider of the `token` pool. The pool prices the token
+    reserve as `tokens * rate_base / rate`, so the pool price follows the rate accrual,
+    see `update_rate`. `None` removes the provider: the pool price goes back to the plain
+    reserves ratio. */
+    pub fn set_rate_provider(&mut self, token: AccountId, provider: Option<RateProvider>) {
+        self.assert_owner();
+        let mut p = self.get_pool(&token);
+        if let Some(r) = &provider {
+            assert_account_is_valid(&r.contract);
+        }
+        self.log_admin_action("set_rate_provider", &(&token, &provider));
+        p.rate_provider = provider;
+        p.rate = 0;
+        p.rate_base = 0;
+        p.rate_updated = 0;
+        self.set_pool(&token, &p);
+    }
+
+    /// Returns the `token` pool exchange rate, if the pool has a rate provider.
+    pub fn pool_rate(&self, token: AccountId) -> Option<PoolRate> {
+        let p = self.get_pool(&token);
+        p.rate_provider.clone().map(|provider| PoolRate {
+            provider,
+            rate: p.rate.into(),
+            rate_base: p.rate_base.into(),
+            updated: p.rate_updated.into(),
+        })
+    }
+
+    /// Fetches the `token` pool exchange rate from the pool rate provider. Can be called by
+    /// anyone, eg by a croncat task. Returns the new rate, or None if the call failed or the
+    /// rate changed by more than `MAX_RATE_CHANGE_BPS` per `RATE_CHANGE_PERIOD` since the
+    /// last update.
+    pub fn update_rate(&mut self, token: AccountId) -> Promise {
+        let p = self.get_pool(&token);
+        let provider = p.rate_provider.expect(ERR122_NO_RATE_PROVIDER);
+        let gas = self.gas_for(GasOp::RateProvider);
+        let call = match provider.method {
+            RateMethod::GetStNearPrice => {
+                ext_rate_provider::get_st_near_price(&provider.contract, 0, gas)
+            }
+            RateMethod::FtPrice => ext_rate_provider::ft_price(&provider.contract, 0, gas),
+        };
+        call.then(ext_self::resolve_update_rate(
+            token,
+            &env::current_account_id(),
+            0,
+            self.gas_for(GasOp::ResolveUpdateRate),
+        ))
+    }
+
+    /// Callback for `update_rate`.
+    #[private]
+    pub fn resolve_update_rate(&mut self, token: AccountId) -> Option<U128> {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Contract expected a result on the callback"
+        );
+        let rate = match env::promise_result(0) {
+            PromiseResult::Successful(data) => {
+                near_sdk::serde_json::from_slice::<U128>(&data).ok()?.0
+            }
+            _ => return None,
+        };
+        let mut p = self.get_pool(&token);
+        p.rate_provider.as_ref()?;
+        let elapsed = env::block_timestamp() - p.rate_updated;
+        if rate == 0 || (p.rate > 0 && !rate_change_allowed(p.rate, rate, elapsed)) {
+            event_log!(
+                self,
+                "Pool {} rate update rejected: {} -> {}",
+                token,
+                p.rate,
+                rate
+            );
+            return None;
+        }
+        if p.rate_base == 0 {
+            p.rate_base = rate;
+        }
+        p.rate = rate;
+        p.rate_updated = env::block_timestamp();
+        self.set_pool(&token, &p);
+        Some(rate.into())
+    }
+}
+
+/// Returns true if `new` differs from `old` by at most `MAX_RATE_CHANGE_BPS` per
+/// `RATE_CHANGE_PERIOD` of the `elapsed` nanoseconds, and at least `MAX_RATE_CHANGE_BPS`, so
+/// the rate catches up after a long gap between the updates.
+fn rate_change_allowed(old: Balance, new: Balance, elapsed: u64) -> bool {
+    let diff = if new > old { new - old } else { old - new };
+    let elapsed = elapsed.max(RATE_CHANGE_PERIOD);
+    u256::from(diff) * 10_000 * u256::from(RATE_CHANGE_PERIOD)
+        <= u256::from(old) * u256::from(MAX_RATE_CHANGE_BPS) * u256::from(elapsed)
+}
@@ -0,0 +1,103 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  VOLUME FEE REBATES
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance};
+
+use crate::constants::*;
+use crate::*;
+
+/// Rebate tier: accounts with a 30 day volume of at least `min_volume` yNEAR get
+/// `rebate_bps` basis points of their swap fees accrued back.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub struct RebateTier {
+    pub min_volume: U128,
+    pub rebate_bps: u16,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: sets the rebate tiers, ordered by strictly increasing `min_volume`. An empty
+    /// list disables rebates. Already accrued rebates are not affected.
+    pub fn set_rebate_tiers(&mut self, tiers: Vec<RebateTier>) {
+        self.assert_owner();
+        assert!(
+            tiers.len() <= MAX_REBATE_TIERS
+                && tiers.iter().all(|t| t.rebate_bps <= 10_000)
+                && tiers.windows(2).all(|w| w[0].min_volume.0 < w[1].min_volume.0),
+            "{}",
+            ERR38_INVALID_REBATE_TIERS
+        );
+        self.log_admin_action("set_rebate_tiers", &tiers);
+        self.rebate_tiers = tiers;
+    }
+
+    pub fn rebate_tiers(&self) -> Vec<RebateTier> {
+        self.rebate_tiers.clone()
+    }
+
+    /// Adds the attached NEAR to the fund paying the rebate claims.
+    #[payable]
+    pub fn fund_rebates(&mut self) {
+        let amount = env::attached_deposit();
+        assert!(amount > 0, "{}", ERR02_POSITIVE_ARGS);
+        self.rebate_fund += amount;
+        event_log!(self, "Rebate fund increased by {}", amount);
+    }
+
+    /// Returns the NEAR available to pay rebate claims.
+    pub fn rebate_fund(&self) -> U128 {
+        self.rebate_fund.into()
+    }
+
+    /// Returns the `account` 30 day volume, in yNEAR, used to select the rebate tier.
+    pub fn trader_volume_30d(&self, account: AccountId) -> U128 {
+        self.trader_stats(account)
+            .volume_30d(env::block_timestamp())
+            .into()
+    }
+
+    /**
+    Moves the caller accrued rebate to the caller NEAR deposit. If the rebate fund is
+    smaller than the accrued rebate, only the fund is claimed and the rest stays accrued.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the claimed amount. */
+    #[payable]
+    pub fn claim_rebate(&mut self) -> U128 {
+        assert_one_yocto();
+        let user = env::predecessor_account_id();
+        let mut s = self.trader_stats(user.clone());
+        let amount = s.rebate.0.min(self.rebate_fund);
+        assert!(amount > 0, "{}", ERR39_NO_REBATE);
+        s.rebate = (s.rebate.0 - amount).into();
+        self.trader_stats.insert(&user, &s);
+        self.rebate_fund -= amount;
+
+        let mut d = self.get_deposit(&user);
+        d.add_near(amount);
+        self.deposits.insert(&user, &d.into());
+        event_log!(self, "Rebate claimed: {}", amount);
+        amount.into()
+    }
+}
+
+impl NearSwap {
+    /// Returns the rebate, in yNEAR, for paying `fee` by an account with `volume_30d`.
+    pub(crate) fn calc_rebate(&self, volume_30d: Balance, fee: Balance) -> Balance {
+        match self
+            .rebate_tiers
+            .iter()
+            .rev()
+            .find(|t| volume_30d >= t.min_volume.0)
+        {
+            Some(t) => clp_math::mul_div_floor(fee, t.rebate_bps as u128, 10_000),
+            None => 0,
+        }
+    }
+}
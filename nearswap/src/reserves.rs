@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  RESERVE VERIFICATION
+***********************/
+
+use std::convert::TryInto;
+
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance, Gas, Promise, PromiseResult};
+
+use crate::ft_token::*;
+use crate::*;
+
+/// Amount of gas for the `verify_reserves` callback.
+pub const GAS_FOR_RESOLVE_VERIFY_RESERVES: Gas = 10 * TGAS;
+
+/// Result of `verify_reserves`.
+#[derive(Serialize, Deserialize)]
+pub struct ReservesReport {
+    /// contract balance reported by the token `ft_balance_of`.
+    pub balance: U128,
+    /// tokens the contract should hold: pool reserves, deposits, escrows and claimable
+    /// failed transfers, see `recorded_token_balance`.
+    pub recorded: U128,
+    /// pool token reserve.
+    pub pool_tokens: U128,
+    /// true when `balance < recorded`.
+    pub underfunded: bool,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Queries the `token` contract balance of this contract (`ft_balance_of`) and compares it
+    with the recorded token balance. A discrepancy is logged. When the contract holds less
+    than recorded and `pause_if_underfunded` is set, the `token` pool is paused until the
+    owner calls `unpause_pool`. Can be called by anyone.
+    Returns the `ReservesReport`, or None if the balance query failed. */
+    pub fn verify_reserves(&mut self, token: AccountId, pause_if_underfunded: bool) -> Promise {
+        self.get_pool(&token);
+        ext_fungible_token::ft_balance_of(
+            env::current_account_id().try_into().unwrap(),
+            &token,
+            0,
+            self.gas_for(GasOp::FtBalanceOf),
+        )
+        .then(ext_self::resolve_verify_reserves(
+            token,
+            pause_if_underfunded,
+            &env::current_account_id(),
+            0,
+            self.gas_for(GasOp::ResolveVerifyReserves),
+        ))
+    }
+
+    /// Callback for `verify_reserves`.
+    #[private]
+    pub fn resolve_verify_reserves(
+        &mut self,
+        token: AccountId,
+        pause_if_underfunded: bool,
+    ) -> Option<ReservesReport> {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Contract expected a result on the callback"
+        );
+        let balance = match env::promise_result(0) {
+            PromiseResult::Successful(data) => {
+                near_sdk::serde_json::from_slice::<U128>(&data).ok()?.0
+            }
+            _ => return None,
+        };
+        let recorded = self.token_balances.get(&token).unwrap_or(0);
+        let mut p = self.get_pool(&token);
+        if balance != recorded {
+            event_log!(
+                self,
+                "Reserves discrepancy {}: balance {}, recorded {}, pool reserve {}",
+                token,
+                balance,
+                recorded,
+                p.tokens
+            );
+        }
+        let underfunded = balance < recorded;
+        if underfunded && pause_if_underfunded && !p.paused {
+            p.paused = true;
+            self.set_pool(&token, &p);
+            self.log_admin_action("pause_underfunded_pool", &token);
+            event_log!(self, "Pool {} paused: underfunded", token);
+        }
+        Some(ReservesReport {
+            balance: balance.into(),
+            recorded: recorded.into(),
+            pool_tokens: p.tokens.into(),
+            underfunded,
+        })
+    }
+
+    /// Returns the amount of `token` the contract should hold, recorded by the transfers in
+    /// and out of the contract.
+    pub fn recorded_token_balance(&self, token: AccountId) -> U128 {
+        self.token_balances.get(&token).unwrap_or(0).into()
+    }
+}
+
+impl NearSwap {
+    /// Records `amount` of `token` received by the contract.
+    pub(crate) fn record_token_in(&mut self, token: &AccountId, amount: Balance) {
+        let b = self.token_balances.get(token).unwrap_or(0);
+        self.token_balances.insert(token, &(b + amount));
+    }
+
+    /// Records `amount` of `token` sent by the contract.
+    pub(crate) fn record_token_out(&mut self, token: &AccountId, amount: Balance) {
+        let b = self.token_balances.get(token).unwrap_or(0);
+        self.token_balances.insert(token, &b.saturating_sub(amount));
+    }
+}
@@ -0,0 +1,208 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  RFQ QUOTES
+***********************/
+
+use std::convert::{TryFrom, TryInto};
+
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base58PublicKey, Base64VecU8, ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance, CryptoHash};
+
+use crate::*;
+
+/// Firm quote signed off-chain by an RFQ maker, see `swap_rfq`.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+pub struct RfqQuote {
+    pub maker: AccountId,
+    /// account allowed to take the quote. Anyone if not set.
+    pub taker: Option<AccountId>,
+    /// `None` token denotes NEAR.
+    pub token_in: Option<AccountId>,
+    pub token_out: Option<AccountId>,
+    pub amount_in: U128,
+    pub amount_out: U128,
+    /// quote expiration time, in nanoseconds.
+    pub expiry: U64,
+    /// distinguishes otherwise identical quotes. Every quote can be taken only once.
+    pub nonce: U64,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: sets the ed25519 public key used by the `maker` to sign RFQ quotes. `None`
+    /// removes the maker. Inventory of a removed maker can still be withdrawn.
+    pub fn set_rfq_maker(&mut self, maker: ValidAccountId, key: Option<Base58PublicKey>) {
+        self.assert_owner();
+        let maker: AccountId = maker.into();
+        match key {
+            Some(k) => {
+                // the first byte is the curve type: 0 for ed25519.
+                assert!(k.0.len() == 33 && k.0[0] == 0, "{}", ERR96_INVALID_MAKER_KEY);
+                self.rfq_makers.insert(&maker, &k.0[1..].to_vec());
+            }
+            None => {
+                self.rfq_makers.remove(&maker);
+            }
+        }
+        self.log_admin_action("set_rfq_maker", &maker);
+    }
+
+    pub fn rfq_makers(&self) -> Vec<(AccountId, Base58PublicKey)> {
+        self.rfq_makers
+            .iter()
+            .map(|(m, k)| {
+                let mut key = vec![0];
+                key.extend(k);
+                (m, Base58PublicKey(key))
+            })
+            .collect()
+    }
+
+    /// RFQ maker: moves `amount` of `token` (`None` for NEAR) from the caller deposit to the
+    /// caller RFQ inventory, which pays out the caller quotes.
+    /// Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    #[payable]
+    pub fn rfq_deposit(&mut self, token: Option<AccountId>, amount: U128) {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let maker = env::predecessor_account_id();
+        assert!(
+            self.rfq_makers.get(&maker).is_some(),
+            "{}",
+            ERR91_NOT_RFQ_MAKER
+        );
+        assert!(amount.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        let mut d = self.get_deposit(&maker);
+        d.remove_asset(&token, amount.0);
+        self.deposits.insert(&maker, &d.into());
+        self.add_inventory(&maker, &token, amount.0);
+        self.unsafe_storage_check(start_storage);
+    }
+
+    /// Moves `amount` of `token` (`None` for NEAR) from the caller RFQ inventory back to the
+    /// caller deposit. Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    #[payable]
+    pub fn rfq_withdraw(&mut self, token: Option<AccountId>, amount: U128) {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let maker = env::predecessor_account_id();
+        self.remove_inventory(&maker, &token, amount.0);
+        let mut d = self.get_deposit(&maker);
+        d.add_asset(&token, amount.0);
+        self.deposits.insert(&maker, &d.into());
+        self.unsafe_storage_check(start_storage);
+    }
+
+    /// Returns the `maker` RFQ inventory of `token` (`None` for NEAR).
+    pub fn rfq_inventory(&self, maker: AccountId, token: Option<AccountId>) -> U128 {
+        self.rfq_inventory.get(&(maker, token)).unwrap_or(0).into()
+    }
+
+    /**
+    Swaps `quote.amount_in` of `quote.token_in` from the caller deposit at the price signed
+    by the quote maker: the caller receives `quote.amount_out` of `quote.token_out` from the
+    maker RFQ inventory, and the maker inventory receives the sold amount.
+    The maker signs (ed25519) the sha256 hash of the borsh serialized
+    `(contract account id, quote)`. A quote can be taken only once.
+    An expired quote falls back to a regular pool swap of `quote.amount_in`.
+    Panics if the caller would receive less than `min_out`.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the amount bought. */
+    #[payable]
+    pub fn swap_rfq(&mut self, quote: RfqQuote, signature: Base64VecU8, min_out: U128) -> U128 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let amount_in = quote.amount_in.0;
+        assert!(amount_in > 0 && min_out.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        let user = env::predecessor_account_id();
+        if env::block_timestamp() >= quote.expiry.0 {
+            let out =
+                self.internal_swap(&user, &quote.token_in, &quote.token_out, amount_in, min_out.0);
+            self.unsafe_storage_check(start_storage);
+            return out.into();
+        }
+
+        assert!(!self.paused, "{}", ERR80_PAUSED);
+        assert!(
+            quote.token_in != quote.token_out,
+            "{}",
+            ERR28_INVALID_SWAP_ASSETS
+        );
+        if let Some(taker) = &quote.taker {
+            assert!(taker == &user, "{}", ERR94_NOT_QUOTE_TAKER);
+        }
+        let hash = self.verify_quote(&quote, &signature.0);
+        assert!(self.rfq_used_quotes.insert(&hash), "{}", ERR93_QUOTE_USED);
+        let out = quote.amount_out.0;
+        assert!(
+            out >= min_out.0,
+            "{} (out={}, min={})",
+            ERR25_MIN_AMOUNT,
+            out,
+            min_out.0
+        );
+
+        let mut d = self.get_deposit(&user);
+        d.remove_asset(&quote.token_in, amount_in);
+        d.add_asset(&quote.token_out, out);
+        self.deposits.insert(&user, &d.into());
+        self.add_inventory(&quote.maker, &quote.token_in, amount_in);
+        self.remove_inventory(&quote.maker, &quote.token_out, out);
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "RFQ quote {} of {} taken by {}: sold {} for {}",
+            quote.nonce.0,
+            quote.maker,
+            user,
+            amount_in,
+            out
+        );
+        out.into()
+    }
+}
+
+impl NearSwap {
+    /// Panics if the `signature` of the quote is not valid for the quote maker key.
+    /// Returns the signed hash.
+    fn verify_quote(&self, q: &RfqQuote, signature: &[u8]) -> CryptoHash {
+        let key = self.rfq_makers.get(&q.maker).expect(ERR91_NOT_RFQ_MAKER);
+        let mut msg = env::current_account_id().try_to_vec().unwrap();
+        msg.extend(q.try_to_vec().unwrap());
+        let hash: CryptoHash = env::sha256(&msg).try_into().unwrap();
+        let valid = match (PublicKey::from_bytes(&key), Signature::try_from(signature)) {
+            (Ok(k), Ok(s)) => k.verify(&hash, &s).is_ok(),
+            _ => false,
+        };
+        assert!(valid, "{}", ERR92_INVALID_QUOTE_SIGNATURE);
+        hash
+    }
+
+    fn add_inventory(&mut self, maker: &AccountId, token: &Option<AccountId>, amount: Balance) {
+        let key = (maker.clone(), token.clone());
+        let b = self.rfq_inventory.get(&key).unwrap_or(0);
+        self.rfq_inventory.insert(&key, &(b + amount));
+    }
+
+    fn remove_inventory(&mut self, maker: &AccountId, token: &Option<AccountId>, amount: Balance) {
+        let key = (maker.clone(), token.clone());
+        let b = self.rfq_inventory.get(&key).unwrap_or(0);
+        assert!(
+            b >= amount,
+            "{} (needed {}, available {})",
+            ERR95_NOT_ENOUGH_INVENTORY,
+            amount,
+            b
+        );
+        if b == amount {
+            self.rfq_inventory.remove(&key);
+        } else {
+            self.rfq_inventory.insert(&key, &(b - amount));
+        }
+    }
+}
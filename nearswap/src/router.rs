@@ -0,0 +1,514 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  MULTI-SWAP OPERATIONS
+***********************/
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance};
+
+use crate::constants::*;
+use crate::*;
+
+/// A deposit swap of `amount_in` of `token_in` for at least `min_out` of `token_out`.
+/// `None` token denotes NEAR.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SwapAction {
+    pub token_in: Option<AccountId>,
+    pub amount_in: U128,
+    pub token_out: Option<AccountId>,
+    pub min_out: U128,
+}
+
+/// Target weight, in basis points, of an asset in a `rebalance`. `None` token denotes NEAR.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TargetWeight {
+    pub token: Option<AccountId>,
+    pub weight_bps: u16,
+}
+
+/// Swap executed by a multi-swap operation. `None` token denotes NEAR.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExecutedSwap {
+    pub token_in: Option<AccountId>,
+    pub amount_in: U128,
+    pub token_out: Option<AccountId>,
+    pub amount_out: U128,
+}
+
+/// Swap quote returned by `best_quote`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SwapQuote {
+    pub amount_out: U128,
+    /// assets the swap goes through, from `token_in` to `token_out`. `None` denotes NEAR.
+    pub route: Vec<Option<AccountId>>,
+    /// difference between the spot price and the quote price (including the swap fees), in
+    /// basis points of the spot price output.
+    pub price_impact_bps: u16,
+    /// `amount_out` reduced by the requested slippage, to be used as the swap `min_out`.
+    pub min_out: U128,
+}
+
+/// Swap route returned by `find_route`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SwapRoute {
+    /// assets the swap goes through, to be passed to `swap_path`. `None` denotes NEAR.
+    pub path: Vec<Option<AccountId>>,
+    /// amount bought by the swap through the pools, without the order book fills.
+    pub amount_out: U128,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Executes up to `MAX_BATCH_SWAPS` independent deposit swaps in order. Each swap must buy
+    at least its `min_out` (with 0, the default max slippage applies, see
+    `set_default_max_slippage`), otherwise the whole batch fails. Panics if the `deadline`
+    expired.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the amounts bought by each swap. */
+    #[payable]
+    pub fn batch_swap(
+        &mut self,
+        swaps: Vec<SwapAction>,
+        deadline: Option<Deadline>,
+    ) -> Vec<U128> {
+        let _m = MethodMetrics::start("batch_swap");
+        assert_one_yocto();
+        assert_deadline(&deadline);
+        let start_storage = env::storage_usage();
+        assert!(
+            !swaps.is_empty() && swaps.len() <= MAX_BATCH_SWAPS,
+            "{}",
+            ERR50_INVALID_BATCH_SIZE
+        );
+        let user = env::predecessor_account_id();
+        let outs: Vec<U128> = swaps
+            .iter()
+            .map(|s| {
+                assert!(s.amount_in.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+                assert!(s.token_in != s.token_out, "{}", ERR28_INVALID_SWAP_ASSETS);
+                let min_out =
+                    self.min_out_or_default(&s.token_in, &s.token_out, s.amount_in.0, s.min_out.0);
+                self.internal_swap(&user, &s.token_in, &s.token_out, s.amount_in.0, min_out)
+                    .into()
+            })
+            .collect();
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Batch swap: {}",
+            swaps
+                .iter()
+                .zip(outs.iter())
+                .map(|(s, out)| format!(
+                    "{} {} -> {} {}",
+                    s.amount_in.0,
+                    s.token_in.as_deref().unwrap_or("yNEAR"),
+                    out.0,
+                    s.token_out.as_deref().unwrap_or("yNEAR")
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        outs
+    }
+
+    /**
+    Swaps `amount_in` of `token_in` from the caller deposit for at least `min_out` of
+    `token_out` (`None` denotes NEAR; with `min_out = 0`, the default max slippage applies)
+    and distributes the bought amount to the deposits of the `recipients`, pro rata to their
+    basis points, which must sum to 10000. The rounding remainder goes to the first
+    recipient. Every recipient must have a storage deposit, which pays for a new deposit
+    token. Panics if the `deadline` expired.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the amounts credited to each recipient. */
+    #[payable]
+    pub fn swap_split(
+        &mut self,
+        token_in: Option<AccountId>,
+        token_out: Option<AccountId>,
+        amount_in: U128,
+        min_out: U128,
+        recipients: Vec<(ValidAccountId, u16)>,
+        deadline: Option<Deadline>,
+    ) -> Vec<U128> {
+        let _m = MethodMetrics::start("swap_split");
+        let recipients: Vec<(AccountId, u16)> =
+            recipients.into_iter().map(|(a, bps)| (a.into(), bps)).collect();
+        assert_one_yocto();
+        assert_deadline(&deadline);
+        let start_storage = env::storage_usage();
+        assert!(amount_in.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        assert!(token_in != token_out, "{}", ERR28_INVALID_SWAP_ASSETS);
+        assert!(
+            !recipients.is_empty()
+                && recipients.len() <= MAX_SPLIT_RECIPIENTS
+                && recipients.iter().map(|r| r.1 as u32).sum::<u32>() == 10_000,
+            "{}",
+            ERR115_INVALID_SPLIT
+        );
+        for (i, r) in recipients.iter().enumerate() {
+            assert!(
+                r.1 > 0 && recipients[..i].iter().all(|o| o.0 != r.0),
+                "{}",
+                ERR115_INVALID_SPLIT
+            );
+        }
+
+        let user = env::predecessor_account_id();
+        let min_out = self.min_out_or_default(&token_in, &token_out, amount_in.0, min_out.0);
+        let out = self.internal_swap(&user, &token_in, &token_out, amount_in.0, min_out);
+        let mut d = self.get_deposit(&user);
+        d.remove_asset(&token_out, out);
+        self.deposits.insert(&user, &d.into());
+        self.unsafe_storage_check(start_storage);
+
+        let mut amounts = vec![0; recipients.len()];
+        let mut left = out;
+        for (i, (account, bps)) in recipients.iter().enumerate().rev() {
+            let x = if i == 0 {
+                left
+            } else {
+                clp_math::mul_div_floor(out, *bps as u128, 10_000)
+            };
+            left -= x;
+            amounts[i] = x;
+            let start_storage = env::storage_usage();
+            let mut d = self.get_deposit(account);
+            d.add_asset(&token_out, x);
+            self.deposits.insert(account, &d.into());
+            // a new deposit token is paid by the recipient.
+            let mut d = self.get_deposit(account);
+            d.update_storage(start_storage);
+            self.deposits.insert(account, &d.into());
+        }
+        event_log!(
+            self,
+            "Split swap: {} {} -> {} {} to {}",
+            amount_in.0,
+            token_in.as_deref().unwrap_or("yNEAR"),
+            out,
+            token_out.as_deref().unwrap_or("yNEAR"),
+            recipients
+                .iter()
+                .zip(amounts.iter())
+                .map(|(r, x)| format!("{} {}", r.0, x))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        amounts.into_iter().map(U128).collect()
+    }
+
+    /**
+    Rebalances the caller deposit of the `targets` assets to their target weights, valued at
+    the pools spot prices. Overweight tokens are sold for NEAR, then NEAR is used to buy
+    the underweight tokens. Assets not in `targets` are not changed. The deposit NEAR is
+    rebalanced only if it's in the `targets`.
+    Every swap output must not be worse than the spot price by more than `max_slippage_bps`
+    basis points. Weights must sum to 10000. Panics if the `deadline` expired.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the executed swaps. */
+    #[payable]
+    pub fn rebalance(
+        &mut self,
+        targets: Vec<TargetWeight>,
+        max_slippage_bps: u16,
+        deadline: Option<Deadline>,
+    ) -> Vec<ExecutedSwap> {
+        let _m = MethodMetrics::start("rebalance");
+        assert_one_yocto();
+        assert_deadline(&deadline);
+        let start_storage = env::storage_usage();
+        assert!(max_slippage_bps <= 10_000, "{}", ERR36_INVALID_SLIPPAGE);
+        assert!(
+            !targets.is_empty()
+                && targets.len() <= MAX_BATCH_SWAPS
+                && targets.iter().map(|t| t.weight_bps as u32).sum::<u32>() == 10_000,
+            "{}",
+            ERR51_INVALID_TARGET_WEIGHTS
+        );
+        for (i, t) in targets.iter().enumerate() {
+            assert!(
+                targets[..i].iter().all(|o| o.token != t.token),
+                "{}",
+                ERR51_INVALID_TARGET_WEIGHTS
+            );
+        }
+
+        let user = env::predecessor_account_id();
+        let d = self.get_deposit(&user);
+        // (amount, value in yNEAR) of each target asset.
+        let holdings: Vec<(Balance, Balance)> = targets
+            .iter()
+            .map(|t| match &t.token {
+                Some(token) => {
+                    let amount = d.tokens.get(token).cloned().unwrap_or(0);
+                    (amount, self.spot_out(&t.token, &None, amount))
+                }
+                None => {
+                    let amount = d.ynear.saturating_sub(d.storage_usage());
+                    (amount, amount)
+                }
+            })
+            .collect();
+        let total: Balance = holdings.iter().map(|h| h.1).sum();
+        let target_value =
+            |t: &TargetWeight| clp_math::mul_div_floor(total, t.weight_bps as u128, 10_000);
+
+        let mut swaps = Vec::new();
+        let mut budget: Balance = 0;
+        let mut deficits: Vec<(AccountId, Balance)> = Vec::new();
+        for (t, (amount, value)) in targets.iter().zip(holdings.into_iter()) {
+            let target = target_value(t);
+            match &t.token {
+                None => budget += value.saturating_sub(target),
+                Some(_) if value > target => {
+                    let sell = clp_math::mul_div_floor(amount, value - target, value);
+                    let s = self.swap_at_spot(&user, &t.token, &None, sell, max_slippage_bps);
+                    if let Some(s) = s {
+                        budget += s.amount_out.0;
+                        swaps.push(s);
+                    }
+                }
+                Some(token) if value < target => deficits.push((token.clone(), target - value)),
+                Some(_) => {}
+            }
+        }
+
+        // spend the NEAR budget pro rata to the deficits.
+        let total_deficit: Balance = deficits.iter().map(|d| d.1).sum();
+        let spend = budget.min(total_deficit);
+        for (token, deficit) in deficits {
+            let ynear = clp_math::mul_div_floor(spend, deficit, total_deficit);
+            let token_out = Some(token);
+            if let Some(s) = self.swap_at_spot(&user, &None, &token_out, ynear, max_slippage_bps) {
+                swaps.push(s);
+            }
+        }
+        self.unsafe_storage_check(start_storage);
+        event_log!(self, "Rebalance: {} swaps", swaps.len());
+        swaps
+    }
+
+    /**
+    Redeems `shares` of the `token` pool and swaps both redeemed assets into `want_token`
+    (`None` for NEAR), which can be the pool token, NEAR or another token (routed through
+    NEAR). The result is credited to the caller deposit.
+    Panics if the total amount of `want_token` is smaller than `min_out` or the `deadline`
+    expired.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the amount of `want_token` received. */
+    #[payable]
+    pub fn zap_out(
+        &mut self,
+        token: AccountId,
+        shares: U128,
+        want_token: Option<AccountId>,
+        min_out: U128,
+        deadline: Option<Deadline>,
+    ) -> U128 {
+        let _m = MethodMetrics::start("zap_out");
+        assert_one_yocto();
+        assert_deadline(&deadline);
+        let start_storage = env::storage_usage();
+        let shares: Balance = shares.into();
+        assert!(shares > 0 && min_out.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        if let Some(t) = &want_token {
+            assert!(self.pools.get(t).is_some(), "Pool for this token doesn't exist");
+        }
+        let user = env::predecessor_account_id();
+        let mut p = self.get_pool(&token);
+        let owned = p.shares.get(&user).unwrap_or(0);
+        assert!(
+            owned >= shares,
+            "{} (owned {}, requested {})",
+            ERR11_NOT_ENOUGH_SHARES,
+            owned,
+            shares
+        );
+        let (NearAmount(ynear), TokenAmount(tokens)) =
+            p.withdraw_liquidity(&user, NearAmount(1), TokenAmount(1), Shares(shares));
+        self.record_liquidity_event(
+            &user,
+            &token,
+            LiquidityEventKind::Withdraw,
+            (ynear, tokens, shares),
+            &p,
+        );
+        self.set_pool(&token, &p);
+        let mut d = self.get_deposit(&user);
+        d.add(&token, tokens);
+        d.add_near(ynear);
+        self.deposits.insert(&user, &d.into());
+
+        let pool_token = Some(token.clone());
+        let out = if want_token == pool_token {
+            tokens + self.internal_swap(&user, &None, &want_token, ynear, 0)
+        } else if want_token.is_none() {
+            ynear + self.internal_swap(&user, &pool_token, &None, tokens, 0)
+        } else {
+            self.internal_swap(&user, &None, &want_token, ynear, 0)
+                + self.internal_swap(&user, &pool_token, &want_token, tokens, 0)
+        };
+        assert!(
+            out >= min_out.0,
+            "{} (out={}, min={})",
+            ERR25_MIN_AMOUNT,
+            out,
+            min_out.0
+        );
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Zap out: {} {} shares for {} {}",
+            shares,
+            token,
+            out,
+            want_token.as_deref().unwrap_or("yNEAR")
+        );
+        out.into()
+    }
+
+    /**
+    Quotes a swap of `amount_in` of `token_in` for `token_out` (`None` denotes NEAR) in one
+    call: the bought amount, the route, the price impact and the `min_out` to use with a
+    `slippage_bps` slippage tolerance. Every pool pairs a token with NEAR, so the best (and
+    only) route is the direct pool for NEAR swaps and the NEAR hop for token to token swaps.
+    Pools have a single fee tier. Order book orders filled by the swap can only improve the
+    bought amount. */
+    pub fn best_quote(
+        &self,
+        token_in: Option<AccountId>,
+        token_out: Option<AccountId>,
+        amount_in: U128,
+        slippage_bps: u16,
+    ) -> SwapQuote {
+        assert!(slippage_bps <= 10_000, "{}", ERR36_INVALID_SLIPPAGE);
+        let out = self.internal_quote(&token_in, &token_out, amount_in.0);
+        let spot_out = self.spot_out(&token_in, &token_out, amount_in.0);
+        let price_impact_bps = if spot_out > out {
+            clp_math::mul_div_ceil(spot_out - out, 10_000, spot_out) as u16
+        } else {
+            0
+        };
+        SwapQuote {
+            amount_out: out.into(),
+            route: route_path(&token_in, &token_out),
+            price_impact_bps,
+            min_out: clp_math::mul_div_floor(out, 10_000 - slippage_bps as u128, 10_000).into(),
+        }
+    }
+
+    /**
+    Finds the best route of a swap of `amount_in` of `from` for `to` (`None` denotes NEAR)
+    and quotes it. Every pool pairs a token with NEAR and has a single fee tier, so the only
+    candidates are the direct pool for NEAR swaps and the NEAR hop for token to token swaps.
+    The examined pools are bounded, so the gas is. Returns None if a pool doesn't exist, is
+    empty or paused. The route `path` can be passed to `swap_path`. */
+    pub fn find_route(
+        &self,
+        from: Option<AccountId>,
+        to: Option<AccountId>,
+        amount_in: U128,
+    ) -> Option<SwapRoute> {
+        if from == to || amount_in.0 == 0 {
+            return None;
+        }
+        for t in from.iter().chain(to.iter()) {
+            let p = self.pools.get(t)?;
+            if p.ynear == 0 || p.tokens == 0 || p.paused || self.paused {
+                return None;
+            }
+        }
+        Some(SwapRoute {
+            path: route_path(&from, &to),
+            amount_out: self.internal_quote(&from, &to, amount_in.0).into(),
+        })
+    }
+
+    /**
+    Swaps `amount_in` of the first `path` asset from the caller deposit for at least
+    `min_out` (with 0, the default max slippage applies) of the last `path` asset, through
+    the `path` assets, see `find_route`. Panics if the path isn't a valid route or the
+    `deadline` expired.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the bought amount. */
+    #[payable]
+    pub fn swap_path(
+        &mut self,
+        path: Vec<Option<AccountId>>,
+        amount_in: U128,
+        min_out: U128,
+        deadline: Option<Deadline>,
+    ) -> U128 {
+        let _m = MethodMetrics::start("swap_path");
+        assert_one_yocto();
+        assert_deadline(&deadline);
+        let start_storage = env::storage_usage();
+        assert!(amount_in.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        let (token_in, token_out) = match (path.first(), path.last()) {
+            (Some(t1), Some(t2)) if t1 != t2 => (t1.clone(), t2.clone()),
+            _ => panic!("{}", ERR130_INVALID_PATH),
+        };
+        assert!(path == route_path(&token_in, &token_out), "{}", ERR130_INVALID_PATH);
+
+        let user = env::predecessor_account_id();
+        let min_out = self.min_out_or_default(&token_in, &token_out, amount_in.0, min_out.0);
+        let out = self.internal_swap(&user, &token_in, &token_out, amount_in.0, min_out);
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "Path swap: {} {} -> {} {}",
+            amount_in.0,
+            token_in.as_deref().unwrap_or("yNEAR"),
+            out,
+            token_out.as_deref().unwrap_or("yNEAR")
+        );
+        out.into()
+    }
+}
+
+impl NearSwap {
+    /// Swaps `amount_in` from the `user` deposit if it buys anything at the spot price. The
+    /// output must not be worse than the spot price by more than `max_slippage_bps`.
+    fn swap_at_spot(
+        &mut self,
+        user: &AccountId,
+        token_in: &Option<AccountId>,
+        token_out: &Option<AccountId>,
+        amount_in: Balance,
+        max_slippage_bps: u16,
+    ) -> Option<ExecutedSwap> {
+        if amount_in == 0 {
+            return None;
+        }
+        let spot_out = self.spot_out(token_in, token_out, amount_in);
+        let min_out = clp_math::mul_div_ceil(spot_out, 10_000 - max_slippage_bps as u128, 10_000);
+        if min_out == 0 {
+            return None;
+        }
+        let out = self.internal_swap(user, token_in, token_out, amount_in, min_out);
+        Some(ExecutedSwap {
+            token_in: token_in.clone(),
+            amount_in: amount_in.into(),
+            token_out: token_out.clone(),
+            amount_out: out.into(),
+        })
+    }
+}
+
+/// Returns the assets a `token_in` -> `token_out` swap goes through, see `swap_legs`.
+fn route_path(
+    token_in: &Option<AccountId>,
+    token_out: &Option<AccountId>,
+) -> Vec<Option<AccountId>> {
+    let mut path = vec![token_in.clone()];
+    if token_in.is_some() && token_out.is_some() {
+        path.push(None);
+    }
+    path.push(token_out.clone());
+    path
+}
@@ -0,0 +1,50 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  SLIPPAGE PROTECTION
+***********************/
+
+use near_sdk::{near_bindgen, AccountId, Balance};
+
+use crate::constants::*;
+use crate::*;
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: sets the default max slippage, in basis points, at most
+    /// `MAX_DEFAULT_SLIPPAGE_BPS`. Swaps called with a zero minimum out amount must buy at
+    /// least the spot price amount reduced by the default max slippage. 0 disables the
+    /// default: a zero minimum out amount is rejected (E2).
+    pub fn set_default_max_slippage(&mut self, bps: u16) {
+        self.assert_owner();
+        assert!(bps <= MAX_DEFAULT_SLIPPAGE_BPS, "{}", ERR128_INVALID_SLIPPAGE);
+        self.default_max_slippage_bps = bps;
+        self.log_admin_action("set_default_max_slippage", &bps);
+    }
+
+    pub fn default_max_slippage_bps(&self) -> u16 {
+        self.default_max_slippage_bps
+    }
+}
+
+impl NearSwap {
+    /// Returns `min_out`, or, when it's 0, the minimum out amount of a `amount_in` swap
+    /// derived from the spot price and the default max slippage. Must be called before the
+    /// swap. Panics when `min_out` is 0 and there is no default max slippage.
+    pub(crate) fn min_out_or_default(
+        &self,
+        token_in: &Option<AccountId>,
+        token_out: &Option<AccountId>,
+        amount_in: Balance,
+        min_out: Balance,
+    ) -> Balance {
+        if min_out > 0 {
+            return min_out;
+        }
+        let bps = self.default_max_slippage_bps;
+        assert!(bps > 0, "{}", ERR02_POSITIVE_ARGS);
+        let spot_out = self.spot_out(token_in, token_out, amount_in);
+        clp_math::mul_div_ceil(spot_out, 10_000 - bps as u128, 10_000).max(1)
+    }
+}
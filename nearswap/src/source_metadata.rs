@@ -0,0 +1,42 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  SOURCE METADATA (NEP-330)
+***********************/
+
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Contract source metadata, see NEP-330. Set at build time.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ContractSourceMetadata {
+    /// crate version.
+    pub version: Option<String>,
+    /// git commit of the source tree the contract was built from.
+    pub commit_hash: Option<String>,
+    /// source code repository.
+    pub link: Option<String>,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Returns the source version, commit and repository of the deployed contract.
+    pub fn contract_source_metadata(&self) -> ContractSourceMetadata {
+        ContractSourceMetadata {
+            version: non_empty(env!("CARGO_PKG_VERSION")),
+            commit_hash: non_empty(env!("NEARSWAP_COMMIT_HASH")),
+            link: non_empty(env!("CARGO_PKG_REPOSITORY")),
+        }
+    }
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.to_string())
+    }
+}
@@ -0,0 +1,300 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  POOL NEAR STAKING
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, ext_contract, near_bindgen, AccountId, Balance, Gas, Promise, PromiseResult};
+
+use crate::accumulator::RewardAcc;
+use crate::constants::*;
+use crate::*;
+
+/// Amount of gas for the staking pool `deposit_and_stake`, `unstake`, `withdraw` and
+/// `get_account_total_balance` calls.
+pub const GAS_FOR_STAKING_POOL_CALL: Gas = 25 * TGAS;
+
+/// Amount of gas for the staking pool calls callbacks.
+pub const GAS_FOR_RESOLVE_STAKING: Gas = 10 * TGAS;
+
+/// Staking pool contract interface (core contracts `staking-pool`).
+#[ext_contract(ext_staking_pool)]
+pub trait StakingPool {
+    fn deposit_and_stake(&mut self);
+
+    fn unstake(&mut self, amount: U128);
+
+    fn withdraw(&mut self, amount: U128);
+
+    fn get_account_total_balance(&self, account_id: AccountId) -> U128;
+}
+
+/// Staking pool call made by `rebalance_stake`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum StakingOp {
+    Stake,
+    Unstake,
+    Withdraw,
+}
+
+/// Delegation of the pools NEAR reserves to a validator staking pool.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Staking {
+    /// validator staking pool. `None` disables staking.
+    pub staking_pool: Option<AccountId>,
+    /// maximum staked part of every pool NEAR reserve, in basis points.
+    pub max_staked_bps: u16,
+    /// NEAR staked by all pools, including the compounded rewards.
+    pub total_staked: Balance,
+    /// NEAR unstaked by all pools, not withdrawn yet.
+    pub total_unstaking: Balance,
+    /// staking rewards per staked yNEAR, see `harvest_staking_rewards`.
+    pub reward_acc: RewardAcc,
+}
+
+/// Staking configuration returned by `staking_config`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct StakingConfig {
+    pub staking_pool: Option<AccountId>,
+    pub max_staked_bps: u16,
+    pub total_staked: U128,
+    pub total_unstaking: U128,
+}
+
+/// Pool NEAR reserve split returned by `pool_staking`. `ynear = liquid + staked + unstaking`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct PoolStakingInfo {
+    /// NEAR available for swaps and withdrawals.
+    pub liquid: U128,
+    /// staked NEAR, including the rewards not settled yet.
+    pub staked: U128,
+    pub unstaking: U128,
+    /// first epoch when the unstaked NEAR can be withdrawn.
+    pub unstake_available_epoch: U64,
+    /// staked amount `rebalance_stake` moves to.
+    pub target_staked: U128,
+    /// a staking pool call is in progress.
+    pub pending: bool,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Owner: sets the validator `staking_pool` and the maximum staked part of every pool NEAR
+    reserve, in basis points, at most `MAX_STAKED_BPS`. The staking pool can be changed only
+    when all NEAR is unstaked and withdrawn: set `max_staked_bps` to 0 and call
+    `rebalance_stake` on every pool first. */
+    pub fn set_staking_config(
+        &mut self,
+        staking_pool: Option<ValidAccountId>,
+        max_staked_bps: u16,
+    ) {
+        self.assert_owner();
+        let staking_pool: Option<AccountId> = staking_pool.map(|s| s.into());
+        assert!(max_staked_bps <= MAX_STAKED_BPS, "{}", ERR116_INVALID_STAKING_CONFIG);
+        assert!(
+            staking_pool == self.staking.staking_pool
+                || self.staking.total_staked + self.staking.total_unstaking == 0,
+            "{}",
+            ERR116_INVALID_STAKING_CONFIG
+        );
+        self.log_admin_action("set_staking_config", &(&staking_pool, max_staked_bps));
+        self.staking.staking_pool = staking_pool;
+        self.staking.max_staked_bps = max_staked_bps;
+    }
+
+    pub fn staking_config(&self) -> StakingConfig {
+        StakingConfig {
+            staking_pool: self.staking.staking_pool.clone(),
+            max_staked_bps: self.staking.max_staked_bps,
+            total_staked: self.staking.total_staked.into(),
+            total_unstaking: self.staking.total_unstaking.into(),
+        }
+    }
+
+    /// Returns the split of the `token` pool NEAR reserve between the liquid, staked and
+    /// unstaking NEAR.
+    pub fn pool_staking(&self, token: AccountId) -> PoolStakingInfo {
+        let p = self.get_pool(&token);
+        let rewards = self.staking.reward_acc.earned(p.staked_ynear, p.staking_checkpoint);
+        PoolStakingInfo {
+            liquid: p.liquid_ynear().into(),
+            staked: (p.staked_ynear + rewards).into(),
+            unstaking: p.unstaking_ynear.into(),
+            unstake_available_epoch: (p.unstake_epoch + NUM_EPOCHS_TO_UNLOCK).into(),
+            target_staked: self.target_staked(&p).into(),
+            pending: p.staking_pending,
+        }
+    }
+
+    /**
+    Moves the `token` pool staked NEAR towards `max_staked_bps` of the pool NEAR reserve with
+    a single staking pool call, in this order:
+    - when swaps and liquidity withdrawals reduced the reserve, the staked NEAR above the
+      target is unstaked;
+    - NEAR unstaked at least `NUM_EPOCHS_TO_UNLOCK` epochs ago is withdrawn back to the
+      pool liquid NEAR;
+    - liquid NEAR above the target is staked.
+    Changes smaller than `MIN_STAKING_CHANGE` are skipped. Only one call per pool can be in
+    progress. Can be called by anyone, eg by a croncat task.
+    Returns true if a staking pool call was scheduled. */
+    pub fn rebalance_stake(&mut self, token: AccountId) -> bool {
+        self.internal_rebalance_stake(&token).is_some()
+    }
+
+    /// Callback for the `rebalance_stake` staking pool calls. Reverts a failed stake and
+    /// records a successful unstake or withdraw.
+    #[private]
+    pub fn resolve_staking(&mut self, token: AccountId, op: StakingOp, amount: U128) -> bool {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Contract expected a result on the callback"
+        );
+        let ok = matches!(env::promise_result(0), PromiseResult::Successful(_));
+        let amount = amount.0;
+        let mut p = self.get_pool(&token);
+        self.settle_staking_rewards(&mut p);
+        p.staking_pending = false;
+        match op {
+            // the attached deposit is refunded on failure.
+            StakingOp::Stake if !ok => {
+                p.staked_ynear -= amount;
+                self.staking.total_staked -= amount;
+            }
+            StakingOp::Unstake if ok => {
+                p.staked_ynear -= amount;
+                p.unstaking_ynear += amount;
+                p.unstake_epoch = env::epoch_height();
+                self.staking.total_staked -= amount;
+                self.staking.total_unstaking += amount;
+            }
+            StakingOp::Withdraw if ok => {
+                p.unstaking_ynear -= amount;
+                self.staking.total_unstaking -= amount;
+            }
+            _ => {}
+        }
+        self.set_pool(&token, &p);
+        event_log!(
+            self,
+            "Pool {} staking {:?} {} yNEAR: {}",
+            token,
+            op,
+            amount,
+            if ok { "done" } else { "failed" }
+        );
+        ok
+    }
+
+    /// Queries the contract balance in the staking pool and distributes the staking rewards
+    /// to the pools pro rata to their staked NEAR. Rewards are compounded: they stay staked
+    /// and are added to the pools NEAR reserves, so they are earned by the LPs pro rata to
+    /// their shares. Can be called by anyone.
+    pub fn harvest_staking_rewards(&mut self) -> Promise {
+        let staking_pool = self.staking.staking_pool.clone().expect(ERR117_STAKING_NOT_SET);
+        ext_staking_pool::get_account_total_balance(
+            env::current_account_id(),
+            &staking_pool,
+            0,
+            self.gas_for(GasOp::StakingPoolCall),
+        )
+        .then(ext_self::resolve_harvest_staking_rewards(
+            &env::current_account_id(),
+            0,
+            self.gas_for(GasOp::ResolveStaking),
+        ))
+    }
+
+    /// Callback for `harvest_staking_rewards`. Returns the distributed rewards.
+    #[private]
+    pub fn resolve_harvest_staking_rewards(&mut self) -> U128 {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "Contract expected a result on the callback"
+        );
+        let balance = match env::promise_result(0) {
+            PromiseResult::Successful(data) => {
+                near_sdk::serde_json::from_slice::<U128>(&data).map_or(0, |b| b.0)
+            }
+            _ => 0,
+        };
+        let s = &mut self.staking;
+        let rewards = balance.saturating_sub(s.total_staked + s.total_unstaking);
+        if rewards == 0 || !s.reward_acc.distribute(rewards, s.total_staked) {
+            return U128(0);
+        }
+        s.total_staked += rewards;
+        event_log!(self, "Staking rewards distributed: {} yNEAR", rewards);
+        rewards.into()
+    }
+}
+
+impl NearSwap {
+    /// Schedules the staking pool call of `rebalance_stake`, if any.
+    pub(crate) fn internal_rebalance_stake(&mut self, token: &AccountId) -> Option<Promise> {
+        let staking_pool = self.staking.staking_pool.clone()?;
+        let mut p = self.get_pool(token);
+        if p.staking_pending {
+            return None;
+        }
+        self.settle_staking_rewards(&mut p);
+        let target = self.target_staked(&p);
+        let (op, amount) = if p.staked_ynear >= target + MIN_STAKING_CHANGE {
+            (StakingOp::Unstake, p.staked_ynear - target)
+        } else if p.unstaking_ynear > 0
+            && env::epoch_height() >= p.unstake_epoch + NUM_EPOCHS_TO_UNLOCK
+        {
+            (StakingOp::Withdraw, p.unstaking_ynear)
+        } else if target >= p.staked_ynear + p.unstaking_ynear + MIN_STAKING_CHANGE {
+            (StakingOp::Stake, target - p.staked_ynear - p.unstaking_ynear)
+        } else {
+            self.set_pool(token, &p);
+            return None;
+        };
+
+        let gas = self.gas_for(GasOp::StakingPoolCall);
+        let call = match op {
+            StakingOp::Stake => {
+                // the NEAR leaves the contract now, so the stake is recorded upfront.
+                p.staked_ynear += amount;
+                self.staking.total_staked += amount;
+                ext_staking_pool::deposit_and_stake(&staking_pool, amount, gas)
+            }
+            StakingOp::Unstake => ext_staking_pool::unstake(amount.into(), &staking_pool, 0, gas),
+            StakingOp::Withdraw => ext_staking_pool::withdraw(amount.into(), &staking_pool, 0, gas),
+        };
+        p.staking_pending = true;
+        self.set_pool(token, &p);
+        Some(call.then(ext_self::resolve_staking(
+            token.clone(),
+            op,
+            amount.into(),
+            &env::current_account_id(),
+            0,
+            self.gas_for(GasOp::ResolveStaking),
+        )))
+    }
+
+    /// Credits the `p` pool part of the harvested staking rewards to its staked NEAR and its
+    /// NEAR reserve. Must be called before the pool staked NEAR changes.
+    pub(crate) fn settle_staking_rewards(&self, p: &mut Pool) {
+        let r = self.staking.reward_acc.settle(p.staked_ynear, &mut p.staking_checkpoint);
+        p.staked_ynear += r;
+        p.ynear += r;
+    }
+
+    /// Staked NEAR target of the `p` pool.
+    fn target_staked(&self, p: &Pool) -> Balance {
+        if self.staking.staking_pool.is_none() {
+            return 0;
+        }
+        clp_math::mul_div_floor(p.ynear, self.staking.max_staked_bps as u128, 10_000)
+    }
+}
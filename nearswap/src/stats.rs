@@ -0,0 +1,106 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  TRADER STATISTICS
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+
+use crate::constants::*;
+use crate::*;
+
+/// Cumulative trading statistics of an account. Has a fixed size, so the per account storage
+/// doesn't grow with the number of trades. Amounts are denominated in yNEAR: swaps are
+/// accounted by their NEAR leg and token fees are converted at the execution price.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Default)]
+pub struct TraderStats {
+    /// number of executed swaps.
+    pub swaps: u64,
+    /// swapped volume, in yNEAR.
+    pub volume: U128,
+    /// paid swap fees, in yNEAR.
+    pub fees: U128,
+    /// index of the current `REBATE_PERIOD`.
+    pub period: u64,
+    /// volume in the current period, in yNEAR.
+    pub period_volume: U128,
+    /// volume in the previous period, in yNEAR.
+    pub prev_period_volume: U128,
+    /// accrued fee rebate, in yNEAR, see `claim_rebate`.
+    pub rebate: U128,
+}
+
+impl TraderStats {
+    /// Moves the period volumes to the period of the `now` timestamp.
+    fn roll(&mut self, now: u64) {
+        let period = now / REBATE_PERIOD;
+        if period == self.period {
+            return;
+        }
+        self.prev_period_volume = if period == self.period + 1 {
+            self.period_volume
+        } else {
+            U128(0)
+        };
+        self.period_volume = U128(0);
+        self.period = period;
+    }
+
+    /// Returns the volume in the last `REBATE_PERIOD` (30 days) before `now`. The previous
+    /// period volume is assumed to be spread evenly, and is weighted by its part in the window.
+    pub fn volume_30d(&self, now: u64) -> Balance {
+        let mut s = Self {
+            period: self.period,
+            period_volume: self.period_volume,
+            prev_period_volume: self.prev_period_volume,
+            ..Default::default()
+        };
+        s.roll(now);
+        let elapsed = (now % REBATE_PERIOD) as u128;
+        let prev = clp_math::mul_div_floor(
+            s.prev_period_volume.0,
+            REBATE_PERIOD as u128 - elapsed,
+            REBATE_PERIOD as u128,
+        );
+        s.period_volume.0 + prev
+    }
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Returns the cumulative trading statistics of `account`.
+    pub fn trader_stats(&self, account: AccountId) -> TraderStats {
+        self.trader_stats.get(&account).unwrap_or_default()
+    }
+}
+
+impl NearSwap {
+    /// Records a swap leg of `user` with `volume` yNEAR traded and `fee` yNEAR paid, and
+    /// accrues the fee rebate of the user tier.
+    /// The caller is responsible for charging the storage of a new record.
+    pub(crate) fn record_trader_stats(&mut self, user: &AccountId, volume: Balance, fee: Balance) {
+        let now = env::block_timestamp();
+        let mut s = self.trader_stats.get(user).unwrap_or_default();
+        s.swaps += 1;
+        s.volume = (s.volume.0 + volume).into();
+        s.fees = (s.fees.0 + fee).into();
+        s.roll(now);
+        s.period_volume = (s.period_volume.0 + volume).into();
+        let rebate = self.calc_rebate(s.volume_30d(now), fee);
+        s.rebate = (s.rebate.0 + rebate).into();
+        self.trader_stats.insert(user, &s);
+    }
+}
+
+/// Converts a `fee` charged in tokens for a swap of `tokens_in` tokens for `ynear_out` yNEAR
+/// into yNEAR, at the swap execution price.
+pub(crate) fn token_fee_in_ynear(fee: Balance, tokens_in: Balance, ynear_out: Balance) -> Balance {
+    if tokens_in <= fee {
+        return 0;
+    }
+    clp_math::mul_div_floor(fee, ynear_out, tokens_in - fee)
+}
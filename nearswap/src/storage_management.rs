@@ -69,7 +69,13 @@ impl StorageManagement for NearSwap {
         // storage available
         let available = account_deposit.ynear - account_deposit.storage_usage();
         let amount = if let Some(a) = amount { a.0 } else { available };
-        assert!(amount <= available, "{}", ERR14_NOT_ENOUGH_NEAR_DEPOSITED);
+        assert!(
+            amount <= available,
+            "{} (requested {}, available {})",
+            ERR14_NOT_ENOUGH_NEAR_DEPOSITED,
+            amount,
+            available
+        );
         Promise::new(account_id.clone()).transfer(amount);
         self.storage_balance_of(account_id.try_into().unwrap())
             .unwrap()
@@ -134,13 +140,7 @@ mod tests {
             tokens: HashMap::new(),
         };
 
-        let mut near = NearSwap {
-            fee_dst: "owner".to_string(),
-            owner: "owner".to_string(),
-            pools: UnorderedMap::new(b"p".to_vec()),
-            deposits: LookupMap::new(b"d".to_vec()),
-            whitelisted_tokens: UnorderedSet::new(b"w".to_vec()),
-        };
+        let mut near = NearSwap::new("owner".to_string().try_into().unwrap());
         near.deposits.insert(&"owner".to_string(), &ac.into());
 
         return near;
@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  TESTNET DEMO HELPERS
+***********************/
+
+//! Compiled only with the `testnet` feature (`make build-testnet`), together with the
+//! `mock-token` faucet. Never deploy it to mainnet.
+
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::{env, near_bindgen, AccountId, Balance};
+
+use crate::*;
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Owner: creates the `token` demo pool, if it doesn't exist, and adds the attached NEAR
+    with up to `tokens` from the owner deposit as liquidity. The owner gets the `token` from
+    the token faucet and deposits it with `ft_transfer_call` beforehand. NEAR not added to
+    the pool stays in the owner deposit.
+    Returns the amount of minted shares. */
+    #[payable]
+    pub fn seed_demo_pool(&mut self, token: ValidAccountId, tokens: U128) -> U128 {
+        self.assert_owner();
+        let start_storage = env::storage_usage();
+        let token = AccountId::from(token);
+        let ynear: Balance = env::attached_deposit();
+        assert!(ynear > 0 && tokens.0 > 0, "{}", ERR02_POSITIVE_ARGS);
+        let mut p = match self.pools.get(&token) {
+            Some(p) => p,
+            None => {
+                self.pool_metadata.insert(
+                    &token,
+                    &PoolMetadata {
+                        creator: self.owner.clone(),
+                        description: "testnet demo pool".to_string(),
+                        reference: None,
+                        creator_campaigns_only: false,
+                    },
+                );
+                self.assign_pool_id(&token);
+                Pool::new(&token)
+            }
+        };
+        let caller = env::predecessor_account_id();
+        let mut d = self.get_deposit(&caller);
+        d.add_near(ynear);
+        let (NearAmount(ynear), TokenAmount(added_tokens), Shares(shares)) =
+            p.add_liquidity(&caller, NearAmount(ynear), TokenAmount(tokens.0), Shares(0));
+        d.remove(&token, added_tokens);
+        d.remove_near(ynear);
+        d.update_storage(start_storage);
+        self.deposits.insert(&caller, &d.into());
+        self.set_pool(&token, &p);
+        event_log!(
+            self,
+            "Demo pool {} seeded with {} yNEAR and {} tokens",
+            token,
+            ynear,
+            added_tokens
+        );
+        shares.into()
+    }
+}
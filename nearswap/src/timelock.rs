@@ -0,0 +1,135 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  GOVERNANCE TIMELOCK
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId};
+
+use crate::constants::*;
+use crate::*;
+
+/// Governance decision executed through the timelock queue.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum GovAction {
+    /// sets the `token` pool swap fee, see `propose_pool_fee`.
+    SetPoolFee { token: AccountId, fee_bps: u16 },
+}
+
+/// Governance action waiting in the timelock queue.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+pub struct QueuedAction {
+    pub action: GovAction,
+    /// proposal which approved the action.
+    pub proposal_id: u64,
+    /// earliest execution timestamp, in nanoseconds.
+    pub eta: U64,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: sets the guardian account, allowed to cancel queued governance actions and to
+    /// slash pool creation bonds.
+    pub fn set_guardian(&mut self, guardian: Option<ValidAccountId>) {
+        self.assert_owner();
+        self.log_admin_action("set_guardian", &guardian);
+        self.guardian = guardian.map(|a| a.into());
+    }
+
+    pub fn guardian(&self) -> Option<AccountId> {
+        self.guardian.clone()
+    }
+
+    /// Returns the queued governance actions, by id, ordered by the queuing time.
+    pub fn queued_actions(&self, from_index: u64, limit: u64) -> Vec<(u64, QueuedAction)> {
+        self.queued_actions
+            .iter()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .collect()
+    }
+
+    /**
+    Executes the queued governance action once its `eta` passed. Can be called by anyone.
+    A pool fee outside of the current `pool_fee_bounds` is not applied and its proposal is
+    rejected. */
+    pub fn execute(&mut self, id: u64) {
+        let a = self.queued_actions.get(&id).expect(ERR76_ACTION_NOT_FOUND);
+        assert!(
+            env::block_timestamp() >= a.eta.0,
+            "{} (eta {})",
+            ERR65_TIMELOCK_NOT_EXPIRED,
+            a.eta.0
+        );
+        self.queued_actions.remove(&id);
+        let status = match a.action {
+            GovAction::SetPoolFee { ref token, fee_bps } => {
+                self.pool_fee_proposals.remove(token);
+                if self.pool_fee_bounds.contains(fee_bps) {
+                    let mut p = self.get_pool(token);
+                    p.fee_bps = fee_bps;
+                    self.set_pool(token, &p);
+                    self.log_admin_action("set_pool_fee", &(token, fee_bps));
+                    ProposalStatus::Applied
+                } else {
+                    ProposalStatus::Rejected
+                }
+            }
+        };
+        self.set_proposal_status(a.proposal_id, status);
+        event_log!(self, "Queued action {} executed: {:?}", id, status);
+    }
+
+    /// Guardian: cancels a queued governance action. The approving proposal is cancelled.
+    /// The council can cancel queued actions with a `Veto` motion.
+    pub fn cancel_queued(&mut self, id: u64) {
+        self.assert_guardian();
+        self.cancel_action(id);
+        self.log_admin_action("cancel_queued", &id);
+    }
+}
+
+impl NearSwap {
+    pub(crate) fn assert_guardian(&self) {
+        assert!(
+            self.guardian.as_ref() == Some(&env::predecessor_account_id()),
+            "{}",
+            ERR77_NOT_GUARDIAN
+        );
+    }
+
+    /// Queues the `action` approved by the `proposal_id` proposal. It can be executed
+    /// `GOV_TIMELOCK` later. Returns the action id.
+    pub(crate) fn queue_action(&mut self, action: GovAction, proposal_id: u64) -> u64 {
+        let id = self.next_action;
+        self.next_action += 1;
+        let eta = env::block_timestamp() + GOV_TIMELOCK;
+        self.queued_actions.insert(
+            &id,
+            &QueuedAction {
+                action,
+                proposal_id,
+                eta: eta.into(),
+            },
+        );
+        event_log!(self, "Action {} queued, eta {}", id, eta);
+        id
+    }
+
+    /// Removes the queued action and cancels its proposal.
+    pub(crate) fn cancel_action(&mut self, id: u64) {
+        let a = self.queued_actions.remove(&id).expect(ERR76_ACTION_NOT_FOUND);
+        match a.action {
+            GovAction::SetPoolFee { ref token, .. } => {
+                self.pool_fee_proposals.remove(token);
+            }
+        }
+        self.set_proposal_status(a.proposal_id, ProposalStatus::Cancelled);
+        event_log!(self, "Queued action {} cancelled", id);
+    }
+}
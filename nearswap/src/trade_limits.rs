@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  TRADE SIZE LIMITS
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId, Balance};
+
+use crate::*;
+
+/// Maximum size of a single swap in a pool, measured on the swap NEAR side (NEAR sold or
+/// bought), which every pool swap has. Larger trades must be split in time with a TWAMM
+/// order (`place_twamm_order`) or an escrowed swap executed in chunks (`request_swap`).
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MaxTrade {
+    /// absolute limit, in yNEAR.
+    Ynear { amount: U128 },
+    /// limit in basis points of the pool NEAR reserve before the swap.
+    ReserveBps { bps: u16 },
+}
+
+impl MaxTrade {
+    /// Returns the limit, in yNEAR, of a swap in a pool with `ynear` NEAR reserve.
+    pub fn ynear_limit(&self, ynear: Balance) -> Balance {
+        match self {
+            MaxTrade::Ynear { amount } => amount.0,
+            MaxTrade::ReserveBps { bps } => clp_math::mul_div_floor(ynear, *bps as u128, 10_000),
+        }
+    }
+
+    pub fn assert_valid(&self) {
+        match self {
+            MaxTrade::Ynear { amount } => assert!(amount.0 > 0, "{}", ERR127_INVALID_MAX_TRADE),
+            MaxTrade::ReserveBps { bps } => {
+                assert!(*bps > 0 && *bps <= 10_000, "{}", ERR127_INVALID_MAX_TRADE)
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /// Owner: sets the `token` pool maximum trade size. `None` removes the limit. The order
+    /// book fills and the TWAMM virtual orders are not limited.
+    pub fn set_pool_max_trade(&mut self, token: AccountId, max_trade: Option<MaxTrade>) {
+        self.assert_owner();
+        if let Some(m) = &max_trade {
+            m.assert_valid();
+        }
+        let mut p = self.get_pool(&token);
+        p.max_trade = max_trade;
+        self.set_pool(&token, &p);
+        self.log_admin_action("set_pool_max_trade", &(token, max_trade));
+    }
+
+    pub fn pool_max_trade(&self, token: AccountId) -> Option<MaxTrade> {
+        self.get_pool(&token).max_trade
+    }
+
+    /// Returns the current `token` pool maximum trade size, in yNEAR, if the pool has one.
+    pub fn pool_max_trade_ynear(&self, token: AccountId) -> Option<U128> {
+        let p = self.get_pool(&token);
+        p.max_trade.map(|m| m.ynear_limit(p.ynear).into())
+    }
+}
+
+impl NearSwap {
+    /// Panics if a swap with the `ynear` NEAR side exceeds the `p` pool maximum trade size.
+    pub(crate) fn assert_trade_size(&self, p: &Pool, ynear: Balance) {
+        if let Some(m) = p.max_trade {
+            let limit = m.ynear_limit(p.ynear);
+            assert!(
+                ynear <= limit,
+                "{} (ynear={}, max={})",
+                ERR126_TRADE_TOO_LARGE,
+                ynear,
+                limit
+            );
+        }
+    }
+}
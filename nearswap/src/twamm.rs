@@ -0,0 +1,321 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+// Copyright (C) 2020 Robert Zaremba and contributors
+
+/**********************
+  TWAMM ORDERS
+***********************/
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{assert_one_yocto, env, near_bindgen, AccountId, Balance};
+
+use crate::accumulator::RewardAcc;
+use crate::constants::*;
+use crate::*;
+
+/// Long-term (TWAMM) order placed with `place_twamm_order`. The order sells `rate` per
+/// second of NEAR (`sell_near`) or of the pool token, from `start` until `end` (in seconds).
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+pub struct TwammOrder {
+    pub account: AccountId,
+    pub token: AccountId,
+    pub sell_near: bool,
+    pub rate: U128,
+    pub start: u64,
+    /// order end, at a `TWAMM_INTERVAL` boundary.
+    pub end: u64,
+    /// accumulator value at the order start, see `RewardAcc`.
+    pub checkpoint: U128,
+}
+
+/// Virtual sale of the pool long-term orders.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct TwammPool {
+    /// NEAR and tokens sold per second by the active orders.
+    pub near_rate: Balance,
+    pub token_rate: Balance,
+    /// tokens bought per unit of `near_rate`.
+    pub near_acc: RewardAcc,
+    /// NEAR bought per unit of `token_rate`.
+    pub token_acc: RewardAcc,
+    /// time of the last execution, in seconds.
+    pub last: u64,
+}
+
+/// Orders ending at a `TWAMM_INTERVAL` boundary.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct TwammBoundary {
+    /// sell rates which stop at the boundary.
+    pub near_rate: Balance,
+    pub token_rate: Balance,
+    /// orders ending at the boundary and not settled yet.
+    pub orders: u32,
+    /// `near_acc` and `token_acc` values at the boundary, once it's crossed.
+    pub acc: Option<(Balance, Balance)>,
+}
+
+/// Amounts settled by `cancel_twamm_order`.
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct TwammSettlement {
+    pub bought: U128,
+    /// unsold amount returned to the deposit.
+    pub refunded: U128,
+}
+
+#[near_bindgen]
+impl NearSwap {
+    /**
+    Places a long-term (TWAMM) order selling `amount` of NEAR (`sell_near`) or of `token`
+    for the other pool asset, at a constant rate until the `intervals`-th `TWAMM_INTERVAL`
+    boundary. The escrowed amount is rounded down to a whole rate per second.
+    The sale is executed virtually whenever the pool is touched (swaps, liquidity changes,
+    `execute_twamm`): opposite order flows are matched at the pool spot price, the rest is
+    swapped in the pool. Bought amounts are claimed with `cancel_twamm_order`.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation.
+    Returns the order id. */
+    #[payable]
+    pub fn place_twamm_order(
+        &mut self,
+        token: AccountId,
+        sell_near: bool,
+        amount: U128,
+        intervals: u32,
+    ) -> u64 {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        assert!(
+            intervals > 0 && intervals <= MAX_TWAMM_INTERVALS,
+            "{}",
+            ERR98_INVALID_TWAMM_INTERVALS
+        );
+        let mut p = self.get_pool(&token);
+        self.assert_pool_active(&p);
+        assert!(p.ynear > 0 && p.tokens > 0, "E10: Pool is empty");
+        let now = env::block_timestamp() / 1_000_000_000;
+        let mut tw = self.twamm.get(&token).unwrap_or_else(|| TwammPool {
+            last: now,
+            ..Default::default()
+        });
+        self.advance_twamm(&token, &mut tw, &mut p);
+
+        let end = (now / TWAMM_INTERVAL + intervals as u64) * TWAMM_INTERVAL;
+        let rate = amount.0 / (end - now) as u128;
+        assert!(rate > 0, "{}", ERR02_POSITIVE_ARGS);
+        let escrow = rate * (end - now) as u128;
+        let user = env::predecessor_account_id();
+        let mut d = self.get_deposit(&user);
+        if sell_near {
+            d.remove_near(escrow);
+            // make sure the settlement doesn't need more deposit storage.
+            d.add(&token, 0);
+        } else {
+            d.remove(&token, escrow);
+        }
+        self.deposits.insert(&user, &d.into());
+
+        let key = (token.clone(), end);
+        let mut b = self.twamm_boundaries.get(&key).unwrap_or_default();
+        let checkpoint = if sell_near {
+            tw.near_rate += rate;
+            b.near_rate += rate;
+            tw.near_acc.acc_reward_per_share
+        } else {
+            tw.token_rate += rate;
+            b.token_rate += rate;
+            tw.token_acc.acc_reward_per_share
+        };
+        b.orders += 1;
+        self.twamm_boundaries.insert(&key, &b);
+        self.twamm.insert(&token, &tw);
+        self.set_pool(&token, &p);
+
+        let id = self.next_order;
+        self.next_order += 1;
+        self.twamm_orders.insert(
+            &id,
+            &TwammOrder {
+                account: user,
+                token: token.clone(),
+                sell_near,
+                rate: rate.into(),
+                start: now,
+                end,
+                checkpoint: checkpoint.into(),
+            },
+        );
+        self.unsafe_storage_check(start_storage);
+        event_log!(
+            self,
+            "TWAMM order {}: selling {} {} until {}",
+            id,
+            escrow,
+            if sell_near { "yNEAR" } else { token.as_str() },
+            end
+        );
+        id
+    }
+
+    /**
+    Cancels the `id` TWAMM order, or settles it after its end: credits the bought amount
+    and the unsold escrow to the caller deposit.
+    Requires payment of exactly one yNEAR to enforce wallet confirmation. */
+    #[payable]
+    pub fn cancel_twamm_order(&mut self, id: u64) -> TwammSettlement {
+        assert_one_yocto();
+        let start_storage = env::storage_usage();
+        let user = env::predecessor_account_id();
+        let o = self.twamm_orders.get(&id).expect(ERR33_ORDER_NOT_FOUND);
+        assert!(o.account == user, "{}", ERR33_ORDER_NOT_FOUND);
+        self.twamm_orders.remove(&id);
+
+        let token = o.token.clone();
+        let mut p = self.get_pool(&token);
+        let mut tw = self.twamm.get(&token).unwrap();
+        self.advance_twamm(&token, &mut tw, &mut p);
+        let key = (token.clone(), o.end);
+        let mut b = self.twamm_boundaries.get(&key).unwrap();
+        let rate = o.rate.0;
+        let (acc, refunded) = match b.acc {
+            Some((near_acc, token_acc)) => (if o.sell_near { near_acc } else { token_acc }, 0),
+            None => {
+                // the order is still active: stop its flow.
+                let acc = if o.sell_near {
+                    tw.near_rate -= rate;
+                    b.near_rate -= rate;
+                    tw.near_acc.acc_reward_per_share
+                } else {
+                    tw.token_rate -= rate;
+                    b.token_rate -= rate;
+                    tw.token_acc.acc_reward_per_share
+                };
+                (acc, rate * (o.end - tw.last) as u128)
+            }
+        };
+        b.orders -= 1;
+        if b.orders == 0 {
+            self.twamm_boundaries.remove(&key);
+        } else {
+            self.twamm_boundaries.insert(&key, &b);
+        }
+        self.twamm.insert(&token, &tw);
+        self.set_pool(&token, &p);
+
+        let bought = RewardAcc {
+            acc_reward_per_share: acc,
+        }
+        .earned(rate, o.checkpoint.0);
+        let mut d = self.get_deposit(&user);
+        if o.sell_near {
+            d.add(&token, bought);
+            d.add_near(refunded);
+        } else {
+            d.add_near(bought);
+            d.add(&token, refunded);
+        }
+        d.update_storage(start_storage);
+        self.deposits.insert(&user, &d.into());
+        event_log!(
+            self,
+            "TWAMM order {} settled: bought {}, refunded {}",
+            id,
+            bought,
+            refunded
+        );
+        TwammSettlement {
+            bought: bought.into(),
+            refunded: refunded.into(),
+        }
+    }
+
+    /// Executes the `token` pool long-term orders up to now. Can be called by anyone, eg
+    /// keepers, to keep the executed chunks small.
+    pub fn execute_twamm(&mut self, token: AccountId) {
+        let mut p = self.get_pool(&token);
+        self.assert_pool_active(&p);
+        self.run_twamm(&token, &mut p);
+        self.set_pool(&token, &p);
+    }
+
+    pub fn twamm_order(&self, id: u64) -> Option<TwammOrder> {
+        self.twamm_orders.get(&id)
+    }
+
+    /// Returns the NEAR and token amounts sold per second by the `token` pool long-term
+    /// orders.
+    pub fn twamm_rates(&self, token: AccountId) -> (U128, U128) {
+        self.twamm
+            .get(&token)
+            .map(|tw| (tw.near_rate.into(), tw.token_rate.into()))
+            .unwrap_or((U128(0), U128(0)))
+    }
+}
+
+impl NearSwap {
+    /// Executes the `token` pool long-term orders, if any, up to now. The caller must save
+    /// the `p` pool.
+    pub(crate) fn run_twamm(&mut self, token: &AccountId, p: &mut Pool) {
+        if let Some(mut tw) = self.twamm.get(token) {
+            self.advance_twamm(token, &mut tw, p);
+            self.twamm.insert(token, &tw);
+        }
+    }
+
+    /// Executes the orders flows since `tw.last`, in chunks ending at the `TWAMM_INTERVAL`
+    /// boundaries, where the ending orders are removed from the flows. The sale is
+    /// suspended while the pool has no liquidity.
+    fn advance_twamm(&mut self, token: &AccountId, tw: &mut TwammPool, p: &mut Pool) {
+        let now = env::block_timestamp() / 1_000_000_000;
+        if p.ynear == 0 || p.tokens == 0 {
+            return;
+        }
+        while tw.last < now {
+            if tw.near_rate == 0 && tw.token_rate == 0 {
+                tw.last = now;
+                break;
+            }
+            let boundary = (tw.last / TWAMM_INTERVAL + 1) * TWAMM_INTERVAL;
+            let next = boundary.min(now);
+            self.twamm_step(tw, p, next - tw.last);
+            tw.last = next;
+            if next < boundary {
+                break;
+            }
+            let key = (token.clone(), boundary);
+            if let Some(mut b) = self.twamm_boundaries.get(&key) {
+                tw.near_rate -= b.near_rate;
+                tw.token_rate -= b.token_rate;
+                b.acc = Some((
+                    tw.near_acc.acc_reward_per_share,
+                    tw.token_acc.acc_reward_per_share,
+                ));
+                self.twamm_boundaries.insert(&key, &b);
+            }
+        }
+    }
+
+    /// Sells `dt` seconds of the orders flows: the opposite flows are matched at the pool
+    /// spot price and the rest is swapped in the pool.
+    fn twamm_step(&self, tw: &mut TwammPool, p: &mut Pool, dt: u64) {
+        let near_in = tw.near_rate * dt as u128;
+        let tokens_in = tw.token_rate * dt as u128;
+        // NEAR value of the sold tokens at the spot price.
+        let tokens_value = clp_math::mul_div_floor(tokens_in, p.ynear, p.tokens);
+        let (near_out, tokens_out) = if near_in >= tokens_value {
+            let swap_in = near_in - tokens_value;
+            let (out, fee) = self.calc_out_with_fee(swap_in, p.ynear, p.tokens, p.fee_bps);
+            p.ynear += swap_in - self.take_fees(p, fee, true);
+            p.tokens -= out;
+            (tokens_value, tokens_in + out)
+        } else {
+            let matched = clp_math::mul_div_floor(near_in, p.tokens, p.ynear);
+            let swap_in = tokens_in - matched;
+            let (out, fee) = self.calc_out_with_fee(swap_in, p.tokens, p.ynear, p.fee_bps);
+            p.tokens += swap_in - self.take_fees(p, fee, false);
+            p.ynear -= out;
+            (near_in + out, matched)
+        };
+        tw.near_acc.distribute(tokens_out, tw.near_rate);
+        tw.token_acc.distribute(near_out, tw.token_rate);
+    }
+}
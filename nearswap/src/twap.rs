@@ -1,5 +1,6 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::collections::Vector;
+use near_sdk::IntoStorageKey;
 
 use crate::*;
 
@@ -43,12 +44,12 @@ pub struct Twap {
 }
 
 impl Twap {
-    pub fn new(length: u64) -> Self {
+    pub fn new<S: IntoStorageKey>(prefix: S, length: u64) -> Self {
         Self {
             current_idx: 0,
             pivoted: false,
             max_length: length,
-            observations: Vector::new("twap".as_bytes().to_vec()),
+            observations: Vector::new(prefix),
             mean_1min: (0, 0),
             mean_5min: (0, 0),
             mean_1h: (0, 0),
@@ -70,6 +71,8 @@ impl Twap {
             num_of_observations: 1,
             price1_cumulative: price1,
             price2_cumulative: price2,
+            log_price1_cumulative: log_price(price1),
+            log_price2_cumulative: log_price(price2),
         });
         self.current_idx = 0;
         return 0;
@@ -173,6 +176,53 @@ impl Twap {
             Mean::M1h => T_1H,
             Mean::M12h => T_12H,
         };
+        self.mean_over(time_diff)
+    }
+
+    /// Returns the mean of the readings in the last `time_diff` nanoseconds before the most
+    /// recent observation. Panics if there are no observations.
+    pub fn mean_over(&self, time_diff: u64) -> (u128, u128) {
+        let (current_o, prev_o) = self.window(time_diff);
+        let (mut total_observe, mut price1cumu, mut price2cumu) = (
+            current_o.num_of_observations,
+            current_o.price1_cumulative,
+            current_o.price2_cumulative,
+        );
+        if let Some(prev_o) = prev_o {
+            total_observe -= prev_o.num_of_observations;
+            price1cumu -= prev_o.price1_cumulative;
+            price2cumu -= prev_o.price2_cumulative;
+        }
+        let mean1 = price1cumu / total_observe;
+        let mean2 = price2cumu / total_observe;
+        return (mean1, mean2);
+    }
+
+    /// Returns the geometric mean of the readings in the last `time_diff` nanoseconds before
+    /// the most recent observation: the exponent of the mean log price. Unlike the arithmetic
+    /// mean, a few outlier readings barely move it. Zero prices are read as 1.
+    /// Panics if there are no observations.
+    pub fn gm_mean_over(&self, time_diff: u64) -> (u128, u128) {
+        let (current_o, prev_o) = self.window(time_diff);
+        let (mut total_observe, mut log1cumu, mut log2cumu) = (
+            current_o.num_of_observations,
+            current_o.log_price1_cumulative,
+            current_o.log_price2_cumulative,
+        );
+        if let Some(prev_o) = prev_o {
+            total_observe -= prev_o.num_of_observations;
+            log1cumu -= prev_o.log_price1_cumulative;
+            log2cumu -= prev_o.log_price2_cumulative;
+        }
+        (
+            clp_math::exp2(log1cumu / total_observe),
+            clp_math::exp2(log2cumu / total_observe),
+        )
+    }
+
+    /// Returns the most recent observation and the observation right before the last
+    /// `time_diff` nanoseconds window, if it's still recorded.
+    fn window(&self, time_diff: u64) -> (Observation, Option<Observation>) {
         let req_timestamp;
         if self
             .observations
@@ -196,24 +246,12 @@ impl Twap {
         }
 
         let left_index = self.binary_search(req_timestamp);
-        let current_o = &self.observations.get(self.current_idx).unwrap();
-
-        let total_observe;
-        let price1cumu;
-        let price2cumu;
+        let current_o = self.observations.get(self.current_idx).unwrap();
         if left_index == 0 {
-            total_observe = current_o.num_of_observations;
-            price1cumu = current_o.price1_cumulative;
-            price2cumu = current_o.price2_cumulative;
+            (current_o, None)
         } else {
-            let prev_o = &self.observations.get(left_index - 1).unwrap();
-            total_observe = current_o.num_of_observations - prev_o.num_of_observations;
-            price1cumu = current_o.price1_cumulative - prev_o.price1_cumulative;
-            price2cumu = current_o.price2_cumulative - prev_o.price2_cumulative;
+            (current_o, self.observations.get(left_index - 1))
         }
-        let mean1 = price1cumu / total_observe;
-        let mean2 = price2cumu / total_observe;
-        return (mean1, mean2);
     }
 
     pub fn log_observation(&mut self, timestamp: u64, price1: u128, price2: u128) -> u64 {
@@ -228,6 +266,13 @@ impl Twap {
         return self.current_idx;
     }
 
+    /// Returns the timestamp of the most recent observation, if any.
+    pub fn last_observation(&self) -> Option<u64> {
+        self.observations
+            .get(self.current_idx)
+            .map(|o| o.block_timestamp)
+    }
+
     pub fn update_mean(&mut self) {
         self.mean_1min = self.calculate_mean(Mean::M1min);
         self.mean_5min = self.calculate_mean(Mean::M5min);
@@ -246,6 +291,10 @@ pub struct Observation {
     pub price1_cumulative: u128,
     // cumulative price of token2 till block_timestamp
     pub price2_cumulative: u128,
+    // cumulative log2 price of token1 till block_timestamp, see `clp_math::log2`
+    pub log_price1_cumulative: u128,
+    // cumulative log2 price of token2 till block_timestamp
+    pub log_price2_cumulative: u128,
 }
 
 impl Observation {
@@ -256,6 +305,8 @@ impl Observation {
             num_of_observations: 0,
             price1_cumulative: 0,
             price2_cumulative: 0,
+            log_price1_cumulative: 0,
+            log_price2_cumulative: 0,
         };
     }
 
@@ -278,10 +329,17 @@ impl Observation {
             num_of_observations: last.num_of_observations + 1,
             price1_cumulative: last.price1_cumulative + price1,
             price2_cumulative: last.price2_cumulative + price2,
+            log_price1_cumulative: last.log_price1_cumulative + log_price(price1),
+            log_price2_cumulative: last.log_price2_cumulative + log_price(price2),
         };
     }
 }
 
+/// Returns the log2 of the `price`, reading a zero price as 1.
+fn log_price(price: u128) -> u128 {
+    clp_math::log2(price.max(1))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -297,7 +355,7 @@ mod tests {
     // returns twap with observation vector with timestamp [1, 2, 3, 4, 5, 6, 7, 9, 10]
     fn setup_twap() -> Twap {
         let max_length = 10;
-        let mut twap: Twap = Twap::new(max_length);
+        let mut twap: Twap = Twap::new(b"twap".to_vec(), max_length);
 
         // fill all places
         for i in 1..11 {
@@ -312,7 +370,7 @@ mod tests {
     fn initialize_works() {
         init_blockchain();
 
-        let mut twap: Twap = Twap::new(10);
+        let mut twap: Twap = Twap::new(b"twap".to_vec(), 10);
         twap.log_observation(1, 1, 1);
 
         assert!(twap.observations.len() == 1, "Mismatch");
@@ -330,7 +388,7 @@ mod tests {
     fn write_works() {
         init_blockchain();
 
-        let mut twap: Twap = Twap::new(10);
+        let mut twap: Twap = Twap::new(b"twap".to_vec(), 10);
         twap.log_observation(1, 1, 1);
 
         let timestamp = 12;
@@ -372,7 +430,7 @@ mod tests {
     fn overwrite_works() {
         init_blockchain();
 
-        let mut twap: Twap = Twap::new(10);
+        let mut twap: Twap = Twap::new(b"twap".to_vec(), 10);
         let mut current_idx;
 
         // fill all places
@@ -496,7 +554,7 @@ mod tests {
 
         let timestamp = 1;
         let max_length = 10;
-        let mut twap: Twap = Twap::new(max_length);
+        let mut twap: Twap = Twap::new(b"twap".to_vec(), max_length);
         twap.log_observation(timestamp, 1, 1);
 
         let min_2_timestamp = timestamp + to_nanoseconds(120);
@@ -525,7 +583,7 @@ mod tests {
 
         let timestamp = 1;
         let max_length = 10;
-        let mut twap: Twap = Twap::new(max_length);
+        let mut twap: Twap = Twap::new(b"twap".to_vec(), max_length);
         twap.log_observation(timestamp, 1, 1);
 
         let min_2_timestamp = timestamp + to_nanoseconds(120);
@@ -2,7 +2,45 @@
 // Copyright (C) 2020 Robert Zaremba and contributors
 
 use near_sdk::ext_contract;
-use near_sdk::json_types::U128;
+use near_sdk::json_types::{Base64VecU8, U128};
+
+// Arguments and return values of the contract public API, shared with off-chain clients.
+pub use crate::adapters::{AdapterSwapMsg, ExternalSwap};
+pub use crate::audit::AuditStatus;
+pub use crate::bonds::{PoolBond, PoolBondConfig};
+pub use crate::candles::{Candle, Resolution};
+pub use crate::council::{Council, CouncilMotion};
+pub use crate::croncat::CronTask;
+pub use crate::deadline::Deadline;
+pub use crate::delegation::{DelegationInfo, VotingPower};
+pub use crate::dynamic_fee::DynamicFee;
+pub use crate::escrow::SwapRequest;
+pub use crate::farming::{CampaignInfo, FarmInfo, FarmStakeInfo, FarmUnstakePolicy};
+pub use crate::gas::{GasAction, GasOp};
+pub use crate::gov_staking::{GovStakeInfo, GovStakingInfo};
+pub use crate::governance::{FeeBounds, FeeProposal, ProposalStatus};
+pub use crate::launch::{Launch, LaunchConfig};
+pub use crate::lending::{LendingOp, PoolLendingInfo};
+pub use crate::locks::{LockInfo, LockTier};
+pub use crate::method_groups::MethodGroup;
+pub use crate::migration::MigrationSource;
+pub use crate::orders::{DcaOrder, LimitOrder, TriggerKind, TriggerOrder};
+pub use crate::pool::{
+    HealthFlag, LiquidityPreview, PoolHealth, PoolImbalance, PoolInfo, PoolInvariant, PoolSnapshot,
+    PoolStorage, PriceBounds,
+};
+pub use crate::pool_templates::PoolTemplate;
+pub use crate::position_history::{LiquidityEvent, LiquidityEventKind};
+pub use crate::price_history::PriceSample;
+pub use crate::rate_provider::{PoolRate, RateMethod, RateProvider};
+pub use crate::rebates::RebateTier;
+pub use crate::reserves::ReservesReport;
+pub use crate::source_metadata::ContractSourceMetadata;
+pub use crate::staking::{PoolStakingInfo, StakingConfig, StakingOp};
+pub use crate::router::{ExecutedSwap, SwapAction, SwapQuote, SwapRoute, TargetWeight};
+pub use crate::stats::TraderStats;
+pub use crate::timelock::{GovAction, QueuedAction};
+pub use crate::trade_limits::MaxTrade;
 
 /// Interface for recipient call on multi fungible-token transfers.
 #[ext_contract(ext_mft_rec)]
@@ -10,3 +48,68 @@ pub trait MFTRecipient {
     fn on_mft_receive(&mut self, token: String, from: AccountId, amount: U128, msg: String)
         -> bool;
 }
+
+/// Callbacks this contract schedules on itself.
+#[ext_contract(ext_self)]
+pub trait NearSwapResolver {
+    fn resolve_withdraw_near(&mut self, sender: AccountId, amount: U128) -> U128;
+
+    fn resolve_withdraw_token(
+        &mut self,
+        sender: AccountId,
+        recipient: AccountId,
+        token: AccountId,
+        amount: U128,
+        is_call: bool,
+    ) -> PromiseOrValue<U128>;
+
+    fn resolve_retry_transfer(&mut self, sender: AccountId, token: AccountId, amount: U128)
+        -> U128;
+
+    fn resolve_claim_failed_transfer(
+        &mut self,
+        account: AccountId,
+        token: Option<AccountId>,
+        amount: U128,
+    ) -> U128;
+
+    fn resolve_croncat_create_task(&mut self, task: CronTask) -> Option<Base64VecU8>;
+
+    fn resolve_verify_reserves(
+        &mut self,
+        token: AccountId,
+        pause_if_underfunded: bool,
+    ) -> Option<ReservesReport>;
+
+    fn resolve_staking(&mut self, token: AccountId, op: StakingOp, amount: U128) -> bool;
+
+    fn resolve_harvest_staking_rewards(&mut self) -> U128;
+
+    fn resolve_lending(&mut self, token: AccountId, op: LendingOp, amount: U128) -> bool;
+
+    fn resolve_harvest_lending_interest(&mut self, token: AccountId) -> U128;
+
+    fn resolve_update_rate(&mut self, token: AccountId) -> Option<U128>;
+
+    fn resolve_migrate_in(
+        &mut self,
+        sender: AccountId,
+        lp_token: AccountId,
+        token: AccountId,
+        amount: U128,
+        min_shares: U128,
+    ) -> U128;
+
+    fn resolve_adapter_swap(&mut self, id: u64) -> U128;
+
+    fn resolve_create_pool(
+        &mut self,
+        token: AccountId,
+        creator: AccountId,
+        price_bounds: Option<PriceBounds>,
+        attached: U128,
+        template: Option<PoolTemplate>,
+    ) -> bool;
+
+    fn resolve_create_launch(&mut self, token: AccountId) -> bool;
+}
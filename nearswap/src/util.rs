@@ -2,8 +2,7 @@
 // Copyright (C) 2020 Robert Zaremba and contributors
 
 use near_sdk::Gas;
-use near_sdk::{env, AccountId, PromiseResult};
-use uint::construct_uint;
+use near_sdk::{env, AccountId, CryptoHash, PromiseResult};
 
 use crate::constants::*;
 
@@ -20,10 +19,7 @@ pub const MAX_GAS: Gas = 200 * TGAS;
 /// Price per 1 byte of storage from mainnet genesis config. 100e18
 pub const STORAGE_BYTE_PRICE: u128 = 100_000_000_000_000_000_000;
 
-construct_uint! {
-    /// 256-bit unsigned integer.
-    pub struct u256(4);
-}
+pub use clp_math::u256;
 
 /** Ensures that an account `a` is valid and panics if it's not.
 `name`: printed name of the account */
@@ -36,6 +32,24 @@ pub fn assert_account_is_valid(a: &AccountId) {
     );
 }
 
+/// Returns the hash of the pool `token` account id, used as the pool collections storage key
+/// suffix, see `StorageKey`.
+pub fn pool_hash(token: &AccountId) -> CryptoHash {
+    let mut h = CryptoHash::default();
+    h.copy_from_slice(&env::sha256(token.as_bytes()));
+    h
+}
+
+/// Asserts that at most one yNEAR is attached. Function-call access keys can't attach a
+/// deposit, so methods allowed for them accept zero or one yNEAR, see `get_method_groups`.
+#[inline]
+pub fn assert_at_most_one_yocto() {
+    assert!(
+        env::attached_deposit() <= 1,
+        "Requires attached deposit of at most 1 yoctoNEAR"
+    )
+}
+
 pub fn is_promise_success() -> bool {
     assert_eq!(
         env::promise_results_count(),
@@ -68,3 +82,68 @@ macro_rules! env_log {
         env::log(msg.as_bytes())
     }}
 }
+
+/// Logs an event prefixed with the next contract event sequence number (`seq`).
+/// Usage: `event_log!(self, "Deposit, {} yNEAR", amount)`.
+#[macro_export]
+macro_rules! event_log {
+    ($contract:expr, $($arg:tt)*) => {{
+        let seq = $contract.next_event_seq();
+        $crate::env_log!("seq={}, {}", seq, format!($($arg)*))
+    }}
+}
+
+/// Logs debug metrics. Compiled out unless the `debug-metrics` feature is enabled.
+#[macro_export]
+macro_rules! metrics_log {
+    ($($arg:tt)*) => {{
+        if cfg!(feature = "debug-metrics") {
+            $crate::env_log!("metrics: {}", format!($($arg)*));
+        }
+    }}
+}
+
+/// Measures gas and storage used by a contract method and logs them when dropped.
+/// Usage: `let _m = MethodMetrics::start("add_liquidity");`.
+/// Without the `debug-metrics` feature it's a no-op.
+#[cfg(feature = "debug-metrics")]
+pub struct MethodMetrics {
+    method: &'static str,
+    gas: Gas,
+    storage: near_sdk::StorageUsage,
+}
+
+#[cfg(feature = "debug-metrics")]
+impl MethodMetrics {
+    pub fn start(method: &'static str) -> Self {
+        Self {
+            method,
+            gas: env::used_gas(),
+            storage: env::storage_usage(),
+        }
+    }
+}
+
+#[cfg(feature = "debug-metrics")]
+impl Drop for MethodMetrics {
+    fn drop(&mut self) {
+        let storage_delta = env::storage_usage() as i64 - self.storage as i64;
+        env_log!(
+            "metrics: {} used_gas={} storage_delta={}",
+            self.method,
+            env::used_gas() - self.gas,
+            storage_delta
+        );
+    }
+}
+
+#[cfg(not(feature = "debug-metrics"))]
+pub struct MethodMetrics;
+
+#[cfg(not(feature = "debug-metrics"))]
+impl MethodMetrics {
+    #[inline]
+    pub fn start(_method: &'static str) -> Self {
+        MethodMetrics
+    }
+}
@@ -1,8 +1,13 @@
 //! View functions for the contract.
 
-use near_sdk::json_types::U128;
-use near_sdk::{near_bindgen, AccountId};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::borsh::BorshSerialize;
+use near_sdk::{env, near_bindgen, AccountId, EpochHeight, IntoStorageKey, StorageUsage};
 
+use crate::candles::{Candle, Resolution};
+use crate::constants::{
+    MAX_TWAP_WINDOW, MIN_TWAP_WINDOW, ORACLE_STALE_PERIOD, STORAGE_RECORD_OVERHEAD,
+};
 use crate::*;
 
 #[near_bindgen]
@@ -29,4 +34,256 @@ impl NearSwap {
         let res = self.get_deposit(&account_id);
         U128(res.ynear)
     }
+
+    /**
+    Simulates `add_liquidity` with the current `token` pool state.
+    Returns the yNEAR and tokens which would be pulled from the deposit, the shares which
+    would be minted and the resulting pool state.
+    Panics with the same conditions as `add_liquidity` related to the pool. */
+    pub fn simulate_add_liquidity(
+        &self,
+        token: AccountId,
+        ynear: U128,
+        max_tokens: U128,
+    ) -> LiquidityPreview {
+        let p = self.get_pool(&token);
+        let (ynear, max_tokens) = (ynear.0, max_tokens.0);
+        assert!(ynear > 0 && max_tokens > 0, "E2: added liquidity must be >0");
+        // the first deposit defines the pool state
+        let (p_ynear, p_tokens) = if p.total_shares == 0 {
+            if let Some(b) = &p.init_price {
+                b.assert_within(ynear, max_tokens);
+            }
+            (0, 0)
+        } else {
+            (p.ynear, p.tokens)
+        };
+        let (NearAmount(ynear), TokenAmount(tokens), Shares(shares)) =
+            p.calc_add_liquidity(NearAmount(ynear), TokenAmount(max_tokens));
+        LiquidityPreview {
+            ynear: ynear.into(),
+            tokens: tokens.into(),
+            shares: shares.into(),
+            pool: PoolInfo {
+                ynear: (p_ynear + ynear).into(),
+                tokens: (p_tokens + tokens).into(),
+                total_shares: (p.total_shares + shares).into(),
+            },
+        }
+    }
+
+    /**
+    Simulates `withdraw_liquidity` of `shares` with the current `token` pool state.
+    Returns the yNEAR and tokens which would be credited to the deposit (rounded down, as in
+    `withdraw_liquidity`), the burned shares and the resulting pool state.
+    Panics if the pool doesn't exist or `shares` is bigger than the pool total shares. */
+    pub fn simulate_withdraw_liquidity(&self, token: AccountId, shares: U128) -> LiquidityPreview {
+        let p = self.get_pool(&token);
+        let shares = shares.0;
+        assert!(shares > 0, "E2: balance arguments must be > 0");
+        assert!(
+            shares <= p.total_shares,
+            "{}",
+            format!(
+                "E5: can't withdraw more shares then currently owned ({}), requested {}",
+                p.total_shares, shares
+            )
+        );
+        let (NearAmount(ynear), TokenAmount(tokens)) = p.calc_withdraw_liquidity(Shares(shares));
+        LiquidityPreview {
+            ynear: ynear.into(),
+            tokens: tokens.into(),
+            shares: shares.into(),
+            pool: PoolInfo {
+                ynear: (p.ynear - ynear).into(),
+                tokens: (p.tokens - tokens).into(),
+                total_shares: (p.total_shares - shares).into(),
+            },
+        }
+    }
+
+    /// Returns up to `limit` per epoch snapshots of the `token` pool reserves, recorded not
+    /// earlier than `from_epoch`, in chronological order. A snapshot is recorded on the first
+    /// pool update in an epoch, before the update is applied.
+    pub fn pool_history(
+        &self,
+        token: AccountId,
+        from_epoch: EpochHeight,
+        limit: u64,
+    ) -> Vec<PoolSnapshot> {
+        self.get_pool(&token).history(from_epoch, limit)
+    }
+
+    /// Returns up to `limit` OHLC price candles of the `token` pool in the given `resolution`,
+    /// starting not earlier than `from` (timestamp in nanoseconds), in chronological order.
+    /// Prices are expressed in yNEAR per `1e24` token units.
+    pub fn candles(
+        &self,
+        token: AccountId,
+        resolution: Resolution,
+        from: U64,
+        limit: u64,
+    ) -> Vec<Candle> {
+        self.get_pool(&token).candles.list(resolution, from.into(), limit)
+    }
+
+    /// Returns the consistency report of up to `limit` pools, starting from the `from` pool
+    /// in the `list_pools` order. Designed for monitoring bots.
+    pub fn health_check(&self, from: u64, limit: u64) -> Vec<PoolHealth> {
+        let now = env::block_timestamp();
+        self.pools
+            .iter()
+            .skip(from as usize)
+            .take(limit as usize)
+            .map(|(token, p)| {
+                let mut flags = Vec::new();
+                if p.total_shares == 0 {
+                    flags.push(HealthFlag::Empty);
+                }
+                if self.paused || p.paused {
+                    flags.push(HealthFlag::Paused);
+                }
+                match p.twap.last_observation() {
+                    Some(t) if now.saturating_sub(t) <= ORACLE_STALE_PERIOD => {}
+                    _ => flags.push(HealthFlag::StaleOracle),
+                }
+                if self.pool_fee_proposals.get(&token).is_some() {
+                    flags.push(HealthFlag::FeeVoting);
+                }
+                PoolHealth {
+                    token,
+                    reserves_consistent: p.reserves_consistent(),
+                    shares_audit: p.audit.last_result,
+                    flags,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the number of accounts holding `token` pool LP shares. Locked, staked and
+    /// delegated shares are not counted.
+    pub fn n_liquidity_providers(&self, token: AccountId) -> u64 {
+        self.get_pool(&token).n_lps
+    }
+
+    /// Returns the accounts holding `token` pool LP shares with their shares, checking up to
+    /// `limit` pool holders starting from the `from` holder index. Past holders without
+    /// shares are skipped, so a page can have less than `limit` records. Locked, staked and
+    /// delegated shares are not included.
+    pub fn get_liquidity_providers(
+        &self,
+        token: AccountId,
+        from: u64,
+        limit: u64,
+    ) -> Vec<(AccountId, U128)> {
+        let p = self.get_pool(&token);
+        let holders = p.holders.as_vector();
+        let end = holders.len().min(from.saturating_add(limit));
+        (from..end)
+            .filter_map(|i| {
+                let a = holders.get(i).unwrap();
+                match p.shares.get(&a).unwrap_or(0) {
+                    0 => None,
+                    s => Some((a, s.into())),
+                }
+            })
+            .collect()
+    }
+
+    /// Returns up to `limit` largest `token` pool LPs with their shares, in descending order.
+    /// The index is updated when the LP shares change, so it can miss LPs whose shares didn't
+    /// change since a larger LP left it, see `refresh_top_lps`.
+    pub fn top_lps(&self, token: AccountId, limit: u64) -> Vec<(AccountId, U128)> {
+        let p = self.get_pool(&token);
+        p.top_lps
+            .get()
+            .unwrap_or_default()
+            .into_iter()
+            .take(limit as usize)
+            .map(|(a, s)| (a, s.into()))
+            .collect()
+    }
+
+    /// Returns the storage used by the `token` pool, in bytes.
+    pub fn pool_storage(&self, token: AccountId) -> PoolStorage {
+        let p = self.get_pool(&token);
+        let entry = pool_entry_storage(&token, &p);
+        PoolStorage {
+            entry: entry.into(),
+            collections: p.storage_usage.into(),
+            total: (entry + p.storage_usage).into(),
+        }
+    }
+
+    /// Returns the `token` pool reserves product (k) now and after the last liquidity add or
+    /// withdrawal, so monitoring tools can verify that swaps only grow k.
+    pub fn pool_invariant(&self, token: AccountId) -> PoolInvariant {
+        let p = self.get_pool(&token);
+        let k_last = u256::from(p.liquidity_ynear) * u256::from(p.liquidity_tokens);
+        PoolInvariant {
+            k: p.invariant().to_string(),
+            k_last_liquidity: k_last.to_string(),
+        }
+    }
+
+    /// Returns the deviation of the `token` pool reserves ratio from the TWAP oracle fair
+    /// ratio, in basis points. A signal for arbitrage bots and dynamic fees. None if the pool
+    /// is empty or the oracle was never poked.
+    pub fn pool_imbalance(&self, token: AccountId) -> Option<PoolImbalance> {
+        self.get_pool(&token).imbalance()
+    }
+
+    /// Returns the geometric mean of the `token` pool TWAP oracle prices over the last
+    /// `window` seconds: yNEAR for 1e24 token units. Outlier blocks barely move it, which
+    /// suits risk sensitive consumers, eg lending protocols. The `window` must be between
+    /// `MIN_TWAP_WINDOW` and `MAX_TWAP_WINDOW`. None if the oracle was never poked.
+    pub fn gm_twap(&self, token: AccountId, window: u64) -> Option<U128> {
+        assert!(
+            window >= MIN_TWAP_WINDOW && window <= MAX_TWAP_WINDOW,
+            "{}",
+            ERR134_INVALID_TWAP_WINDOW
+        );
+        let p = self.get_pool(&token);
+        p.twap.last_observation()?;
+        Some(p.twap.gm_mean_over(to_nanoseconds(window)).0.into())
+    }
+
+    /// Returns the value of `amount` of `token` in yNEAR at the pool spot price: without
+    /// the swap fee and price impact. Designed for a quick valuation by other contracts, eg
+    /// collateral calculators.
+    pub fn to_near_value(&self, token: AccountId, amount: U128) -> U128 {
+        self.assert_pool_not_empty(&token);
+        self.spot_out(&Some(token), &None, amount.into()).into()
+    }
+
+    /// Returns the amount of `token` worth `near_amount` yNEAR at the pool spot price: without
+    /// the swap fee and price impact. Inverse of `to_near_value`.
+    pub fn from_near_value(&self, token: AccountId, near_amount: U128) -> U128 {
+        self.assert_pool_not_empty(&token);
+        self.spot_out(&None, &Some(token), near_amount.into()).into()
+    }
+
+    /// Returns the sequence number of the last logged event (0 if no event was logged).
+    pub fn event_seq(&self) -> u64 {
+        self.event_seq
+    }
+}
+
+impl NearSwap {
+    fn assert_pool_not_empty(&self, token: &AccountId) {
+        let p = self.get_pool(token);
+        assert!(p.ynear > 0 && p.tokens > 0, "E10: Pool is empty");
+    }
+}
+
+/// Returns the storage used by the `token` pool entry in the pools `UnorderedMap`: three
+/// records, the key index (key -> index), the key (index -> key) and the value
+/// (index -> pool).
+fn pool_entry_storage(token: &AccountId, p: &Pool) -> StorageUsage {
+    let prefix = StorageKey::Pools.into_storage_key().len() as u64 + 1;
+    let key = token.try_to_vec().unwrap().len() as u64;
+    let value = p.try_to_vec().unwrap().len() as u64;
+    let index = 8;
+    (prefix + key + index) + (prefix + index + key) + (prefix + index + value)
+        + 3 * STORAGE_RECORD_OVERHEAD
 }
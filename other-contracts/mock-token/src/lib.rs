@@ -1,7 +1,11 @@
+//! Mock NEP-141 fungible token for the NEARswap simulation, sandbox (e2e) tests and local
+//! development. Implements the token interface called by NEARswap (`nearswap::ft_token`),
+//! with unrestricted `mint` and `burn`.
+
 #![allow(unused)]
 
 use near_contract_standards::fungible_token::metadata::{
-    FungibleTokenMetadata, FungibleTokenMetadataProvider,
+    FungibleTokenMetadata, FungibleTokenMetadataProvider, FT_METADATA_SPEC,
 };
 use near_contract_standards::fungible_token::FungibleToken;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
@@ -10,6 +14,10 @@ use near_sdk::{near_bindgen, AccountId, PanicOnDefault, PromiseOrValue, env};
 
 near_sdk::setup_alloc!();
 
+/// Maximum amount minted by one `faucet` call: 1000 tokens with 24 decimals.
+#[cfg(feature = "faucet")]
+pub const FAUCET_LIMIT: u128 = 1_000_000_000_000_000_000_000_000_000;
+
 #[near_bindgen]
 #[derive(BorshSerialize, BorshDeserialize, PanicOnDefault)]
 pub struct Contract {
@@ -35,6 +43,19 @@ impl Contract {
         self.token
             .internal_withdraw(account_id.as_ref(), amount.into());
     }
+
+    /// Testnet faucet: mints `amount` (by default and at most `FAUCET_LIMIT`) tokens to the
+    /// caller, registering the caller account if needed.
+    #[cfg(feature = "faucet")]
+    pub fn faucet(&mut self, amount: Option<U128>) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let amount = amount.map_or(FAUCET_LIMIT, |a| a.0.min(FAUCET_LIMIT));
+        if !self.token.accounts.contains_key(&account_id) {
+            self.token.internal_register_account(&account_id);
+        }
+        self.token.internal_deposit(&account_id, amount);
+        amount.into()
+    }
 }
 
 near_contract_standards::impl_fungible_token_core!(Contract, token);
@@ -43,7 +64,15 @@ near_contract_standards::impl_fungible_token_storage!(Contract, token);
 #[near_bindgen]
 impl FungibleTokenMetadataProvider for Contract {
     fn ft_metadata(&self) -> FungibleTokenMetadata {
-        unimplemented!()
+        FungibleTokenMetadata {
+            spec: FT_METADATA_SPEC.to_string(),
+            name: "Mock Token".to_string(),
+            symbol: "MOCK".to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: 24,
+        }
     }
 }
 
@@ -78,5 +107,19 @@ mod tests {
 
         contract.burn(accounts(1), 500.into());
         assert_eq!(contract.ft_balance_of(accounts(1)), 500.into());
+        assert_eq!(contract.ft_total_supply(), 999_500.into());
+        assert_eq!(contract.ft_metadata().decimals, 24);
+    }
+
+    #[cfg(feature = "faucet")]
+    #[test]
+    fn test_faucet() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.build());
+        let mut contract = Contract::new();
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        assert_eq!(contract.faucet(Some(1_000.into())), 1_000.into());
+        assert_eq!(contract.faucet(Some(u128::MAX.into())), FAUCET_LIMIT.into());
+        assert_eq!(contract.ft_balance_of(accounts(2)), (FAUCET_LIMIT + 1_000).into());
     }
 }